@@ -0,0 +1,23 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/llmserver.proto")?;
+
+    // 崩潰報告裡要列出的函式庫版本
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    let lock = std::fs::read_to_string("Cargo.lock").unwrap_or_default();
+    for (name, env) in [
+        ("rkllm-rs", "RKLLM_RS_VERSION"),
+        ("rknn-rs", "RKNN_RS_VERSION"),
+        ("sensevoice-rs", "SENSEVOICE_RS_VERSION"),
+    ] {
+        let version = lock
+            .split("[[package]]")
+            .find(|package| package.contains(&format!("name = \"{}\"\n", name)))
+            .and_then(|package| package.split("version = \"").nth(1))
+            .and_then(|rest| rest.split('"').next())
+            .unwrap_or("unknown");
+        println!("cargo:rustc-env={}={}", env, version);
+    }
+    Ok(())
+}
@@ -0,0 +1,286 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use actix_web::{delete, get, web, HttpRequest, HttpResponse, Responder};
+use serde::Serialize;
+
+use crate::{chat::ShutdownPool, utils::ModelConfig, OpenAiError};
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct CachedFile {
+    pub name: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct CachedRepo {
+    /// `owner/name`
+    pub repo: String,
+    pub path: String,
+    /// Bytes of its blobs, unfinished downloads included
+    pub size: u64,
+    pub files: Vec<CachedFile>,
+    /// Models whose files or tokenizer come from the repository
+    pub models: Vec<String>,
+    pub loaded: bool,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct CacheUsage {
+    pub total_size: u64,
+    /// Bytes left on the disk of the default cache
+    pub free: Option<u64>,
+    pub repos: Vec<CachedRepo>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct CacheDeleted {
+    pub repo: String,
+    pub freed: u64,
+}
+
+/// 目錄底下所有檔案，路徑相對於 `dir`
+fn walk(dir: &Path, prefix: &str, files: &mut BTreeMap<String, u64>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let path = entry.path();
+        // snapshot 裡是指向 blob 的連結，metadata 會跟著連結走
+        match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => walk(&path, &format!("{}/", name), files),
+            Ok(metadata) => {
+                files.insert(name, metadata.len());
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// Model repositories in the hf-hub cache `dir`.
+fn scan(dir: &Path) -> Vec<CachedRepo> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut repos: Vec<CachedRepo> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let folder = entry.file_name().to_string_lossy().into_owned();
+            let repo = folder.strip_prefix("models--")?.replacen("--", "/", 1);
+            let path = entry.path();
+            let mut blobs = BTreeMap::new();
+            walk(&path.join("blobs"), "", &mut blobs);
+            let mut files = BTreeMap::new();
+            if let Ok(snapshots) = std::fs::read_dir(path.join("snapshots")) {
+                for snapshot in snapshots.flatten() {
+                    walk(&snapshot.path(), "", &mut files);
+                }
+            }
+            Some(CachedRepo {
+                repo,
+                path: path.to_string_lossy().into_owned(),
+                size: blobs.values().sum(),
+                files: files
+                    .into_iter()
+                    .map(|(name, size)| CachedFile { name, size })
+                    .collect(),
+                models: Vec::new(),
+                loaded: false,
+            })
+        })
+        .collect();
+    repos.sort_by(|a, b| a.repo.cmp(&b.repo));
+    repos
+}
+
+/// Repositories of every cache a model config points at, with the models using them.
+fn usage(all_configs: &HashMap<String, ModelConfig>, loaded: &[String]) -> CacheUsage {
+    let default_cache = crate::hub::cache(&ModelConfig::default()).path().clone();
+    let caches: BTreeSet<PathBuf> = all_configs
+        .values()
+        .map(|config| crate::hub::cache(config).path().clone())
+        .chain([default_cache.clone()])
+        .collect();
+    let mut repos = Vec::new();
+    for cache in &caches {
+        for mut repo in scan(cache) {
+            let mut models: Vec<String> = all_configs
+                .values()
+                .filter(|config| crate::hub::cache(config).path() == cache)
+                .filter(|config| {
+                    config.model_repo == repo.repo
+                        || config.tokenizer_repo.as_deref() == Some(repo.repo.as_str())
+                })
+                .map(|config| config.model_name.clone())
+                .collect();
+            models.sort();
+            repo.loaded = models.iter().any(|model| loaded.contains(model));
+            repo.models = models;
+            repos.push(repo);
+        }
+    }
+    CacheUsage {
+        total_size: repos.iter().map(|repo| repo.size).sum(),
+        free: crate::hub::available_space(&default_cache),
+        repos,
+    }
+}
+
+fn loaded_models(shutdown_pool: &ShutdownPool) -> Vec<String> {
+    let mut loaded: Vec<String> = shutdown_pool.lock().unwrap().keys().cloned().collect();
+    loaded.extend(crate::backends::loaded());
+    loaded
+}
+
+/// Downloaded model repositories with their files and sizes, and the total cache usage.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", body = CacheUsage, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/cache")]
+pub async fn list_cache(
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    shutdown_pool: web::Data<ShutdownPool>,
+) -> impl Responder {
+    let loaded = loaded_models(&shutdown_pool);
+    let all_configs = all_configs.into_inner();
+    match web::block(move || usage(&all_configs, &loaded)).await {
+        Ok(usage) => HttpResponse::Ok().json(usage),
+        Err(e) => HttpResponse::InternalServerError().json(OpenAiError {
+            message: e.to_string(),
+            code: "internal_error".to_owned(),
+            r#type: "internal_error".to_owned(),
+            param: None,
+        }),
+    }
+}
+
+/// Delete a downloaded repository from the cache. Repositories of loaded models are kept.
+#[utoipa::path(
+    params(
+        ("owner" = String, Path, description = "Owner of the repository"),
+        ("name" = String, Path, description = "Name of the repository"),
+    ),
+    responses(
+        (status = OK, description = "Success", body = CacheDeleted, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[delete("/cache/{owner}/{name}")]
+pub async fn delete_cache(
+    path: web::Path<(String, String)>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    shutdown_pool: web::Data<ShutdownPool>,
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))] req: HttpRequest,
+) -> impl Responder {
+    let (owner, name) = path.into_inner();
+    let repo_id = format!("{}/{}", owner, name);
+    let loaded = loaded_models(&shutdown_pool);
+    let all_configs = all_configs.into_inner();
+    let repo = {
+        let repo_id = repo_id.clone();
+        web::block(move || {
+            usage(&all_configs, &loaded)
+                .repos
+                .into_iter()
+                .filter(|repo| repo.repo == repo_id)
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default()
+    };
+    if repo.is_empty() {
+        return HttpResponse::NotFound().json(OpenAiError {
+            message: format!("Repository \"{}\" is not in the cache.", repo_id),
+            code: "not_found".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: None,
+        });
+    }
+    if let Some(repo) = repo.iter().find(|repo| repo.loaded) {
+        return HttpResponse::Conflict().json(OpenAiError {
+            message: format!(
+                "Repository \"{}\" is used by loaded models {}, unload them first.",
+                repo_id,
+                repo.models.join(", ")
+            ),
+            code: "model_loaded".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: None,
+        });
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Some(registry) = crate::registry::from_request(&req) {
+        if let Err(e) = registry.forget_repo(&repo_id) {
+            log::warn!("Failed to unregister {}: {}", repo_id, e);
+        }
+    }
+    let removed = web::block(move || {
+        let mut freed = 0;
+        for repo in repo {
+            std::fs::remove_dir_all(&repo.path)?;
+            log::info!("Removed {} ({} bytes)", repo.path, repo.size);
+            freed += repo.size;
+        }
+        Ok::<_, std::io::Error>(freed)
+    })
+    .await;
+    match removed {
+        Ok(Ok(freed)) => HttpResponse::Ok().json(CacheDeleted {
+            repo: repo_id,
+            freed,
+        }),
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(OpenAiError {
+            message: format!("Failed to remove \"{}\": {}", repo_id, e),
+            code: "internal_error".to_owned(),
+            r#type: "internal_error".to_owned(),
+            param: None,
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(OpenAiError {
+            message: e.to_string(),
+            code: "internal_error".to_owned(),
+            r#type: "internal_error".to_owned(),
+            param: None,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_cached_repos() {
+        let dir = std::env::temp_dir().join(format!("llmserver-hub-{}", rand::random::<u32>()));
+        let repo = dir.join("models--kautism--qwen");
+        std::fs::create_dir_all(repo.join("blobs")).unwrap();
+        std::fs::create_dir_all(repo.join("snapshots/abc/onnx")).unwrap();
+        std::fs::write(repo.join("blobs/0123"), b"weights").unwrap();
+        std::fs::write(repo.join("blobs/4567.part"), b"half").unwrap();
+        std::fs::write(repo.join("snapshots/abc/model.rkllm"), b"weights").unwrap();
+        std::fs::write(repo.join("snapshots/abc/onnx/vision.rknn"), b"v").unwrap();
+        std::fs::create_dir_all(dir.join("datasets--other--data")).unwrap();
+
+        let repos = scan(&dir);
+        assert_eq!(repos.len(), 1);
+        assert_eq!(repos[0].repo, "kautism/qwen");
+        assert_eq!(repos[0].size, 11);
+        let files: Vec<(&str, u64)> = repos[0]
+            .files
+            .iter()
+            .map(|file| (file.name.as_str(), file.size))
+            .collect();
+        assert_eq!(files, vec![("model.rkllm", 7), ("onnx/vision.rknn", 1)]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use crate::utils::ModelConfig;
+
+/// Names added at runtime by `POST /api/copy`, `alias -> model_name`.
+///
+/// 寫回 `assets/model_map.json`，重開後由 `apply_model_map` 變成一般的模型名稱
+#[derive(Debug, Clone, Default)]
+pub struct Aliases {
+    path: Option<PathBuf>,
+    names: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl Aliases {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: Some(path.into()),
+            ..Default::default()
+        }
+    }
+
+    /// The configured model a name stands for, the name itself when it is not an alias.
+    pub fn resolve(&self, name: &str) -> String {
+        self.names
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_owned())
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.names.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Aliases saved elsewhere, skipping the ones whose model is gone or that became model names.
+    pub fn restore(&self, names: HashMap<String, String>, configs: &HashMap<String, ModelConfig>) {
+        let mut current = self.names.write().unwrap();
+        for (alias, model_name) in names {
+            if configs.contains_key(&model_name) && !configs.contains_key(&alias) {
+                current.entry(alias).or_insert(model_name);
+            }
+        }
+    }
+
+    /// Register `destination` as another name of `source`.
+    pub fn copy(
+        &self,
+        source: &str,
+        destination: &str,
+        configs: &HashMap<String, ModelConfig>,
+    ) -> Result<(), String> {
+        let source = self.resolve(source);
+        if !configs.contains_key(&source) {
+            return Err(format!("model \"{}\" not found", source));
+        }
+        if configs.contains_key(destination) {
+            return Err(format!("\"{}\" is already a model name", destination));
+        }
+        self.names
+            .write()
+            .unwrap()
+            .insert(destination.to_owned(), source.clone());
+        if let Some(path) = &self.path {
+            // 已經生效了，寫檔失敗只影響重開之後
+            if let Err(e) = save(path, destination, &source) {
+                log::warn!("Failed to save alias to {}: {}", path.display(), e);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn save(path: &PathBuf, alias: &str, model_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut map: HashMap<String, String> = match std::fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents)?,
+        Err(_) => HashMap::new(),
+    };
+    map.insert(alias.to_owned(), model_name.to_owned());
+    std::fs::write(path, serde_json::to_string_pretty(&map)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_resolve_to_the_model() {
+        let config = ModelConfig {
+            model_name: "qwen".to_owned(),
+            ..Default::default()
+        };
+        let configs = HashMap::from([("qwen".to_owned(), config)]);
+        let aliases = Aliases::default();
+        aliases.copy("qwen", "assistant:latest", &configs).unwrap();
+        // 別名的別名還是指到原本的模型
+        aliases
+            .copy("assistant:latest", "helper", &configs)
+            .unwrap();
+        assert_eq!(aliases.resolve("helper"), "qwen");
+        assert_eq!(aliases.resolve("qwen"), "qwen");
+        assert!(aliases.copy("missing", "x", &configs).is_err());
+        assert!(aliases.copy("helper", "qwen", &configs).is_err());
+
+        let restored = Aliases::default();
+        restored.restore(
+            HashMap::from([
+                ("helper".to_owned(), "qwen".to_owned()),
+                ("old".to_owned(), "removed".to_owned()),
+            ]),
+            &configs,
+        );
+        assert_eq!(restored.names(), ["helper"]);
+    }
+}
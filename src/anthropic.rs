@@ -0,0 +1,370 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    chat::{ChatCompletionsRequest, Stop, REQUEST_ID_HEADER},
+    errors::relay_status,
+    ws::{chunk_text, sse_data, ChatContext},
+    Content, ContentPart, ImageUrl, Message, OpenAiError, Role,
+};
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(untagged)]
+pub enum AnthropicContent {
+    String(String),
+    Blocks(Vec<ContentBlock>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    Text {
+        text: String,
+    },
+    Image {
+        source: ImageSource,
+    },
+    /// 工具結果當成一般文字交給模型
+    ToolResult {
+        tool_use_id: String,
+        #[serde(default)]
+        #[schema(value_type = Object)]
+        content: Option<Value>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct ImageSource {
+    /// Only `base64` is supported
+    pub r#type: String,
+    pub media_type: String,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct AnthropicMessage {
+    /// `user` or `assistant`
+    pub role: String,
+    pub content: AnthropicContent,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[schema(example = json!({
+    "model": "Qwen2.5-3B-abliterated",
+    "max_tokens": 1024,
+    "system": "You are a helpful assistant.",
+    "messages": [{ "role": "user", "content": "Hello" }]
+}))]
+pub struct MessagesRequest {
+    pub model: String,
+    pub messages: Vec<AnthropicMessage>,
+    #[serde(default)]
+    pub system: Option<AnthropicContent>,
+    #[serde(default)]
+    pub max_tokens: Option<i32>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub stop_sequences: Option<Vec<String>>,
+}
+
+fn block_text(block: &ContentBlock) -> Option<String> {
+    match block {
+        ContentBlock::Text { text } => Some(text.clone()),
+        ContentBlock::Image { .. } => None,
+        ContentBlock::ToolResult { content, .. } => match content {
+            Some(Value::String(text)) => Some(text.clone()),
+            Some(Value::Array(blocks)) => Some(
+                blocks
+                    .iter()
+                    .filter_map(|b| b.get("text").and_then(|t| t.as_str()))
+                    .collect(),
+            ),
+            _ => None,
+        },
+    }
+}
+
+impl AnthropicContent {
+    fn text(&self) -> String {
+        match self {
+            AnthropicContent::String(text) => text.clone(),
+            AnthropicContent::Blocks(blocks) => blocks.iter().filter_map(block_text).collect(),
+        }
+    }
+
+    fn into_content(self) -> Content {
+        let AnthropicContent::Blocks(blocks) = &self else {
+            return Content::String(self.text());
+        };
+        if !blocks
+            .iter()
+            .any(|b| matches!(b, ContentBlock::Image { .. }))
+        {
+            return Content::String(self.text());
+        }
+        // 有圖片時轉成 OpenAI 的 image_url parts
+        Content::Parts(
+            blocks
+                .iter()
+                .map(|block| match block {
+                    ContentBlock::Image { source } => ContentPart {
+                        r#type: "image_url".to_owned(),
+                        text: None,
+                        image_url: Some(ImageUrl {
+                            url: Some(format!("data:{};base64,{}", source.media_type, source.data)),
+                            detail: None,
+                        }),
+                        input_audio: None,
+                    },
+                    block => ContentPart {
+                        r#type: "text".to_owned(),
+                        text: block_text(block),
+                        image_url: None,
+                        input_audio: None,
+                    },
+                })
+                .collect(),
+        )
+    }
+}
+
+impl MessagesRequest {
+    fn into_chat_request(self) -> Result<ChatCompletionsRequest, String> {
+        let mut chat_messages: Vec<Message> = self
+            .system
+            .map(|system| Message {
+                role: Some(Role::System),
+                content: Some(Content::String(system.text())),
+                ..Default::default()
+            })
+            .into_iter()
+            .collect();
+        for message in self.messages {
+            let role = match message.role.as_str() {
+                "user" => Role::User,
+                "assistant" => Role::Assistant,
+                role => return Err(format!("Unknown role {}", role)),
+            };
+            chat_messages.push(Message {
+                role: Some(role),
+                content: Some(message.content.into_content()),
+                ..Default::default()
+            });
+        }
+        Ok(crate::chat::streamed(ChatCompletionsRequest {
+            model: self.model,
+            messages: chat_messages,
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: self.max_tokens,
+            stop: self.stop_sequences.map(Stop::Array),
+            ..Default::default()
+        }))
+    }
+}
+
+fn error_body(r#type: &str, message: String) -> Value {
+    json!({ "type": "error", "error": { "type": r#type, "message": message } })
+}
+
+/// `stop_reason` for the `finish_reason` of a chat chunk.
+fn stop_reason(data: &str) -> Option<&'static str> {
+    let chunk: Value = serde_json::from_str(data).ok()?;
+    match chunk["choices"][0]["finish_reason"].as_str()? {
+        "length" => Some("max_tokens"),
+        "tool_calls" => Some("tool_use"),
+        _ => Some("end_turn"),
+    }
+}
+
+fn sse_event(event: &str, data: Value) -> web::Bytes {
+    web::Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+/// Anthropic Messages API compatible endpoint.
+#[utoipa::path(
+    request_body = MessagesRequest,
+    responses(
+        (status = OK, description = "Success", content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/messages")]
+pub async fn messages(body: web::Json<MessagesRequest>, ctx: ChatContext) -> impl Responder {
+    let body = body.into_inner();
+    let model = body.model.clone();
+    let stream = body.stream;
+    let chat_request = match body.into_chat_request() {
+        Ok(chat_request) => chat_request,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(error_body("invalid_request_error", e));
+        }
+    };
+
+    let response = ctx.chat(chat_request).await;
+    if !response.status().is_success() {
+        let status = response.status();
+        let mut builder = relay_status(&response);
+        let bytes = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap_or_default();
+        let message = serde_json::from_slice::<OpenAiError>(&bytes)
+            .map(|e| e.message)
+            .unwrap_or_else(|_| String::from_utf8_lossy(&bytes).to_string());
+        let r#type = if status.is_client_error() {
+            "invalid_request_error"
+        } else {
+            "api_error"
+        };
+        return builder.json(error_body(r#type, message));
+    }
+
+    // 跟 chat completion 同一個 id，log 對得起來
+    let id = response
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .map(|id| format!("msg_{}", id.trim_start_matches("chatcmpl-")))
+        .unwrap_or_else(|| format!("msg_{:016x}", rand::random::<u64>()));
+    let mut chunks = Box::pin(sse_data(response));
+
+    if !stream {
+        let mut text = String::new();
+        let mut input_tokens = 0;
+        let mut output_tokens = 0;
+        let mut reason = "end_turn";
+        while let Some(data) = chunks.next().await {
+            reason = stop_reason(&data).unwrap_or(reason);
+            if let Some(usage) = crate::usage::from_chunk(&data) {
+                (input_tokens, output_tokens) = (usage.prompt_tokens, usage.completion_tokens);
+                continue;
+            }
+            match chunk_text(&data) {
+                Ok(Some(delta)) => {
+                    text.push_str(&delta);
+                    output_tokens += 1;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    return HttpResponse::InternalServerError().json(error_body("api_error", e))
+                }
+            }
+        }
+        return HttpResponse::Ok().json(json!({
+            "id": id,
+            "type": "message",
+            "role": "assistant",
+            "model": model,
+            "content": [{ "type": "text", "text": text }],
+            "stop_reason": reason,
+            "stop_sequence": null,
+            "usage": { "input_tokens": input_tokens, "output_tokens": output_tokens },
+        }));
+    }
+
+    let events = async_stream::stream! {
+        yield Ok::<_, actix_web::Error>(sse_event("message_start", json!({
+            "type": "message_start",
+            "message": {
+                "id": id,
+                "type": "message",
+                "role": "assistant",
+                "model": model,
+                "content": [],
+                "stop_reason": null,
+                "stop_sequence": null,
+                "usage": { "input_tokens": 0, "output_tokens": 0 },
+            }
+        })));
+        yield Ok(sse_event("content_block_start", json!({
+            "type": "content_block_start",
+            "index": 0,
+            "content_block": { "type": "text", "text": "" },
+        })));
+        yield Ok(sse_event("ping", json!({ "type": "ping" })));
+
+        let mut input_tokens = 0;
+        let mut output_tokens = 0;
+        let mut reason = "end_turn";
+        while let Some(data) = chunks.next().await {
+            reason = stop_reason(&data).unwrap_or(reason);
+            if let Some(usage) = crate::usage::from_chunk(&data) {
+                (input_tokens, output_tokens) = (usage.prompt_tokens, usage.completion_tokens);
+                continue;
+            }
+            match chunk_text(&data) {
+                Ok(Some(delta)) => {
+                    output_tokens += 1;
+                    yield Ok(sse_event("content_block_delta", json!({
+                        "type": "content_block_delta",
+                        "index": 0,
+                        "delta": { "type": "text_delta", "text": delta },
+                    })));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    yield Ok(sse_event("error", error_body("api_error", e)));
+                    return;
+                }
+            }
+        }
+
+        yield Ok(sse_event("content_block_stop", json!({ "type": "content_block_stop", "index": 0 })));
+        yield Ok(sse_event("message_delta", json!({
+            "type": "message_delta",
+            "delta": { "stop_reason": reason, "stop_sequence": null },
+            "usage": { "input_tokens": input_tokens, "output_tokens": output_tokens },
+        })));
+        yield Ok(sse_event("message_stop", json!({ "type": "message_stop" })));
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .streaming(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anthropic_request_maps_to_chat() {
+        let body: MessagesRequest = serde_json::from_str(
+            r#"{
+                "model": "qwen",
+                "max_tokens": 256,
+                "system": [{"type": "text", "text": "Be brief."}],
+                "messages": [
+                    {"role": "user", "content": [
+                        {"type": "text", "text": "What is this?"},
+                        {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "AAAA"}}
+                    ]},
+                    {"role": "assistant", "content": "A cat."}
+                ],
+                "stop_sequences": ["\n\n"]
+            }"#,
+        )
+        .unwrap();
+        let chat = body.into_chat_request().unwrap();
+        assert!(chat.stream);
+        assert_eq!(chat.max_tokens, Some(256));
+        assert_eq!(chat.messages.len(), 3);
+        assert!(matches!(&chat.messages[0].content, Some(Content::String(s)) if s == "Be brief."));
+        let Some(Content::Parts(parts)) = &chat.messages[1].content else {
+            panic!("image content should become parts");
+        };
+        assert_eq!(
+            parts[1].image_url.as_ref().unwrap().url.as_deref(),
+            Some("data:image/png;base64,AAAA")
+        );
+    }
+}
@@ -3,11 +3,16 @@ use std::{pin::Pin, sync::Arc};
 use actix::Actor;
 use hf_hub::api::Progress;
 use hound::WavReader;
-use sensevoice_rs::{silero_vad::VadConfig, SenseVoiceSmall};
+use sensevoice_rs::{
+    silero_vad::{VadConfig, VadOutput, VadProcessor, CHUNK_SIZE},
+    SenseVoiceSmall, VoiceText,
+};
 use serde::Deserialize;
 use tokio_stream::wrappers::ReceiverStream;
 
-use crate::{AIModel, AsrText, ModelProgress, ProcessAudio, ShutdownMessages, ASR};
+use crate::{AIModel, AsrText, AudioSpan, ModelProgress, ProcessAudio, ShutdownMessages, ASR};
+
+type BoxError = Box<dyn std::error::Error>;
 
 #[derive(Debug, Clone, Default, Deserialize)]
 pub struct SimpleASRConfig {
@@ -23,6 +28,63 @@ impl Actor for SimpleASR {
     type Context = actix::Context<Self>;
 }
 
+/// Span of a VAD segment handed out after `fed` samples. Segments closed by silence had that
+/// silence cut from their tail, segments cut at the maximum length or at the end did not.
+fn span(segment: usize, fed: usize, config: &VadConfig, closed_by_silence: bool) -> AudioSpan {
+    let ms = |samples: usize| (samples as u64 * 1000 / config.sample_rate as u64) as u32;
+    let mut end = fed;
+    if closed_by_silence && ms(segment) < config.max_speech_duration_ms {
+        let chunk_ms = CHUNK_SIZE as f32 * 1000.0 / config.sample_rate as f32;
+        let silence_chunks = (config.silence_duration_ms as f32 / chunk_ms).ceil() as usize;
+        end = end.saturating_sub(silence_chunks * CHUNK_SIZE);
+    }
+    AudioSpan {
+        start_ms: ms(end.saturating_sub(segment)),
+        end_ms: ms(end),
+    }
+}
+
+/// Like `SenseVoiceSmall::infer_vec`, keeping where each segment is.
+fn transcribe(
+    handle: &SenseVoiceSmall,
+    mut samples: Vec<i16>,
+    sample_rate: u32,
+) -> Result<Vec<(VoiceText, AudioSpan)>, BoxError> {
+    let config = VadConfig {
+        sample_rate,
+        ..Default::default()
+    };
+    let mut vad = VadProcessor::new(config)?;
+    let mut segments = Vec::new();
+    let padding = (CHUNK_SIZE - samples.len() % CHUNK_SIZE) % CHUNK_SIZE;
+    samples.extend(std::iter::repeat_n(0, padding));
+    for (index, chunk) in samples.chunks_exact(CHUNK_SIZE).enumerate() {
+        let chunk: &[i16; CHUNK_SIZE] = chunk.try_into()?;
+        if let Some(VadOutput::Segment(segment)) = vad.process_chunk(chunk) {
+            let span = span(segment.len(), (index + 1) * CHUNK_SIZE, &config, true);
+            segments.push((handle.recognition(&segment)?, span));
+        }
+    }
+    if let Some(VadOutput::Segment(segment)) = vad.finish() {
+        let span = span(segment.len(), samples.len() - padding, &config, false);
+        segments.push((handle.recognition(&segment)?, span));
+    }
+    Ok(segments)
+}
+
+fn read_wav<R: std::io::Read>(reader: R) -> Result<(Vec<i16>, u32), BoxError> {
+    let mut wav_reader = WavReader::new(reader)?;
+    let sample_rate = wav_reader.spec().sample_rate;
+    if sample_rate != 8000 && sample_rate != 16000 {
+        return Err("Unsupported sample rate. Expect 8 kHz or 16 kHz.".into());
+    }
+    let content = wav_reader
+        .samples()
+        .filter_map(|x| x.ok())
+        .collect::<Vec<i16>>();
+    Ok((content, sample_rate))
+}
+
 impl actix::Handler<ProcessAudio> for SimpleASR {
     type Result = Result<Pin<Box<dyn futures::Stream<Item = AsrText> + Send + 'static>>, ()>;
     fn handle(&mut self, msg: ProcessAudio, _ctx: &mut Self::Context) -> Self::Result {
@@ -30,26 +92,25 @@ impl actix::Handler<ProcessAudio> for SimpleASR {
 
         let handle_clone = self.handle.clone();
         actix_web::rt::spawn(async move {
-            let allseg = match msg {
-                ProcessAudio::FilePath(audio_path) => handle_clone
-                    .infer_file(audio_path)
-                    .expect("Infer file failed"),
-                ProcessAudio::Buffer(read) => {
-                    // TODO: sensevoice-rs not support reader now, so read all!
-                    let mut wav_reader =
-                        WavReader::new(read).expect("Should give me wave reader but not!");
-                    let content = wav_reader
-                        .samples()
-                        .filter_map(|x| x.ok())
-                        .collect::<Vec<i16>>();
-                    handle_clone
-                        .infer_vec(content, 16000)
-                        .expect("Infer vec failed")
+            let wav = match msg {
+                ProcessAudio::FilePath(audio_path) => std::fs::File::open(audio_path)
+                    .map_err(BoxError::from)
+                    .and_then(|file| read_wav(std::io::BufReader::new(file))),
+                ProcessAudio::Buffer(read) => read_wav(read),
+            };
+            let allseg = match wav
+                .and_then(|(content, sample_rate)| transcribe(&handle_clone, content, sample_rate))
+            {
+                Ok(allseg) => allseg,
+                Err(e) => {
+                    // TODO: Maybe someday should have good error handling
+                    log::error!("Transcription failed: {}", e);
+                    return;
                 }
             };
-            for seg in allseg {
+            for (seg, span) in allseg {
                 // TODO: Maybe someday should have good error handling
-                let _ = tx.send(AsrText::SenseVoice(seg)).await;
+                let _ = tx.send(AsrText::SenseVoice(seg, span)).await;
             }
         });
 
@@ -87,3 +148,29 @@ impl AIModel for SimpleASR {
         Ok(SimpleASR { handle })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans_of_vad_segments() {
+        let config = VadConfig::default();
+        // 1 秒的語音，後面 16 個 chunk (512 ms) 的靜音被剪掉
+        let fed = 100 * CHUNK_SIZE;
+        assert_eq!(
+            span(16000, fed, &config, true),
+            AudioSpan {
+                start_ms: 1688,
+                end_ms: 2688
+            }
+        );
+        assert_eq!(
+            span(16000, 16000, &config, false),
+            AudioSpan {
+                start_ms: 0,
+                end_ms: 1000
+            }
+        );
+    }
+}
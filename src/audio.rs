@@ -5,12 +5,50 @@ use std::{
 
 use actix::Recipient;
 use actix_multipart::form::{tempfile::TempFile, text::Text, MultipartForm};
-use actix_web::{post, HttpResponse, Responder};
+use actix_web::{post, HttpRequest, HttpResponse, Responder};
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use crate::{OpenAiError, ProcessAudio};
+use crate::{
+    errors::ApiError,
+    files::FileStore,
+    transcript::{self, Segment},
+    OpenAiError, ProcessAudio,
+};
+
+pub type AudioPool = Arc<Mutex<HashMap<String, Recipient<ProcessAudio>>>>;
+
+/// Text of a 16 kHz `wav` from the ASR `model`, or from the first loaded one.
+pub(crate) async fn transcribe(
+    audio_pool: &AudioPool,
+    model: Option<&str>,
+    wav: Vec<u8>,
+) -> Result<String, String> {
+    let asr = {
+        let pool = audio_pool.lock().unwrap();
+        match model {
+            Some(model) => pool.get(model).cloned(),
+            None => pool.values().next().cloned(),
+        }
+    }
+    .ok_or("No ASR model is loaded.")?;
+    let send_future = asr.send(ProcessAudio::Buffer(Box::new(std::io::Cursor::new(wav))));
+    let stream =
+        match actix_web::rt::time::timeout(std::time::Duration::from_secs(30), send_future).await {
+            Ok(Ok(Ok(stream))) => stream,
+            Ok(Ok(Err(_))) => return Err("Internal processing error".to_owned()),
+            Ok(Err(e)) => return Err(format!("Mailbox error: {}", e)),
+            Err(_) => return Err("Server Busy.".to_owned()),
+        };
+    Ok(stream
+        .map(|content| match content {
+            crate::AsrText::SenseVoice(voice_text, _) => voice_text.content,
+        })
+        .collect::<Vec<_>>()
+        .await
+        .join(""))
+}
 
 #[derive(Deserialize, Serialize, utoipa::ToSchema)]
 pub struct TranscriptionsResponse {
@@ -20,7 +58,11 @@ pub struct TranscriptionsResponse {
 #[derive(Debug, MultipartForm)]
 struct UploadForm {
     model: Text<String>,
-    file: TempFile,
+    file: Option<TempFile>,
+    /// 用 /v1/uploads 傳完的檔案
+    file_id: Option<Text<String>>,
+    /// `json` (default), `text`, `verbose_json`, `srt` or `vtt`
+    response_format: Option<Text<String>>,
 }
 
 #[utoipa::path(
@@ -34,20 +76,61 @@ struct UploadForm {
 #[post("/audio/transcriptions")]
 pub async fn audio_transcriptions(
     form: MultipartForm<UploadForm>,
-    asr_pool: actix_web::web::Data<Arc<Mutex<HashMap<String, Recipient<ProcessAudio>>>>>,
+    asr_pool: actix_web::web::Data<AudioPool>,
+    req: HttpRequest,
 ) -> impl Responder {
+    let started = std::time::Instant::now();
     log::info!("{:?}", form.file);
     log::info!("{:?}", form.model);
 
-    let Ok(asr_pool_locked) = asr_pool.try_lock() else {
+    let response_format = form
+        .response_format
+        .as_ref()
+        .map(|f| f.0.clone())
+        .unwrap_or_else(|| "json".to_owned());
+    if !["json", "text", "verbose_json", "srt", "vtt"].contains(&response_format.as_str()) {
         return HttpResponse::BadRequest().json(OpenAiError {
-            message: format!(
-                "There is another instance running, please wait other instance finished."
-            ),
-            code: "busy".to_owned(),
-            r#type: "busy".to_owned(),
-            param: None,
+            message: format!("Unsupported response_format {}.", response_format),
+            code: "invalid_value".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some("response_format".to_owned()),
         });
+    }
+
+    let (path, file_name) = match (&form.file, &form.file_id) {
+        (Some(file), _) => (
+            file.file.as_ref().to_string_lossy().to_string(),
+            file.file_name.clone(),
+        ),
+        (None, Some(file_id)) => {
+            let files = req.app_data::<actix_web::web::Data<FileStore>>();
+            match files.and_then(|files| Some((files.path(&file_id.0)?, files.get(&file_id.0)?))) {
+                Some((path, file)) => (path.to_string_lossy().to_string(), Some(file.filename)),
+                None => {
+                    return HttpResponse::NotFound().json(OpenAiError {
+                        message: format!("No such file: {}", file_id.0),
+                        code: "file_not_found".to_owned(),
+                        r#type: "invalid_request_error".to_owned(),
+                        param: Some("file_id".to_owned()),
+                    })
+                }
+            }
+        }
+        (None, None) => {
+            return HttpResponse::BadRequest().json(OpenAiError {
+                message: "Either file or file_id is required.".to_owned(),
+                code: "missing_file".to_owned(),
+                r#type: "invalid_request_error".to_owned(),
+                param: Some("file".to_owned()),
+            })
+        }
+    };
+
+    let Ok(asr_pool_locked) = asr_pool.try_lock() else {
+        return ApiError::busy(
+            "There is another instance running, please wait other instance finished.",
+        )
+        .response();
     };
 
     let Some(asr) = asr_pool_locked.get(&form.model.0) else {
@@ -62,36 +145,58 @@ pub async fn audio_transcriptions(
         });
     };
 
-    let path = form.file.file.as_ref().to_string_lossy().to_string();
+    let duration = hound::WavReader::open(&path)
+        .map(|wav| wav.duration() as f32 / wav.spec().sample_rate as f32)
+        .unwrap_or_default();
     let send_future = asr.send(ProcessAudio::FilePath(path));
 
     match actix_web::rt::time::timeout(std::time::Duration::from_secs(5), send_future).await {
         Ok(Ok(Ok(receiver))) => {
-            let sse_stream = receiver.map(move |content| match content {
-                crate::AsrText::SenseVoice(voice_text) => voice_text.content,
-            });
-
-            let transcription_parts: Vec<String> = sse_stream.collect().await;
-            let full_transcription = transcription_parts.join("");
-            HttpResponse::Ok().json(json!({ "text": full_transcription }))
+            let parts: Vec<_> = receiver
+                .map(|content| match content {
+                    crate::AsrText::SenseVoice(voice_text, span) => (voice_text, span),
+                })
+                .collect()
+                .await;
+            let language = parts
+                .iter()
+                .find_map(|(voice_text, _)| transcript::language(voice_text.language));
+            let segments: Vec<Segment> = parts
+                .into_iter()
+                .filter(|(voice_text, _)| !voice_text.content.is_empty())
+                .enumerate()
+                .map(|(id, (voice_text, span))| Segment::new(id, span, voice_text.content))
+                .collect();
+            let full_transcription: String = segments.iter().map(|s| s.text.as_str()).collect();
+            if let Some(exporter) = crate::export::from_request(&req) {
+                crate::export::record_transcription(
+                    &exporter,
+                    &form.model.0,
+                    file_name.as_deref(),
+                    &full_transcription,
+                    started,
+                );
+            }
+            match response_format.as_str() {
+                "text" => HttpResponse::Ok()
+                    .content_type("text/plain; charset=utf-8")
+                    .body(full_transcription),
+                "srt" => HttpResponse::Ok()
+                    .content_type("application/x-subrip; charset=utf-8")
+                    .body(transcript::srt(&segments)),
+                "vtt" => HttpResponse::Ok()
+                    .content_type("text/vtt; charset=utf-8")
+                    .body(transcript::vtt(&segments)),
+                "verbose_json" => {
+                    HttpResponse::Ok().json(transcript::verbose_json(language, duration, &segments))
+                }
+                _ => HttpResponse::Ok().json(json!({ "text": full_transcription })),
+            }
         }
-        Ok(Ok(Err(e))) => HttpResponse::InternalServerError().json(OpenAiError {
-            message: format!("Internal processing error: {:?}", e),
-            code: "processing_error".to_owned(),
-            r#type: "internal_error".to_owned(),
-            param: None,
-        }),
-        Err(_timeout) => HttpResponse::UnavailableForLegalReasons().json(OpenAiError {
-            message: format!("Server Busy."),
-            code: "server_".to_owned(),
-            r#type: "internal_error".to_owned(),
-            param: None,
-        }),
-        Ok(Err(e)) => HttpResponse::UnavailableForLegalReasons().json(OpenAiError {
-            message: format!("Internal server error:{}", e),
-            code: "server_".to_owned(),
-            r#type: "internal_error".to_owned(),
-            param: None,
-        }),
+        Ok(Ok(Err(e))) => {
+            ApiError::internal(format!("Internal processing error: {:?}", e)).response()
+        }
+        Err(_timeout) => ApiError::busy("Server Busy.").response(),
+        Ok(Err(e)) => ApiError::internal(format!("Internal server error:{}", e)).response(),
     }
 }
@@ -0,0 +1,160 @@
+use std::{
+    io::{Cursor, Write},
+    process::{Command, Stdio},
+};
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use base64::Engine;
+
+use crate::{
+    audio::{self, AudioPool},
+    errors::ApiError,
+    realtime::{resample, to_wav, ASR_SAMPLE_RATE},
+    Content, InputAudio, Message, OpenAiError,
+};
+
+fn invalid_audio(message: String) -> OpenAiError {
+    OpenAiError {
+        message,
+        code: "invalid_input_audio".to_owned(),
+        r#type: "invalid_request_error".to_owned(),
+        param: Some("messages".to_owned()),
+    }
+}
+
+/// 平均各聲道成 mono
+fn read_wav(bytes: &[u8]) -> Result<(Vec<i16>, u32), hound::Error> {
+    let mut reader = hound::WavReader::new(Cursor::new(bytes))?;
+    let spec = reader.spec();
+    let samples = reader.samples::<i16>().collect::<Result<Vec<_>, _>>()?;
+    let mono = samples
+        .chunks(spec.channels.max(1) as usize)
+        .map(|frame| (frame.iter().map(|s| *s as i32).sum::<i32>() / frame.len() as i32) as i16)
+        .collect();
+    Ok((mono, spec.sample_rate))
+}
+
+/// mp3 交給 ffmpeg 解成 16k mono
+fn decode_mp3(bytes: &[u8]) -> Result<Vec<i16>, String> {
+    let mut child = Command::new("ffmpeg")
+        .args([
+            "-loglevel",
+            "error",
+            "-i",
+            "pipe:0",
+            "-f",
+            "s16le",
+            "-ac",
+            "1",
+            "-ar",
+        ])
+        .arg(ASR_SAMPLE_RATE.to_string())
+        .arg("pipe:1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("mp3 input needs ffmpeg: {}", e))?;
+    let mut stdin = child.stdin.take().ok_or("ffmpeg has no stdin")?;
+    let input = bytes.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    let _ = writer.join();
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_owned());
+    }
+    Ok(output
+        .stdout
+        .chunks_exact(2)
+        .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+        .collect())
+}
+
+/// An `input_audio` part as the 16 kHz wav the ASR model reads.
+fn to_asr_wav(audio: &InputAudio) -> Result<Vec<u8>, String> {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(audio.data.trim())
+        .map_err(|e| format!("Invalid base64 audio data: {}", e))?;
+    let samples = match audio.format.as_str() {
+        "wav" => {
+            let (samples, sample_rate) =
+                read_wav(&bytes).map_err(|e| format!("Invalid wav audio: {}", e))?;
+            resample(&samples, sample_rate, ASR_SAMPLE_RATE)
+        }
+        "mp3" => decode_mp3(&bytes).map_err(|e| format!("Invalid mp3 audio: {}", e))?,
+        format => {
+            return Err(format!(
+                "Unsupported audio format {}, use wav or mp3.",
+                format
+            ))
+        }
+    };
+    to_wav(&samples).map_err(|e| e.to_string())
+}
+
+/// Replace the `input_audio` parts of `messages` with their transcription by the loaded ASR
+/// model, so any chat model can answer spoken questions.
+pub(crate) async fn transcribe_parts(
+    messages: &mut [Message],
+    req: &HttpRequest,
+) -> Result<(), HttpResponse> {
+    let parts = messages
+        .iter_mut()
+        .flat_map(|message| match &mut message.content {
+            Some(Content::Parts(parts)) => parts.iter_mut().collect(),
+            _ => vec![],
+        })
+        .filter(|part| part.input_audio.is_some() || part.r#type == "input_audio");
+    for part in parts {
+        let Some(input) = part.input_audio.take() else {
+            return Err(HttpResponse::BadRequest().json(invalid_audio(
+                "input_audio parts need input_audio.".to_owned(),
+            )));
+        };
+        let wav =
+            to_asr_wav(&input).map_err(|e| HttpResponse::BadRequest().json(invalid_audio(e)))?;
+        let Some(audio_pool) = req.app_data::<web::Data<AudioPool>>() else {
+            return Err(ApiError::internal("Audio input is not configured.").response());
+        };
+        let text = audio::transcribe(audio_pool, None, wav)
+            .await
+            .map_err(|e| ApiError::internal(format!("Transcription failed: {}", e)).response())?;
+        part.r#type = "text".to_owned();
+        part.text = Some(text);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wav_becomes_16k_mono() {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut wav = Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut wav, spec).unwrap();
+        for sample in [100, 300, 100, 300] {
+            writer.write_sample(sample as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        let audio = InputAudio {
+            data: base64::engine::general_purpose::STANDARD.encode(wav.into_inner()),
+            format: "wav".to_owned(),
+        };
+        let (samples, sample_rate) = read_wav(&to_asr_wav(&audio).unwrap()).unwrap();
+        assert_eq!(sample_rate, ASR_SAMPLE_RATE);
+        assert_eq!(samples, vec![200; 4]);
+
+        let audio = InputAudio {
+            format: "flac".to_owned(),
+            ..audio
+        };
+        assert!(to_asr_wav(&audio).unwrap_err().contains("flac"));
+    }
+}
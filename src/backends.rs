@@ -0,0 +1,107 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use actix::{Actor, Context, Handler, Message, Recipient};
+
+use crate::{utils::ModelConfig, AIModel, ShutdownMessages};
+
+/// Shutdown handles of the loaded embedding, rerank, image and TTS models.
+///
+/// 跟 LLM 的 ShutdownPool 分開，載入 LLM 時的卸載不會動到它們，關機時還是要通知
+static LOADED: LazyLock<Mutex<HashMap<String, Recipient<ShutdownMessages>>>> =
+    LazyLock::new(Default::default);
+/// 每個模型一把鎖，同時進來的第一批請求只載入一次
+static LOADING: LazyLock<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(Default::default);
+
+pub fn register(model: &str, shutdown: Recipient<ShutdownMessages>) {
+    LOADED.lock().unwrap().insert(model.to_owned(), shutdown);
+}
+
+/// Names of the loaded backend models.
+pub fn loaded() -> Vec<String> {
+    LOADED
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, shutdown)| shutdown.connected())
+        .map(|(model, _)| model.clone())
+        .collect()
+}
+
+/// Stop `model` if it is loaded, the next request loads it again.
+pub async fn unload(model: &str) -> bool {
+    let Some(shutdown) = LOADED.lock().unwrap().remove(model) else {
+        return false;
+    };
+    log::info!("Unloading {}", model);
+    if let Err(e) = shutdown.send(ShutdownMessages).await {
+        log::warn!("Actor is already dead, skipping shutdown signal: {}", e);
+    }
+    true
+}
+
+/// Stop every loaded backend model, on exit.
+pub async fn unload_all() {
+    let shutdowns: Vec<_> = LOADED.lock().unwrap().drain().collect();
+    futures::future::join_all(shutdowns.into_iter().map(|(_, shutdown)| async move {
+        if let Err(e) = shutdown.send(ShutdownMessages).await {
+            log::warn!("Actor is already dead, skipping shutdown signal: {}", e);
+        }
+    }))
+    .await;
+}
+
+/// Recipient of `config` in `pool`, loading the model first if it isn't running.
+pub(crate) async fn get_or_load<A, M>(
+    config: &ModelConfig,
+    pool: &Mutex<HashMap<String, Recipient<M>>>,
+) -> Result<Recipient<M>, String>
+where
+    A: AIModel<Config = ModelConfig>
+        + Actor<Context = Context<A>>
+        + Handler<M>
+        + Handler<ShutdownMessages>
+        + Send,
+    M: Message + Send + 'static,
+    M::Result: Send,
+{
+    let running = |pool: &Mutex<HashMap<String, Recipient<M>>>| {
+        pool.lock()
+            .unwrap()
+            .get(&config.model_name)
+            .filter(|recipient| recipient.connected())
+            .cloned()
+    };
+    if let Some(recipient) = running(pool) {
+        return Ok(recipient);
+    }
+
+    let guard = LOADING
+        .lock()
+        .unwrap()
+        .entry(config.model_name.clone())
+        .or_default()
+        .clone();
+    let _loading = guard.lock().await;
+    // 等鎖的時候別人可能已經載好了
+    if let Some(recipient) = running(pool) {
+        return Ok(recipient);
+    }
+
+    log::info!("Loading {}", config.model_name);
+    let load_config = config.clone();
+    let model = tokio::task::spawn_blocking(move || A::init(&load_config))
+        .await
+        .map_err(|e| format!("Join err: {}", e))?
+        .map_err(|e| format!("Init err: {}", e))?;
+    let addr = model.start();
+    let recipient = addr.clone().recipient::<M>();
+    pool.lock()
+        .unwrap()
+        .insert(config.model_name.clone(), recipient.clone());
+    register(&config.model_name, addr.recipient::<ShutdownMessages>());
+    Ok(recipient)
+}
@@ -0,0 +1,543 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::SystemTime};
+
+use actix_web::{get, post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    chat::{collect, ChatCompletionsRequest},
+    files::FileStore,
+    ws::ChatContext,
+    OpenAiError,
+};
+
+pub const BATCHES_DIR: &str = "assets/batches";
+const CHAT_ENDPOINT: &str = "/v1/chat/completions";
+const COMPLETION_WINDOW: &str = "24h";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Validating,
+    Failed,
+    InProgress,
+    Finalizing,
+    Completed,
+    Cancelling,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct RequestCounts {
+    pub total: usize,
+    pub completed: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct Batch {
+    pub id: String,
+    pub object: String,
+    pub endpoint: String,
+    /// `{"object": "list", "data": [{"code", "message", "line"}]}` when the input is invalid
+    #[schema(value_type = Object)]
+    pub errors: Option<Value>,
+    pub input_file_id: String,
+    pub completion_window: String,
+    pub status: BatchStatus,
+    pub output_file_id: Option<String>,
+    pub error_file_id: Option<String>,
+    pub created_at: u64,
+    pub in_progress_at: Option<u64>,
+    pub expires_at: u64,
+    pub finalizing_at: Option<u64>,
+    pub completed_at: Option<u64>,
+    pub failed_at: Option<u64>,
+    pub cancelling_at: Option<u64>,
+    pub cancelled_at: Option<u64>,
+    pub request_counts: RequestCounts,
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Batches, each saved as `<id>.json`. They run one at a time, the requests of a batch one after
+/// another through the normal model queue.
+#[derive(Debug, Clone)]
+pub struct BatchStore {
+    dir: PathBuf,
+    running: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl BatchStore {
+    /// 重開機前沒跑完的 batch 沒辦法接著跑，標成失敗
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let store = Self {
+            dir: dir.into(),
+            running: Default::default(),
+        };
+        for mut batch in store.list() {
+            if !matches!(
+                batch.status,
+                BatchStatus::Completed | BatchStatus::Failed | BatchStatus::Cancelled
+            ) {
+                batch.status = BatchStatus::Failed;
+                batch.failed_at = Some(now());
+                batch.errors = Some(error_list(
+                    "server_restarted",
+                    "The server restarted before the batch finished.",
+                    None,
+                ));
+                let _ = store.save(&batch);
+            }
+        }
+        store
+    }
+
+    fn valid_id(id: &str) -> bool {
+        id.strip_prefix("batch_")
+            .is_some_and(|hex| !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()))
+    }
+
+    fn save(&self, batch: &Batch) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(
+            self.dir.join(format!("{}.json", batch.id)),
+            serde_json::to_vec(batch)?,
+        )
+    }
+
+    pub fn get(&self, id: &str) -> Option<Batch> {
+        if !Self::valid_id(id) {
+            return None;
+        }
+        let json = std::fs::read(self.dir.join(format!("{}.json", id))).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    /// 新的排前面
+    pub fn list(&self) -> Vec<Batch> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return vec![];
+        };
+        let mut batches: Vec<Batch> = entries
+            .filter_map(|entry| {
+                serde_json::from_slice(&std::fs::read(entry.ok()?.path()).ok()?).ok()
+            })
+            .collect();
+        batches.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(b.id.cmp(&a.id)));
+        batches
+    }
+
+    /// Change a saved batch, `None` when it does not exist.
+    fn update(&self, id: &str, change: impl FnOnce(&mut Batch)) -> Option<Batch> {
+        let mut batch = self.get(id)?;
+        change(&mut batch);
+        self.save(&batch).ok()?;
+        Some(batch)
+    }
+}
+
+fn error_list(code: &str, message: &str, line: Option<usize>) -> Value {
+    json!({ "object": "list", "data": [{ "code": code, "message": message, "line": line }] })
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchLine {
+    custom_id: String,
+    #[serde(default)]
+    method: Option<String>,
+    url: String,
+    body: Value,
+}
+
+/// The requests of an input file, or the problem with its first bad line.
+fn parse_input(content: &str) -> Result<Vec<(String, ChatCompletionsRequest)>, Value> {
+    let mut requests = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = |message: String| error_list("invalid_request", &message, Some(index + 1));
+        let line: BatchLine = serde_json::from_str(line).map_err(|e| invalid(e.to_string()))?;
+        if line.method.as_deref().is_some_and(|m| m != "POST") || line.url != CHAT_ENDPOINT {
+            return Err(invalid(format!(
+                "Only POST {} is supported.",
+                CHAT_ENDPOINT
+            )));
+        }
+        if requests.iter().any(|(id, _)| *id == line.custom_id) {
+            return Err(invalid(format!("Duplicate custom_id {}.", line.custom_id)));
+        }
+        let body = serde_json::from_value(line.body).map_err(|e| invalid(e.to_string()))?;
+        requests.push((line.custom_id, body));
+    }
+    if requests.is_empty() {
+        return Err(error_list(
+            "empty_file",
+            "The input file has no requests.",
+            None,
+        ));
+    }
+    Ok(requests)
+}
+
+/// One request of a batch through the same steps as `/v1/chat/completions` without streaming.
+async fn answer(ctx: &ChatContext, body: ChatCompletionsRequest) -> (u16, Value) {
+    let n = match crate::choices::count(&body) {
+        Ok(n) => n,
+        Err(error) => return (400, json!({ "error": error })),
+    };
+    let json_mode = crate::structured::json_mode(&body);
+    let retry = crate::structured::schema(&body).map(|_| (ctx.clone(), body.clone()));
+    let body = crate::chat::streamed(body);
+    let response = if n > 1 {
+        crate::choices::generate(ctx.clone(), body, n).await
+    } else if crate::tools::wants_tool_calls(&body) {
+        crate::tools::relay(ctx.chat(body).await)
+    } else {
+        ctx.chat(body).await
+    };
+    let response = if json_mode {
+        crate::structured::enforce(collect(response).await, retry).await
+    } else {
+        collect(response).await
+    };
+    let status = response.status().as_u16();
+    let bytes = actix_web::body::to_bytes(response.into_body())
+        .await
+        .unwrap_or_default();
+    let body = serde_json::from_slice(&bytes).unwrap_or_default();
+    // 錯誤都包成 {"error": ...}
+    match (status, body) {
+        (200, body) => (status, body),
+        (_, Value::Object(error)) if !error.contains_key("error") => {
+            (status, json!({ "error": error }))
+        }
+        (_, body) => (status, body),
+    }
+}
+
+async fn run(store: BatchStore, files: FileStore, ctx: ChatContext, id: String) {
+    let _running = store.running.clone().lock_owned().await;
+    let Some(batch) = store.get(&id) else {
+        return;
+    };
+    if batch.status != BatchStatus::Validating {
+        return;
+    }
+    let content = files.content(&batch.input_file_id).unwrap_or_default();
+    let requests = match parse_input(&String::from_utf8_lossy(&content)) {
+        Ok(requests) => requests,
+        Err(errors) => {
+            store.update(&id, |batch| {
+                batch.status = BatchStatus::Failed;
+                batch.failed_at = Some(now());
+                batch.errors = Some(errors);
+            });
+            return;
+        }
+    };
+    store.update(&id, |batch| {
+        batch.status = BatchStatus::InProgress;
+        batch.in_progress_at = Some(now());
+        batch.request_counts.total = requests.len();
+    });
+
+    let mut output = String::new();
+    let mut errors = String::new();
+    let mut counts = RequestCounts {
+        total: requests.len(),
+        ..Default::default()
+    };
+    for (custom_id, body) in requests {
+        if store
+            .get(&id)
+            .is_none_or(|batch| batch.status == BatchStatus::Cancelling)
+        {
+            break;
+        }
+        let (status_code, body) = answer(&ctx, body).await;
+        let line = json!({
+            "id": format!("batch_req_{:016x}", rand::random::<u64>()),
+            "custom_id": custom_id,
+            "response": { "status_code": status_code, "request_id": body["id"], "body": body },
+            "error": null,
+        });
+        if status_code == 200 {
+            counts.completed += 1;
+            output.push_str(&line.to_string());
+            output.push('\n');
+        } else {
+            counts.failed += 1;
+            errors.push_str(&line.to_string());
+            errors.push('\n');
+        }
+        let progress = counts.clone();
+        store.update(&id, |batch| batch.request_counts = progress);
+    }
+
+    let Some(batch) = store.update(&id, |batch| {
+        if batch.status == BatchStatus::InProgress {
+            batch.status = BatchStatus::Finalizing;
+            batch.finalizing_at = Some(now());
+        }
+    }) else {
+        return;
+    };
+    let save = |content: &str, name: &str| {
+        (!content.is_empty())
+            .then(|| {
+                files
+                    .create_bytes(
+                        content.as_bytes(),
+                        format!("{}_{}.jsonl", id, name),
+                        "batch_output".to_owned(),
+                    )
+                    .map_err(|e| log::error!("Failed to save batch {} {}: {}", id, name, e))
+                    .ok()
+            })
+            .flatten()
+            .map(|file| file.id)
+    };
+    let output_file_id = save(&output, "output");
+    let error_file_id = save(&errors, "error");
+    let cancelled = batch.status == BatchStatus::Cancelling;
+    store.update(&id, |batch| {
+        batch.output_file_id = output_file_id;
+        batch.error_file_id = error_file_id;
+        if cancelled {
+            batch.status = BatchStatus::Cancelled;
+            batch.cancelled_at = Some(now());
+        } else {
+            batch.status = BatchStatus::Completed;
+            batch.completed_at = Some(now());
+        }
+    });
+    log::info!(
+        "Batch {} finished: {} completed, {} failed",
+        id,
+        counts.completed,
+        counts.failed
+    );
+}
+
+fn bad_request(message: String, param: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(OpenAiError {
+        message,
+        code: "invalid_value".to_owned(),
+        r#type: "invalid_request_error".to_owned(),
+        param: Some(param.to_owned()),
+    })
+}
+
+fn not_found(id: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(OpenAiError {
+        message: format!("No such batch: {}", id),
+        code: "batch_not_found".to_owned(),
+        r#type: "invalid_request_error".to_owned(),
+        param: None,
+    })
+}
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub struct CreateBatchRequest {
+    /// A `/v1/files` JSONL file, one `{"custom_id", "method", "url", "body"}` request per line
+    pub input_file_id: String,
+    /// Only `/v1/chat/completions`
+    pub endpoint: String,
+    /// Only `24h`
+    pub completion_window: String,
+    #[serde(default)]
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+/// Start a batch job.
+#[utoipa::path(
+    request_body = CreateBatchRequest,
+    responses(
+        (status = OK, description = "Success", body = Batch, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/batches")]
+pub async fn create(
+    body: web::Json<CreateBatchRequest>,
+    batches: web::Data<BatchStore>,
+    files: web::Data<FileStore>,
+    ctx: ChatContext,
+) -> impl Responder {
+    let body = body.into_inner();
+    if body.endpoint != CHAT_ENDPOINT {
+        return bad_request(format!("Only {} is supported.", CHAT_ENDPOINT), "endpoint");
+    }
+    if body.completion_window != COMPLETION_WINDOW {
+        return bad_request(
+            format!("completion_window must be {}.", COMPLETION_WINDOW),
+            "completion_window",
+        );
+    }
+    if files.get(&body.input_file_id).is_none() {
+        return HttpResponse::NotFound().json(OpenAiError {
+            message: format!("No such file: {}", body.input_file_id),
+            code: "file_not_found".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some("input_file_id".to_owned()),
+        });
+    }
+    let created_at = now();
+    let batch = Batch {
+        id: format!("batch_{:016x}", rand::random::<u64>()),
+        object: "batch".to_owned(),
+        endpoint: body.endpoint,
+        errors: None,
+        input_file_id: body.input_file_id,
+        completion_window: body.completion_window,
+        status: BatchStatus::Validating,
+        output_file_id: None,
+        error_file_id: None,
+        created_at,
+        in_progress_at: None,
+        expires_at: created_at + 24 * 60 * 60,
+        finalizing_at: None,
+        completed_at: None,
+        failed_at: None,
+        cancelling_at: None,
+        cancelled_at: None,
+        request_counts: RequestCounts::default(),
+        metadata: body.metadata,
+    };
+    if let Err(e) = batches.save(&batch) {
+        return crate::errors::ApiError::internal(e.to_string()).response();
+    }
+    // 背景執行，客戶端輪詢狀態
+    actix_web::rt::spawn(run(
+        batches.get_ref().clone(),
+        files.get_ref().clone(),
+        ctx,
+        batch.id.clone(),
+    ));
+    HttpResponse::Ok().json(batch)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    pub after: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// List batches, newest first.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/batches")]
+pub async fn list(query: web::Query<ListQuery>, batches: web::Data<BatchStore>) -> impl Responder {
+    let all = batches.list();
+    let start = match &query.after {
+        Some(after) => all
+            .iter()
+            .position(|b| b.id == *after)
+            .map_or(all.len(), |i| i + 1),
+        None => 0,
+    };
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let data: Vec<Batch> = all.into_iter().skip(start).take(limit + 1).collect();
+    let has_more = data.len() > limit;
+    let data: Vec<Batch> = data.into_iter().take(limit).collect();
+    HttpResponse::Ok().json(json!({
+        "object": "list",
+        "first_id": data.first().map(|b| b.id.clone()),
+        "last_id": data.last().map(|b| b.id.clone()),
+        "has_more": has_more,
+        "data": data,
+    }))
+}
+
+/// Get a batch, e.g. to poll its status.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", body = Batch, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/batches/{id}")]
+pub async fn retrieve(path: web::Path<String>, batches: web::Data<BatchStore>) -> impl Responder {
+    match batches.get(&path) {
+        Some(batch) => HttpResponse::Ok().json(batch),
+        None => not_found(&path),
+    }
+}
+
+/// Cancel a batch. The running request is finished first, results so far are kept.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", body = Batch, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/batches/{id}/cancel")]
+pub async fn cancel(path: web::Path<String>, batches: web::Data<BatchStore>) -> impl Responder {
+    let batch = batches.update(&path, |batch| {
+        if matches!(
+            batch.status,
+            BatchStatus::Validating | BatchStatus::InProgress
+        ) {
+            batch.status = BatchStatus::Cancelling;
+            batch.cancelling_at = Some(now());
+        }
+    });
+    match batch {
+        Some(batch) => HttpResponse::Ok().json(batch),
+        None => not_found(&path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_input_lines() {
+        let line = |id: &str, url: &str| {
+            json!({
+                "custom_id": id,
+                "method": "POST",
+                "url": url,
+                "body": { "model": "qwen", "messages": [{ "role": "user", "content": "Hi" }] }
+            })
+            .to_string()
+        };
+        let content = [
+            line("a", CHAT_ENDPOINT),
+            String::new(),
+            line("b", CHAT_ENDPOINT),
+        ]
+        .join("\n");
+        let requests = parse_input(&content).unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].0, "b");
+        assert_eq!(requests[1].1.model, "qwen");
+
+        let content = [line("a", CHAT_ENDPOINT), line("a", CHAT_ENDPOINT)].join("\n");
+        assert_eq!(parse_input(&content).unwrap_err()["data"][0]["line"], 2);
+        let content = line("a", "/v1/embeddings");
+        assert_eq!(parse_input(&content).unwrap_err()["data"][0]["line"], 1);
+        assert!(parse_input("\n").is_err());
+    }
+}
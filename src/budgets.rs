@@ -0,0 +1,180 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use actix_web::{delete, get, put, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ApiError;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Usage {
+    /// 沒設定就用 `--session-budget`
+    limit: Option<u64>,
+    used: u64,
+}
+
+/// Generated tokens per session id (`X-Session-Id` or `user`), limited by a per-session budget
+/// or the `--session-budget` default. Kept in memory, a restart resets the counts.
+#[derive(Debug, Clone, Default)]
+pub struct Budgets {
+    default_limit: Option<u64>,
+    sessions: Arc<Mutex<HashMap<String, Usage>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct SessionBudget {
+    pub session: String,
+    pub limit: Option<u64>,
+    pub used: u64,
+    pub remaining: Option<u64>,
+}
+
+impl Budgets {
+    pub fn new(default_limit: Option<u64>) -> Self {
+        Self {
+            default_limit,
+            ..Default::default()
+        }
+    }
+
+    pub fn get(&self, session: &str) -> SessionBudget {
+        let usage = self
+            .sessions
+            .lock()
+            .unwrap()
+            .get(session)
+            .copied()
+            .unwrap_or_default();
+        let limit = usage.limit.or(self.default_limit);
+        SessionBudget {
+            session: session.to_owned(),
+            limit,
+            used: usage.used,
+            remaining: limit.map(|limit| limit.saturating_sub(usage.used)),
+        }
+    }
+
+    pub fn set_limit(&self, session: &str, limit: Option<u64>, reset: bool) -> SessionBudget {
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            let usage = sessions.entry(session.to_owned()).or_default();
+            usage.limit = limit;
+            if reset {
+                usage.used = 0;
+            }
+        }
+        self.get(session)
+    }
+
+    pub fn remove(&self, session: &str) -> bool {
+        self.sessions.lock().unwrap().remove(session).is_some()
+    }
+
+    pub fn add(&self, session: &str, tokens: u64) {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.entry(session.to_owned()).or_default().used += tokens;
+    }
+
+    /// Refuse the request once the session used up its budget.
+    pub fn check(&self, session: &str) -> Result<(), ApiError> {
+        let budget = self.get(session);
+        match (budget.limit, budget.remaining) {
+            (Some(limit), Some(0)) => Err(ApiError::Quota(format!(
+                "Session \"{}\" used its budget of {} tokens.",
+                session, limit
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub struct SetBudgetRequest {
+    /// Tokens the session may generate, `null` falls back to the server default
+    pub limit: Option<u64>,
+    /// Also start counting from zero again
+    #[serde(default)]
+    pub reset: bool,
+}
+
+/// Budget and usage of a session.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", body = SessionBudget, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/sessions/{session}/budget")]
+pub async fn get_budget(session: web::Path<String>, budgets: web::Data<Budgets>) -> impl Responder {
+    HttpResponse::Ok().json(budgets.get(&session))
+}
+
+/// Set the token budget of a session.
+#[utoipa::path(
+    request_body = SetBudgetRequest,
+    responses(
+        (status = OK, description = "Success", body = SessionBudget, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[put("/sessions/{session}/budget")]
+pub async fn set_budget(
+    session: web::Path<String>,
+    body: web::Json<SetBudgetRequest>,
+    budgets: web::Data<Budgets>,
+) -> impl Responder {
+    HttpResponse::Ok().json(budgets.set_limit(&session, body.limit, body.reset))
+}
+
+/// Forget the budget and usage of a session.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[delete("/sessions/{session}/budget")]
+pub async fn delete_budget(
+    session: web::Path<String>,
+    budgets: web::Data<Budgets>,
+) -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({
+        "session": session.as_str(),
+        "deleted": budgets.remove(&session),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_after_budget() {
+        let budgets = Budgets::new(Some(10));
+        budgets.add("kiosk-1", 9);
+        assert!(budgets.check("kiosk-1").is_ok());
+        budgets.add("kiosk-1", 3);
+        let error = budgets.check("kiosk-1").unwrap_err();
+        assert_eq!(
+            error.status(),
+            actix_web::http::StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(budgets.get("kiosk-1").remaining, Some(0));
+
+        assert_eq!(
+            budgets.set_limit("kiosk-1", Some(20), false).remaining,
+            Some(8)
+        );
+        assert!(budgets.check("kiosk-1").is_ok());
+        assert_eq!(budgets.set_limit("kiosk-1", None, true).used, 0);
+        assert_eq!(Budgets::default().get("other").limit, None);
+    }
+}
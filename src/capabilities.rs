@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chat::{ChatCompletionsRequest, ToolChoice},
+    utils::{ModelConfig, ModelType},
+    Content, OpenAiError,
+};
+
+/// Request features a model may or may not handle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    Vision,
+    Tools,
+    JsonSchema,
+    Thinking,
+}
+
+impl Capability {
+    fn describe(self) -> &'static str {
+        match self {
+            Capability::Vision => "image input",
+            Capability::Tools => "tool calling",
+            Capability::JsonSchema => "structured output (response_format)",
+            Capability::Thinking => "thinking",
+        }
+    }
+}
+
+/// `capabilities` of the config, or what its model type can do when not set.
+pub fn capabilities(config: &ModelConfig) -> Vec<Capability> {
+    if let Some(capabilities) = &config.capabilities {
+        return capabilities.clone();
+    }
+    let mut capabilities = match config.model_type {
+        ModelType::LLM => vec![Capability::Tools, Capability::JsonSchema],
+        ModelType::VLM => vec![
+            Capability::Vision,
+            Capability::Tools,
+            Capability::JsonSchema,
+        ],
+        // echo 只是測試用，什麼都收
+        ModelType::Echo => vec![
+            Capability::Vision,
+            Capability::Tools,
+            Capability::JsonSchema,
+            Capability::Thinking,
+        ],
+        _ => vec![],
+    };
+    if config.think == Some(true) && !capabilities.contains(&Capability::Thinking) {
+        capabilities.push(Capability::Thinking);
+    }
+    capabilities
+}
+
+/// Features `request` uses, with the parameter that asks for them.
+fn required(request: &ChatCompletionsRequest) -> Vec<(Capability, &'static str)> {
+    let mut required = vec![];
+    let has_image = request.messages.iter().any(|m| match &m.content {
+        Some(Content::Parts(parts)) => parts.iter().any(|p| {
+            p.image_url.is_some() || matches!(p.r#type.as_str(), "image_url" | "input_image")
+        }),
+        _ => false,
+    });
+    if has_image {
+        required.push((Capability::Vision, "messages"));
+    }
+    let no_tools = matches!(&request.tool_choice, Some(ToolChoice::Mode(mode)) if mode == "none");
+    if request.tools.as_ref().is_some_and(|t| !t.is_empty()) && !no_tools {
+        required.push((Capability::Tools, "tools"));
+    }
+    if request
+        .response_format
+        .as_ref()
+        .is_some_and(|f| matches!(f.r#type.as_str(), "json_object" | "json_schema"))
+    {
+        required.push((Capability::JsonSchema, "response_format"));
+    }
+    required
+}
+
+/// The `vision_companion` of `config` when `request` has images `config` cannot read.
+pub fn vision_route<'a>(
+    request: &ChatCompletionsRequest,
+    config: &ModelConfig,
+    all_configs: &'a HashMap<String, ModelConfig>,
+) -> Option<&'a ModelConfig> {
+    let has_image = required(request)
+        .iter()
+        .any(|(capability, _)| *capability == Capability::Vision);
+    if !has_image || capabilities(config).contains(&Capability::Vision) {
+        return None;
+    }
+    all_configs
+        .get(config.vision_companion.as_deref()?)
+        .filter(|companion| capabilities(companion).contains(&Capability::Vision))
+}
+
+/// Vision models are never re-initialized, so sampling values other than their config's are
+/// rejected instead of ignored.
+pub fn sampling(request: &ChatCompletionsRequest, config: &ModelConfig) -> Result<(), OpenAiError> {
+    if config.model_type != ModelType::VLM {
+        return Ok(());
+    }
+    match crate::sampling::differing(request, config) {
+        Some(param) => Err(OpenAiError {
+            message: format!(
+                "Model \"{}\" does not support setting {} per request, it samples with the values of its config.",
+                request.model, param
+            ),
+            code: "unsupported_feature".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some(param.to_owned()),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Reject requests using a feature the model does not support instead of dropping it.
+pub fn check(request: &ChatCompletionsRequest, config: &ModelConfig) -> Result<(), OpenAiError> {
+    let supported = capabilities(config);
+    match required(request)
+        .into_iter()
+        .find(|(capability, _)| !supported.contains(capability))
+    {
+        Some((capability, param)) => Err(OpenAiError {
+            message: format!(
+                "Model \"{}\" does not support {}.",
+                request.model,
+                capability.describe()
+            ),
+            code: "unsupported_feature".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some(param.to_owned()),
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_images_for_text_models() {
+        let request: ChatCompletionsRequest = serde_json::from_value(serde_json::json!({
+            "model": "qwen",
+            "messages": [{ "role": "user", "content": [
+                { "type": "text", "text": "what is this?" },
+                { "type": "image_url", "image_url": { "url": "data:image/png;base64,AAAA" } }
+            ]}]
+        }))
+        .unwrap();
+        let mut config = ModelConfig {
+            model_name: "qwen".to_owned(),
+            ..Default::default()
+        };
+        let error = check(&request, &config).unwrap_err();
+        assert_eq!(
+            error.message,
+            "Model \"qwen\" does not support image input."
+        );
+        assert_eq!(error.param.as_deref(), Some("messages"));
+
+        config.model_type = ModelType::VLM;
+        assert!(check(&request, &config).is_ok());
+        config.capabilities = Some(vec![Capability::Tools]);
+        assert!(check(&request, &config).is_err());
+    }
+
+    #[test]
+    fn images_go_to_the_vision_companion() {
+        let request = |content: serde_json::Value| -> ChatCompletionsRequest {
+            serde_json::from_value(serde_json::json!({
+                "model": "qwen", "messages": [{ "role": "user", "content": content }]
+            }))
+            .unwrap()
+        };
+        let image = request(serde_json::json!([
+            { "type": "image_url", "image_url": { "url": "data:image/png;base64,AAAA" } }
+        ]));
+        let qwen = ModelConfig {
+            model_name: "qwen".to_owned(),
+            vision_companion: Some("qwen-vl".to_owned()),
+            ..Default::default()
+        };
+        let vl = ModelConfig {
+            model_name: "qwen-vl".to_owned(),
+            model_type: ModelType::VLM,
+            ..Default::default()
+        };
+        let all_configs = HashMap::from([("qwen-vl".to_owned(), vl.clone())]);
+        assert_eq!(
+            vision_route(&image, &qwen, &all_configs).map(|c| c.model_name.as_str()),
+            Some("qwen-vl")
+        );
+        assert!(vision_route(&request("hi".into()), &qwen, &all_configs).is_none());
+
+        // 視覺模型不會重新初始化，不同的取樣值直接拒絕
+        let mut hot = request("hi".into());
+        hot.temperature = Some(1.5);
+        assert_eq!(
+            sampling(&hot, &vl).unwrap_err().param.as_deref(),
+            Some("temperature")
+        );
+        assert!(sampling(&request("hi".into()), &vl).is_ok());
+    }
+}
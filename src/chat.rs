@@ -1,23 +1,54 @@
-use actix::{Actor, Recipient};
+use actix::Recipient;
 use actix_web::{
     post,
     web::{self, Json},
-    HttpResponse, Responder,
+    HttpRequest, HttpResponse, Responder,
 };
-use futures::{Stream, StreamExt};
+use futures::{future::Shared, FutureExt, Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
+    future::Future,
     pin::Pin,
     sync::{Arc, Mutex},
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 
 use crate::{
-    utils::{ModelConfig, OpenWebUIProgress},
-    AIModel, Content, Message, OpenAiError, ProcessMessages, Role, ShutdownMessages,
+    budgets::Budgets,
+    capabilities,
+    cluster::Cluster,
+    coalesce,
+    errors::{self, ApiError},
+    eviction::MemoryBudget,
+    fallback::{self, FailedModels, Route},
+    i18n::{self, MessageKey},
+    mcp::{self, McpClient},
+    prefill,
+    quotas::Quotas,
+    rag, sampling,
+    sessions::Sessions,
+    traffic,
+    utils::{ModelConfig, OpenWebUIProgress, ProgressMessage},
+    ws::ChatContext,
+    Content, Message, OpenAiError, ProcessMessages, Role, ShutdownMessages,
 };
 
+pub type LlmPool = Arc<Mutex<HashMap<String, Recipient<ProcessMessages>>>>;
+pub type ShutdownPool = Arc<Mutex<HashMap<String, Recipient<ShutdownMessages>>>>;
+
+/// A model load in progress, every request for the model waits for the same one.
+pub(crate) type SharedLoad =
+    Shared<Pin<Box<dyn Future<Output = Result<Recipient<ProcessMessages>, String>> + Send>>>;
+
+/// Models being loaded, so a retry after the `--load-timeout` 503 waits for the running load
+/// instead of initializing the model a second time.
+#[derive(Clone, Default)]
+pub struct Loads(Arc<Mutex<HashMap<String, SharedLoad>>>);
+
+/// The `chatcmpl-` id of the completion, to find a request in the server log
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
 #[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
 pub struct Delta {
     #[schema(value_type = Role)]
@@ -37,6 +68,20 @@ pub enum Stop {
 pub struct ResponseFormat {
     //#[schema(enum = ["json_object", "json_object"])]
     pub r#type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_schema: Option<JsonSchemaFormat>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[schema(value_type = Object)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
@@ -89,21 +134,49 @@ pub struct ChatCompletionsRequest {
     pub messages: Vec<Message>,
     pub temperature: Option<f32>,
     pub top_p: Option<f32>,
+    #[serde(default)]
+    pub top_k: Option<i32>,
+    /// Name of a sampling preset filling the values left out, e.g. `precise`
+    #[serde(default)]
+    pub preset: Option<String>,
     pub n: Option<i32>,
     #[serde(default)]
     pub stream: bool,
     pub stop: Option<Stop>,
     pub max_tokens: Option<i32>,
+    /// Newer name of `max_tokens`, wins when both are set
+    #[serde(default)]
+    pub max_completion_tokens: Option<i32>,
     pub presence_penalty: Option<f32>,
     pub frequency_penalty: Option<f32>,
+    /// rkllm repeat penalty, 1 turns it off
+    #[serde(default)]
+    pub repeat_penalty: Option<f32>,
     #[serde(default, deserialize_with = "deserialize_logit_bias")]
     pub logit_bias: Option<HashMap<String, f32>>,
     pub user: Option<String>,
     pub response_format: Option<ResponseFormat>,
     pub seed: Option<i32>,
+    /// Return the log probability of each generated token
+    #[serde(default)]
+    pub logprobs: Option<bool>,
+    /// Most likely alternatives listed per token, up to 20
+    #[serde(default)]
+    pub top_logprobs: Option<usize>,
     pub tools: Option<Vec<Tool>>,
     pub tool_choice: Option<ToolChoice>,
     pub metadata: Option<HashMap<String, String>>,
+    /// Id of a local knowledge base, the top-k chunks are added to the system prompt
+    #[serde(default)]
+    pub knowledge_base: Option<String>,
+    /// Merge tokens into fewer SSE events, overrides `--coalesce-ms`/`--coalesce-tokens`
+    #[serde(default)]
+    pub coalesce: Option<crate::coalesce::Coalesce>,
+    /// Seconds or a duration like `"10m"` the model stays loaded after this answer, `0` unloads
+    /// it right away and `-1` keeps it loaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub keep_alive: Option<crate::keep_alive::KeepAlive>,
 }
 
 fn deserialize_logit_bias<'de, D>(deserializer: D) -> Result<Option<HashMap<String, f32>>, D::Error>
@@ -121,12 +194,16 @@ where
     }
 }
 
-#[derive(Deserialize, Serialize, utoipa::ToSchema)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, utoipa::ToSchema)]
 pub enum FinishReason {
     #[serde(rename = "stop")]
     Stop,
+    #[serde(rename = "length")]
     Length,
+    #[serde(rename = "function_call")]
     FunctionCall,
+    #[serde(rename = "tool_calls")]
+    ToolCalls,
     InvalidRequestError,
     ModelError,
     InternalError,
@@ -139,13 +216,14 @@ pub struct Choice {
     pub delta: Option<Message>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub message: Option<Message>,
-    #[schema(value_type = String)]
-    pub logprobs: Option<String>,
+    /// `{"content": [...]}` with an entry per token
+    #[schema(value_type = Object)]
+    pub logprobs: Option<serde_json::Value>,
     #[schema(value_type = String)]
     pub finish_reason: Option<FinishReason>,
 }
 
-#[derive(Deserialize, Serialize, utoipa::ToSchema)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, utoipa::ToSchema)]
 pub struct Usage {
     pub completion_tokens: i32,
     pub prompt_tokens: i32,
@@ -161,6 +239,14 @@ pub struct ChatCompletionsResponse {
     pub choices: Vec<Choice>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub usage: Option<Usage>,
+    /// The request `metadata`, echoed back
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_fingerprint: Option<String>,
+    /// Load, prompt and generation time of a local run, on the usage chunk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timings: Option<crate::usage::Timings>,
 }
 
 #[utoipa::path(
@@ -175,20 +261,203 @@ pub struct ChatCompletionsResponse {
 #[post("/chat/completions")]
 pub async fn chat_completions(
     body: Json<ChatCompletionsRequest>,
-    llm_pool: web::Data<Arc<Mutex<HashMap<String, Recipient<ProcessMessages>>>>>,
-    shutdown_pool: web::Data<Arc<Mutex<HashMap<String, Recipient<ShutdownMessages>>>>>,
-    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    ctx: ChatContext,
+    mcp: web::Data<McpClient>,
 ) -> impl Responder {
-    log::debug!("Received chat request: {:?}", serde_json::to_string(&body.0).unwrap_or_default());
+    let body = match rag::augment(body.into_inner(), &ctx).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    #[cfg(feature = "sqlite")]
+    let store = crate::store::from_request(&ctx.req)
+        .map(|store| {
+            let caller = (crate::store::api_key_id(&ctx.req), crate::proxy::client_ip(&ctx.req));
+            (store, body.clone(), caller)
+        });
+    let exporter = crate::export::from_request(&ctx.req).map(|exporter| (exporter, body.clone()));
+    let stream = body.stream;
+    let json_mode = crate::structured::json_mode(&body);
+    // json_schema 不符合時重問模型
+    let retry = crate::structured::schema(&body).map(|_| (ctx.clone(), body.clone()));
+    let n = match crate::choices::count(&body) {
+        Ok(n) => n,
+        Err(error) => return HttpResponse::BadRequest().json(error),
+    };
+    let response = if n > 1 {
+        // 一個接一個生成，每個答案是一個 choice
+        crate::choices::generate(ctx, body, n).await
+    } else if crate::tools::wants_tool_calls(&body) {
+        // 客戶端自己帶了 tools 就交給客戶端執行
+        crate::tools::relay(ctx.chat(body).await)
+    } else if !mcp.is_empty() {
+        // 有設定 MCP server 才走工具迴圈
+        mcp::agent_chat(body, ctx, mcp).await
+    } else {
+        ctx.chat(body).await
+    };
+    let response = match exporter {
+        Some((exporter, body)) => crate::export::record_chat(response, exporter, body),
+        None => response,
+    };
+    #[cfg(feature = "sqlite")]
+    let response = match store {
+        Some((store, body, (api_key, client_ip))) => {
+            crate::store::record(response, store, body, api_key, client_ip)
+        }
+        None => response,
+    };
+    if stream {
+        response
+    } else if json_mode {
+        crate::structured::enforce(collect(response).await, retry).await
+    } else {
+        collect(response).await
+    }
+}
+
+type Answer = (
+    String,
+    Vec<crate::MessageToolCall>,
+    Option<FinishReason>,
+    Option<Vec<serde_json::Value>>,
+    Option<String>,
+);
+
+/// Non-stream answer: the SSE chunks of `response` folded into one `chat.completion`.
+pub(crate) async fn collect(response: HttpResponse) -> HttpResponse {
+    if !response.status().is_success() {
+        return response;
+    }
+    let mut builder = HttpResponse::build(response.status());
+    for (name, value) in response.headers() {
+        if name != actix_web::http::header::CONTENT_TYPE {
+            builder.insert_header((name.clone(), value.clone()));
+        }
+    }
+    let mut chunks = Box::pin(crate::ws::sse_data(response));
+    let mut completion: Option<ChatCompletionsResponse> = None;
+    // index -> (內容, tool calls, finish_reason, logprobs, 思考)，n > 1 時有多個
+    let mut answers: BTreeMap<usize, Answer> = BTreeMap::new();
+    let mut usage = None;
+    while let Some(data) = chunks.next().await {
+        if let Err(e) = crate::ws::chunk_text(&data) {
+            return ApiError::internal(e).response();
+        }
+        // 進度事件不是 chunk，略過
+        let Ok(mut chunk) = serde_json::from_str::<ChatCompletionsResponse>(&data) else {
+            continue;
+        };
+        usage = chunk.usage.take().or(usage);
+        for choice in chunk.choices.drain(..) {
+            let answer = answers.entry(choice.index).or_default();
+            if let Some(delta) = choice.delta.filter(|d| !matches!(d.role, Some(Role::System))) {
+                if let Some(content) = &delta.content {
+                    answer.0.push_str(&content.text());
+                }
+                if let Some(thought) = &delta.reasoning_content {
+                    answer.4.get_or_insert_with(String::new).push_str(thought);
+                }
+                answer.1.extend(delta.tool_calls.into_iter().flatten().map(|call| {
+                    crate::MessageToolCall {
+                        index: None,
+                        ..call
+                    }
+                }));
+            }
+            answer.2 = choice.finish_reason.or(answer.2);
+            if let Some(serde_json::Value::Array(entries)) =
+                choice.logprobs.map(|mut logprobs| logprobs["content"].take())
+            {
+                answer.3.get_or_insert_with(Vec::new).extend(entries);
+            }
+        }
+        completion.get_or_insert(chunk);
+    }
+    let Some(mut completion) = completion else {
+        return ApiError::internal("The model returned no answer.").response();
+    };
+    completion.object = "chat.completion".to_owned();
+    completion.usage = usage;
+    completion.choices = answers
+        .into_iter()
+        .map(|(index, (content, tool_calls, finish_reason, logprobs, reasoning))| Choice {
+            index,
+            delta: None,
+            message: Some(Message {
+                role: Some(Role::Assistant),
+                content: (tool_calls.is_empty() || !content.is_empty())
+                    .then_some(Content::String(content)),
+                reasoning_content: reasoning,
+                tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+                ..Default::default()
+            }),
+            logprobs: logprobs.map(|entries| serde_json::json!({ "content": entries })),
+            finish_reason: finish_reason.or(Some(FinishReason::Stop)),
+        })
+        .collect();
+    builder.json(completion)
+}
+
+/// 共用的對話流程，HTTP 跟 WebSocket 都走這裡
+pub(crate) async fn chat_response(
+    body: ChatCompletionsRequest,
+    llm_pool: web::Data<LlmPool>,
+    shutdown_pool: web::Data<ShutdownPool>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    failed_models: web::Data<FailedModels>,
+    cluster: web::Data<Cluster>,
+    req: &HttpRequest,
+) -> HttpResponse {
+    log::debug!("Received chat request: {:?}", serde_json::to_string(&body).unwrap_or_default());
     
-    let id = "chatcmpl-123".to_owned();
+    // 存起來的對話要靠 id 找回來
+    let id = format!("chatcmpl-{:016x}", rand::random::<u64>());
+    log::info!("Chat completion {} for model {}", id, body.model);
     let created = SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap_or_default()
         .as_secs();
 
+    // /api/copy 加的別名換成原本的模型
+    let mut body = body;
+    if let Some(aliases) = req.app_data::<web::Data<crate::aliases::Aliases>>() {
+        body.model = aliases.resolve(&body.model);
+    }
+
+    // 沒有設定檔的 HF repo id，叢集裡也沒有的話就直接用 repo 裡的 .rkllm
+    let mut all_configs = all_configs;
+    if !all_configs.contains_key(&body.model) && crate::discover::looks_like_repo(&body.model) {
+        if let Some(peer) = cluster.peer_for(&body.model, req).await {
+            return cluster.forward(&peer, "/v1/chat/completions", &body).await;
+        }
+        if let Some(discovered) = req
+            .app_data::<web::Data<crate::discover::Discovered>>()
+            .filter(|discovered| discovered.allows(&body.model))
+        {
+            match discovered.config(&body.model).await {
+                Ok(config) => {
+                    let mut configs = all_configs.as_ref().clone();
+                    configs.insert(config.model_name.clone(), config);
+                    all_configs = web::Data::new(configs);
+                }
+                Err(message) => {
+                    return HttpResponse::BadRequest().json(OpenAiError {
+                        message,
+                        code: "model_not_found".to_owned(),
+                        r#type: "invalid_request_error".to_owned(),
+                        param: None,
+                    });
+                }
+            }
+        }
+    }
+
     // 1. 檢查模型設定是否存在
     let Some(llm_config) = all_configs.get(&body.model) else {
+        // 本機沒有就看看其他節點
+        if let Some(peer) = cluster.peer_for(&body.model, req).await {
+            return cluster.forward(&peer, "/v1/chat/completions", &body).await;
+        }
         return HttpResponse::BadRequest().json(OpenAiError {
             message: format!("Model \"{}\" does not exist.", body.model),
             code: "model_not_found".to_owned(),
@@ -197,94 +466,286 @@ pub async fn chat_completions(
         });
     };
 
+    // 文字模型收到圖片就改問設定的視覺模型
+    let llm_config = match capabilities::vision_route(&body, llm_config, &all_configs) {
+        Some(companion) => {
+            log::info!("{}: images of {} go to {}", id, body.model, companion.model_name);
+            body.model = companion.model_name.clone();
+            companion
+        }
+        None => llm_config,
+    };
+
+    // 伺服器強制的 system prompt，客戶端沒給的話再加上模型預設的
+    if let Err(message) = crate::utils::apply_system_prompts(llm_config, &mut body.messages) {
+        return HttpResponse::BadRequest().json(OpenAiError {
+            message,
+            code: "system_prompt_not_allowed".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some("messages".to_owned()),
+        });
+    }
+
+    // 語音先轉成文字再問模型
+    if let Err(response) = crate::audio_input::transcribe_parts(&mut body.messages, req).await {
+        return response;
+    }
+
+    // 模型做不到的功能直接拒絕，不要默默丟掉
+    if let Err(error) = capabilities::check(&body, llm_config) {
+        return HttpResponse::BadRequest().json(error);
+    }
+    // 圖片先檢查，網址的先下載，視覺模型只讀記憶體裡的圖
+    if matches!(llm_config.model_type, crate::utils::ModelType::VLM) {
+        let max_bytes = llm_config
+            .max_image_bytes
+            .unwrap_or(crate::image_input::MAX_IMAGE_BYTES);
+        let allow = &llm_config.image_url_allow;
+        if let Err(error) =
+            crate::image_input::prepare_images(&mut body.messages, max_bytes, allow).await
+        {
+            return HttpResponse::BadRequest().json(error);
+        }
+    }
+    if let Err(error) = sampling::apply(&mut body, llm_config)
+        .and_then(|_| sampling::validate(&body))
+        .and_then(|_| sampling::fixed(&body, llm_config))
+        .and_then(|_| capabilities::sampling(&body, llm_config))
+    {
+        return HttpResponse::BadRequest().json(error);
+    }
+
     // 準備要移入 Stream 的資源 (Clone 指標)
     let llm_pool = llm_pool.clone();
     let shutdown_pool = shutdown_pool.clone();
-    let model_name = body.model.clone();
-    let messages = body.messages.clone();
+    let failed_models = failed_models.get_ref().clone();
+    let all_configs = all_configs.clone();
+    let mut messages = crate::tools::prompt_messages(&body);
+    if let Some(instruction) = crate::structured::instruction(&body) {
+        append_system_prompt(&mut messages, &instruction);
+    }
     let is_stream_mode = body.stream;
-    let llm_config = llm_config.clone();
+    let stop = crate::stop::sequences(body.stop.as_ref());
+    let requested = sampling::requested(&body);
+    let seed = body.seed;
+    let max_tokens = match crate::finish::max_tokens(&body) {
+        Ok(max_tokens) => max_tokens,
+        Err(error) => return HttpResponse::BadRequest().json(error),
+    };
+    let logprobs = match crate::logprobs::requested(&body) {
+        Ok(logprobs) => logprobs,
+        Err(error) => return HttpResponse::BadRequest().json(error),
+    };
+    let metadata = body.metadata.clone();
+    // 每個 token 各自帶 logprobs，不能合併
+    let coalesce_settings = match logprobs {
+        Some(_) => coalesce::Coalesce::default(),
+        None => coalesce::Coalesce::resolve(req, body.coalesce),
+    };
+    let session_id = req
+        .headers()
+        .get(crate::prompt_cache::SESSION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned)
+        .or_else(|| body.user.clone());
+    let lang = i18n::request_lang(req);
+
+    // A/B 分流，之後的 fallback 以抽到的變體為準
+    let variant = traffic::pick_variant(llm_config, &all_configs);
+    let variant_used = (variant.model_name != body.model).then(|| variant.model_name.clone());
+
+    // 主模型最近失敗過就直接改走 fallback
+    let mut route = fallback::initial_route(variant, &all_configs, &failed_models);
+    let mut fallback_used = (route.name() != variant.model_name).then(|| route.name().to_owned());
 
     // 檢查模型是否已載入 (這裡只做快速檢查，不長時間持有鎖)
-    let model_exists = llm_pool
-        .lock()
-        .unwrap()
-        .contains_key(&model_name);
+    let model_exists = match &route {
+        Route::Local(config) => llm_pool.lock().unwrap().contains_key(&config.model_name),
+        Route::Remote(_) => true,
+    };
 
-    // 如果模型不存在且不是 Stream 模式，直接報錯
-    if !model_exists && !is_stream_mode {
-        return HttpResponse::BadRequest().json(OpenAiError {
-            message: "Model is not loaded. Use streaming mode to load it first.".to_owned(),
-            code: "resource_not_found".to_owned(),
-            r#type: "resource_not_found".to_owned(),
-            param: None,
-        });
+    // 記錄最近用過的模型，超過記憶體預算時先卸載最久沒用的
+    let memory_budget = req
+        .app_data::<web::Data<MemoryBudget>>()
+        .map(|b| b.get_ref().clone());
+    if let (Route::Local(config), Some(budget)) = (&route, &memory_budget) {
+        budget.touch(&config.model_name);
     }
+
+    // 最後一個請求的 keep_alive 決定閒置多久卸載
+    if let (Route::Local(config), Some(keep_alives)) =
+        (&route, req.app_data::<web::Data<crate::keep_alive::KeepAlives>>())
+    {
+        keep_alives.set(
+            &config.model_name,
+            body.keep_alive.or(config.keep_alive).unwrap_or_default(),
+        );
+    }
+    #[cfg(feature = "sqlite")]
+    if let (Route::Local(config), Some(registry)) = (&route, crate::registry::from_request(&req)) {
+        let keep_alive = body.keep_alive.or(config.keep_alive).unwrap_or_default();
+        if let Err(e) = registry.used(config, keep_alive) {
+            log::warn!("Failed to register use of {}: {}", config.model_name, e);
+        }
+    }
+
+    // 用完額度的對話直接拒絕
+    let budgets = req.app_data::<web::Data<Budgets>>().map(|b| b.get_ref().clone());
+    if let (Some(budgets), Some(session)) = (&budgets, &session_id) {
+        if let Err(e) = budgets.check(session) {
+            return e.response();
+        }
+    }
+    let metered = budgets.zip(session_id.clone());
+    // 每個 user 每天、每月的額度
+    let quotas = req
+        .app_data::<web::Data<Quotas>>()
+        .map(|q| q.get_ref().clone())
+        .zip(body.user.clone());
+    if let Some((quotas, user)) = &quotas {
+        if let Err(e) = quotas.check(user) {
+            return e.response();
+        }
+    }
+
+    let loads = req.app_data::<web::Data<Loads>>().map(|l| l.get_ref().clone());
+
+    // 非串流的請求也會載入模型，只是看不到進度。等超過 --load-timeout 就先回 503，背景繼續載入
+    if let (false, false, Route::Local(config)) = (model_exists, is_stream_mode, &route) {
+        let model_name = config.model_name.clone();
+        let (mut progress_rx, loading) = spawn_load(
+            config.as_ref().clone(),
+            &llm_pool,
+            &shutdown_pool,
+            memory_budget.as_ref(),
+            &all_configs,
+            loads.as_ref(),
+        );
+        let wait = async move {
+            while progress_rx.recv().await.is_some() {}
+            loading.await
+        };
+        let loaded = match req.app_data::<web::Data<LoadTimeout>>().and_then(|t| t.0) {
+            Some(timeout) => actix_web::rt::time::timeout(timeout, wait).await.ok(),
+            None => Some(wait.await),
+        };
+        match loaded {
+            None => {
+                return ApiError::loading(format!(
+                    "Model \"{}\" is still loading, please retry later.",
+                    model_name
+                ))
+                .response();
+            }
+            Some(Ok(_)) => {}
+            Some(Err(e)) => {
+                log::warn!("{}: model {} failed to load ({})", id, model_name, e);
+                fallback::mark_failed(&failed_models, &model_name);
+                route = fallback::initial_route(variant, &all_configs, &failed_models);
+                if route.name() == model_name {
+                    return ApiError::internal(e).response();
+                }
+                fallback_used = Some(route.name().to_owned());
+            }
+        }
+    }
+
+    // 同一個模型排隊的請求太多就直接拒絕
+    let session = match req.app_data::<web::Data<Sessions>>() {
+        Some(sessions) => {
+            match sessions.try_start(&llm_config.model_name, llm_config.max_sessions) {
+                Some(session) => Some(session),
+                None => {
+                    return ApiError::Capacity {
+                        message: format!(
+                            "Model \"{}\" already has {} active sessions, please retry later.",
+                            body.model,
+                            llm_config.max_sessions.unwrap_or_default()
+                        ),
+                        code: "too_many_sessions",
+                        retry_after: errors::BUSY_RETRY_AFTER_SECS,
+                    }
+                    .response();
+                }
+            }
+        }
+        None => None,
+    };
+
+    // 遠端一律用 stream 模式轉發，回傳格式跟本地一致
+    let mut remote_body = serde_json::to_value(&body).unwrap_or_default();
+    remote_body["stream"] = serde_json::Value::Bool(true);
+
+    let request_id = id.clone();
     // 定義單一的輸出串流：這是你的主要骨牌鏈
     let outbound_stream: Pin<Box<dyn Stream<Item = Result<web::Bytes, actix_web::Error>>>> =
         Box::pin(async_stream::try_stream! {
+            // 串流結束時才釋放
+            let _session = session;
+            // 每次失敗就換成 fallback 重試，直到成功或沒有 fallback 為止
+            'attempt: loop {
+            let llm_config = match route.clone() {
+                Route::Local(config) => *config,
+                Route::Remote(url) => {
+                    log::info!("Proxying chat completion {} to {}", id, url);
+                    let mut remote = match fallback::proxy_chat(&url, remote_body.clone()).await {
+                        Ok(remote) => Box::pin(remote),
+                        Err(e) => {
+                            log::error!("{}", e);
+                            yield ApiError::internal(e).event();
+                            return;
+                        }
+                    };
+                    while let Some(chunk) = remote.next().await {
+                        match chunk {
+                            Ok(chunk) => yield chunk,
+                            Err(e) => {
+                                yield ApiError::internal(e).event();
+                                return;
+                            }
+                        }
+                    }
+                    return;
+                }
+            };
+            let model_name = llm_config.model_name.clone();
+            let fingerprint = crate::fingerprint::system_fingerprint(&llm_config);
+            let next_route = fallback::fallback_of(&llm_config, &all_configs);
 
             // ==========================================
             // 階段一：取得 LLM Actor (可能是現有的，或是剛載入的)
             // ==========================================
 
-            let recipient = if model_exists {
+            let loaded = llm_pool.lock().unwrap().get(&model_name).cloned();
+            let load_started = Instant::now();
+            let mut load_ms = 0.0;
+            let recipient = if let Some(recipient) = loaded {
                 // [情況 A] 模型已經在 Pool 裡
-                let pool = llm_pool.lock().unwrap(); // 這裡可以 blocking lock，因為只是讀 HashMap 很快
-                pool.get(&model_name).cloned().unwrap() // 拿出 Recipient
+                recipient
             } else {
                 // [情況 B] 需要載入模型 (長任務)
 
-                // 1. 先進行清理工作 (快速同步操作)
-                {
-                    let mut pool = llm_pool.lock().unwrap();
-                    let mut shutdown = shutdown_pool.lock().unwrap();
-                    pool.clear();
-                    let tasks: Vec<_> = shutdown.drain().map(|(_, addr)| async move {
-                        let _ = addr.send(ShutdownMessages).await;
-                    }).collect();
-                    // 在背景執行關閉，不等待
-                    tokio::spawn(futures::future::join_all(tasks));
-                }
-
-                // 2. 建立溝通管道
+                // 在背景載入，串流斷了也會載完放進 pool
                 log::info!("Creating progress stream");
-                let (progress_tx, mut progress_rx) = tokio::sync::mpsc::channel(64);
+                let (mut progress_rx, loading) = spawn_load(llm_config.clone(), &llm_pool, &shutdown_pool, memory_budget.as_ref(), &all_configs, loads.as_ref());
 
-                // 3. 啟動背景任務
-                // 注意：這裡不用 await，讓它開始在另一個執行緒跑
-                let join_handle = tokio::task::spawn_blocking(move || {
-                    let progress = OpenWebUIProgress::new(progress_tx);
-                    // 這會跑很久 (5-10分鐘)
-                    crate::llm::simple::SimpleRkLLM::init_with_progress(&llm_config, Some(progress))
-                });
-
-                // 4. 進入「讀取進度」迴圈
+                // 進入「讀取進度」迴圈
                 // 只要背景任務還在跑，progress_rx 就會一直收到資料
                 let mut bar = indicatif::ProgressBar::new(1000);
                 let mut first_download_done = true;
                 let mut percent = -1_i64;
-                let mut count = 0;
                 while let Some(msg) = progress_rx.recv().await {
-                    let mut edited_msg = msg.message.clone();
                     if !msg.download_done && msg.current == 0 { // 剛開始下載
                         bar = indicatif::ProgressBar::new(msg.total as u64);
-                        edited_msg += "<think>"
                     } else if msg.download_done && first_download_done { // 剛結束下載
                         first_download_done=false;
                         bar.finish();
                         bar = indicatif::ProgressBar::new_spinner();
                         bar.enable_steady_tick(Duration::from_millis(100));
-                        log::info!("Progress: {}", msg.message);
-                        if count == 0 { // 這個case是一開始就從快取拿
-                            edited_msg = format!("{}<think>\n", edited_msg);
-                        } else {
-                            edited_msg = format!("</think>{}<think>下載完成\n", edited_msg);
-                        }
+                        log::info!("Progress: {}", msg.key.text(lang));
                     } else if msg.finished { // 整個結束
                         bar.finish();
-                        log::info!("Progress: {}", msg.message);
-                        edited_msg = format!("</think>");
+                        log::info!("Progress: {}", msg.key.text(lang));
                     } else {
                         bar.set_position(msg.current as u64);
                     }
@@ -292,36 +753,37 @@ pub async fn chat_completions(
                     if msg.current != 0 && !msg.download_done && percent == (msg.current*100/ msg.total) as i64 {
                         // do not display
                     } else {
+                        let mut description = msg.key.text(lang);
                         if !msg.download_done {
                             percent = (msg.current*100/ msg.total) as i64;
+                            description = format!("{} ({}%)", description, percent);
                         }
-                        // 立即吐出 SSE 給前端
-                        let sse = create_sse_chunk_data(
-                            &id, created, &model_name,
-                            Some(Role::System), Some(Content::String(edited_msg))
-                        );
-                        yield web::Bytes::from(sse);
+                        // 獨立的 progress 事件，不會混進對話內容
+                        yield web::Bytes::from(progress_event(&msg, &description));
                     }
-                    count += 1;
                 }
                 // 當迴圈結束，表示背景任務做完了 (Channel 被 Drop)
 
                 // 因為上面迴圈結束代表任務已停，這裡的 await 會瞬間完成
-                let llm_result = join_handle.await
-                    .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Join err: {}", e)))?;
-
-                let llm = llm_result
-                    .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Init err: {}", e)))?;
+                let llm_result = loading.await;
 
-                // 6. 啟動 Actor 並更新 Pool
-                log::info!("模型載入完成，啟動 Actor");
-                let addr = llm.start();
-                let recipient = addr.clone().recipient::<ProcessMessages>();
-
-                // 更新全域狀態
-                llm_pool.lock().unwrap().insert(model_name.clone(), recipient.clone());
-                shutdown_pool.lock().unwrap().insert(model_name.clone(), addr.recipient::<ShutdownMessages>());
+                let recipient = match (llm_result, next_route.clone()) {
+                    (Ok(recipient), _) => recipient,
+                    (Err(e), Some(next)) => {
+                        log::warn!("{}: model {} failed to load ({}), falling back to {}", id, model_name, e, next.name());
+                        fallback::mark_failed(&failed_models, &model_name);
+                        yield web::Bytes::from(status_event(&MessageKey::FallbackUsed { model: model_name.clone(), fallback: next.name().to_owned() }.text(lang), false));
+                        route = next;
+                        continue 'attempt;
+                    }
+                    (Err(e), None) => {
+                        fallback::mark_failed(&failed_models, &model_name);
+                        yield ApiError::internal(e).event();
+                        return;
+                    }
+                };
 
+                load_ms = load_started.elapsed().as_secs_f64() * 1000.0;
                 recipient
             };
 
@@ -333,6 +795,12 @@ pub async fn chat_completions(
             // 發送訊息給 Actor
             let send_future = recipient.send(ProcessMessages {
                 messages: messages.clone(),
+                session: session_id.clone(),
+                stop: stop.clone(),
+                max_tokens,
+                sampling: requested.clone(),
+                seed,
+                logprobs,
             });
 
 
@@ -341,11 +809,18 @@ pub async fn chat_completions(
             let actor_response = match actix_web::rt::time::timeout(std::time::Duration::from_secs(60), send_future).await {
                 Ok(Ok(res)) => res,
                 Ok(Err(e)) => {
-                    yield web::Bytes::from(format!("data: {{\"error\": \"Mailbox error: {}\"}}\n\n", e));
+                    if let Some(next) = next_route {
+                        log::warn!("{}: model {} mailbox error ({}), falling back to {}", id, model_name, e, next.name());
+                        fallback::mark_failed(&failed_models, &model_name);
+                        llm_pool.lock().unwrap().remove(&model_name);
+                        route = next;
+                        continue 'attempt;
+                    }
+                    yield ApiError::internal(format!("Mailbox error: {}", e)).event();
                     return;
                 },
                 Err(_) => {
-                    yield web::Bytes::from("data: {\"error\": \"Timeout waiting for model slot\"}\n\n");
+                    yield ApiError::busy("Timeout waiting for model slot").event();
                     return;
                 }
             };
@@ -354,61 +829,373 @@ pub async fn chat_completions(
             let mut chat_stream = match actor_response {
                 Ok(s) => s,
                 Err(_) => {
-                    yield web::Bytes::from("data: {\"error\": \"Internal stream error\"}\n\n");
+                    if let Some(next) = next_route {
+                        log::warn!("{}: model {} failed to start generation, falling back to {}", id, model_name, next.name());
+                        fallback::mark_failed(&failed_models, &model_name);
+                        route = next;
+                        continue 'attempt;
+                    }
+                    yield ApiError::internal("Internal stream error").event();
                     return;
                 }
             };
 
+            // 從開始跑到第一個 token 算 prompt 的時間
+            let run_started = Instant::now();
+            // 長 prompt 讀取中的進度
+            let mut first = chat_stream.next().await;
+            while let Some((current, total)) = first.as_deref().and_then(prefill::parse) {
+                let key = MessageKey::Prefilling { current, total };
+                let msg = ProgressMessage { current, total, download_done: false, finished: false, key: key.clone() };
+                yield web::Bytes::from(progress_event(&msg, &key.text(lang)));
+                first = chat_stream.next().await;
+            }
+
+            // 還沒吐出任何 token 前出錯，才能無痛切換到 fallback
+            if let (Some(text), Some(next)) = (&first, &next_route) {
+                if text.starts_with("Model error:") {
+                    log::warn!("{}: model {} errored ({}), falling back to {}", id, model_name, text, next.name());
+                    fallback::mark_failed(&failed_models, &model_name);
+                    route = next.clone();
+                    continue 'attempt;
+                }
+            }
+            fallback::mark_healthy(&failed_models, &model_name);
+            let first_token_at = Instant::now();
+            let prompt_ms = (first_token_at - run_started).as_secs_f64() * 1000.0;
+            // 模型最後送來的 token 數，串流結束後另外送一個 usage chunk
+            let usage = Arc::new(Mutex::new(first.as_deref().and_then(crate::usage::parse)));
+            if usage.lock().unwrap().is_some() {
+                first = None;
+            }
+            // 模型提早結束的原因，例如碰到 max_tokens
+            let finish = Arc::new(Mutex::new(first.as_deref().and_then(crate::finish::parse)));
+            if finish.lock().unwrap().is_some() {
+                first = None;
+            }
+            // 生成到一半出錯，最後送 error 事件，不當成回答
+            let failure = Arc::new(Mutex::new(first.take_if(|text| text.starts_with("Model error:"))));
+            let counted = usage.clone();
+            let finished = finish.clone();
+            let failed = failure.clone();
+            let chat_stream = chat_stream.filter(move |text| {
+                let marker = crate::usage::parse(text);
+                if marker.is_some() {
+                    *counted.lock().unwrap() = marker;
+                }
+                let reason = crate::finish::parse(text);
+                if reason.is_some() {
+                    *finished.lock().unwrap() = reason;
+                }
+                let error = text.starts_with("Model error:");
+                if error {
+                    *failed.lock().unwrap() = Some(text.clone());
+                }
+                std::future::ready(marker.is_none() && reason.is_none() && !error && prefill::parse(text).is_none())
+            });
+            // 產生的 token 算進對話的額度
+            let metered = metered.clone();
+            let chat_stream = chat_stream.inspect(move |text| {
+                if let (Some((budgets, session)), false) = (&metered, text.is_empty()) {
+                    budgets.add(session, 1);
+                }
+            });
+            // 結尾補一個空字串，每個回答都會收到帶 finish_reason 的 chunk
+            let chat_stream = futures::stream::iter(first).chain(chat_stream).chain(futures::stream::iter([String::new()]));
+            let mut chat_stream = coalesce::coalesce(chat_stream, coalesce_settings);
+
             // ==========================================
             // 階段三：串流輸出 Token
             // ==========================================
             let mut stream_counter = 0;
+            // 思考的部分放到 reasoning_content
+            let mut reasoning = llm_config.think.unwrap_or(false).then(crate::reasoning::Reasoning::default);
             while let Some(content) = chat_stream.next().await {
+                let (content, logprobs) = match crate::logprobs::parse(&content) {
+                    Some((text, entries)) => (text, Some(serde_json::json!({ "content": entries }))),
+                    None => (content, None),
+                };
+                let done = content.is_empty();
+                let (thought, content) = match reasoning.as_mut() {
+                    Some(reasoning) if done => (reasoning.flush(), content),
+                    Some(reasoning) => reasoning.push(&content),
+                    None => (String::new(), content),
+                };
+                let error = if done { failure.lock().unwrap().take() } else { None };
+                // 留住的文字先送，最後一個 chunk 才帶 finish_reason
+                let mut pieces = vec![(thought, content, logprobs, false)];
+                if done && error.is_none() {
+                    pieces.push((String::new(), String::new(), None, true));
+                }
+                for (thought, content, logprobs, done) in pieces {
+                    if !done && thought.is_empty() && content.is_empty() && logprobs.is_none() {
+                        continue;
+                    }
+                    let chunk = ChatCompletionsResponse {
+                        id: id.clone(),
+                        object: "chat.completion.chunk".to_owned(),
+                        created,
+                        model: model_name.clone(),
+                        choices: vec![Choice {
+                            index: 0,
+                            finish_reason: if done {
+                                Some(finish.lock().unwrap().take().unwrap_or(FinishReason::Stop))
+                            } else {
+                                None
+                            },
+                            delta: Some(Message {
+                                role: if stream_counter == 0 { Some(Role::Assistant) } else { None },
+                                content: if content.is_empty() { None } else { Some(Content::String(content)) },
+                                reasoning_content: (!thought.is_empty()).then_some(thought),
+                                ..Default::default()
+                            }),
+                            logprobs,
+                            message: None,
+                        }],
+                        usage: None,
+                        metadata: metadata.clone(),
+                        system_fingerprint: Some(fingerprint.clone()),
+                        timings: None,
+                    };
+                    stream_counter += 1;
+                    
+                    let sse_data = format!("data: {}\n\n", serde_json::to_string(&chunk).unwrap());
+                    yield web::Bytes::from(sse_data);
+                }
+                if let Some(error) = error {
+                    log::error!("{}: {}", id, error);
+                    yield ApiError::internal(error).event();
+                    return;
+                }
+                if done {
+                    break;
+                }
+            }
+            let usage = usage.lock().unwrap().take();
+            if let (Some((quotas, user)), Some(usage)) = (&quotas, &usage) {
+                quotas.add(user, usage.total_tokens.max(0) as u64);
+            }
+            if let Some(usage) = usage {
                 let chunk = ChatCompletionsResponse {
                     id: id.clone(),
                     object: "chat.completion.chunk".to_owned(),
                     created,
                     model: model_name.clone(),
-                    choices: vec![Choice {
-                        index: 0,
-                        finish_reason: if content.is_empty() { Some(FinishReason::Stop) } else { None },
-                        delta: Some(Message {
-                            role: if stream_counter == 0 { Some(Role::Assistant) } else { None },
-                            content: if content.is_empty() { None } else { Some(Content::String(content)) },
-                        }),
-                        logprobs: None,
-                        message: None,
-                    }],
-                    usage: None,
+                    choices: vec![],
+                    usage: Some(usage),
+                    metadata: metadata.clone(),
+                    system_fingerprint: Some(fingerprint.clone()),
+                    timings: Some(crate::usage::Timings {
+                        load_ms,
+                        prompt_ms,
+                        generate_ms: first_token_at.elapsed().as_secs_f64() * 1000.0,
+                    }),
                 };
-                stream_counter += 1;
-                
-                let sse_data = format!("data: {}\n\n", serde_json::to_string(&chunk).unwrap());
-                yield web::Bytes::from(sse_data);
+                yield web::Bytes::from(format!("data: {}\n\n", serde_json::to_string(&chunk).unwrap()));
+            }
+            yield web::Bytes::from(SSE_DONE);
+            break;
             }
         });
 
-    // 根據請求模式回傳
-    if is_stream_mode {
-        HttpResponse::Ok()
-            .content_type("text/event-stream")
-            .streaming(outbound_stream)
+    let mut response = HttpResponse::Ok();
+    response.content_type("text/event-stream");
+    response.insert_header((REQUEST_ID_HEADER, request_id));
+    // 一開始就決定走 fallback 的話用 header 告知，中途切換則看 chunk 裡的 model
+    if let Some(fallback_model) = fallback_used {
+        response.insert_header((fallback::FALLBACK_HEADER, fallback_model));
+    }
+    if let Some(variant) = variant_used {
+        response.insert_header((traffic::VARIANT_HEADER, variant));
+    }
+
+    response.streaming(outbound_stream)
+}
+
+/// `--load-timeout`: how long a non-streaming request waits for its model to load, `None` waits
+/// until it is loaded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadTimeout(pub Option<Duration>);
+
+/// Unload the models `budget` wants gone (all of them without one) and load `config` in the
+/// background. The task puts the model in the pools when it is ready, even if nobody waits for it
+/// anymore.
+pub(crate) fn spawn_load(
+    config: ModelConfig,
+    llm_pool: &LlmPool,
+    shutdown_pool: &ShutdownPool,
+    budget: Option<&MemoryBudget>,
+    all_configs: &HashMap<String, ModelConfig>,
+    loads: Option<&Loads>,
+) -> (tokio::sync::mpsc::Receiver<ProgressMessage>, SharedLoad) {
+    let (progress_tx, progress_rx) = tokio::sync::mpsc::channel(64);
+    let mut loading = loads.map(|loads| loads.0.lock().unwrap());
+    // 已經在載入的話等同一個，進度只有第一個請求看得到
+    if let Some(load) = loading.as_ref().and_then(|l| l.get(&config.model_name)) {
+        log::info!("Model {} is already loading, waiting for it", config.model_name);
+        return (progress_rx, load.clone());
+    }
+    // 剛載完的話直接用
+    if let Some(recipient) = llm_pool.lock().unwrap().get(&config.model_name).cloned() {
+        let ready: Pin<Box<dyn Future<Output = _> + Send>> =
+            Box::pin(std::future::ready(Ok(recipient)));
+        return (progress_rx, ready.shared());
+    }
+
+    // 先進行清理工作 (快速同步操作)
+    {
+        let mut pool = llm_pool.lock().unwrap();
+        let mut shutdown = shutdown_pool.lock().unwrap();
+        let loaded = pool.keys().cloned().collect();
+        let evicted = match budget {
+            Some(budget) => {
+                let loading = loading
+                    .as_ref()
+                    .map(|loading| loading.keys().cloned().collect())
+                    .unwrap_or_default();
+                budget.evictions(&config, loaded, loading, all_configs)
+            }
+            None => loaded,
+        };
+        let tasks: Vec<_> = evicted
+            .iter()
+            .filter_map(|model| {
+                pool.remove(model);
+                shutdown.remove(model)
+            })
+            .map(|addr| async move {
+                let _ = addr.send(ShutdownMessages).await;
+            })
+            .collect();
+        // 在背景執行關閉，不等待
+        tokio::spawn(futures::future::join_all(tasks));
+    }
+
+    let llm_pool = llm_pool.clone();
+    let shutdown_pool = shutdown_pool.clone();
+    let done = loads.cloned();
+    let model_name = config.model_name.clone();
+    let load = async move {
+        let load_config = config.clone();
+        let llm = tokio::task::spawn_blocking(move || {
+            let progress = OpenWebUIProgress::new(progress_tx);
+            // 這會跑很久 (5-10分鐘)
+            crate::llm::LoadedLLM::init_with_progress(&load_config, Some(progress))
+        })
+        .await
+        .map_err(|e| format!("Join err: {}", e))
+        .and_then(|r| r.map_err(|e| format!("Init err: {}", e)))?;
+
+        log::info!("模型載入完成，啟動 Actor");
+        let (recipient, shutdown_recipient) = llm.start();
+        llm_pool
+            .lock()
+            .unwrap()
+            .insert(config.model_name.clone(), recipient.clone());
+        shutdown_pool
+            .lock()
+            .unwrap()
+            .insert(config.model_name, shutdown_recipient);
+        Ok::<_, String>(recipient)
+    };
+    let key = model_name.clone();
+    let task = actix_web::rt::spawn(async move {
+        let result = load.await;
+        // 放進 pool 之後才移除，之後的請求直接用 pool 裡的
+        if let Some(loads) = done {
+            loads.0.lock().unwrap().remove(&key);
+        }
+        result
+    });
+    let load: Pin<Box<dyn Future<Output = _> + Send>> = Box::pin(async move {
+        task.await
+            .map_err(|e| format!("Join err: {}", e))
+            .and_then(|r| r)
+    });
+    let load = load.shared();
+    // 還拿著鎖，背景任務要等這裡放進去之後才能移除
+    if let Some(loading) = loading.as_mut() {
+        loading.insert(model_name, load.clone());
+    }
+    (progress_rx, load)
+}
+
+/// `body` as a streaming request. The endpoints answering in their own format (`/v1/responses`,
+/// `/v1/messages`, ollama, TGI, batches, ...) always run the chat streamed and collect the chunks
+/// themselves, so a model that isn't loaded yet is loaded for them like for a streaming client.
+pub(crate) fn streamed(body: ChatCompletionsRequest) -> ChatCompletionsRequest {
+    ChatCompletionsRequest {
+        stream: true,
+        ..body
+    }
+}
+
+/// 把文字接在第一個 system 訊息後面，沒有就新增一個
+pub(crate) fn append_system_prompt(messages: &mut Vec<Message>, prompt: &str) {
+    if let Some(Message {
+        role: Some(Role::System | Role::Developer),
+        content: Some(Content::String(system)),
+        ..
+    }) = messages.first_mut()
+    {
+        system.push_str("\n\n");
+        system.push_str(prompt);
+    } else {
+        messages.insert(
+            0,
+            Message {
+                role: Some(Role::System),
+                content: Some(Content::String(prompt.to_owned())),
+                ..Default::default()
+            },
+        );
+    }
+}
+
+/// Open WebUI status event, shown as a progress line above the answer instead of in it.
+pub(crate) fn status_event(description: &str, done: bool) -> String {
+    let event = serde_json::json!({
+        "event": {
+            "type": "status",
+            "data": { "description": description, "done": done },
+        }
+    });
+    format!("data: {}\n\n", event)
+}
+
+/// Loading progress as a named `progress` SSE event. The payload also carries the Open WebUI
+/// status event, so clients reading only `data:` lines still get a status line.
+pub(crate) fn progress_event(msg: &ProgressMessage, description: &str) -> String {
+    let mut progress = serde_json::to_value(msg).unwrap_or_default();
+    progress["description"] = description.into();
+    if !msg.download_done && msg.total > 0 {
+        progress["percent"] = (msg.current * 100 / msg.total).into();
+    }
+    let event = serde_json::json!({
+        "event": {
+            "type": "status",
+            "data": { "description": description, "done": msg.finished },
+        },
+        "progress": progress,
+    });
+    format!("event: progress\ndata: {}\n\n", event)
+}
+
+/// Last event of an OpenAI stream, clients read until it.
+pub(crate) const SSE_DONE: &str = "data: [DONE]\n\n";
+
+/// Re-frame a relayed `data:` payload, keeping progress events named.
+pub(crate) fn sse_frame(data: &str) -> String {
+    let is_progress = serde_json::from_str::<serde_json::Value>(data)
+        .is_ok_and(|chunk| chunk.get("progress").is_some());
+    if is_progress {
+        format!("event: progress\ndata: {}\n\n", data)
     } else {
-        // 為了相容舊邏輯，如果不是 Stream 模式但還是跑到這裡 (例如本來已存在)
-        // 我們還是得把 Stream 收集回來轉成 JSON
-        // 但因為上面的 outbound_stream 已經包含了 SSE 格式化，這會變得有點髒。
-        // 建議：如果你的前端能支援，統一都用 SSE 回傳最簡單。
-        // 若必須支援非 Stream，建議在最外層拆開邏輯，或者這裡做個簡易的 collect
-
-        // 這裡示範直接用 Streaming 回傳，通常現代 LLM API 即使不開 stream 參數，
-        // 內部邏輯一致比較好維護，或者你需要重寫一段專門收集 Vec 的邏輯。
-        HttpResponse::Ok()
-            .content_type("text/event-stream")
-            .streaming(outbound_stream)
+        format!("data: {}\n\n", data)
     }
 }
 
-fn create_sse_chunk_data(
+pub(crate) fn create_sse_chunk_data(
     id: &str,
     created: u64,
     model: &str,
@@ -427,11 +1214,18 @@ fn create_sse_chunk_data(
             } else {
                 None
             },
-            delta: Some(Message { role, content }),
+            delta: Some(Message {
+                role,
+                content,
+                ..Default::default()
+            }),
             logprobs: None,
             message: None,
         }],
         usage: None,
+        metadata: None,
+        system_fingerprint: None,
+        timings: None,
     };
     "data: ".to_owned() + &serde_json::to_string(&chunk).unwrap() + "\n\n"
 }
@@ -459,6 +1253,61 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse: {:?}", result.err());
     }
 
+    #[test]
+    fn progress_is_a_named_event() {
+        let msg = ProgressMessage {
+            current: 50,
+            total: 200,
+            download_done: false,
+            finished: false,
+            key: MessageKey::Downloading { current: 50, total: 200 },
+        };
+        let frame = progress_event(&msg, "Downloading... 50/200 (25%)");
+        let data = frame.strip_prefix("event: progress\ndata: ").unwrap().trim_end();
+        let chunk: serde_json::Value = serde_json::from_str(data).unwrap();
+        assert_eq!(chunk["progress"]["percent"], 25);
+        assert_eq!(chunk["progress"]["message"]["key"], "downloading");
+        assert_eq!(chunk["event"]["type"], "status");
+        assert_eq!(sse_frame(data), frame);
+        assert_eq!(crate::ws::chunk_text(data), Ok(None));
+    }
+
+    #[actix_web::test]
+    async fn concurrent_loads_share_one_init() {
+        let config = ModelConfig {
+            model_name: "echo".to_owned(),
+            model_type: crate::utils::ModelType::Echo,
+            ..Default::default()
+        };
+        let (pool, shutdown, loads) = (LlmPool::default(), ShutdownPool::default(), Loads::default());
+        let (_, first) = spawn_load(config.clone(), &pool, &shutdown, None, &HashMap::new(), Some(&loads));
+        // 第一個還沒載完，重試的請求等同一個
+        let (_, second) = spawn_load(config, &pool, &shutdown, None, &HashMap::new(), Some(&loads));
+        assert_eq!(loads.0.lock().unwrap().len(), 1);
+        let (first, second) = futures::join!(first, second);
+        assert!(first.unwrap() == second.unwrap());
+        assert!(loads.0.lock().unwrap().is_empty());
+        assert_eq!(shutdown.lock().unwrap().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn collects_stream_into_completion() {
+        let chunks = [
+            create_sse_chunk_data("chatcmpl-1", 1, "qwen", Some(Role::Assistant), Some(Content::String("Hi".to_owned()))),
+            create_sse_chunk_data("chatcmpl-1", 1, "qwen", None, Some(Content::String(" there".to_owned()))),
+            create_sse_chunk_data("chatcmpl-1", 1, "qwen", None, None),
+            format!("data: {}\n\n", serde_json::json!({ "id": "chatcmpl-1", "object": "chat.completion.chunk", "created": 1, "model": "qwen", "choices": [], "usage": crate::usage::usage(4, 2) })),
+            SSE_DONE.to_owned(),
+        ];
+        let response = HttpResponse::Ok().content_type("text/event-stream").body(chunks.concat());
+        let body = actix_web::body::to_bytes(collect(response).await.into_body()).await.unwrap();
+        let completion: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(completion["object"], "chat.completion");
+        assert_eq!(completion["choices"][0]["message"]["content"], "Hi there");
+        assert_eq!(completion["choices"][0]["finish_reason"], "stop");
+        assert_eq!(completion["usage"]["total_tokens"], 6);
+    }
+
     #[test]
     fn test_openai_tools_schema_parsing() {
         let json_str = r#"{
@@ -0,0 +1,148 @@
+use actix_web::{web, HttpResponse};
+use futures::StreamExt;
+use serde_json::Value;
+
+use crate::{
+    chat::{sse_frame, ChatCompletionsRequest, SSE_DONE},
+    ws::{sse_data, ChatContext},
+    OpenAiError,
+};
+
+/// NPU 一次只跑一個，太多會排很久
+pub const MAX_CHOICES: usize = 8;
+
+/// `n` of a chat request.
+pub fn count(request: &ChatCompletionsRequest) -> Result<usize, OpenAiError> {
+    match request.n.unwrap_or(1) {
+        n if n >= 1 && n as usize <= MAX_CHOICES => Ok(n as usize),
+        _ => Err(OpenAiError {
+            message: format!("n must be between 1 and {}.", MAX_CHOICES),
+            code: "invalid_value".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some("n".to_owned()),
+        }),
+    }
+}
+
+/// `data` of one run as a chunk of choice `index`, with the id of the first run. The usage is
+/// taken out and added to `usage`.
+fn reindex(
+    data: &str,
+    index: usize,
+    id: &mut Option<Value>,
+    usage: &mut Option<Value>,
+) -> Option<String> {
+    let Ok(mut chunk) = serde_json::from_str::<Value>(data) else {
+        return Some(data.to_owned());
+    };
+    if chunk.get("choices").is_none() {
+        return Some(data.to_owned());
+    }
+    if let Some(counted) = chunk.get("usage").filter(|u| !u.is_null()) {
+        let total = usage.get_or_insert_with(|| {
+            serde_json::json!({
+                "prompt_tokens": 0, "completion_tokens": 0, "total_tokens": 0
+            })
+        });
+        for key in ["prompt_tokens", "completion_tokens", "total_tokens"] {
+            let sum = total[key].as_i64().unwrap_or(0) + counted[key].as_i64().unwrap_or(0);
+            total[key] = sum.into();
+        }
+        return None;
+    }
+    chunk["id"] = id.get_or_insert_with(|| chunk["id"].clone()).clone();
+    for choice in chunk["choices"].as_array_mut().into_iter().flatten() {
+        choice["index"] = index.into();
+    }
+    Some(chunk.to_string())
+}
+
+/// Answer `body` `n` times one after another, streamed as choices `0..n`. The usage of all runs
+/// comes in one chunk at the end.
+pub(crate) async fn generate(
+    ctx: ChatContext,
+    body: ChatCompletionsRequest,
+    n: usize,
+) -> HttpResponse {
+    let tools = crate::tools::wants_tool_calls(&body);
+    let chat = move |ctx: ChatContext, body: ChatCompletionsRequest| async move {
+        let response = ctx.chat(body).await;
+        if tools {
+            crate::tools::relay(response)
+        } else {
+            response
+        }
+    };
+    let first = chat(ctx.clone(), body.clone()).await;
+    if !first.status().is_success() {
+        return first;
+    }
+
+    let events = async_stream::stream! {
+        let mut id = None;
+        let mut usage = None;
+        let mut response = Some(first);
+        for index in 0..n {
+            let response = match response.take() {
+                Some(response) => response,
+                None => chat(ctx.clone(), body.clone()).await,
+            };
+            if !response.status().is_success() {
+                let body = actix_web::body::to_bytes(response.into_body()).await.unwrap_or_default();
+                let error: Value = serde_json::from_slice(&body).unwrap_or_default();
+                let message = error["message"].as_str().unwrap_or("Generation failed.").to_owned();
+                yield Ok::<_, actix_web::Error>(web::Bytes::from(sse_frame(&serde_json::json!({ "error": message }).to_string())));
+                return;
+            }
+            let mut chunks = Box::pin(sse_data(response));
+            while let Some(data) = chunks.next().await {
+                if let Some(data) = reindex(&data, index, &mut id, &mut usage) {
+                    yield Ok(web::Bytes::from(sse_frame(&data)));
+                }
+            }
+        }
+        if let (Some(id), Some(usage)) = (id, usage) {
+            let chunk = serde_json::json!({
+                "id": id, "object": "chat.completion.chunk", "choices": [], "usage": usage
+            });
+            yield Ok(web::Bytes::from(sse_frame(&chunk.to_string())));
+        }
+        yield Ok(web::Bytes::from(SSE_DONE));
+    };
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .streaming(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reindexes_runs() {
+        let (mut id, mut usage) = (None, None);
+        let chunk = |id: &str| {
+            format!(
+                r#"{{"id":"{}","choices":[{{"index":0,"delta":{{"content":"Hi"}}}}]}}"#,
+                id
+            )
+        };
+        let first = reindex(&chunk("chatcmpl-a"), 0, &mut id, &mut usage).unwrap();
+        let second: Value =
+            serde_json::from_str(&reindex(&chunk("chatcmpl-b"), 1, &mut id, &mut usage).unwrap())
+                .unwrap();
+        assert!(first.contains("chatcmpl-a"));
+        assert_eq!(second["id"], "chatcmpl-a");
+        assert_eq!(second["choices"][0]["index"], 1);
+
+        let counted = r#"{"id":"x","choices":[],"usage":{"prompt_tokens":3,"completion_tokens":5,"total_tokens":8}}"#;
+        assert!(reindex(counted, 0, &mut id, &mut usage).is_none());
+        assert!(reindex(counted, 1, &mut id, &mut usage).is_none());
+        assert_eq!(usage.unwrap()["total_tokens"], 16);
+
+        let request: ChatCompletionsRequest =
+            serde_json::from_value(serde_json::json!({ "model": "m", "messages": [], "n": 9 }))
+                .unwrap();
+        assert!(count(&request).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use actix_web::{http::StatusCode, HttpRequest, HttpResponse};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use crate::OpenAiError;
+
+/// Set on requests forwarded to a peer, the peer then only answers with its local models.
+pub const FORWARDED_HEADER: &str = "X-Llmserver-Forwarded";
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+/// Unknown models refresh the peers at most this often, the background refresh does the rest.
+const MISS_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Deserialize)]
+struct PeerModels {
+    data: Vec<PeerModel>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PeerModel {
+    id: String,
+}
+
+/// Other llmserver nodes of the rack, requests for models they serve are forwarded to them.
+#[derive(Debug, Clone, Default)]
+pub struct Cluster {
+    peers: Arc<Vec<String>>,
+    /// model name -> peer base URL
+    models: Arc<Mutex<HashMap<String, String>>>,
+    /// 上次因為找不到模型而更新的時間
+    miss_refreshed: Arc<Mutex<Option<Instant>>>,
+    client: reqwest::Client,
+}
+
+impl Cluster {
+    pub fn new(peers: Vec<String>) -> Self {
+        let peers = peers
+            .into_iter()
+            .map(|peer| peer.trim_end_matches('/').to_owned())
+            .collect();
+        Self {
+            peers: Arc::new(peers),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Ask every peer for its local models. Unreachable peers are dropped until the next refresh.
+    pub async fn refresh(&self) {
+        let mut models = HashMap::new();
+        for peer in self.peers.iter() {
+            let response = self
+                .client
+                .get(format!("{}/v1/models", peer))
+                .header(FORWARDED_HEADER, "1")
+                .timeout(Duration::from_secs(5))
+                .send()
+                .await;
+            let list = match response {
+                Ok(response) => response.json::<PeerModels>().await,
+                Err(e) => Err(e),
+            };
+            match list {
+                Ok(list) => {
+                    for model in list.data {
+                        models.entry(model.id).or_insert_with(|| peer.clone());
+                    }
+                }
+                Err(e) => log::warn!("Peer {} is unreachable: {}", peer, e),
+            }
+        }
+        *self.models.lock().unwrap() = models;
+    }
+
+    /// 背景定期更新各節點的模型清單
+    pub fn spawn_refresh(&self) {
+        if self.is_empty() {
+            return;
+        }
+        let cluster = self.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(REFRESH_INTERVAL);
+            loop {
+                interval.tick().await;
+                cluster.refresh().await;
+            }
+        });
+    }
+
+    /// Whether a request for an unknown model may refresh the peers now, counting it if so.
+    fn miss_refresh_due(&self) -> bool {
+        let mut refreshed = self.miss_refreshed.lock().unwrap();
+        if refreshed.is_some_and(|at| at.elapsed() < MISS_REFRESH_INTERVAL) {
+            return false;
+        }
+        *refreshed = Some(Instant::now());
+        true
+    }
+
+    /// Models served by peers, for merging into the local model list.
+    pub fn peer_models(&self, req: &HttpRequest) -> Vec<String> {
+        if is_forwarded(req) {
+            return vec![];
+        }
+        self.models.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// The peer serving `model`. Forwarded requests are never forwarded again so peers can't loop.
+    pub async fn peer_for(&self, model: &str, req: &HttpRequest) -> Option<String> {
+        if self.is_empty() || is_forwarded(req) {
+            return None;
+        }
+        if let Some(peer) = self.models.lock().unwrap().get(model) {
+            return Some(peer.clone());
+        }
+        // 可能是剛在別台載入的新模型，但不要每個請求都去問
+        if !self.miss_refresh_due() {
+            return None;
+        }
+        self.refresh().await;
+        self.models.lock().unwrap().get(model).cloned()
+    }
+
+    /// Forward a JSON request to `peer` and stream its response back untouched.
+    pub async fn forward<T: Serialize>(&self, peer: &str, path: &str, body: &T) -> HttpResponse {
+        log::info!("Forwarding {} to peer {}", path, peer);
+        let response = match self
+            .client
+            .post(format!("{}{}", peer, path))
+            .header(FORWARDED_HEADER, "1")
+            .json(body)
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                return HttpResponse::BadGateway().json(OpenAiError {
+                    message: format!("Peer {} is unreachable: {}", peer, e),
+                    code: "peer_unreachable".to_owned(),
+                    r#type: "internal_error".to_owned(),
+                    param: None,
+                })
+            }
+        };
+
+        let status =
+            StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+        let mut builder = HttpResponse::build(status);
+        if let Some(content_type) = response.headers().get(reqwest::header::CONTENT_TYPE) {
+            if let Ok(content_type) = content_type.to_str() {
+                builder.content_type(content_type);
+            }
+        }
+        builder.streaming(
+            response
+                .bytes_stream()
+                .map(|chunk| chunk.map_err(actix_web::error::ErrorBadGateway)),
+        )
+    }
+}
+
+fn is_forwarded(req: &HttpRequest) -> bool {
+    req.headers().contains_key(FORWARDED_HEADER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn forwarded_requests_are_not_forwarded_again() {
+        let cluster = Cluster::new(vec!["http://10.0.0.2:8080/".to_owned()]);
+        assert_eq!(cluster.peers[0], "http://10.0.0.2:8080");
+        cluster
+            .models
+            .lock()
+            .unwrap()
+            .insert("qwen".to_owned(), cluster.peers[0].clone());
+
+        let req = actix_web::test::TestRequest::default().to_http_request();
+        assert_eq!(
+            cluster.peer_for("qwen", &req).await.as_deref(),
+            Some("http://10.0.0.2:8080")
+        );
+        let forwarded = actix_web::test::TestRequest::default()
+            .insert_header((FORWARDED_HEADER, "1"))
+            .to_http_request();
+        assert_eq!(cluster.peer_for("qwen", &forwarded).await, None);
+        assert!(cluster.peer_models(&forwarded).is_empty());
+    }
+
+    #[test]
+    fn misses_refresh_at_most_every_few_seconds() {
+        let cluster = Cluster::new(vec!["http://10.0.0.2:8080".to_owned()]);
+        assert!(cluster.miss_refresh_due());
+        assert!(!cluster.miss_refresh_due());
+        *cluster.miss_refreshed.lock().unwrap() = Some(Instant::now() - MISS_REFRESH_INTERVAL);
+        assert!(cluster.miss_refresh_due());
+    }
+}
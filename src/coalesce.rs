@@ -0,0 +1,111 @@
+use std::{
+    pin::Pin,
+    time::{Duration, Instant},
+};
+
+use actix_web::{web, HttpRequest};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+/// Merge tokens into fewer SSE events. Set for the server with `--coalesce-ms` and
+/// `--coalesce-tokens`, or per request with `coalesce`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct Coalesce {
+    /// Send what was buffered at least this often, 0 disables the window
+    #[serde(default)]
+    pub window_ms: u64,
+    /// Send once this many tokens are buffered
+    pub max_tokens: Option<usize>,
+}
+
+impl Coalesce {
+    pub fn is_enabled(&self) -> bool {
+        self.window_ms > 0 || self.max_tokens.is_some_and(|n| n > 1)
+    }
+
+    /// The request's setting, otherwise the server's.
+    pub fn resolve(req: &HttpRequest, requested: Option<Coalesce>) -> Coalesce {
+        requested
+            .or_else(|| req.app_data::<web::Data<Coalesce>>().map(|c| *c.get_ref()))
+            .unwrap_or_default()
+    }
+}
+
+/// Buffer the text stream of a model. The final `""` and the end of the stream flush right away.
+pub fn coalesce<S>(mut tokens: S, settings: Coalesce) -> Pin<Box<dyn Stream<Item = String> + Send>>
+where
+    S: Stream<Item = String> + Unpin + Send + 'static,
+{
+    if !settings.is_enabled() {
+        return Box::pin(tokens);
+    }
+    let window = Duration::from_millis(settings.window_ms);
+    Box::pin(async_stream::stream! {
+        let mut text = String::new();
+        let mut count = 0;
+        let mut deadline: Option<Instant> = None;
+        loop {
+            let next = match deadline {
+                Some(at) => {
+                    let wait = at.saturating_duration_since(Instant::now());
+                    match actix_web::rt::time::timeout(wait, tokens.next()).await {
+                        Ok(next) => next,
+                        Err(_) => {
+                            // 時間到，先把手上的送出去
+                            yield std::mem::take(&mut text);
+                            count = 0;
+                            deadline = None;
+                            continue;
+                        }
+                    }
+                }
+                None => tokens.next().await,
+            };
+            match next {
+                Some(token) if !token.is_empty() => {
+                    if text.is_empty() && !window.is_zero() {
+                        deadline = Some(Instant::now() + window);
+                    }
+                    text.push_str(&token);
+                    count += 1;
+                    if settings.max_tokens.is_some_and(|n| count >= n) {
+                        yield std::mem::take(&mut text);
+                        count = 0;
+                        deadline = None;
+                    }
+                }
+                next => {
+                    if !text.is_empty() {
+                        yield std::mem::take(&mut text);
+                    }
+                    count = 0;
+                    deadline = None;
+                    match next {
+                        Some(end) => yield end,
+                        None => break,
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn merges_tokens() {
+        let tokens = futures::stream::iter(["你", "好", "嗎", "？", "!", ""].map(str::to_owned));
+        let settings = Coalesce {
+            window_ms: 0,
+            max_tokens: Some(2),
+        };
+        let merged: Vec<String> = coalesce(tokens, settings).collect().await;
+        assert_eq!(merged, ["你好", "嗎？", "!", ""]);
+
+        let tokens = futures::stream::iter(["a", "b"].map(str::to_owned));
+        let merged: Vec<String> = coalesce(tokens, Coalesce::default()).collect().await;
+        assert_eq!(merged, ["a", "b"]);
+    }
+}
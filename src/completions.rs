@@ -0,0 +1,389 @@
+use std::collections::{BTreeMap, HashMap};
+
+use actix_web::{post, web, HttpResponse, Responder};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    chat::{sse_frame, ChatCompletionsRequest, Stop, Usage, SSE_DONE},
+    utils::ModelConfig,
+    ws::{chunk_text, sse_data, ChatContext},
+    Content, Message, OpenAiError, Role,
+};
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(untagged)]
+pub enum Prompt {
+    String(String),
+    Array(Vec<String>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[schema(example = json!({
+    "model": "DeepSeek-R1-Distill-Qwen-1.5B",
+    "prompt": "Say this is a test",
+    "max_tokens": 16
+}))]
+pub struct CompletionsRequest {
+    pub model: String,
+    pub prompt: Prompt,
+    pub max_tokens: Option<i32>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub n: Option<i32>,
+    /// Generate this many completions and return the one with the highest log probability
+    pub best_of: Option<i32>,
+    #[serde(default)]
+    pub stream: bool,
+    pub stop: Option<Stop>,
+    /// Put the prompt in front of the completion
+    #[serde(default)]
+    pub echo: bool,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    pub seed: Option<i32>,
+    pub user: Option<String>,
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct CompletionChoice {
+    pub text: String,
+    pub index: usize,
+    #[schema(value_type = Option<String>)]
+    pub logprobs: Option<Value>,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, utoipa::ToSchema)]
+pub struct CompletionsResponse {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<CompletionChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+fn invalid(message: &str, param: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(OpenAiError {
+        message: message.to_owned(),
+        code: "invalid_request_error".to_owned(),
+        r#type: "invalid_request_error".to_owned(),
+        param: Some(param.to_owned()),
+    })
+}
+
+impl CompletionsRequest {
+    /// 不支援的參數先擋下來，不要默默忽略。`config` 是本機的設定，別台的模型自己檢查
+    fn validate(&self, config: Option<&ModelConfig>) -> Result<String, HttpResponse> {
+        if self.n.is_some_and(|n| n > 1) {
+            return Err(invalid("Only n = 1 is supported.", "n"));
+        }
+        match self.best_of.unwrap_or(1) {
+            n if n < 1 || n as usize > crate::choices::MAX_CHOICES => {
+                return Err(invalid(
+                    &format!(
+                        "best_of must be between 1 and {}.",
+                        crate::choices::MAX_CHOICES
+                    ),
+                    "best_of",
+                ))
+            }
+            n if n > 1 && self.stream => {
+                return Err(invalid("best_of cannot be used with stream.", "best_of"))
+            }
+            // 沒有 logprobs 就沒辦法挑
+            n if n > 1 && config.is_some_and(|config| !crate::logprobs::supported(config)) => {
+                return Err(invalid(
+                    &format!(
+                        "Model \"{}\" returns no logprobs, best_of needs them to pick a completion.",
+                        self.model
+                    ),
+                    "best_of",
+                ))
+            }
+            _ => {}
+        }
+        match &self.prompt {
+            Prompt::String(prompt) => Ok(prompt.clone()),
+            Prompt::Array(prompts) if prompts.len() == 1 => Ok(prompts[0].clone()),
+            Prompt::Array(_) => Err(invalid("Exactly one prompt is supported.", "prompt")),
+        }
+    }
+
+    fn best_of(&self) -> usize {
+        self.best_of.unwrap_or(1).max(1) as usize
+    }
+
+    fn into_chat_request(self, prompt: String) -> ChatCompletionsRequest {
+        // best_of 要比較每個候選的 logprob
+        let logprobs = (self.best_of() > 1).then_some(true);
+        // 跟 TGI 一樣，prompt 會經過模型的 chat template
+        crate::chat::streamed(ChatCompletionsRequest {
+            model: self.model,
+            messages: vec![Message {
+                role: Some(Role::User),
+                content: Some(Content::String(prompt)),
+                ..Default::default()
+            }],
+            max_tokens: self.max_tokens,
+            temperature: self.temperature,
+            top_p: self.top_p,
+            stop: self.stop,
+            presence_penalty: self.presence_penalty,
+            frequency_penalty: self.frequency_penalty,
+            seed: self.seed,
+            logprobs,
+            user: self.user,
+            metadata: self.metadata,
+            ..Default::default()
+        })
+    }
+}
+
+/// A chat completion chunk as a `text_completion` chunk, `None` for anything else.
+fn text_chunk(data: &str) -> Option<Value> {
+    let chunk: Value = serde_json::from_str(data).ok()?;
+    let choice = chunk["choices"].get(0)?;
+    Some(json!({
+        "id": chunk["id"].as_str()?.replacen("chatcmpl-", "cmpl-", 1),
+        "object": "text_completion",
+        "created": chunk["created"],
+        "model": chunk["model"],
+        "choices": [{
+            "text": choice["delta"]["content"].as_str().unwrap_or_default(),
+            "index": 0,
+            "logprobs": null,
+            "finish_reason": choice["finish_reason"],
+        }],
+    }))
+}
+
+/// One generated completion, `best_of` makes several.
+#[derive(Debug)]
+struct Candidate {
+    text: String,
+    finish_reason: String,
+    /// Summed log probability of the sampled tokens
+    logprob: f64,
+}
+
+impl Default for Candidate {
+    fn default() -> Self {
+        Self {
+            text: String::new(),
+            finish_reason: "stop".to_owned(),
+            logprob: 0.0,
+        }
+    }
+}
+
+impl Candidate {
+    fn push(&mut self, choice: &Value) {
+        if choice["delta"]["role"] != "system" {
+            let text = choice["delta"]["content"].as_str().unwrap_or_default();
+            self.text.push_str(text);
+        }
+        if let Some(reason) = choice["finish_reason"].as_str() {
+            self.finish_reason = reason.to_owned();
+        }
+        let entries = choice["logprobs"]["content"]
+            .as_array()
+            .into_iter()
+            .flatten();
+        self.logprob += entries.filter_map(|e| e["logprob"].as_f64()).sum::<f64>();
+    }
+}
+
+/// The completion with the highest log probability, the first one on ties.
+fn best(candidates: BTreeMap<u64, Candidate>) -> Candidate {
+    candidates
+        .into_values()
+        .reduce(|best, c| if c.logprob > best.logprob { c } else { best })
+        .unwrap_or_default()
+}
+
+/// Legacy text completions, for SDKs and tools that predate the chat API.
+#[utoipa::path(
+    request_body = CompletionsRequest,
+    responses(
+        (status = OK, description = "Success", body = CompletionsResponse, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/completions")]
+pub async fn completions(body: web::Json<CompletionsRequest>, ctx: ChatContext) -> impl Responder {
+    let body = body.into_inner();
+    let prompt = match body.validate(ctx.all_configs.get(&body.model)) {
+        Ok(prompt) => prompt,
+        Err(response) => return response,
+    };
+    let (stream, echo, best_of) = (body.stream, body.echo, body.best_of());
+    let model = body.model.clone();
+    let chat = body.into_chat_request(prompt.clone());
+    let response = if best_of > 1 {
+        // 一個接一個生成，usage 是全部加總
+        crate::choices::generate(ctx, chat, best_of).await
+    } else {
+        ctx.chat(chat).await
+    };
+    if !response.status().is_success() {
+        return response;
+    }
+    let mut chunks = Box::pin(sse_data(response));
+
+    if stream {
+        let events = async_stream::stream! {
+            let mut echoed = !echo;
+            while let Some(data) = chunks.next().await {
+                if let Some(usage) = crate::usage::from_chunk(&data) {
+                    let id = format!("cmpl-{:016x}", rand::random::<u64>());
+                    let chunk = json!({ "id": id, "object": "text_completion", "choices": [], "usage": usage });
+                    yield Ok::<_, actix_web::Error>(web::Bytes::from(format!("data: {}\n\n", chunk)));
+                    continue;
+                }
+                let frame = match text_chunk(&data) {
+                    Some(mut chunk) => {
+                        if !echoed {
+                            let text = chunk["choices"][0]["text"].as_str().unwrap_or_default();
+                            chunk["choices"][0]["text"] = format!("{}{}", prompt, text).into();
+                            echoed = true;
+                        }
+                        format!("data: {}\n\n", chunk)
+                    }
+                    // 進度跟錯誤照原樣轉送
+                    None => sse_frame(&data),
+                };
+                yield Ok::<_, actix_web::Error>(web::Bytes::from(frame));
+            }
+            yield Ok(web::Bytes::from(SSE_DONE));
+        };
+        return HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .streaming(events);
+    }
+
+    let mut id = None;
+    let mut created = 0;
+    let mut usage = None;
+    let mut candidates: BTreeMap<u64, Candidate> = BTreeMap::new();
+    while let Some(data) = chunks.next().await {
+        usage = crate::usage::from_chunk(&data).or(usage);
+        match chunk_text(&data) {
+            Ok(_) => {}
+            Err(e) => {
+                return HttpResponse::InternalServerError().json(OpenAiError {
+                    message: e,
+                    code: "processing_error".to_owned(),
+                    r#type: "internal_error".to_owned(),
+                    param: None,
+                })
+            }
+        }
+        let Ok(chunk) = serde_json::from_str::<Value>(&data) else {
+            continue;
+        };
+        for choice in chunk["choices"].as_array().into_iter().flatten() {
+            let index = choice["index"].as_u64().unwrap_or_default();
+            candidates.entry(index).or_default().push(choice);
+        }
+        let Some(chunk) = text_chunk(&data) else {
+            continue;
+        };
+        if id.is_none() {
+            id = chunk["id"].as_str().map(str::to_owned);
+            created = chunk["created"].as_u64().unwrap_or_default();
+        }
+    }
+    let best = best(candidates);
+    let text = if echo {
+        format!("{}{}", prompt, best.text)
+    } else {
+        best.text
+    };
+    HttpResponse::Ok().json(CompletionsResponse {
+        id: id.unwrap_or_else(|| format!("cmpl-{:016x}", rand::random::<u64>())),
+        object: "text_completion".to_owned(),
+        created,
+        model,
+        choices: vec![CompletionChoice {
+            text,
+            index: 0,
+            logprobs: None,
+            finish_reason: Some(best.finish_reason),
+        }],
+        usage,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_chat_chunks() {
+        let body: CompletionsRequest = serde_json::from_value(json!({
+            "model": "qwen", "prompt": ["Say this is a test"], "max_tokens": 16, "echo": true
+        }))
+        .unwrap();
+        let prompt = body.validate(None).unwrap();
+        let chat = body.into_chat_request(prompt);
+        assert_eq!(chat.max_tokens, Some(16));
+        assert!(
+            matches!(&chat.messages[0].content, Some(Content::String(s)) if s == "Say this is a test")
+        );
+
+        let chunk = text_chunk(
+            r#"{"id":"chatcmpl-1","created":1,"model":"qwen","choices":[{"index":0,"delta":{"content":"Hi"},"finish_reason":null}]}"#,
+        )
+        .unwrap();
+        assert_eq!(chunk["id"], "cmpl-1");
+        assert_eq!(chunk["choices"][0]["text"], "Hi");
+        assert!(text_chunk(r#"{"event":{"type":"status"}}"#).is_none());
+
+        let request = |best_of: i32, stream: bool| {
+            serde_json::from_value::<CompletionsRequest>(json!({
+                "model": "qwen", "prompt": "a", "best_of": best_of, "stream": stream
+            }))
+            .unwrap()
+        };
+        assert!(request(3, true).validate(None).is_err());
+        assert!(request(9, false).validate(None).is_err());
+        let vlm = ModelConfig {
+            model_type: crate::utils::ModelType::VLM,
+            ..Default::default()
+        };
+        let error = request(3, false).validate(Some(&vlm)).unwrap_err();
+        assert_eq!(error.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        assert!(request(3, false)
+            .validate(Some(&ModelConfig::default()))
+            .is_ok());
+        let body = request(3, false);
+        assert!(body.validate(None).is_ok());
+        let chat = body.into_chat_request("a".to_owned());
+        assert_eq!(chat.logprobs, Some(true));
+    }
+
+    #[test]
+    fn best_of_keeps_the_most_likely() {
+        let mut candidates: BTreeMap<u64, Candidate> = BTreeMap::new();
+        let choices = [
+            json!({"index": 0, "delta": {"content": "Hi"}, "logprobs": {"content": [{"logprob": -0.5}]}}),
+            json!({"index": 0, "delta": {"content": "!"}, "logprobs": {"content": [{"logprob": -1.0}]}, "finish_reason": "length"}),
+            json!({"index": 1, "delta": {"content": "Hello"}, "logprobs": {"content": [{"logprob": -0.25}]}}),
+            json!({"index": 1, "delta": {}, "finish_reason": "stop"}),
+        ];
+        for choice in &choices {
+            let index = choice["index"].as_u64().unwrap();
+            candidates.entry(index).or_default().push(choice);
+        }
+        let best = best(candidates);
+        assert_eq!(best.text, "Hello");
+        assert_eq!(best.finish_reason, "stop");
+    }
+}
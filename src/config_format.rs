@@ -0,0 +1,22 @@
+//! Model configs in JSON, YAML or TOML, picked by the file extension.
+
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+
+/// Extensions of the files loaded from the config directory.
+pub const EXTENSIONS: [&str; 4] = ["json", "yaml", "yml", "toml"];
+
+pub fn is_config(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| EXTENSIONS.contains(&e))
+}
+
+pub fn parse<T: DeserializeOwned>(path: &Path, contents: &str) -> Result<T, String> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("yaml" | "yml") => serde_yaml::from_str(contents).map_err(|e| e.to_string()),
+        Some("toml") => toml::from_str(contents).map_err(|e| e.to_string()),
+        _ => serde_json::from_str(contents).map_err(|e| e.to_string()),
+    }
+}
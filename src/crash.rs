@@ -0,0 +1,237 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock, PoisonError,
+    },
+    time::{Instant, SystemTime},
+};
+
+use crate::{chat::LlmPool, sessions::Sessions};
+
+pub const CRASH_DIR: &str = "assets/crash";
+
+/// 由 build.rs 從 Cargo.lock 取出
+const LIBRARIES: [(&str, &str); 4] = [
+    ("llmserver-rs", env!("CARGO_PKG_VERSION")),
+    ("rkllm-rs", env!("RKLLM_RS_VERSION")),
+    ("rknn-rs", env!("RKNN_RS_VERSION")),
+    ("sensevoice-rs", env!("SENSEVOICE_RS_VERSION")),
+];
+
+struct CrashContext {
+    dir: PathBuf,
+    llm_pool: LlmPool,
+    sessions: Sessions,
+}
+
+#[derive(Debug)]
+struct InFlight {
+    method: String,
+    path: String,
+    started: Instant,
+}
+
+type Cleanup = Box<dyn Fn() + Send + Sync>;
+
+static CONTEXT: OnceLock<CrashContext> = OnceLock::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static IN_FLIGHT: Mutex<BTreeMap<u64, InFlight>> = Mutex::new(BTreeMap::new());
+static CLEANUPS: Mutex<BTreeMap<u64, Cleanup>> = Mutex::new(BTreeMap::new());
+
+/// A request being handled, removed from the crash report when dropped.
+#[derive(Debug)]
+pub struct RequestGuard(u64);
+
+impl Drop for RequestGuard {
+    // 在 panic 中 drop 時鎖可能已經 poisoned，再 panic 會直接 abort
+    fn drop(&mut self) {
+        IN_FLIGHT
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.0);
+    }
+}
+
+pub fn track(method: &str, path: &str) -> RequestGuard {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    IN_FLIGHT
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(
+            id,
+            InFlight {
+                method: method.to_owned(),
+                path: path.to_owned(),
+                started: Instant::now(),
+            },
+        );
+    RequestGuard(id)
+}
+
+/// Cleanup run by the panic hook while the model is alive, e.g. aborting rkllm so the NPU is
+/// left idle.
+#[derive(Debug)]
+pub struct CleanupGuard(u64);
+
+impl Drop for CleanupGuard {
+    fn drop(&mut self) {
+        CLEANUPS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.0);
+    }
+}
+
+pub fn on_crash(cleanup: impl Fn() + Send + Sync + 'static) -> CleanupGuard {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    CLEANUPS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(id, Box::new(cleanup));
+    CleanupGuard(id)
+}
+
+/// 共享函式庫的實際路徑，看得出 librkllmrt 的版本
+fn native_libraries() -> Vec<String> {
+    let maps = std::fs::read_to_string("/proc/self/maps").unwrap_or_default();
+    let mut libraries: Vec<String> = maps
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(5))
+        .filter(|path| path.contains("librkllmrt") || path.contains("librknnrt"))
+        .map(str::to_owned)
+        .collect();
+    libraries.dedup();
+    libraries
+}
+
+fn report(message: &str) -> String {
+    // panic 時可能正好有人拿著鎖，一律用 try_lock
+    let mut report = String::new();
+    let _ = writeln!(report, "{}\n", message);
+    let _ = writeln!(report, "[libraries]");
+    for (name, version) in LIBRARIES {
+        let _ = writeln!(report, "{} {}", name, version);
+    }
+    for path in native_libraries() {
+        let _ = writeln!(report, "{}", path);
+    }
+    if let Some(context) = CONTEXT.get() {
+        let _ = writeln!(report, "\n[active models]");
+        match context.llm_pool.try_lock() {
+            Ok(pool) => {
+                for model in pool.keys() {
+                    let _ = writeln!(report, "{}", model);
+                }
+            }
+            Err(_) => {
+                let _ = writeln!(report, "(model pool is locked)");
+            }
+        }
+        let _ = writeln!(report, "\n[chat sessions]");
+        for (model, count) in context.sessions.try_snapshot().unwrap_or_default() {
+            let _ = writeln!(report, "{} {}", model, count);
+        }
+    }
+    let _ = writeln!(report, "\n[in-flight requests]");
+    if let Ok(requests) = IN_FLIGHT.try_lock() {
+        for request in requests.values() {
+            let _ = writeln!(
+                report,
+                "{} {} ({:.1}s)",
+                request.method,
+                request.path,
+                request.started.elapsed().as_secs_f32()
+            );
+        }
+    }
+    let _ = writeln!(
+        report,
+        "\n[backtrace]\n{}",
+        std::backtrace::Backtrace::force_capture()
+    );
+    report
+}
+
+fn write_report(dir: &Path, message: &str) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let secs = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let path = dir.join(format!("crash-{}-{}.log", secs, std::process::id()));
+    std::fs::write(&path, report(message))?;
+    Ok(path)
+}
+
+/// Write a crash report to `dir` on panic, then abort running inference and continue with the
+/// default hook.
+pub fn install(dir: impl Into<PathBuf>, llm_pool: LlmPool, sessions: Sessions) {
+    let dir = dir.into();
+    if CONTEXT
+        .set(CrashContext {
+            dir,
+            llm_pool,
+            sessions,
+        })
+        .is_err()
+    {
+        return;
+    }
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let thread = std::thread::current();
+        let message = format!("thread '{}' {}", thread.name().unwrap_or("<unnamed>"), info);
+        if let Some(context) = CONTEXT.get() {
+            match write_report(&context.dir, &message) {
+                Ok(path) => eprintln!("Crash report written to {}", path.display()),
+                Err(e) => eprintln!("Failed to write crash report: {}", e),
+            }
+        }
+        if let Ok(cleanups) = CLEANUPS.try_lock() {
+            for cleanup in cleanups.values() {
+                cleanup();
+            }
+        }
+        default_hook(info);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_lists_requests() {
+        let dir = std::env::temp_dir().join(format!("llmserver-crash-{}", rand::random::<u32>()));
+        let request = track("POST", "/v1/chat/completions");
+        let path = write_report(&dir, "thread 'main' panicked at src/main.rs").unwrap();
+        let report = std::fs::read_to_string(&path).unwrap();
+        assert!(report.starts_with("thread 'main' panicked"));
+        assert!(report.contains("POST /v1/chat/completions"));
+        assert!(report.contains("rkllm-rs "));
+
+        drop(request);
+        assert!(!IN_FLIGHT
+            .lock()
+            .unwrap()
+            .values()
+            .any(|r| r.path == "/v1/chat/completions"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn guards_drop_on_poisoned_locks() {
+        let guard = on_crash(|| {});
+        let _ = std::thread::spawn(|| {
+            let _held = CLEANUPS.lock().unwrap();
+            panic!("poison");
+        })
+        .join();
+        assert!(CLEANUPS.is_poisoned());
+        drop(guard);
+        CLEANUPS.clear_poison();
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    llm::{
+        render_prompt,
+        simple::{load_bpe, load_tokenizer},
+    },
+    utils::{ModelConfig, ModelType},
+    Message, OpenAiError,
+};
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[schema(example = json!({
+    "model": "Qwen2.5-3B-abliterated",
+    "messages": [
+        { "role": "system", "content": "You are a helpful assistant." },
+        { "role": "user", "content": "Hello" }
+    ]
+}))]
+pub struct RenderRequest {
+    pub model: String,
+    pub messages: Vec<Message>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct RenderResponse {
+    pub model: String,
+    pub prompt: String,
+    /// `None` when the repository has no `tokenizer.json`
+    pub prompt_tokens: Option<usize>,
+}
+
+enum RenderError {
+    Tokenizer(String),
+    Template(String),
+}
+
+/// Render the chat template of a model without loading it.
+#[utoipa::path(
+    request_body = RenderRequest,
+    responses(
+        (status = OK, description = "Success", body = RenderResponse, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/render")]
+pub async fn render(
+    body: web::Json<RenderRequest>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+) -> impl Responder {
+    let body = body.into_inner();
+    let Some(config) = all_configs
+        .get(&body.model)
+        .filter(|c| matches!(c.model_type, ModelType::LLM | ModelType::VLM))
+        .cloned()
+    else {
+        return HttpResponse::BadRequest().json(OpenAiError {
+            message: format!("Model \"{}\" is not a chat template model.", body.model),
+            code: "model_not_found".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some("model".to_owned()),
+        });
+    };
+
+    // 下載 tokenizer 可能會花一點時間
+    let messages = body.messages;
+    let rendered = web::block(move || {
+        let atoken = load_tokenizer(&config).map_err(|e| RenderError::Tokenizer(e.to_string()))?;
+        let prompt = render_prompt(&atoken, &config, &messages).map_err(RenderError::Template)?;
+        let prompt_tokens = match load_bpe(&config) {
+            Ok(bpe) => Some(bpe.count(&prompt)),
+            Err(e) => {
+                log::warn!("Cannot count tokens of {}: {}", config.model_name, e);
+                None
+            }
+        };
+        Ok::<_, RenderError>((prompt, prompt_tokens))
+    })
+    .await;
+
+    match rendered {
+        Ok(Ok((prompt, prompt_tokens))) => HttpResponse::Ok().json(RenderResponse {
+            model: body.model,
+            prompt,
+            prompt_tokens,
+        }),
+        Ok(Err(RenderError::Template(e))) => {
+            HttpResponse::UnprocessableEntity().json(OpenAiError {
+                message: format!("Failed to apply chat template: {}", e),
+                code: "template_error".to_owned(),
+                r#type: "invalid_request_error".to_owned(),
+                param: Some("messages".to_owned()),
+            })
+        }
+        Ok(Err(RenderError::Tokenizer(e))) => {
+            HttpResponse::InternalServerError().json(OpenAiError {
+                message: format!("Failed to load tokenizer: {}", e),
+                code: "tokenizer_error".to_owned(),
+                r#type: "internal_error".to_owned(),
+                param: None,
+            })
+        }
+        Err(e) => HttpResponse::InternalServerError().json(OpenAiError {
+            message: e.to_string(),
+            code: "internal_error".to_owned(),
+            r#type: "internal_error".to_owned(),
+            param: None,
+        }),
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+/// CLIP byte-level BPE tokenizer (vocab.json + merges.txt) used by the Stable Diffusion text encoder.
+#[derive(Debug, Clone)]
+pub struct ClipTokenizer {
+    vocab: HashMap<String, i64>,
+    ranks: HashMap<(String, String), usize>,
+    byte_encoder: Vec<char>,
+    bos_id: i64,
+    eos_id: i64,
+}
+
+/// GPT-2 `bytes_to_unicode`，把每個 byte 對應到一個可見字元
+fn bytes_to_unicode() -> Vec<char> {
+    let mut printable: Vec<u32> = (b'!' as u32..=b'~' as u32)
+        .chain(0xA1..=0xAC)
+        .chain(0xAE..=0xFF)
+        .collect();
+    let mut chars = printable.clone();
+    let mut n = 0;
+    for b in 0..256_u32 {
+        if !printable.contains(&b) {
+            printable.push(b);
+            chars.push(256 + n);
+            n += 1;
+        }
+    }
+    let mut table = vec!['\0'; 256];
+    for (b, c) in printable.iter().zip(chars) {
+        table[*b as usize] = char::from_u32(c).unwrap();
+    }
+    table
+}
+
+impl ClipTokenizer {
+    pub fn from_files<P: AsRef<Path>>(
+        vocab_path: P,
+        merges_path: P,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let vocab: HashMap<String, i64> =
+            serde_json::from_str(&std::fs::read_to_string(vocab_path)?)?;
+        let merges = std::fs::read_to_string(merges_path)?;
+        Self::new(vocab, &merges)
+    }
+
+    pub fn new(
+        vocab: HashMap<String, i64>,
+        merges: &str,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let ranks = merges
+            .lines()
+            .filter(|line| !line.starts_with("#version") && !line.trim().is_empty())
+            .filter_map(|line| line.split_once(' '))
+            .enumerate()
+            .map(|(rank, (a, b))| ((a.to_owned(), b.to_owned()), rank))
+            .collect();
+        let id = |token: &str| {
+            vocab
+                .get(token)
+                .copied()
+                .ok_or_else(|| format!("Special token {} is missing in vocab", token))
+        };
+        Ok(Self {
+            bos_id: id("<|startoftext|>")?,
+            eos_id: id("<|endoftext|>")?,
+            vocab,
+            ranks,
+            byte_encoder: bytes_to_unicode(),
+        })
+    }
+
+    /// `<|startoftext|> tokens <|endoftext|>`，不足 `max_len` 的部分補 `<|endoftext|>`
+    pub fn encode(&self, text: &str, max_len: usize) -> Vec<i64> {
+        let mut ids = vec![self.bos_id];
+        ids.extend(
+            split_words(&text.to_lowercase())
+                .iter()
+                .flat_map(|word| self.bpe(word))
+                .take(max_len.saturating_sub(2)),
+        );
+        ids.push(self.eos_id);
+        ids.resize(max_len, self.eos_id);
+        ids
+    }
+
+    fn bpe(&self, word: &str) -> Vec<i64> {
+        let encoded: Vec<String> = word
+            .bytes()
+            .map(|b| self.byte_encoder[b as usize].to_string())
+            .collect();
+        let Some((last, init)) = encoded.split_last() else {
+            return vec![];
+        };
+        let mut parts: Vec<String> = init.to_vec();
+        parts.push(format!("{}</w>", last));
+
+        loop {
+            let best = parts
+                .windows(2)
+                .enumerate()
+                .filter_map(|(i, pair)| {
+                    self.ranks
+                        .get(&(pair[0].clone(), pair[1].clone()))
+                        .map(|rank| (*rank, i))
+                })
+                .min();
+            let Some((_, i)) = best else {
+                break;
+            };
+            let merged = format!("{}{}", parts[i], parts[i + 1]);
+            parts.splice(i..i + 2, [merged]);
+        }
+
+        parts
+            .iter()
+            .filter_map(|part| self.vocab.get(part).copied())
+            .collect()
+    }
+}
+
+/// 近似 CLIP 的 pre-tokenize：連續字母、單一數字、連續符號
+fn split_words(text: &str) -> Vec<String> {
+    #[derive(PartialEq)]
+    enum Kind {
+        Letter,
+        Other,
+    }
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut kind = Kind::Letter;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !current.is_empty() {
+                words.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+        if c.is_numeric() {
+            if !current.is_empty() {
+                words.push(std::mem::take(&mut current));
+            }
+            words.push(c.to_string());
+            continue;
+        }
+        let c_kind = if c.is_alphabetic() {
+            Kind::Letter
+        } else {
+            Kind::Other
+        };
+        if !current.is_empty() && c_kind != kind {
+            words.push(std::mem::take(&mut current));
+        }
+        kind = c_kind;
+        current.push(c);
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bpe_merges_and_pads() {
+        let vocab = [
+            "<|startoftext|>",
+            "<|endoftext|>",
+            "c",
+            "a",
+            "t</w>",
+            "ca",
+            "cat</w>",
+            "!</w>",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (t.to_string(), i as i64))
+        .collect();
+        let merges = "#version: 0.2\nc a\nca t</w>\n";
+        let tokenizer = ClipTokenizer::new(vocab, merges).unwrap();
+        assert_eq!(tokenizer.encode("Cat!", 6), vec![0, 6, 7, 1, 1, 1]);
+    }
+}
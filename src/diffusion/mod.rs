@@ -0,0 +1,3 @@
+pub mod clip;
+pub mod scheduler;
+pub mod simple;
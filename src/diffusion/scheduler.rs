@@ -0,0 +1,106 @@
+use rand::Rng;
+use rand_distr::StandardNormal;
+
+const NUM_TRAIN_TIMESTEPS: usize = 1000;
+const ORIGINAL_INFERENCE_STEPS: usize = 50;
+
+/// Latent Consistency Model scheduler, same defaults as diffusers `LCMScheduler` for SD 1.5.
+#[derive(Debug, Clone)]
+pub struct LcmScheduler {
+    alphas_cumprod: Vec<f32>,
+    pub timesteps: Vec<usize>,
+}
+
+impl LcmScheduler {
+    pub fn new(num_inference_steps: usize) -> Self {
+        // scaled_linear betas
+        let (start, end) = (0.00085_f64.sqrt(), 0.012_f64.sqrt());
+        let mut cumprod = 1.0_f64;
+        let alphas_cumprod = (0..NUM_TRAIN_TIMESTEPS)
+            .map(|i| {
+                let beta = start + (end - start) * i as f64 / (NUM_TRAIN_TIMESTEPS - 1) as f64;
+                cumprod *= 1.0 - beta * beta;
+                cumprod as f32
+            })
+            .collect();
+
+        let steps = num_inference_steps.clamp(1, ORIGINAL_INFERENCE_STEPS);
+        let c = NUM_TRAIN_TIMESTEPS / ORIGINAL_INFERENCE_STEPS;
+        // lcm_origin_timesteps 反轉後，以 linspace(0, 50, steps) 取樣
+        let timesteps = (0..steps)
+            .map(|k| k * ORIGINAL_INFERENCE_STEPS / steps)
+            .map(|index| (ORIGINAL_INFERENCE_STEPS - index) * c - 1)
+            .collect();
+        Self {
+            alphas_cumprod,
+            timesteps,
+        }
+    }
+
+    /// One denoising step, returns (prev_sample, denoised).
+    pub fn step<R: Rng>(
+        &self,
+        model_output: &[f32],
+        step_index: usize,
+        sample: &[f32],
+        rng: &mut R,
+    ) -> (Vec<f32>, Vec<f32>) {
+        let timestep = self.timesteps[step_index];
+        let alpha_prod_t = self.alphas_cumprod[timestep];
+        let alpha_prod_t_prev = self
+            .timesteps
+            .get(step_index + 1)
+            .map(|prev| self.alphas_cumprod[*prev])
+            .unwrap_or(1.0);
+
+        // boundary condition, sigma_data = 0.5, timestep_scaling = 10
+        let scaled = timestep as f32 * 10.0;
+        let c_skip = 0.25 / (scaled * scaled + 0.25);
+        let c_out = scaled / (scaled * scaled + 0.25).sqrt();
+
+        let denoised: Vec<f32> = sample
+            .iter()
+            .zip(model_output)
+            .map(|(x, eps)| {
+                let x0 = (x - (1.0 - alpha_prod_t).sqrt() * eps) / alpha_prod_t.sqrt();
+                c_out * x0 + c_skip * x
+            })
+            .collect();
+
+        let prev_sample = if step_index + 1 < self.timesteps.len() {
+            denoised
+                .iter()
+                .map(|d| {
+                    let noise: f32 = rng.sample(StandardNormal);
+                    alpha_prod_t_prev.sqrt() * d + (1.0 - alpha_prod_t_prev).sqrt() * noise
+                })
+                .collect()
+        } else {
+            denoised.clone()
+        };
+        (prev_sample, denoised)
+    }
+}
+
+/// LCM guidance scale embedding (`timestep_cond` of the UNet).
+pub fn guidance_scale_embedding(guidance_scale: f32, dim: usize) -> Vec<f32> {
+    let w = (guidance_scale - 1.0) * 1000.0;
+    let half = dim / 2;
+    let scale = (10000_f32).ln() / (half as f32 - 1.0);
+    let freqs: Vec<f32> = (0..half).map(|i| (-(i as f32) * scale).exp() * w).collect();
+    let mut emb: Vec<f32> = freqs.iter().map(|f| f.sin()).collect();
+    emb.extend(freqs.iter().map(|f| f.cos()));
+    emb.resize(dim, 0.0);
+    emb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lcm_timesteps_match_diffusers() {
+        assert_eq!(LcmScheduler::new(4).timesteps, vec![999, 759, 499, 259]);
+        assert_eq!(LcmScheduler::new(1).timesteps, vec![999]);
+    }
+}
@@ -0,0 +1,258 @@
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use actix::{Actor, ActorContext};
+use hf_hub::api::Progress;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::StandardNormal;
+use rknn_rs::prelude::{Rknn, RknnTensorAttr, RknnTensorFormat, RknnTensorType};
+
+use crate::diffusion::clip::ClipTokenizer;
+use crate::diffusion::scheduler::{guidance_scale_embedding, LcmScheduler};
+use crate::llm::simple::{fetch_file, render_local_path_template};
+use crate::utils::ModelConfig;
+use crate::{AIModel, Diffusion, ModelProgress, ProcessImages, ShutdownMessages};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+const VAE_SCALING_FACTOR: f32 = 0.18215;
+
+#[derive(Debug)]
+struct Graph {
+    rknn: Rknn,
+    inputs: Vec<RknnTensorAttr>,
+}
+
+impl Graph {
+    fn new(path: &Path) -> Result<Self, BoxError> {
+        let rknn = Rknn::new(path)?;
+        let inputs = rknn.input_attrs()?;
+        Ok(Self { rknn, inputs })
+    }
+
+    fn input_index(&self, name: &str) -> Option<usize> {
+        self.inputs.iter().position(|attr| attr.name == name)
+    }
+
+    fn input_dims(&self, name: &str) -> Option<&[u32]> {
+        self.input_index(name)
+            .map(|index| self.inputs[index].dims.as_slice())
+    }
+
+    fn set_f32(&self, index: usize, data: &[f32]) -> Result<(), BoxError> {
+        self.rknn.input_set_slice(
+            index,
+            data,
+            false,
+            RknnTensorType::Float32,
+            RknnTensorFormat::NCHW,
+        )?;
+        Ok(())
+    }
+
+    fn set_int(&self, index: usize, data: &[i64]) -> Result<(), BoxError> {
+        match self.inputs[index].type_ {
+            RknnTensorType::Int32 => {
+                let data: Vec<i32> = data.iter().map(|v| *v as i32).collect();
+                self.rknn.input_set_slice(
+                    index,
+                    &data,
+                    false,
+                    RknnTensorType::Int32,
+                    RknnTensorFormat::Undefined,
+                )?
+            }
+            _ => self.rknn.input_set_slice(
+                index,
+                data,
+                false,
+                RknnTensorType::Int64,
+                RknnTensorFormat::Undefined,
+            )?,
+        }
+        Ok(())
+    }
+
+    fn run(&self) -> Result<Vec<f32>, BoxError> {
+        self.rknn.run()?;
+        Ok(self.rknn.outputs_get::<f32>()?.to_vec())
+    }
+}
+
+/// Stable Diffusion 1.5 LCM pipeline, text encoder / UNet / VAE decoder are separated RKNN graphs.
+#[derive(Debug)]
+struct Pipeline {
+    text_encoder: Graph,
+    unet: Graph,
+    vae_decoder: Graph,
+    tokenizer: ClipTokenizer,
+    max_len: usize,
+    latent_shape: [usize; 4],
+    guidance_scale: f32,
+    default_steps: usize,
+}
+
+impl Pipeline {
+    fn generate(&self, prompt: &str, steps: usize, seed: u64) -> Result<Vec<u8>, BoxError> {
+        let ids = self.tokenizer.encode(prompt, self.max_len);
+        self.text_encoder.set_int(0, &ids)?;
+        let hidden_states = self.text_encoder.run()?;
+
+        let [_, channels, height, width] = self.latent_shape;
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut latents: Vec<f32> = (0..channels * height * width)
+            .map(|_| rng.sample(StandardNormal))
+            .collect();
+
+        let scheduler = LcmScheduler::new(steps);
+        let mut denoised = latents.clone();
+        for (step_index, timestep) in scheduler.timesteps.iter().enumerate() {
+            for (index, attr) in self.unet.inputs.iter().enumerate() {
+                match attr.name.as_str() {
+                    "sample" => self.unet.set_f32(index, &latents)?,
+                    "timestep" => self.unet.set_int(index, &[*timestep as i64])?,
+                    "encoder_hidden_states" => self.unet.set_f32(index, &hidden_states)?,
+                    "timestep_cond" => {
+                        let dim = attr.dims.last().copied().unwrap_or(256) as usize;
+                        self.unet
+                            .set_f32(index, &guidance_scale_embedding(self.guidance_scale, dim))?
+                    }
+                    name => return Err(format!("Unknown UNet input {}", name).into()),
+                }
+            }
+            let noise_pred = self.unet.run()?;
+            (latents, denoised) = scheduler.step(&noise_pred, step_index, &latents, &mut rng);
+        }
+
+        let scaled: Vec<f32> = denoised.iter().map(|v| v / VAE_SCALING_FACTOR).collect();
+        self.vae_decoder.set_f32(0, &scaled)?;
+        let decoded = self.vae_decoder.run()?;
+        to_png(&decoded, width * 8, height * 8)
+    }
+}
+
+/// NCHW [-1, 1] 轉成 PNG
+fn to_png(chw: &[f32], width: usize, height: usize) -> Result<Vec<u8>, BoxError> {
+    let plane = width * height;
+    if chw.len() < plane * 3 {
+        return Err(format!("VAE output too small: {}", chw.len()).into());
+    }
+    let pixels = (0..plane)
+        .flat_map(|i| (0..3).map(move |c| c * plane + i))
+        .map(|idx| ((chw[idx] / 2.0 + 0.5).clamp(0.0, 1.0) * 255.0).round() as u8)
+        .collect();
+    let image = image::RgbImage::from_raw(width as u32, height as u32, pixels)
+        .ok_or("Invalid image buffer")?;
+    let mut png = Cursor::new(Vec::new());
+    image.write_to(&mut png, image::ImageFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+pub struct SimpleDiffusion {
+    pipeline: Arc<Mutex<Pipeline>>,
+}
+
+impl Actor for SimpleDiffusion {
+    type Context = actix::Context<Self>;
+}
+
+impl actix::Handler<ProcessImages> for SimpleDiffusion {
+    type Result = actix::ResponseFuture<Result<Vec<Vec<u8>>, ()>>;
+
+    fn handle(&mut self, msg: ProcessImages, _ctx: &mut Self::Context) -> Self::Result {
+        let pipeline = self.pipeline.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                let pipeline = pipeline.lock().unwrap();
+                let steps = msg.steps.unwrap_or(pipeline.default_steps);
+                let seed = msg.seed.unwrap_or_else(rand::random);
+                (0..msg.n as u64)
+                    .map(|i| {
+                        let started = Instant::now();
+                        let png = pipeline.generate(&msg.prompt, steps, seed.wrapping_add(i));
+                        log::info!("Image generated in {:?}", started.elapsed());
+                        png
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| log::error!("Image generation failed: {}", e))
+            })
+            .await
+            .map_err(|e| log::error!("Image generation task failed: {}", e))?
+        })
+    }
+}
+
+impl actix::Handler<ShutdownMessages> for SimpleDiffusion {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, _: ShutdownMessages, ctx: &mut Self::Context) -> Self::Result {
+        let _guard = self.pipeline.lock().unwrap();
+        ctx.stop();
+        Ok(())
+    }
+}
+
+impl AIModel for SimpleDiffusion {
+    type Config = ModelConfig;
+
+    fn init_with_progress<P: Progress + ModelProgress + Clone>(
+        config: &Self::Config,
+        p: Option<P>,
+    ) -> Result<Self, BoxError> {
+        let diffusion = config.diffusion.clone().unwrap_or_default();
+        let file = |name: &str| render_local_path_template(name, config);
+        // UNet 最大，用它顯示下載進度
+        let (unet_path, progress) = fetch_file(config, &file(&diffusion.unet), p)?;
+        let (text_encoder_path, _) = fetch_file(config, &file(&diffusion.text_encoder), None::<P>)?;
+        let (vae_decoder_path, _) = fetch_file(config, &file(&diffusion.vae_decoder), None::<P>)?;
+        let (vocab_path, _) = fetch_file(config, &file(&diffusion.vocab), None::<P>)?;
+        let (merges_path, _) = fetch_file(config, &file(&diffusion.merges), None::<P>)?;
+
+        let mut progress = progress;
+        if let Some(progress) = progress.as_mut() {
+            let size = std::fs::metadata(&unet_path)?.len();
+            let filename = unet_path.file_name().unwrap().to_string_lossy();
+            progress.model_load(size.try_into().unwrap(), &filename, Instant::now());
+        }
+
+        let text_encoder = Graph::new(&text_encoder_path)
+            .map_err(|e| format!("Error initializing text encoder: {}", e))?;
+        let unet = Graph::new(&unet_path).map_err(|e| format!("Error initializing UNet: {}", e))?;
+        let vae_decoder = Graph::new(&vae_decoder_path)
+            .map_err(|e| format!("Error initializing VAE decoder: {}", e))?;
+        let tokenizer = ClipTokenizer::from_files(&vocab_path, &merges_path)?;
+
+        let max_len = text_encoder
+            .inputs
+            .first()
+            .and_then(|attr| attr.dims.last())
+            .map(|len| *len as usize)
+            .unwrap_or(77);
+        let latent_shape = match unet.input_dims("sample") {
+            Some([b, c, h, w]) => [*b as usize, *c as usize, *h as usize, *w as usize],
+            _ => return Err("UNet should have a [1, 4, h, w] sample input".into()),
+        };
+
+        if let Some(mut progress) = progress {
+            progress.model_finished();
+        }
+
+        Ok(Self {
+            pipeline: Arc::new(Mutex::new(Pipeline {
+                text_encoder,
+                unet,
+                vae_decoder,
+                tokenizer,
+                max_len,
+                latent_shape,
+                guidance_scale: diffusion.guidance_scale,
+                default_steps: diffusion.num_inference_steps,
+            })),
+        })
+    }
+}
+
+impl Diffusion for SimpleDiffusion {}
@@ -0,0 +1,196 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use crate::utils::ModelConfig;
+
+/// `owner/name`, the shape of a Hugging Face repository id.
+pub fn looks_like_repo(name: &str) -> bool {
+    let valid = |part: &str| {
+        !part.is_empty()
+            && !part.starts_with('.')
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+    matches!(name.split_once('/'), Some((owner, repo)) if valid(owner) && valid(repo))
+}
+
+/// The `.rkllm` file to load among the files of a repository, the first by name when there are
+/// several.
+fn pick_rkllm<'a>(files: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    let mut rkllm: Vec<&str> = files
+        .into_iter()
+        .filter(|file| file.ends_with(".rkllm"))
+        .collect();
+    rkllm.sort();
+    rkllm.first().map(|file| file.to_string())
+}
+
+/// Config of a repository without one in `assets/config`, with the defaults of a config file.
+fn config_for(repo: &str, model_path: String) -> Result<ModelConfig, String> {
+    serde_json::from_value(serde_json::json!({
+        "model_repo": repo,
+        "model_name": repo,
+        "model_type": "LLM",
+        "model_path": model_path,
+    }))
+    .map_err(|e| e.to_string())
+}
+
+/// `.rkllm` files of a repository already in the hf-hub cache, for offline mode.
+fn cached_files(config: &ModelConfig) -> Vec<String> {
+    let snapshots = crate::hub::cache(config)
+        .path()
+        .join(format!("models--{}", config.model_repo.replace('/', "--")))
+        .join("snapshots");
+    std::fs::read_dir(snapshots)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|snapshot| std::fs::read_dir(snapshot.path()).ok())
+        .flatten()
+        .flatten()
+        .map(|file| file.file_name().to_string_lossy().into_owned())
+        .collect()
+}
+
+fn discover(repo: &str) -> Result<ModelConfig, String> {
+    let probe = ModelConfig {
+        model_repo: repo.to_owned(),
+        ..Default::default()
+    };
+    let cached = cached_files(&probe);
+    let model_path = match pick_rkllm(cached.iter().map(String::as_str)) {
+        Some(file) => file,
+        None if crate::hub::offline() => {
+            return Err(format!(
+                "Model \"{}\" has no config and no .rkllm file in the hf-hub cache.",
+                repo
+            ))
+        }
+        None => {
+            let info = crate::hub::api(&probe)
+                .and_then(|api| api.model(repo.to_owned()).info())
+                .map_err(|e| format!("Model \"{}\" does not exist: {}", repo, e))?;
+            pick_rkllm(info.siblings.iter().map(|s| s.rfilename.as_str())).ok_or_else(|| {
+                format!(
+                    "Model \"{}\" has no config and the repository has no .rkllm file.",
+                    repo
+                )
+            })?
+        }
+    };
+    log::info!("Using {} of {} without a config", model_path, repo);
+    config_for(repo, model_path)
+}
+
+/// How long a repository that could not be used is not looked up again.
+const FAILED_TTL: Duration = Duration::from_secs(60);
+
+/// Configs made up for requests naming a Hugging Face repository that has no config file, so
+/// trying a model takes no JSON. Only repositories of the `--discover-owner` owners are used.
+#[derive(Debug, Clone, Default)]
+pub struct Discovered {
+    owners: Arc<Vec<String>>,
+    configs: Arc<RwLock<HashMap<String, ModelConfig>>>,
+    /// 查不到的 repo，短時間內不再問 hub
+    failed: Arc<Mutex<HashMap<String, (Instant, String)>>>,
+}
+
+impl Discovered {
+    pub fn new(owners: Vec<String>) -> Self {
+        Self {
+            owners: Arc::new(owners),
+            ..Default::default()
+        }
+    }
+
+    /// `repo` belongs to one of the owners, without owners nothing is discovered.
+    pub fn allows(&self, repo: &str) -> bool {
+        repo.split_once('/').is_some_and(|(owner, _)| {
+            self.owners
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(owner))
+        })
+    }
+
+    /// The config of `repo`, looking into the repository the first time.
+    pub async fn config(&self, repo: &str) -> Result<ModelConfig, String> {
+        if let Some(config) = self.configs.read().unwrap().get(repo) {
+            return Ok(config.clone());
+        }
+        if let Some((at, message)) = self.failed.lock().unwrap().get(repo) {
+            if at.elapsed() < FAILED_TTL {
+                return Err(message.clone());
+            }
+        }
+        let name = repo.to_owned();
+        let config = actix_web::web::block(move || discover(&name))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|r| r);
+        let config = match config {
+            Ok(config) => config,
+            Err(message) => {
+                let mut failed = self.failed.lock().unwrap();
+                failed.retain(|_, (at, _)| at.elapsed() < FAILED_TTL);
+                failed.insert(repo.to_owned(), (Instant::now(), message.clone()));
+                return Err(message);
+            }
+        };
+        self.failed.lock().unwrap().remove(repo);
+        self.configs
+            .write()
+            .unwrap()
+            .insert(repo.to_owned(), config.clone());
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configs_from_repo_ids() {
+        assert!(looks_like_repo("kautism/Qwen3-1.7B-RK3588"));
+        assert!(!looks_like_repo("qwen"));
+        assert!(!looks_like_repo("a/b/c"));
+        assert!(!looks_like_repo("../etc"));
+        assert_eq!(
+            pick_rkllm(["README.md", "qwen-w8a8.rkllm", "qwen-w4a16.rkllm"]),
+            Some("qwen-w4a16.rkllm".to_owned())
+        );
+        assert_eq!(pick_rkllm(["config.json"]), None);
+
+        let config = config_for("kautism/qwen", "qwen.rkllm".to_owned()).unwrap();
+        assert_eq!(config.model_name, "kautism/qwen");
+        assert_eq!(config.model_path.as_deref(), Some("qwen.rkllm"));
+        assert_eq!(config.max_context_len, 16384);
+    }
+
+    #[actix_web::test]
+    async fn only_allowed_owners_and_failures_are_cached() {
+        let discovered = Discovered::new(vec!["kautism".to_owned()]);
+        assert!(discovered.allows("kautism/Qwen3-1.7B-RK3588"));
+        assert!(discovered.allows("Kautism/qwen"));
+        assert!(!discovered.allows("someone/huge-model"));
+        assert!(!Discovered::default().allows("kautism/qwen"));
+
+        // 剛失敗過的 repo 直接回同一個錯誤，不再問 hub
+        discovered.failed.lock().unwrap().insert(
+            "kautism/missing".to_owned(),
+            (
+                Instant::now(),
+                "Model \"kautism/missing\" does not exist".to_owned(),
+            ),
+        );
+        assert_eq!(
+            discovered.config("kautism/missing").await.unwrap_err(),
+            "Model \"kautism/missing\" does not exist"
+        );
+    }
+}
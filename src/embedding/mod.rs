@@ -0,0 +1,2 @@
+pub mod simple;
+pub mod wordpiece;
\ No newline at end of file
@@ -0,0 +1,284 @@
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use actix::{Actor, ActorContext};
+use hf_hub::api::Progress;
+use rknn_rs::prelude::{Rknn, RknnTensorAttr, RknnTensorFormat, RknnTensorType};
+
+use crate::embedding::wordpiece::WordPiece;
+use crate::llm::simple::{fetch_file, render_local_path_template};
+use crate::utils::{ModelConfig, Pooling};
+use crate::{AIModel, Embedding, Embeddings, ModelProgress, ProcessEmbeddings, ShutdownMessages};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// bge/gte style text encoder converted to RKNN.
+///
+/// The RKNN graph has a fixed `[batch, seq_len]` input shape, inputs are padded to it.
+#[derive(Debug)]
+pub(crate) struct Encoder {
+    rknn: Rknn,
+    inputs: Vec<RknnTensorAttr>,
+    pub(crate) batch: usize,
+    pub(crate) seq_len: usize,
+    // [batch, seq_len, hidden] 要自己做 pooling，[batch, hidden] 代表模型已經 pooling 過
+    pooled: bool,
+    pub(crate) hidden: usize,
+}
+
+impl Encoder {
+    pub(crate) fn new(path: &std::path::Path) -> Result<Self, BoxError> {
+        let rknn = Rknn::new(path)?;
+        let inputs = rknn.input_attrs()?;
+        let (batch, seq_len) = match inputs.first().map(|a| a.dims.as_slice()) {
+            Some([batch, seq_len, ..]) => (*batch as usize, *seq_len as usize),
+            _ => return Err("Embedding model should have a [batch, seq_len] input".into()),
+        };
+        let outputs = rknn.output_attrs()?;
+        let (pooled, hidden) = match outputs.first().map(|a| a.dims.as_slice()) {
+            Some([_, _, hidden]) => (false, *hidden as usize),
+            Some([_, hidden]) => (true, *hidden as usize),
+            _ => return Err("Embedding model should output [batch, (seq_len,) hidden]".into()),
+        };
+        Ok(Self {
+            rknn,
+            inputs,
+            batch: batch.max(1),
+            seq_len,
+            pooled,
+            hidden,
+        })
+    }
+
+    fn set_input(&self, index: usize, data: &[i64]) -> Result<(), BoxError> {
+        match self.inputs[index].type_ {
+            RknnTensorType::Int32 => {
+                let data: Vec<i32> = data.iter().map(|v| *v as i32).collect();
+                self.rknn.input_set_slice(
+                    index,
+                    &data,
+                    false,
+                    RknnTensorType::Int32,
+                    RknnTensorFormat::Undefined,
+                )?;
+            }
+            _ => self.rknn.input_set_slice(
+                index,
+                data,
+                false,
+                RknnTensorType::Int64,
+                RknnTensorFormat::Undefined,
+            )?,
+        }
+        Ok(())
+    }
+
+    /// Run one padded batch and return the raw output, `ids.len()` must not exceed the model batch size.
+    pub(crate) fn run(
+        &self,
+        ids: &[Vec<i64>],
+        type_ids: Option<&[Vec<i64>]>,
+        pad_id: i64,
+    ) -> Result<Vec<f32>, BoxError> {
+        let mut input_ids = vec![pad_id; self.batch * self.seq_len];
+        let mut attention_mask = vec![0_i64; self.batch * self.seq_len];
+        let mut token_type_ids = vec![0_i64; self.batch * self.seq_len];
+        for (row, tokens) in ids.iter().enumerate() {
+            let offset = row * self.seq_len;
+            input_ids[offset..offset + tokens.len()].copy_from_slice(tokens);
+            attention_mask[offset..offset + tokens.len()].fill(1);
+            if let Some(types) = type_ids.and_then(|t| t.get(row)) {
+                token_type_ids[offset..offset + types.len()].copy_from_slice(types);
+            }
+        }
+
+        for (index, attr) in self.inputs.iter().enumerate() {
+            let data = match attr.name.as_str() {
+                name if name.contains("mask") => &attention_mask,
+                name if name.contains("type") => &token_type_ids,
+                name if name.contains("ids") => &input_ids,
+                // 沒有名字就照 BERT 的順序
+                _ => [&input_ids, &attention_mask, &token_type_ids][index.min(2)],
+            };
+            self.set_input(index, data)?;
+        }
+        self.rknn.run()?;
+        Ok(self.rknn.outputs_get::<f32>()?.to_vec())
+    }
+
+    fn forward(
+        &self,
+        ids: &[Vec<i64>],
+        pad_id: i64,
+        pooling: Pooling,
+    ) -> Result<Vec<Vec<f32>>, BoxError> {
+        let output = self.run(ids, None, pad_id)?;
+
+        Ok(ids
+            .iter()
+            .enumerate()
+            .map(|(row, tokens)| {
+                let vector = if self.pooled {
+                    output[row * self.hidden..(row + 1) * self.hidden].to_vec()
+                } else {
+                    let offset = row * self.seq_len * self.hidden;
+                    pool(
+                        &output[offset..offset + self.seq_len * self.hidden],
+                        tokens.len(),
+                        self.hidden,
+                        pooling,
+                    )
+                };
+                l2_normalize(vector)
+            })
+            .collect())
+    }
+}
+
+fn pool(hidden_states: &[f32], len: usize, hidden: usize, pooling: Pooling) -> Vec<f32> {
+    match pooling {
+        Pooling::Cls => hidden_states[..hidden].to_vec(),
+        Pooling::Mean => {
+            let mut sum = vec![0_f32; hidden];
+            for token in hidden_states[..len * hidden].chunks(hidden) {
+                sum.iter_mut().zip(token).for_each(|(s, v)| *s += v);
+            }
+            sum.iter_mut().for_each(|s| *s /= len.max(1) as f32);
+            sum
+        }
+    }
+}
+
+fn l2_normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > f32::EPSILON {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+    vector
+}
+
+pub struct SimpleEmbedding {
+    encoder: Arc<Mutex<Encoder>>,
+    tokenizer: Arc<WordPiece>,
+    pooling: Pooling,
+}
+
+impl Actor for SimpleEmbedding {
+    type Context = actix::Context<Self>;
+}
+
+impl actix::Handler<ProcessEmbeddings> for SimpleEmbedding {
+    type Result = actix::ResponseFuture<Result<Embeddings, ()>>;
+
+    fn handle(&mut self, msg: ProcessEmbeddings, _ctx: &mut Self::Context) -> Self::Result {
+        let encoder = self.encoder.clone();
+        let tokenizer = self.tokenizer.clone();
+        let pooling = self.pooling;
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                let encoder = encoder.lock().unwrap();
+                let ids: Vec<Vec<i64>> = msg
+                    .input
+                    .iter()
+                    .map(|text| tokenizer.encode(text, encoder.seq_len))
+                    .collect();
+                let prompt_tokens = ids.iter().map(|i| i.len()).sum();
+
+                let mut data = Vec::with_capacity(ids.len());
+                for batch in ids.chunks(encoder.batch) {
+                    match encoder.forward(batch, tokenizer.pad_id(), pooling) {
+                        Ok(vectors) => data.extend(vectors),
+                        Err(e) => {
+                            log::error!("Embedding inference failed: {}", e);
+                            return Err(());
+                        }
+                    }
+                }
+                Ok(Embeddings {
+                    data,
+                    prompt_tokens,
+                })
+            })
+            .await
+            .map_err(|e| log::error!("Embedding task failed: {}", e))?
+        })
+    }
+}
+
+impl actix::Handler<ShutdownMessages> for SimpleEmbedding {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, _: ShutdownMessages, ctx: &mut Self::Context) -> Self::Result {
+        let _guard = self.encoder.lock().unwrap();
+        ctx.stop();
+        Ok(())
+    }
+}
+
+impl AIModel for SimpleEmbedding {
+    type Config = ModelConfig;
+
+    fn init_with_progress<P: Progress + ModelProgress + Clone>(
+        config: &Self::Config,
+        p: Option<P>,
+    ) -> Result<Self, BoxError> {
+        let model_file = render_local_path_template(
+            config.model_path.as_deref().unwrap_or("model.rknn"),
+            config,
+        );
+        let (model_path, progress) = fetch_file(config, &model_file, p)?;
+        let (vocab_path, _) = fetch_file(
+            config,
+            config.vocab_path.as_deref().unwrap_or("vocab.txt"),
+            None::<P>,
+        )?;
+
+        let mut progress = progress;
+        if let Some(progress) = progress.as_mut() {
+            let size = std::fs::metadata(&model_path)?.len();
+            let filename = model_path.file_name().unwrap().to_string_lossy();
+            progress.model_load(size.try_into().unwrap(), &filename, Instant::now());
+        }
+
+        let encoder = Encoder::new(&model_path)
+            .map_err(|e| format!("Error initializing embedding model: {}", e))?;
+        let tokenizer = WordPiece::from_file(&vocab_path, true)?;
+        log::info!(
+            "Embedding model {} loaded, batch {}, seq_len {}",
+            config.model_name,
+            encoder.batch,
+            encoder.seq_len
+        );
+
+        if let Some(mut progress) = progress {
+            progress.model_finished();
+        }
+
+        Ok(Self {
+            encoder: Arc::new(Mutex::new(encoder)),
+            tokenizer: Arc::new(tokenizer),
+            pooling: config.pooling.unwrap_or_default(),
+        })
+    }
+}
+
+impl Embedding for SimpleEmbedding {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mean_pooling_ignores_padding() {
+        // 2 個 token + 1 個 padding，hidden = 2
+        let hidden_states = [1.0, 2.0, 3.0, 4.0, 100.0, 100.0];
+        assert_eq!(pool(&hidden_states, 2, 2, Pooling::Mean), vec![2.0, 3.0]);
+        assert_eq!(pool(&hidden_states, 2, 2, Pooling::Cls), vec![1.0, 2.0]);
+    }
+
+    #[test]
+    fn vectors_are_l2_normalized() {
+        assert_eq!(l2_normalize(vec![3.0, 4.0]), vec![0.6, 0.8]);
+        assert_eq!(l2_normalize(vec![0.0, 0.0]), vec![0.0, 0.0]);
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Minimal BERT WordPiece tokenizer (vocab.txt), enough for bge/gte style encoders.
+#[derive(Debug, Clone)]
+pub struct WordPiece {
+    vocab: HashMap<String, i64>,
+    unk_id: i64,
+    cls_id: i64,
+    sep_id: i64,
+    pad_id: i64,
+    lowercase: bool,
+}
+
+const MAX_CHARS_PER_WORD: usize = 100;
+
+impl WordPiece {
+    pub fn from_file<P: AsRef<Path>>(
+        path: P,
+        lowercase: bool,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let content = std::fs::read_to_string(path)?;
+        let vocab = content
+            .lines()
+            .enumerate()
+            .map(|(id, token)| (token.trim_end_matches('\r').to_owned(), id as i64))
+            .collect();
+        Self::from_vocab(vocab, lowercase)
+    }
+
+    pub fn from_vocab(
+        vocab: HashMap<String, i64>,
+        lowercase: bool,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let id = |token: &str| {
+            vocab
+                .get(token)
+                .copied()
+                .ok_or_else(|| format!("Special token {} is missing in vocab", token))
+        };
+        Ok(Self {
+            unk_id: id("[UNK]")?,
+            cls_id: id("[CLS]")?,
+            sep_id: id("[SEP]")?,
+            pad_id: id("[PAD]")?,
+            vocab,
+            lowercase,
+        })
+    }
+
+    pub fn pad_id(&self) -> i64 {
+        self.pad_id
+    }
+
+    /// `[CLS] tokens [SEP]`, truncated to `max_len`.
+    pub fn encode(&self, text: &str, max_len: usize) -> Vec<i64> {
+        let mut ids = vec![self.cls_id];
+        ids.extend(
+            self.split_words(text)
+                .iter()
+                .flat_map(|word| self.word_to_ids(word))
+                .take(max_len.saturating_sub(2)),
+        );
+        ids.push(self.sep_id);
+        ids
+    }
+
+    /// `[CLS] a [SEP] b [SEP]` with token type ids, the longer side is truncated first.
+    pub fn encode_pair(&self, a: &str, b: &str, max_len: usize) -> (Vec<i64>, Vec<i64>) {
+        let mut a_ids: Vec<i64> = self
+            .split_words(a)
+            .iter()
+            .flat_map(|word| self.word_to_ids(word))
+            .collect();
+        let mut b_ids: Vec<i64> = self
+            .split_words(b)
+            .iter()
+            .flat_map(|word| self.word_to_ids(word))
+            .collect();
+        let budget = max_len.saturating_sub(3);
+        while a_ids.len() + b_ids.len() > budget {
+            if a_ids.len() > b_ids.len() {
+                a_ids.pop();
+            } else {
+                b_ids.pop();
+            }
+        }
+
+        let mut ids = vec![self.cls_id];
+        ids.extend(a_ids);
+        ids.push(self.sep_id);
+        let first_len = ids.len();
+        ids.extend(b_ids);
+        ids.push(self.sep_id);
+        let type_ids = (0..ids.len())
+            .map(|i| if i < first_len { 0 } else { 1 })
+            .collect();
+        (ids, type_ids)
+    }
+
+    fn split_words(&self, text: &str) -> Vec<String> {
+        let mut words = Vec::new();
+        let mut current = String::new();
+        // TODO: Accents are not stripped, no unicode normalization table here.
+        let text = if self.lowercase {
+            text.to_lowercase()
+        } else {
+            text.to_owned()
+        };
+        for c in text.chars() {
+            if c.is_whitespace() || c.is_control() {
+                if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+            } else if c.is_ascii_punctuation() || is_cjk(c) || is_unicode_punctuation(c) {
+                if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+                words.push(c.to_string());
+            } else {
+                current.push(c);
+            }
+        }
+        if !current.is_empty() {
+            words.push(current);
+        }
+        words
+    }
+
+    fn word_to_ids(&self, word: &str) -> Vec<i64> {
+        let chars: Vec<char> = word.chars().collect();
+        if chars.len() > MAX_CHARS_PER_WORD {
+            return vec![self.unk_id];
+        }
+        let mut ids = Vec::new();
+        let mut start = 0;
+        while start < chars.len() {
+            let mut end = chars.len();
+            let mut found = None;
+            while start < end {
+                let piece: String = chars[start..end].iter().collect();
+                let piece = if start > 0 {
+                    format!("##{}", piece)
+                } else {
+                    piece
+                };
+                if let Some(id) = self.vocab.get(&piece) {
+                    found = Some(*id);
+                    break;
+                }
+                end -= 1;
+            }
+            match found {
+                Some(id) => ids.push(id),
+                None => return vec![self.unk_id],
+            }
+            start = end;
+        }
+        ids
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x4E00..=0x9FFF
+        | 0x3400..=0x4DBF
+        | 0x20000..=0x2A6DF
+        | 0x2A700..=0x2B73F
+        | 0x2B740..=0x2B81F
+        | 0x2B820..=0x2CEAF
+        | 0xF900..=0xFAFF
+        | 0x2F800..=0x2FA1F)
+}
+
+fn is_unicode_punctuation(c: char) -> bool {
+    matches!(c as u32,
+        0x2000..=0x206F // General Punctuation
+        | 0x3000..=0x303F // CJK Symbols and Punctuation
+        | 0xFF00..=0xFF0F
+        | 0xFF1A..=0xFF20
+        | 0xFF3B..=0xFF40
+        | 0xFF5B..=0xFF65)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> WordPiece {
+        let vocab = [
+            "[PAD]", "[UNK]", "[CLS]", "[SEP]", "hello", "world", "##s", ",", "你", "好",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (t.to_string(), i as i64))
+        .collect();
+        WordPiece::from_vocab(vocab, true).unwrap()
+    }
+
+    #[test]
+    fn encode_splits_wordpieces_and_cjk() {
+        let tokenizer = sample();
+        assert_eq!(
+            tokenizer.encode("Hello, worlds 你好", 512),
+            vec![2, 4, 7, 5, 6, 8, 9, 3]
+        );
+        assert_eq!(tokenizer.encode("helloX", 512), vec![2, 1, 3]);
+        assert_eq!(tokenizer.encode("hello world hello", 4), vec![2, 4, 5, 3]);
+    }
+
+    #[test]
+    fn encode_pair_sets_token_types() {
+        let tokenizer = sample();
+        let (ids, types) = tokenizer.encode_pair("hello", "world", 512);
+        assert_eq!(ids, vec![2, 4, 3, 5, 3]);
+        assert_eq!(types, vec![0, 0, 0, 1, 1]);
+    }
+}
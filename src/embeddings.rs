@@ -0,0 +1,217 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use actix::Recipient;
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cluster::Cluster,
+    embedding::simple::SimpleEmbedding,
+    errors::ApiError,
+    utils::{ModelConfig, ModelType},
+    Embeddings, OpenAiError, ProcessEmbeddings,
+};
+
+pub type EmbeddingPool = Arc<Mutex<HashMap<String, Recipient<ProcessEmbeddings>>>>;
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(untagged)]
+pub enum EmbeddingInput {
+    String(String),
+    Array(Vec<String>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct EmbeddingsRequest {
+    pub model: String,
+    pub input: EmbeddingInput,
+    /// `float` (default) or `base64`
+    #[serde(default)]
+    pub encoding_format: Option<String>,
+    /// Keep only the first dimensions, renormalized
+    #[serde(default)]
+    pub dimensions: Option<usize>,
+    #[serde(default)]
+    pub user: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+#[serde(untagged)]
+pub enum EmbeddingVector {
+    Float(Vec<f32>),
+    Base64(String),
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct EmbeddingData {
+    pub object: String,
+    pub embedding: EmbeddingVector,
+    pub index: usize,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct EmbeddingsUsage {
+    pub prompt_tokens: usize,
+    pub total_tokens: usize,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct EmbeddingsResponse {
+    pub object: String,
+    pub data: Vec<EmbeddingData>,
+    pub model: String,
+    pub usage: EmbeddingsUsage,
+}
+
+fn encode_base64(vector: &[f32]) -> String {
+    let bytes: Vec<u8> = vector.iter().flat_map(|v| v.to_le_bytes()).collect();
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+/// 截短後重新做 L2 normalize，長度不夠就原樣回傳
+fn truncate(mut vector: Vec<f32>, dimensions: Option<usize>) -> Vec<f32> {
+    let Some(dimensions) = dimensions.filter(|d| *d < vector.len()) else {
+        return vector;
+    };
+    vector.truncate(dimensions);
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+    vector
+}
+
+/// 取得 Embedding Actor，沒有的話就載入
+pub(crate) async fn get_or_load(
+    config: &ModelConfig,
+    embedding_pool: &EmbeddingPool,
+) -> Result<Recipient<ProcessEmbeddings>, String> {
+    crate::backends::get_or_load::<SimpleEmbedding, _>(config, embedding_pool).await
+}
+
+/// 載入模型並計算向量
+pub(crate) async fn embed(
+    config: &ModelConfig,
+    embedding_pool: &EmbeddingPool,
+    input: Vec<String>,
+) -> Result<Embeddings, ApiError> {
+    let recipient = get_or_load(config, embedding_pool)
+        .await
+        .map_err(ApiError::Internal)?;
+    let send_future = recipient.send(ProcessEmbeddings { input });
+    match actix_web::rt::time::timeout(Duration::from_secs(60), send_future).await {
+        Ok(Ok(Ok(result))) => Ok(result),
+        Ok(Ok(Err(_))) => Err(ApiError::internal("Embedding inference failed.")),
+        Ok(Err(e)) => Err(ApiError::internal(format!("Internal server error:{}", e))),
+        Err(_timeout) => Err(ApiError::busy("Server Busy.")),
+    }
+}
+
+#[utoipa::path(
+    request_body = EmbeddingsRequest,
+    responses(
+        (status = OK, description = "Success", body = EmbeddingsResponse, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/embeddings")]
+pub async fn embeddings(
+    req_body: web::Json<EmbeddingsRequest>,
+    embedding_pool: web::Data<EmbeddingPool>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    cluster: web::Data<Cluster>,
+    req: HttpRequest,
+) -> impl Responder {
+    if !all_configs.contains_key(&req_body.model) {
+        if let Some(peer) = cluster.peer_for(&req_body.model, &req).await {
+            return cluster.forward(&peer, "/v1/embeddings", &req_body.0).await;
+        }
+    }
+    let Some(config) = all_configs
+        .get(&req_body.model)
+        .filter(|c| c.model_type == ModelType::Embedding)
+    else {
+        return HttpResponse::BadRequest().json(OpenAiError {
+            message: format!(
+                "The model {} does not exist or is not an embedding model.",
+                req_body.model
+            ),
+            code: "model_not_found".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some("model".to_owned()),
+        });
+    };
+
+    let input = match &req_body.input {
+        EmbeddingInput::String(s) => vec![s.clone()],
+        EmbeddingInput::Array(arr) => arr.clone(),
+    };
+    if req_body.dimensions == Some(0) {
+        return HttpResponse::BadRequest().json(OpenAiError {
+            message: "dimensions must be at least 1.".to_owned(),
+            code: "invalid_request_error".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some("dimensions".to_owned()),
+        });
+    }
+    if input.is_empty() {
+        return HttpResponse::BadRequest().json(OpenAiError {
+            message: "Input should not be empty.".to_owned(),
+            code: "invalid_request_error".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some("input".to_owned()),
+        });
+    }
+
+    let result = match embed(config, &embedding_pool, input).await {
+        Ok(result) => result,
+        Err(e) => return e.response(),
+    };
+
+    let base64 = req_body.encoding_format.as_deref() == Some("base64");
+    HttpResponse::Ok().json(EmbeddingsResponse {
+        object: "list".to_owned(),
+        data: result
+            .data
+            .into_iter()
+            .enumerate()
+            .map(|(index, vector)| {
+                let vector = truncate(vector, req_body.dimensions);
+                EmbeddingData {
+                    object: "embedding".to_owned(),
+                    embedding: if base64 {
+                        EmbeddingVector::Base64(encode_base64(&vector))
+                    } else {
+                        EmbeddingVector::Float(vector)
+                    },
+                    index,
+                }
+            })
+            .collect(),
+        model: req_body.model.clone(),
+        usage: EmbeddingsUsage {
+            prompt_tokens: result.prompt_tokens,
+            total_tokens: result.prompt_tokens,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_and_renormalizes() {
+        let vector = vec![0.6, 0.0, 0.8];
+        assert_eq!(truncate(vector.clone(), None), vector);
+        assert_eq!(truncate(vector.clone(), Some(8)), vector);
+        assert_eq!(truncate(vector, Some(1)), [1.0]);
+    }
+}
@@ -0,0 +1,165 @@
+use actix_web::{
+    http::{header, StatusCode},
+    web::Bytes,
+    HttpResponse, HttpResponseBuilder,
+};
+
+use crate::OpenAiError;
+
+/// 排隊、逾時這種等一下就好的情況
+pub const BUSY_RETRY_AFTER_SECS: u64 = 5;
+/// 模型載入通常要幾十秒
+pub const LOADING_RETRY_AFTER_SECS: u64 = 30;
+
+/// Server side failures, each kind maps to the status code SDK retry logic expects.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiError {
+    /// No free slot right now: 429 with `Retry-After`.
+    Capacity {
+        message: String,
+        code: &'static str,
+        retry_after: u64,
+    },
+    /// The model is not ready yet: 503 with `Retry-After`.
+    Loading { message: String, retry_after: u64 },
+    /// A budget is used up, retrying won't help: 429 without `Retry-After`.
+    Quota(String),
+    /// 500
+    Internal(String),
+}
+
+impl ApiError {
+    pub fn busy(message: impl Into<String>) -> Self {
+        ApiError::Capacity {
+            message: message.into(),
+            code: "server_busy",
+            retry_after: BUSY_RETRY_AFTER_SECS,
+        }
+    }
+
+    pub fn loading(message: impl Into<String>) -> Self {
+        ApiError::Loading {
+            message: message.into(),
+            retry_after: LOADING_RETRY_AFTER_SECS,
+        }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        ApiError::Internal(message.into())
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::Capacity { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Loading { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Quota(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            ApiError::Capacity { retry_after, .. } | ApiError::Loading { retry_after, .. } => {
+                Some(*retry_after)
+            }
+            ApiError::Quota(_) | ApiError::Internal(_) => None,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::Capacity { message, .. }
+            | ApiError::Loading { message, .. }
+            | ApiError::Quota(message)
+            | ApiError::Internal(message) => message,
+        }
+    }
+
+    /// Status line and `Retry-After`, for routes with their own error body (Ollama, Anthropic).
+    pub fn builder(&self) -> HttpResponseBuilder {
+        let mut builder = HttpResponse::build(self.status());
+        if let Some(secs) = self.retry_after() {
+            builder.insert_header((header::RETRY_AFTER, secs.to_string()));
+        }
+        builder
+    }
+
+    pub fn body(&self) -> OpenAiError {
+        let (code, r#type) = match self {
+            ApiError::Capacity { code, .. } => (*code, "rate_limit_error"),
+            ApiError::Loading { .. } => ("model_loading", "service_unavailable"),
+            ApiError::Quota(_) => ("insufficient_quota", "insufficient_quota"),
+            ApiError::Internal(_) => ("processing_error", "internal_error"),
+        };
+        OpenAiError {
+            message: self.message().to_owned(),
+            code: code.to_owned(),
+            r#type: r#type.to_owned(),
+            param: None,
+        }
+    }
+
+    pub fn response(&self) -> HttpResponse {
+        self.builder().json(self.body())
+    }
+
+    /// `data: {"error": {...}}` for a stream that already started, the last event before it closes.
+    pub fn event(&self) -> Bytes {
+        let event = serde_json::json!({ "error": self.body() });
+        Bytes::from(format!("data: {}\n\n", event))
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+/// 轉發別的 handler 的錯誤時保留 `Retry-After`
+pub fn relay_status(response: &HttpResponse) -> HttpResponseBuilder {
+    let mut builder = HttpResponse::build(response.status());
+    if let Some(value) = response.headers().get(header::RETRY_AFTER) {
+        builder.insert_header((header::RETRY_AFTER, value.clone()));
+    }
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_mapping() {
+        let busy = ApiError::busy("Server Busy.").response();
+        assert_eq!(busy.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(busy.headers().get(header::RETRY_AFTER).unwrap(), "5");
+        assert_eq!(
+            relay_status(&busy)
+                .finish()
+                .headers()
+                .get(header::RETRY_AFTER)
+                .unwrap(),
+            "5"
+        );
+
+        let loading = ApiError::loading("loading").response();
+        assert_eq!(loading.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(loading.headers().get(header::RETRY_AFTER).unwrap(), "30");
+
+        let internal = ApiError::internal("boom").response();
+        assert_eq!(internal.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(internal.headers().get(header::RETRY_AFTER).is_none());
+
+        let event = ApiError::internal("boom").event();
+        let data = std::str::from_utf8(&event).unwrap();
+        let event: serde_json::Value =
+            serde_json::from_str(data.strip_prefix("data: ").unwrap().trim_end()).unwrap();
+        assert_eq!(event["error"]["message"], "boom");
+        assert_eq!(event["error"]["type"], "internal_error");
+        assert_eq!(
+            crate::ws::chunk_text(data.strip_prefix("data: ").unwrap()),
+            Err("boom".to_owned())
+        );
+    }
+}
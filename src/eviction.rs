@@ -0,0 +1,162 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::{
+    llm::simple::{cached_model_file, footprint},
+    utils::ModelConfig,
+};
+
+/// `--memory-budget`: models loaded at once may take this many bytes, the least recently used
+/// ones are unloaded to make room. Without a budget only one LLM stays loaded.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBudget {
+    limit: Option<u64>,
+    used: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl MemoryBudget {
+    pub fn new(limit_mb: Option<u64>) -> Self {
+        Self {
+            limit: limit_mb.map(|mb| mb * 1024 * 1024),
+            ..Default::default()
+        }
+    }
+
+    /// A request for the model came in.
+    pub fn touch(&self, model: &str) {
+        self.used
+            .lock()
+            .unwrap()
+            .insert(model.to_owned(), Instant::now());
+    }
+
+    /// The model was last used `ago`, before a restart.
+    pub fn restore(&self, model: &str, ago: std::time::Duration) {
+        let now = Instant::now();
+        self.used
+            .lock()
+            .unwrap()
+            .insert(model.to_owned(), now.checked_sub(ago).unwrap_or(now));
+    }
+
+    /// Loaded models to unload before `config` is loaded. The models still `loading` take their
+    /// room too, they just can't be unloaded yet.
+    pub(crate) fn evictions(
+        &self,
+        config: &ModelConfig,
+        loaded: Vec<String>,
+        loading: Vec<String>,
+        all_configs: &HashMap<String, ModelConfig>,
+    ) -> Vec<String> {
+        let Some(limit) = self.limit else {
+            return loaded;
+        };
+        let mut used = self.used.lock().unwrap();
+        used.retain(|model, _| {
+            loaded.contains(model) || loading.contains(model) || *model == config.model_name
+        });
+        let reserved = loading
+            .iter()
+            .filter(|model| **model != config.model_name)
+            .filter_map(|model| all_configs.get(model))
+            .map(footprint)
+            .sum();
+        let loaded = loaded
+            .into_iter()
+            .map(|model| {
+                let size = all_configs.get(&model).map(footprint).unwrap_or_default();
+                let last_used = used.get(&model).copied();
+                (model, size, last_used)
+            })
+            .collect();
+        // 還沒下載的話不知道多大
+        let needed = cached_model_file(config).map(|_| footprint(config));
+        let evicted = lru_victims(loaded, needed, reserved, limit);
+        if !evicted.is_empty() {
+            log::info!(
+                "Unloading {:?} to make room for {}",
+                evicted,
+                config.model_name
+            );
+        }
+        evicted
+    }
+}
+
+/// Least recently used first until `needed` more bytes fit in `limit` next to the `reserved` ones.
+/// Models never used since they were loaded (by the schedule, at startup) go first. Of a model
+/// with unknown size at least the least recently used one makes room.
+fn lru_victims(
+    mut loaded: Vec<(String, u64, Option<Instant>)>,
+    needed: Option<u64>,
+    reserved: u64,
+    limit: u64,
+) -> Vec<String> {
+    loaded.sort_by_key(|(_, _, last_used)| *last_used);
+    let mut total: u64 =
+        loaded.iter().map(|(_, size, _)| size).sum::<u64>() + reserved + needed.unwrap_or(0);
+    let mut unknown = needed.is_none();
+    loaded
+        .into_iter()
+        .take_while(|(_, size, _)| {
+            let over = total > limit || std::mem::take(&mut unknown);
+            if over {
+                total -= size;
+            }
+            over
+        })
+        .map(|(model, _, _)| model)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let now = Instant::now();
+        let loaded = || {
+            vec![
+                ("new".to_owned(), 300, Some(now + Duration::from_secs(2))),
+                ("old".to_owned(), 300, Some(now)),
+                ("warm".to_owned(), 200, None),
+            ]
+        };
+        assert!(lru_victims(loaded(), Some(100), 0, 1000).is_empty());
+        assert_eq!(
+            lru_victims(loaded(), Some(300), 0, 1000),
+            vec!["warm".to_owned()]
+        );
+        assert_eq!(
+            lru_victims(loaded(), Some(500), 0, 1000),
+            vec!["warm".to_owned(), "old".to_owned()]
+        );
+        // 比預算還大的模型就全部卸載
+        assert_eq!(lru_victims(loaded(), Some(2000), 0, 1000).len(), 3);
+        // 正在載入的也佔空間
+        assert_eq!(
+            lru_victims(loaded(), Some(100), 400, 1000),
+            vec!["warm".to_owned(), "old".to_owned()]
+        );
+        assert_eq!(
+            lru_victims(loaded(), None, 0, 1000),
+            vec!["warm".to_owned()]
+        );
+        assert_eq!(
+            lru_victims(loaded(), None, 0, 700),
+            vec!["warm".to_owned(), "old".to_owned()]
+        );
+
+        let budget = MemoryBudget::default();
+        let config = ModelConfig::default();
+        assert_eq!(
+            budget.evictions(&config, vec!["a".to_owned()], vec![], &HashMap::new()),
+            vec!["a".to_owned()]
+        );
+    }
+}
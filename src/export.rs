@@ -0,0 +1,269 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime},
+};
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    chat::ChatCompletionsRequest,
+    ws::{chunk_text, take_sse_events},
+};
+
+/// 一個檔案超過這個大小就換新檔
+pub const DEFAULT_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Timings {
+    pub total_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_token_ms: Option<u64>,
+}
+
+/// One line of the export.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportRecord {
+    /// `chat.completion` or `transcription`
+    pub r#type: &'static str,
+    pub id: String,
+    pub created: u64,
+    pub model: String,
+    /// Request messages, or the uploaded file name for transcriptions
+    pub prompt: Value,
+    pub output: String,
+    pub params: Value,
+    /// The request `metadata` tags
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<HashMap<String, String>>,
+    pub timings: Timings,
+}
+
+#[derive(Debug)]
+struct CurrentFile {
+    file: File,
+    size: u64,
+}
+
+/// Appends finished completions and transcriptions to `<dir>/export-<time>.jsonl`, starting a
+/// new file once `max_bytes` is reached.
+#[derive(Debug, Clone)]
+pub struct Exporter {
+    dir: PathBuf,
+    max_bytes: u64,
+    current: Arc<Mutex<Option<CurrentFile>>>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl Exporter {
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        Self {
+            dir: dir.into(),
+            max_bytes,
+            current: Default::default(),
+        }
+    }
+
+    fn open(&self) -> std::io::Result<CurrentFile> {
+        std::fs::create_dir_all(&self.dir)?;
+        let mut path = self.dir.join(format!("export-{}.jsonl", now_secs()));
+        // 同一秒內換檔就加序號
+        let mut n = 1;
+        while path.exists() {
+            path = self.dir.join(format!("export-{}-{}.jsonl", now_secs(), n));
+            n += 1;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(CurrentFile { file, size: 0 })
+    }
+
+    pub fn append(&self, record: &ExportRecord) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut current = self.current.lock().unwrap();
+        if current
+            .as_ref()
+            .is_none_or(|c| c.size > 0 && c.size + line.len() as u64 > self.max_bytes)
+        {
+            *current = Some(self.open()?);
+        }
+        let current = current.as_mut().unwrap();
+        current.file.write_all(&line)?;
+        current.size += line.len() as u64;
+        Ok(())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+pub(crate) fn from_request(req: &HttpRequest) -> Option<web::Data<Exporter>> {
+    req.app_data::<web::Data<Exporter>>().cloned()
+}
+
+/// Request parameters without the messages and metadata.
+fn chat_params(request: &ChatCompletionsRequest) -> Value {
+    let mut params = serde_json::to_value(request).unwrap_or_default();
+    if let Some(params) = params.as_object_mut() {
+        params.remove("messages");
+        params.remove("model");
+        params.remove("metadata");
+        params.retain(|_, v| !v.is_null());
+    }
+    params
+}
+
+/// Pass the SSE response through untouched and export the answer once it is complete.
+pub(crate) fn record_chat(
+    response: HttpResponse,
+    exporter: web::Data<Exporter>,
+    request: ChatCompletionsRequest,
+) -> HttpResponse {
+    if !response.status().is_success() {
+        return response;
+    }
+    let mut builder = HttpResponse::build(response.status());
+    for (name, value) in response.headers() {
+        builder.insert_header((name.clone(), value.clone()));
+    }
+    let mut body = Box::pin(response.into_body());
+    let created = now_secs();
+    let started = Instant::now();
+
+    let stream = async_stream::stream! {
+        let mut buf = Vec::new();
+        let mut id = None;
+        let mut model = request.model.clone();
+        let mut output = String::new();
+        let mut first_token_ms = None;
+        while let Some(chunk) = futures::future::poll_fn(|cx| {
+            actix_web::body::MessageBody::poll_next(body.as_mut(), cx)
+        })
+        .await
+        {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    yield Err(actix_web::error::ErrorInternalServerError(e.to_string()));
+                    return;
+                }
+            };
+            buf.extend_from_slice(&chunk);
+            for data in take_sse_events(&mut buf) {
+                let Ok(parsed) = serde_json::from_str::<Value>(&data) else {
+                    continue;
+                };
+                if let (None, Some(chunk_id)) = (&id, parsed["id"].as_str()) {
+                    id = Some(chunk_id.to_owned());
+                }
+                if let Some(chunk_model) = parsed["model"].as_str() {
+                    model = chunk_model.to_owned();
+                }
+                if let Ok(Some(delta)) = chunk_text(&data) {
+                    first_token_ms.get_or_insert(started.elapsed().as_millis() as u64);
+                    output.push_str(&delta);
+                }
+            }
+            yield Ok(chunk);
+        }
+
+        if let Some(id) = id {
+            let record = ExportRecord {
+                r#type: "chat.completion",
+                id,
+                created,
+                model,
+                prompt: serde_json::to_value(&request.messages).unwrap_or_default(),
+                output,
+                params: chat_params(&request),
+                metadata: request.metadata.clone(),
+                timings: Timings {
+                    total_ms: started.elapsed().as_millis() as u64,
+                    first_token_ms,
+                },
+            };
+            if let Err(e) = exporter.append(&record) {
+                log::error!("Failed to export completion {}: {}", record.id, e);
+            }
+        }
+    };
+    builder.streaming(stream)
+}
+
+pub(crate) fn record_transcription(
+    exporter: &Exporter,
+    model: &str,
+    filename: Option<&str>,
+    text: &str,
+    started: Instant,
+) {
+    let record = ExportRecord {
+        r#type: "transcription",
+        id: format!("transcription-{:016x}", rand::random::<u64>()),
+        created: now_secs(),
+        model: model.to_owned(),
+        prompt: filename.map(Value::from).unwrap_or_default(),
+        output: text.to_owned(),
+        params: Value::Object(Default::default()),
+        metadata: None,
+        timings: Timings {
+            total_ms: started.elapsed().as_millis() as u64,
+            first_token_ms: None,
+        },
+    };
+    if let Err(e) = exporter.append(&record) {
+        log::error!("Failed to export transcription: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_files() {
+        let dir = std::env::temp_dir().join(format!("llmserver-export-{}", rand::random::<u32>()));
+        let exporter = Exporter::new(&dir, 400);
+        for _ in 0..3 {
+            record_transcription(
+                &exporter,
+                "sensevoice",
+                Some("a.wav"),
+                "你好",
+                Instant::now(),
+            );
+        }
+        let mut files: Vec<_> = std::fs::read_dir(exporter.dir())
+            .unwrap()
+            .map(|e| std::fs::read_to_string(e.unwrap().path()).unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 2);
+        let line: Value = serde_json::from_str(files[0].lines().next().unwrap()).unwrap();
+        assert_eq!(line["type"], "transcription");
+        assert_eq!(line["prompt"], "a.wav");
+        assert_eq!(line["output"], "你好");
+
+        let request: ChatCompletionsRequest = serde_json::from_value(serde_json::json!({
+            "model": "qwen", "messages": [], "temperature": 0.2, "metadata": { "app": "kitchen-display" }
+        }))
+        .unwrap();
+        assert_eq!(
+            chat_params(&request),
+            serde_json::json!({ "stream": false, "temperature": 0.2f32 })
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
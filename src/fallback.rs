@@ -0,0 +1,150 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use actix_web::web;
+use futures::{Stream, StreamExt};
+
+use crate::utils::ModelConfig;
+
+/// Response header telling the client which model actually served the request.
+pub const FALLBACK_HEADER: &str = "X-Fallback-Model";
+
+/// 失敗過的模型在這段時間內直接走 fallback，不再重新載入
+const FAILURE_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// Models that recently failed to load or errored, keyed by model name.
+pub type FailedModels = Arc<Mutex<HashMap<String, Instant>>>;
+
+#[derive(Debug, Clone)]
+pub enum Route {
+    Local(Box<ModelConfig>),
+    /// OpenAI compatible `/chat/completions` endpoint of another server
+    Remote(String),
+}
+
+impl Route {
+    pub fn name(&self) -> &str {
+        match self {
+            Route::Local(config) => &config.model_name,
+            Route::Remote(url) => url,
+        }
+    }
+}
+
+/// The route to try after `config` failed, if it declares a fallback.
+pub fn fallback_of(
+    config: &ModelConfig,
+    all_configs: &HashMap<String, ModelConfig>,
+) -> Option<Route> {
+    let fallback = config.fallback.as_ref()?;
+    if fallback.starts_with("http://") || fallback.starts_with("https://") {
+        return Some(Route::Remote(fallback.clone()));
+    }
+    match all_configs.get(fallback) {
+        Some(config) => Some(Route::Local(Box::new(config.clone()))),
+        None => {
+            log::warn!(
+                "Fallback \"{}\" of model \"{}\" is not configured",
+                fallback,
+                config.model_name
+            );
+            None
+        }
+    }
+}
+
+pub fn mark_failed(failed: &FailedModels, model_name: &str) {
+    failed
+        .lock()
+        .unwrap()
+        .insert(model_name.to_owned(), Instant::now());
+}
+
+pub fn mark_healthy(failed: &FailedModels, model_name: &str) {
+    failed.lock().unwrap().remove(model_name);
+}
+
+/// Pick the first route to try, skipping models that failed within the cooldown.
+pub fn initial_route(
+    config: &ModelConfig,
+    all_configs: &HashMap<String, ModelConfig>,
+    failed: &FailedModels,
+) -> Route {
+    let recently_failed = failed
+        .lock()
+        .unwrap()
+        .get(&config.model_name)
+        .is_some_and(|at| at.elapsed() < FAILURE_COOLDOWN);
+    if recently_failed {
+        if let Some(route) = fallback_of(config, all_configs) {
+            return route;
+        }
+    }
+    Route::Local(Box::new(config.clone()))
+}
+
+/// Forward the request to a remote server and pass its SSE bytes through untouched.
+pub async fn proxy_chat(
+    url: &str,
+    body: serde_json::Value,
+) -> Result<impl Stream<Item = Result<web::Bytes, String>>, String> {
+    let response = reqwest::Client::new()
+        .post(url)
+        .json(&body)
+        .send()
+        .await
+        .map_err(|e| format!("Fallback request failed: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "Fallback server returned {}",
+            response.status().as_u16()
+        ));
+    }
+    Ok(response
+        .bytes_stream()
+        .map(|chunk| chunk.map_err(|e| format!("Fallback stream failed: {}", e))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(name: &str, fallback: Option<&str>) -> ModelConfig {
+        ModelConfig {
+            model_name: name.to_owned(),
+            fallback: fallback.map(str::to_owned),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn failed_primary_routes_to_fallback() {
+        let primary = config("big", Some("small"));
+        let all_configs = HashMap::from([
+            ("big".to_owned(), primary.clone()),
+            (
+                "small".to_owned(),
+                config("small", Some("http://peer:8080/v1/chat/completions")),
+            ),
+        ]);
+        let failed = FailedModels::default();
+        assert_eq!(initial_route(&primary, &all_configs, &failed).name(), "big");
+
+        mark_failed(&failed, "big");
+        let route = initial_route(&primary, &all_configs, &failed);
+        assert_eq!(route.name(), "small");
+        let Route::Local(small) = route else {
+            panic!("small should be a local route");
+        };
+        assert!(matches!(
+            fallback_of(&small, &all_configs),
+            Some(Route::Remote(_))
+        ));
+
+        mark_healthy(&failed, "big");
+        assert_eq!(initial_route(&primary, &all_configs, &failed).name(), "big");
+    }
+}
@@ -0,0 +1,294 @@
+use std::path::PathBuf;
+
+use actix_multipart::form::{tempfile::TempFile, text::Text, MultipartForm};
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::OpenAiError;
+
+pub const FILES_DIR: &str = "assets/files";
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct FileObject {
+    pub id: String,
+    pub object: String,
+    pub bytes: u64,
+    pub created_at: u64,
+    pub filename: String,
+    pub purpose: String,
+}
+
+/// 上傳的檔案，內容存成 `<id>`，描述存成 `<id>.json`
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn valid_id(id: &str) -> bool {
+        id.strip_prefix("file-")
+            .is_some_and(|hex| !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()))
+    }
+
+    pub fn create(
+        &self,
+        source: &std::path::Path,
+        filename: String,
+        purpose: String,
+    ) -> std::io::Result<FileObject> {
+        self.store(filename, purpose, |path| std::fs::copy(source, path))
+    }
+
+    /// A file holding `bytes`, e.g. the results of a batch.
+    pub fn create_bytes(
+        &self,
+        bytes: &[u8],
+        filename: String,
+        purpose: String,
+    ) -> std::io::Result<FileObject> {
+        self.store(filename, purpose, |path| {
+            std::fs::write(path, bytes).map(|_| bytes.len() as u64)
+        })
+    }
+
+    fn store(
+        &self,
+        filename: String,
+        purpose: String,
+        write: impl FnOnce(&std::path::Path) -> std::io::Result<u64>,
+    ) -> std::io::Result<FileObject> {
+        std::fs::create_dir_all(&self.dir)?;
+        let id = format!("file-{:016x}", rand::random::<u64>());
+        let bytes = write(&self.dir.join(&id))?;
+        let file = FileObject {
+            id,
+            object: "file".to_owned(),
+            bytes,
+            created_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            filename,
+            purpose,
+        };
+        std::fs::write(
+            self.dir.join(format!("{}.json", file.id)),
+            serde_json::to_vec(&file)?,
+        )?;
+        Ok(file)
+    }
+
+    pub fn get(&self, id: &str) -> Option<FileObject> {
+        if !Self::valid_id(id) {
+            return None;
+        }
+        let json = std::fs::read(self.dir.join(format!("{}.json", id))).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    pub fn content(&self, id: &str) -> Option<Vec<u8>> {
+        self.get(id)?;
+        std::fs::read(self.dir.join(id)).ok()
+    }
+
+    /// Where the content of a file is stored.
+    pub fn path(&self, id: &str) -> Option<PathBuf> {
+        self.get(id)?;
+        Some(self.dir.join(id))
+    }
+
+    /// 新的排前面
+    pub fn list(&self, purpose: Option<&str>) -> Vec<FileObject> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return vec![];
+        };
+        let mut files: Vec<FileObject> = entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != "json" {
+                    return None;
+                }
+                serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+            })
+            .filter(|file: &FileObject| purpose.is_none_or(|p| file.purpose == p))
+            .collect();
+        files.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(b.id.cmp(&a.id)));
+        files
+    }
+
+    pub fn delete(&self, id: &str) -> std::io::Result<bool> {
+        if self.get(id).is_none() {
+            return Ok(false);
+        }
+        std::fs::remove_file(self.dir.join(format!("{}.json", id)))?;
+        let _ = std::fs::remove_file(self.dir.join(id));
+        Ok(true)
+    }
+}
+
+fn not_found(id: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(OpenAiError {
+        message: format!("No such file: {}", id),
+        code: "file_not_found".to_owned(),
+        r#type: "invalid_request_error".to_owned(),
+        param: Some("file_id".to_owned()),
+    })
+}
+
+fn internal_error(e: std::io::Error) -> HttpResponse {
+    HttpResponse::InternalServerError().json(OpenAiError {
+        message: e.to_string(),
+        code: "processing_error".to_owned(),
+        r#type: "internal_error".to_owned(),
+        param: None,
+    })
+}
+
+#[derive(Debug, MultipartForm)]
+struct UploadForm {
+    file: TempFile,
+    purpose: Text<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct ListFiles {
+    pub object: String,
+    pub data: Vec<FileObject>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    pub purpose: Option<String>,
+}
+
+/// Upload a file.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", body = FileObject, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/files")]
+pub async fn upload(
+    form: MultipartForm<UploadForm>,
+    files: web::Data<FileStore>,
+) -> impl Responder {
+    let form = form.into_inner();
+    let filename = form
+        .file
+        .file_name
+        .clone()
+        .unwrap_or_else(|| "upload".to_owned());
+    match files.create(form.file.file.path(), filename, form.purpose.0) {
+        Ok(file) => HttpResponse::Ok().json(file),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// List uploaded files.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", body = ListFiles, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/files")]
+pub async fn list(query: web::Query<ListQuery>, files: web::Data<FileStore>) -> impl Responder {
+    HttpResponse::Ok().json(ListFiles {
+        object: "list".to_owned(),
+        data: files.list(query.purpose.as_deref()),
+    })
+}
+
+/// Get a file.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", body = FileObject, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/files/{id}")]
+pub async fn retrieve(path: web::Path<String>, files: web::Data<FileStore>) -> impl Responder {
+    match files.get(&path) {
+        Some(file) => HttpResponse::Ok().json(file),
+        None => not_found(&path),
+    }
+}
+
+/// Download the content of a file.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", content_type = "application/octet-stream")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/files/{id}/content")]
+pub async fn content(path: web::Path<String>, files: web::Data<FileStore>) -> impl Responder {
+    match files.content(&path) {
+        Some(bytes) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(bytes),
+        None => not_found(&path),
+    }
+}
+
+/// Delete a file.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[delete("/files/{id}")]
+pub async fn remove(path: web::Path<String>, files: web::Data<FileStore>) -> impl Responder {
+    match files.delete(&path) {
+        Ok(true) => HttpResponse::Ok().json(serde_json::json!({
+            "id": path.as_str(),
+            "object": "file",
+            "deleted": true,
+        })),
+        Ok(false) => not_found(&path),
+        Err(e) => internal_error(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_list_delete() {
+        let dir = std::env::temp_dir().join(format!("llmserver-files-{}", rand::random::<u32>()));
+        let store = FileStore::new(&dir);
+        let source = dir.with_extension("txt");
+        std::fs::write(&source, "hello").unwrap();
+
+        let file = store
+            .create(&source, "a.txt".to_owned(), "assistants".to_owned())
+            .unwrap();
+        assert_eq!(file.bytes, 5);
+        assert_eq!(store.content(&file.id).unwrap(), b"hello");
+        assert_eq!(store.list(Some("assistants")).len(), 1);
+        assert!(store.list(Some("batch")).is_empty());
+        assert!(store.get("../a").is_none());
+        assert!(store.delete(&file.id).unwrap());
+        assert!(store.get(&file.id).is_none());
+
+        std::fs::remove_file(source).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,83 @@
+use std::{path::Path, sync::LazyLock, time::UNIX_EPOCH};
+
+use crate::utils::ModelConfig;
+
+/// Where the install steps put the rkllm runtime
+const RUNTIME_LIBRARY: &str = "/lib/librkllmrt.so";
+
+/// The runtime library does not say its version, a new one is noticed by its size and time.
+static RUNTIME: LazyLock<String> =
+    LazyLock::new(|| file_identity(Path::new(RUNTIME_LIBRARY)).unwrap_or_default());
+
+/// FNV-1a, stays the same across builds unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8], mut hash: u64) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn file_identity(path: &Path) -> Option<String> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("{}:{}", metadata.len(), modified.as_secs()))
+}
+
+/// Digest of the model file once it is on disk. hf-hub names its blobs by their sha256, other
+/// files go by size and time.
+pub fn model_digest(config: &ModelConfig) -> Option<String> {
+    let path = crate::llm::simple::cached_model_file(config)?;
+    let file = std::fs::canonicalize(path).ok()?;
+    if file.parent().and_then(Path::file_name) == Some("blobs".as_ref()) {
+        return Some(file.file_name()?.to_string_lossy().into_owned());
+    }
+    file_identity(&file)
+}
+
+/// `system_fingerprint` of answers from `config`: changes when the server version, the rkllm
+/// runtime or the model file changes, so clients relying on `seed` can tell.
+pub fn system_fingerprint(config: &ModelConfig) -> String {
+    let parts = [
+        env!("CARGO_PKG_VERSION"),
+        RUNTIME.as_str(),
+        &config.model_repo,
+        config.model_path.as_deref().unwrap_or_default(),
+        &model_digest(config).unwrap_or_default(),
+        &config.max_context_len.to_string(),
+    ];
+    let hash = parts.iter().fold(0xcbf29ce484222325, |hash, part| {
+        fnv1a(&[0], fnv1a(part.as_bytes(), hash))
+    });
+    format!("fp_{:012x}", hash & 0xffff_ffff_ffff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_model_file() {
+        let mut config = ModelConfig {
+            model_repo: "kautism/qwen".to_owned(),
+            model_path: Some("qwen-8k.rkllm".to_owned()),
+            ..Default::default()
+        };
+        let fingerprint = system_fingerprint(&config);
+        assert!(fingerprint.starts_with("fp_") && fingerprint.len() == 15);
+        assert_eq!(system_fingerprint(&config), fingerprint);
+        config.model_path = Some("qwen-16k.rkllm".to_owned());
+        assert_ne!(system_fingerprint(&config), fingerprint);
+
+        // 換了檔案內容也要變
+        let dir = std::env::temp_dir().join(format!("llmserver-fp-{}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("qwen-16k.rkllm"), b"weights").unwrap();
+        config.local_repo = Some(dir.to_string_lossy().into_owned());
+        let digest = model_digest(&config).unwrap();
+        assert!(digest.starts_with("7:"));
+        std::fs::write(dir.join("qwen-16k.rkllm"), b"new weights").unwrap();
+        assert_ne!(model_digest(&config).unwrap(), digest);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
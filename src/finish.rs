@@ -0,0 +1,70 @@
+use crate::{
+    chat::{ChatCompletionsRequest, FinishReason},
+    OpenAiError,
+};
+
+/// Marks why a model stopped early in its text stream, like `usage:`.
+const PREFIX: &str = "\u{0}finish:";
+
+pub fn message(reason: FinishReason) -> String {
+    let reason = serde_json::to_value(reason).unwrap_or_default();
+    format!("{}{}", PREFIX, reason.as_str().unwrap_or_default())
+}
+
+/// Reason of a finish message, `None` for model output.
+pub fn parse(text: &str) -> Option<FinishReason> {
+    let reason = text.strip_prefix(PREFIX)?;
+    serde_json::from_value(reason.into()).ok()
+}
+
+/// `max_completion_tokens`, or the older `max_tokens`.
+pub fn max_tokens(request: &ChatCompletionsRequest) -> Result<Option<usize>, OpenAiError> {
+    let (param, limit) = match (request.max_completion_tokens, request.max_tokens) {
+        (Some(limit), _) => ("max_completion_tokens", limit),
+        (None, Some(limit)) => ("max_tokens", limit),
+        (None, None) => return Ok(None),
+    };
+    if limit < 1 {
+        return Err(OpenAiError {
+            message: format!("{} must be at least 1.", param),
+            code: "invalid_value".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some(param.to_owned()),
+        });
+    }
+    Ok(Some(limit as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        assert_eq!(message(FinishReason::Length), "\u{0}finish:length");
+        assert_eq!(
+            parse(&message(FinishReason::Length)),
+            Some(FinishReason::Length)
+        );
+        assert_eq!(parse("length"), None);
+
+        let request = |body: serde_json::Value| -> ChatCompletionsRequest {
+            serde_json::from_value(body).unwrap()
+        };
+        let limit = |body| max_tokens(&request(body)).map_err(|e| e.param);
+        assert_eq!(
+            limit(serde_json::json!({ "model": "m", "messages": [] })),
+            Ok(None)
+        );
+        assert_eq!(
+            limit(
+                serde_json::json!({ "model": "m", "messages": [], "max_tokens": 8, "max_completion_tokens": 4 })
+            ),
+            Ok(Some(4))
+        );
+        assert_eq!(
+            limit(serde_json::json!({ "model": "m", "messages": [], "max_tokens": 0 })),
+            Err(Some("max_tokens".to_owned()))
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use std::{collections::HashMap, io::Cursor, net::SocketAddr, pin::Pin, time::Duration};
+
+use futures::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+use crate::{
+    audio::AudioPool,
+    chat::LlmPool,
+    embeddings::EmbeddingPool,
+    utils::{ModelConfig, ModelType},
+    AsrText, Content, Message, ProcessAudio, ProcessEmbeddings, ProcessMessages, Role,
+};
+
+pub mod proto {
+    tonic::include_proto!("llmserver");
+}
+
+use proto::llm_server_server::{LlmServer, LlmServerServer};
+
+/// gRPC front of the same actor pools the HTTP API uses.
+#[derive(Clone)]
+pub struct GrpcService {
+    pub llm_pool: LlmPool,
+    pub embedding_pool: EmbeddingPool,
+    pub audio_pool: AudioPool,
+    pub all_configs: HashMap<String, ModelConfig>,
+}
+
+fn parse_role(role: &str) -> Result<Role, Status> {
+    serde_json::from_value(serde_json::Value::String(role.to_owned()))
+        .map_err(|_| Status::invalid_argument(format!("Unknown role {}", role)))
+}
+
+#[tonic::async_trait]
+impl LlmServer for GrpcService {
+    type ChatStream = Pin<Box<dyn Stream<Item = Result<proto::ChatDelta, Status>> + Send>>;
+
+    async fn chat(
+        &self,
+        request: Request<proto::ChatRequest>,
+    ) -> Result<Response<Self::ChatStream>, Status> {
+        let request = request.into_inner();
+        // 跟 HTTP 非串流一樣，不在這裡載入模型
+        let recipient = self
+            .llm_pool
+            .lock()
+            .unwrap()
+            .get(&request.model)
+            .cloned()
+            .ok_or_else(|| Status::failed_precondition("Model is not loaded."))?;
+        let messages = request
+            .messages
+            .into_iter()
+            .map(|m| {
+                Ok(Message {
+                    role: Some(parse_role(&m.role)?),
+                    content: Some(Content::String(m.content)),
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<Vec<_>, Status>>()?;
+
+        let send_future = recipient.send(ProcessMessages {
+            messages,
+            ..Default::default()
+        });
+        let stream = match actix_web::rt::time::timeout(Duration::from_secs(60), send_future).await
+        {
+            Ok(Ok(Ok(stream))) => stream,
+            Ok(Ok(Err(_))) => return Err(Status::internal("Internal stream error")),
+            Ok(Err(e)) => return Err(Status::unavailable(format!("Mailbox error: {}", e))),
+            Err(_) => return Err(Status::resource_exhausted("Timeout waiting for model slot")),
+        };
+        // prefill 進度跟 usage 不送給 gRPC 客戶端
+        let stream = stream.filter(|content| {
+            std::future::ready(
+                crate::prefill::parse(content).is_none()
+                    && crate::usage::parse(content).is_none()
+                    && crate::finish::parse(content).is_none(),
+            )
+        });
+        let deltas = stream.map(|content| {
+            if let Some(error) = content.strip_prefix("Model error: ") {
+                return Err(Status::internal(error.to_owned()));
+            }
+            Ok(proto::ChatDelta {
+                finished: content.is_empty(),
+                content,
+            })
+        });
+        Ok(Response::new(Box::pin(deltas)))
+    }
+
+    async fn embed(
+        &self,
+        request: Request<proto::EmbedRequest>,
+    ) -> Result<Response<proto::EmbedResponse>, Status> {
+        let request = request.into_inner();
+        let config = self
+            .all_configs
+            .get(&request.model)
+            .filter(|c| c.model_type == ModelType::Embedding)
+            .ok_or_else(|| Status::not_found(format!("Model {} not found", request.model)))?;
+        if request.input.is_empty() {
+            return Err(Status::invalid_argument("input should not be empty."));
+        }
+        let recipient = crate::embeddings::get_or_load(config, &self.embedding_pool)
+            .await
+            .map_err(Status::internal)?;
+        let embeddings = recipient
+            .send(ProcessEmbeddings {
+                input: request.input,
+            })
+            .await
+            .map_err(|e| Status::unavailable(format!("Mailbox error: {}", e)))?
+            .map_err(|_| Status::internal("Embedding failed."))?;
+        Ok(Response::new(proto::EmbedResponse {
+            data: embeddings
+                .data
+                .into_iter()
+                .map(|values| proto::Embedding { values })
+                .collect(),
+            prompt_tokens: embeddings.prompt_tokens as u32,
+        }))
+    }
+
+    async fn transcribe(
+        &self,
+        request: Request<proto::TranscribeRequest>,
+    ) -> Result<Response<proto::TranscribeResponse>, Status> {
+        let request = request.into_inner();
+        let asr = self
+            .audio_pool
+            .lock()
+            .unwrap()
+            .get(&request.model)
+            .cloned()
+            .ok_or_else(|| Status::not_found(format!("Model {} not found", request.model)))?;
+        let send_future = asr.send(ProcessAudio::Buffer(Box::new(Cursor::new(request.audio))));
+        let stream = match actix_web::rt::time::timeout(Duration::from_secs(5), send_future).await {
+            Ok(Ok(Ok(stream))) => stream,
+            Ok(Ok(Err(_))) => return Err(Status::internal("Internal processing error")),
+            Ok(Err(e)) => return Err(Status::unavailable(format!("Mailbox error: {}", e))),
+            Err(_) => return Err(Status::resource_exhausted("Server Busy.")),
+        };
+        let text = stream
+            .map(|content| match content {
+                AsrText::SenseVoice(voice_text, _) => voice_text.content,
+            })
+            .collect::<Vec<_>>()
+            .await
+            .join("");
+        Ok(Response::new(proto::TranscribeResponse { text }))
+    }
+}
+
+pub async fn serve(addr: SocketAddr, service: GrpcService) {
+    log::info!("gRPC listening on {}", addr);
+    if let Err(e) = tonic::transport::Server::builder()
+        .add_service(LlmServerServer::new(service))
+        .serve(addr)
+        .await
+    {
+        log::error!("gRPC server stopped: {}", e);
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+use hf_hub::{
+    api::sync::{Api, ApiBuilder, ApiError},
+    Cache,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::utils::ModelConfig;
+
+/// `HF_HUB_OFFLINE=1` turns offline mode on like `--offline`, the same variable the Python hub
+/// client reads.
+pub const OFFLINE_ENV: &str = "HF_HUB_OFFLINE";
+
+/// 斷線時從 `.part` 檔接著下載的次數
+const DOWNLOAD_RETRIES: usize = 5;
+
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// `--offline`: files come from `local_repo`, `tokenizer_path` and the hf-hub cache only, a
+/// missing one is an error instead of a download.
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+pub fn offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+pub fn offline_from_env(value: Option<&str>) -> bool {
+    matches!(value.map(str::trim), Some("1" | "true" | "TRUE" | "yes"))
+}
+
+/// Where model files come from, `hub` of `assets/server.json` and of each model config. Unset
+/// fields fall back to `HF_ENDPOINT`, `HF_HOME` and `HF_TOKEN` like the Python client.
+#[derive(Clone, Default, Deserialize)]
+pub struct HubConfig {
+    /// Mirror such as `https://hf-mirror.com`
+    pub endpoint: Option<String>,
+    /// 取代 `~/.cache/huggingface/hub`
+    pub cache_dir: Option<PathBuf>,
+    /// Access token of gated and private repos
+    pub token: Option<String>,
+}
+
+impl std::fmt::Debug for HubConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // token 不要進 log
+        f.debug_struct("HubConfig")
+            .field("endpoint", &self.endpoint)
+            .field("cache_dir", &self.cache_dir)
+            .field("token", &self.token.as_ref().map(|_| "***"))
+            .finish()
+    }
+}
+
+impl HubConfig {
+    /// Fields of `self`, the others from `defaults`.
+    pub fn or(&self, defaults: &HubConfig) -> HubConfig {
+        HubConfig {
+            endpoint: self.endpoint.clone().or_else(|| defaults.endpoint.clone()),
+            cache_dir: self
+                .cache_dir
+                .clone()
+                .or_else(|| defaults.cache_dir.clone()),
+            token: self.token.clone().or_else(|| defaults.token.clone()),
+        }
+    }
+}
+
+static SERVER_HUB: OnceLock<HubConfig> = OnceLock::new();
+
+/// `hub` of the server config, for models without their own.
+pub fn set_defaults(hub: HubConfig) {
+    let _ = SERVER_HUB.set(hub);
+}
+
+fn settings(config: &ModelConfig) -> HubConfig {
+    let defaults = SERVER_HUB.get().cloned().unwrap_or_default();
+    match &config.hub {
+        Some(hub) => hub.or(&defaults),
+        None => defaults,
+    }
+}
+
+/// The hf-hub cache of the model.
+pub(crate) fn cache(config: &ModelConfig) -> Cache {
+    match settings(config).cache_dir {
+        Some(dir) => Cache::new(dir),
+        None => Cache::from_env(),
+    }
+}
+
+/// Download client of the model, with its endpoint and token.
+pub(crate) fn api(config: &ModelConfig) -> Result<Api, ApiError> {
+    let hub = settings(config);
+    let mut builder = ApiBuilder::from_cache(cache(config)).with_retries(DOWNLOAD_RETRIES);
+    if let Some(endpoint) = hub.endpoint.or_else(|| std::env::var("HF_ENDPOINT").ok()) {
+        builder = builder.with_endpoint(endpoint.trim_end_matches('/').to_owned());
+    }
+    if let Some(token) = hub.token.or_else(|| std::env::var("HF_TOKEN").ok()) {
+        builder = builder.with_token(Some(token));
+    }
+    builder.build()
+}
+
+/// Expected size and digest of a model file, `checksums` of the model config.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Checksum {
+    pub sha256: Option<String>,
+    pub size: Option<u64>,
+}
+
+fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+fn corrupt(config: &ModelConfig, filename: &str, problem: String) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!(
+            "{} of {} {}, the file is truncated or corrupt. Pull the model again",
+            filename, config.model_name, problem
+        ),
+    )
+}
+
+/// Size check of a file about to be loaded, cheap enough for every load. rkllm crashes on a
+/// truncated model instead of failing.
+pub(crate) fn check_size(config: &ModelConfig, filename: &str, path: &Path) -> std::io::Result<()> {
+    let Some(expected) = config.checksums.get(filename).and_then(|c| c.size) else {
+        return Ok(());
+    };
+    let size = std::fs::metadata(path)?.len();
+    if size != expected {
+        return Err(corrupt(
+            config,
+            filename,
+            format!("is {} bytes instead of {}", size, expected),
+        ));
+    }
+    Ok(())
+}
+
+/// Size and sha256 of a finished download. Without a `sha256` in the config, hub blobs named by
+/// their sha256 (LFS files) are checked against their name.
+pub(crate) fn verify_download(
+    config: &ModelConfig,
+    filename: &str,
+    path: &Path,
+) -> std::io::Result<()> {
+    check_size(config, filename, path)?;
+    let blob = std::fs::canonicalize(path)?;
+    let blob_name = blob
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .filter(|name| name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit()));
+    let Some(expected) = config
+        .checksums
+        .get(filename)
+        .and_then(|c| c.sha256.clone())
+        .or(blob_name)
+    else {
+        return Ok(());
+    };
+    let actual = sha256_file(&blob)?;
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+        return Err(corrupt(
+            config,
+            filename,
+            format!("has sha256 {} instead of {}", actual, expected),
+        ));
+    }
+    log::info!("Verified {} of {}", filename, config.model_name);
+    Ok(())
+}
+
+/// Bytes unprivileged users may still write on the file system of `path`, or of its nearest
+/// existing parent when the cache does not exist yet.
+#[cfg(unix)]
+pub(crate) fn available_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let existing = path.ancestors().find(|p| p.exists())?;
+    let c_path = std::ffi::CString::new(existing.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+fn gigabytes(bytes: u64) -> String {
+    format!("{:.1} GB", bytes as f64 / 1e9)
+}
+
+/// Refuse a download of `size` bytes into `dir` that would fill the disk midway, `partial` of
+/// them are already in the `.part` file.
+pub(crate) fn check_space(
+    config: &ModelConfig,
+    filename: &str,
+    dir: &Path,
+    size: u64,
+    partial: u64,
+) -> std::io::Result<()> {
+    let Some(available) = available_space(dir) else {
+        return Ok(());
+    };
+    let needed = size.saturating_sub(partial);
+    if needed > available {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::StorageFull,
+            format!(
+                "{} of {} needs {} but only {} is free in {}, remove unused models first",
+                filename,
+                config.model_name,
+                gigabytes(needed),
+                gigabytes(available),
+                dir.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Error of a file that would have to be downloaded in offline mode.
+pub(crate) fn offline_error(repo: &str, filename: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!(
+            "{} of {} is not in local_repo or the hf-hub cache, and offline mode forbids downloading it",
+            filename, repo
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_env_values() {
+        assert!(offline_from_env(Some("1")));
+        assert!(offline_from_env(Some("true")));
+        assert!(!offline_from_env(Some("0")));
+        assert!(!offline_from_env(None));
+        assert_eq!(
+            offline_error("kautism/qwen", "model.rkllm").to_string(),
+            "model.rkllm of kautism/qwen is not in local_repo or the hf-hub cache, and offline mode forbids downloading it"
+        );
+    }
+
+    #[test]
+    fn model_hub_settings_win() {
+        let server = HubConfig {
+            endpoint: Some("https://hf-mirror.com".to_owned()),
+            token: Some("hf_server".to_owned()),
+            ..Default::default()
+        };
+        let model = HubConfig {
+            token: Some("hf_model".to_owned()),
+            cache_dir: Some(PathBuf::from("/data/hub")),
+            ..Default::default()
+        };
+        let hub = model.or(&server);
+        assert_eq!(hub.endpoint.as_deref(), Some("https://hf-mirror.com"));
+        assert_eq!(hub.token.as_deref(), Some("hf_model"));
+        assert!(!format!("{:?}", hub).contains("hf_model"));
+        let config = ModelConfig {
+            hub: Some(model),
+            ..Default::default()
+        };
+        assert_eq!(cache(&config).path(), &PathBuf::from("/data/hub"));
+    }
+
+    #[test]
+    fn rejects_corrupt_downloads() {
+        let dir = std::env::temp_dir().join(format!("llmserver-sum-{}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("model.rkllm");
+        std::fs::write(&file, b"weights").unwrap();
+        let checksum = |sha256: &str, size| Checksum {
+            sha256: Some(sha256.to_owned()),
+            size: Some(size),
+        };
+        let mut config = ModelConfig::default();
+        let sha256 = "9a129038d9a00aed0cf6a7ea059ca50a813449061ab87848cf1a13eafdf33b2c";
+        config
+            .checksums
+            .insert("model.rkllm".to_owned(), checksum(sha256, 7));
+        assert!(verify_download(&config, "model.rkllm", &file).is_ok());
+
+        // 下載到一半斷掉的檔案
+        config
+            .checksums
+            .insert("model.rkllm".to_owned(), checksum(sha256, 1024));
+        assert!(check_size(&config, "model.rkllm", &file).is_err());
+        config
+            .checksums
+            .insert("model.rkllm".to_owned(), checksum(&"0".repeat(64), 7));
+        let error = verify_download(&config, "model.rkllm", &file).unwrap_err();
+        assert!(error.to_string().contains("has sha256 9a1290"));
+
+        // hub 的 blob 用 sha256 命名
+        let blob = dir.join("0".repeat(64));
+        std::fs::write(&blob, b"weights").unwrap();
+        assert!(verify_download(&ModelConfig::default(), "model.rkllm", &blob).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_downloads_that_do_not_fit() {
+        let dir = std::env::temp_dir().join("llmserver-not-created-yet");
+        assert!(available_space(&dir).is_some_and(|free| free > 0));
+        let config = ModelConfig::default();
+        assert!(check_space(&config, "model.rkllm", &dir, 1024, 0).is_ok());
+        let error = check_space(&config, "model.rkllm", &dir, u64::MAX, 0).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::StorageFull);
+        // 已經下載的部分不用再算
+        assert!(check_space(&config, "model.rkllm", &dir, u64::MAX, u64::MAX).is_ok());
+    }
+}
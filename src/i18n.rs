@@ -0,0 +1,149 @@
+use std::sync::OnceLock;
+
+use actix_web::HttpRequest;
+use indicatif::HumanBytes;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Lang {
+    En,
+    #[default]
+    Zh,
+}
+
+impl Lang {
+    /// `zh-TW`、`en-US` 這類 tag，只看主語言
+    pub fn parse(tag: &str) -> Option<Self> {
+        let primary = tag.trim().split(['-', '_']).next()?.to_ascii_lowercase();
+        match primary.as_str() {
+            "en" => Some(Lang::En),
+            "zh" => Some(Lang::Zh),
+            _ => None,
+        }
+    }
+}
+
+static DEFAULT_LANG: OnceLock<Lang> = OnceLock::new();
+
+/// Server wide language, set once at startup with `--lang`.
+pub fn set_default_lang(lang: Lang) {
+    let _ = DEFAULT_LANG.set(lang);
+}
+
+pub fn default_lang() -> Lang {
+    DEFAULT_LANG.get().copied().unwrap_or_default()
+}
+
+/// First supported language of `Accept-Language`, else the server default.
+pub fn request_lang(req: &HttpRequest) -> Lang {
+    req.headers()
+        .get(actix_web::http::header::ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| {
+            v.split(',')
+                .find_map(|tag| Lang::parse(tag.split(';').next().unwrap_or("")))
+        })
+        .unwrap_or_else(default_lang)
+}
+
+/// Progress and status messages sent to clients, rendered with [`MessageKey::text`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "key", rename_all = "snake_case")]
+pub enum MessageKey {
+    DownloadStarted { file: String },
+    Downloading { current: usize, total: usize },
+    DownloadFinished,
+    ModelLoading { file: String, size: u64 },
+    ModelLoadingElapsed { secs: u64 },
+    ModelReady,
+    FallbackUsed { model: String, fallback: String },
+    ToolCalling { name: String, arguments: String },
+    Prefilling { current: usize, total: usize },
+}
+
+impl MessageKey {
+    pub fn text(&self, lang: Lang) -> String {
+        match (self, lang) {
+            (MessageKey::DownloadStarted { file }, Lang::En) => {
+                format!("Downloading model: {}", file)
+            }
+            (MessageKey::DownloadStarted { file }, Lang::Zh) => format!("開始下載模型：{}", file),
+            (MessageKey::Downloading { current, total }, Lang::En) => {
+                format!("Downloading... {}/{}", current, total)
+            }
+            (MessageKey::Downloading { current, total }, Lang::Zh) => {
+                format!("下載中... {}/{}", current, total)
+            }
+            (MessageKey::DownloadFinished, Lang::En) => {
+                "Download finished, initializing model...".to_owned()
+            }
+            (MessageKey::DownloadFinished, Lang::Zh) => "下載完成，正在初始化模型...".to_owned(),
+            (MessageKey::ModelLoading { file, size }, Lang::En) => {
+                format!("Loading {} ({})...", file, HumanBytes(*size))
+            }
+            (MessageKey::ModelLoading { file, size }, Lang::Zh) => {
+                format!(
+                    "下載完成，開始載入 RKLLM 核心 {} ({})...",
+                    file,
+                    HumanBytes(*size)
+                )
+            }
+            (MessageKey::ModelLoadingElapsed { secs }, Lang::En) => {
+                format!("Loading model, {}s elapsed", secs)
+            }
+            (MessageKey::ModelLoadingElapsed { secs }, Lang::Zh) => {
+                format!("讀取模型中，已過去{}秒", secs)
+            }
+            (MessageKey::ModelReady, Lang::En) => "Model loaded, starting actor.".to_owned(),
+            (MessageKey::ModelReady, Lang::Zh) => "模型完全初始化完成，正在啟動 Actor。".to_owned(),
+            (MessageKey::FallbackUsed { model, fallback }, Lang::En) => {
+                format!("{} failed to load, using {}", model, fallback)
+            }
+            (MessageKey::FallbackUsed { model, fallback }, Lang::Zh) => {
+                format!("{} 載入失敗，改用 {}", model, fallback)
+            }
+            (MessageKey::ToolCalling { name, arguments }, Lang::En) => {
+                format!("Calling {} {}", name, arguments)
+            }
+            (MessageKey::ToolCalling { name, arguments }, Lang::Zh) => {
+                format!("呼叫工具 {} {}", name, arguments)
+            }
+            (MessageKey::Prefilling { current, total }, Lang::En) => {
+                format!(
+                    "Reading your {}-token prompt... {}/{}",
+                    total, current, total
+                )
+            }
+            (MessageKey::Prefilling { current, total }, Lang::Zh) => {
+                format!("正在閱讀 {} tokens 的內容... {}/{}", total, current, total)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn languages() {
+        assert_eq!(Lang::parse("zh-TW"), Some(Lang::Zh));
+        assert_eq!(Lang::parse(" en_US"), Some(Lang::En));
+        assert_eq!(Lang::parse("fr"), None);
+
+        let req = actix_web::test::TestRequest::default()
+            .insert_header(("Accept-Language", "fr-FR, en;q=0.8"))
+            .to_http_request();
+        assert_eq!(request_lang(&req), Lang::En);
+
+        assert_eq!(
+            MessageKey::ModelReady.text(Lang::En),
+            "Model loaded, starting actor."
+        );
+        assert_eq!(
+            serde_json::to_value(MessageKey::ModelLoadingElapsed { secs: 3 }).unwrap(),
+            serde_json::json!({ "key": "model_loading_elapsed", "secs": 3 })
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use base64::Engine;
+use futures::StreamExt;
+
+use crate::{Content, Message, OpenAiError};
+
+/// 圖片太大編碼器也只會縮小，不用收
+pub const MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn invalid_image(message: String) -> OpenAiError {
+    OpenAiError {
+        message,
+        code: "invalid_image_url".to_owned(),
+        r#type: "invalid_request_error".to_owned(),
+        param: Some("messages".to_owned()),
+    }
+}
+
+/// Format of `bytes`, if they are an image the vision encoder can read.
+fn image_format(bytes: &[u8]) -> Option<image::ImageFormat> {
+    image::guess_format(bytes)
+        .ok()
+        .filter(|format| matches!(format, image::ImageFormat::Png | image::ImageFormat::Jpeg))
+}
+
+/// `bytes` as a base64 `data:` URL, if they are an image the vision encoder can read.
+pub(crate) fn data_url(bytes: &[u8]) -> Option<String> {
+    Some(format!(
+        "data:{};base64,{}",
+        image_format(bytes)?.to_mime_type(),
+        base64::engine::general_purpose::STANDARD.encode(bytes)
+    ))
+}
+
+/// Check a `data:image/...;base64,` URL before it gets to the model: a PNG or JPEG image of at
+/// most `max_bytes` once decoded, matching its declared type.
+fn check_data_url(url: &str, max_bytes: usize) -> Result<(), OpenAiError> {
+    let (mime, payload) = url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"))
+        .ok_or_else(|| invalid_image("Image data URLs must be base64 encoded.".to_owned()))?;
+    let mime = match mime.to_ascii_lowercase().as_str() {
+        "image/png" => image::ImageFormat::Png,
+        "image/jpeg" | "image/jpg" => image::ImageFormat::Jpeg,
+        _ => {
+            return Err(invalid_image(format!(
+                "Unsupported image type {}, use image/png or image/jpeg.",
+                mime
+            )))
+        }
+    };
+    let too_large = || {
+        invalid_image(format!(
+            "Image is larger than {} bytes once decoded.",
+            max_bytes
+        ))
+    };
+    // 先用長度估，不要為了拒絕而整個解碼
+    let payload: String = payload.chars().filter(|c| !c.is_whitespace()).collect();
+    if payload.len() / 4 * 3 > max_bytes + 2 {
+        return Err(too_large());
+    }
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(&payload)
+        .map_err(|e| invalid_image(format!("Invalid base64 image data: {}", e)))?;
+    if bytes.len() > max_bytes {
+        return Err(too_large());
+    }
+    if image_format(&bytes) != Some(mime) {
+        return Err(invalid_image(format!(
+            "Image data is not {}.",
+            mime.to_mime_type()
+        )));
+    }
+    Ok(())
+}
+
+/// Loopback, private, link-local and other addresses of the board's own network.
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // 100.64.0.0/10 carrier-grade NAT
+                || (a == 100 && b & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80
+                || ip
+                    .to_ipv4_mapped()
+                    .is_some_and(|ip| is_internal(IpAddr::V4(ip)))
+        }
+    }
+}
+
+/// Address to download `url` from. Hosts that resolve into the board's network are refused
+/// unless `internal` ones are trusted.
+async fn public_addr(url: &reqwest::Url, internal: bool) -> Result<SocketAddr, String> {
+    let host = url.host_str().ok_or("the URL has no host")?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.trim_matches(['[', ']']), port))
+        .await
+        .map_err(|e| e.to_string())?
+        .collect();
+    if let Some(addr) = addrs
+        .iter()
+        .find(|addr| !internal && is_internal(addr.ip()))
+    {
+        return Err(format!("{} is a private address", addr.ip()));
+    }
+    addrs
+        .first()
+        .copied()
+        .ok_or_else(|| format!("{} has no address", host))
+}
+
+/// Whether `url` has the scheme, host and port of an `allow` prefix and a path under it.
+pub(crate) fn allowed(url: &reqwest::Url, allow: &[String]) -> bool {
+    allow
+        .iter()
+        .filter_map(|prefix| reqwest::Url::parse(prefix).ok())
+        .any(|prefix| {
+            prefix.scheme() == url.scheme()
+                && prefix.host_str() == url.host_str()
+                && prefix.port_or_known_default() == url.port_or_known_default()
+                && url.path().starts_with(prefix.path())
+        })
+}
+
+/// Client that only connects to the checked address of `url` and never follows redirects, so
+/// neither DNS changing its answer nor a redirect reaches the board's network.
+pub(crate) async fn pinned_client(
+    url: &reqwest::Url,
+    internal: bool,
+) -> Result<reqwest::Client, String> {
+    let addr = public_addr(url, internal).await?;
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve(url.host_str().unwrap_or_default(), addr)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+async fn download(url: &str, max_bytes: usize, allow: &[String]) -> Result<String, OpenAiError> {
+    let failed = |e: String| invalid_image(format!("Failed to download image {}: {}", url, e));
+    let parsed = reqwest::Url::parse(url).map_err(|e| failed(e.to_string()))?;
+    // 白名單的可以在內網，但一樣不跟轉址，轉不到別的內網位址
+    let trusted = allowed(&parsed, allow);
+    let client = pinned_client(&parsed, trusted).await.map_err(|e| {
+        if trusted {
+            failed(e)
+        } else {
+            failed(format!("{}, add the URL to image_url_allow to fetch it", e))
+        }
+    })?;
+    let response = client
+        .get(parsed)
+        .timeout(FETCH_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| failed(e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(failed(format!("status {}", response.status().as_u16())));
+    }
+    let mut bytes = Vec::new();
+    let mut body = response.bytes_stream();
+    while let Some(chunk) = body.next().await {
+        bytes.extend_from_slice(&chunk.map_err(|e| failed(e.to_string()))?);
+        if bytes.len() > max_bytes {
+            return Err(failed(format!("larger than {} bytes", max_bytes)));
+        }
+    }
+    data_url(&bytes).ok_or_else(|| failed("not a PNG or JPEG image".to_owned()))
+}
+
+/// Check the `image_url` parts of `messages` and put `http(s)` images in as downloaded `data:`
+/// URLs, so the vision model only reads valid images from memory. Only URLs under one of the
+/// `allow` prefixes may point at private addresses.
+pub(crate) async fn prepare_images(
+    messages: &mut [Message],
+    max_bytes: usize,
+    allow: &[String],
+) -> Result<(), OpenAiError> {
+    let parts = messages
+        .iter_mut()
+        .flat_map(|message| match &mut message.content {
+            Some(Content::Parts(parts)) => parts.iter_mut().collect(),
+            _ => vec![],
+        });
+    for part in parts {
+        let Some(url) = part.image_url.as_mut().and_then(|image| image.url.as_mut()) else {
+            continue;
+        };
+        if url.starts_with("data:") {
+            check_data_url(url, max_bytes)?;
+        } else if url.starts_with("http://") || url.starts_with("https://") {
+            *url = download(url, max_bytes, allow).await?;
+        } else {
+            return Err(invalid_image(
+                "Image URLs must be base64 data URLs or http(s) URLs.".to_owned(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_images_only() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert!(data_url(png)
+            .unwrap()
+            .starts_with("data:image/png;base64,iVBOR"));
+        assert!(data_url(b"GIF89a").is_none());
+        assert!(data_url(b"<html></html>").is_none());
+
+        let url = data_url(png).unwrap();
+        assert!(check_data_url(&url, 1024).is_ok());
+        assert!(check_data_url(&url, 8).is_err());
+        assert!(check_data_url(&url.replace("image/png", "image/jpeg"), 1024).is_err());
+        assert!(check_data_url(&url.replace("image/png", "image/gif"), 1024).is_err());
+        assert!(check_data_url("data:image/png,raw", 1024).is_err());
+    }
+
+    #[test]
+    fn allow_matches_the_origin() {
+        let allow = vec!["http://192.168.50.10:9000/images/".to_owned()];
+        let url = |url: &str| reqwest::Url::parse(url).unwrap();
+        assert!(allowed(
+            &url("http://192.168.50.10:9000/images/cat.png"),
+            &allow
+        ));
+        for other in [
+            "http://192.168.50.10:9000/private/cat.png",
+            "http://192.168.50.10:9001/images/cat.png",
+            "https://192.168.50.10:9000/images/cat.png",
+            "http://192.168.50.10.evil.com:9000/images/cat.png",
+            "http://192.168.50.10.evil.com:9000/images/cat.png",
+        ] {
+            assert!(!allowed(&url(other), &allow), "{}", other);
+        }
+    }
+
+    #[actix_web::test]
+    async fn refuses_private_image_urls() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "192.168.50.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "::1",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:192.168.0.1",
+        ] {
+            assert!(is_internal(ip.parse().unwrap()), "{}", ip);
+        }
+        assert!(!is_internal("93.184.216.34".parse().unwrap()));
+        assert!(!is_internal("2606:4700::1111".parse().unwrap()));
+
+        let error = download("http://127.0.0.1:8080/cat.png", 1024, &[])
+            .await
+            .unwrap_err();
+        assert!(
+            error.message.contains("127.0.0.1 is a private address"),
+            "{}",
+            error.message
+        );
+        let error = download("http://[::1]/cat.png", 1024, &[])
+            .await
+            .unwrap_err();
+        assert!(error.message.contains("private address"));
+        // 允許的網址照常下載，這裡沒有伺服器所以是連線失敗
+        let error = download(
+            "http://127.0.0.1:9/cat.png",
+            1024,
+            &["http://127.0.0.1:9/".to_owned()],
+        )
+        .await
+        .unwrap_err();
+        assert!(!error.message.contains("private address"));
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use actix::Recipient;
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cluster::Cluster,
+    diffusion::simple::SimpleDiffusion,
+    errors::ApiError,
+    utils::{ModelConfig, ModelType},
+    OpenAiError, ProcessImages,
+};
+
+pub type ImagePool = Arc<Mutex<HashMap<String, Recipient<ProcessImages>>>>;
+
+fn default_n() -> usize {
+    1
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct ImagesRequest {
+    pub prompt: String,
+    pub model: String,
+    #[serde(default = "default_n")]
+    pub n: usize,
+    #[serde(default)]
+    pub size: Option<String>,
+    /// Only `b64_json` is supported
+    #[serde(default)]
+    pub response_format: Option<String>,
+    /// Extension: override the configured LCM steps
+    #[serde(default)]
+    pub num_inference_steps: Option<usize>,
+    /// Extension: fixed seed for reproducible images
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ImageData {
+    pub b64_json: String,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ImagesResponse {
+    pub created: u64,
+    pub data: Vec<ImageData>,
+}
+
+fn bad_request(message: String, param: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(OpenAiError {
+        message,
+        code: "invalid_request_error".to_owned(),
+        r#type: "invalid_request_error".to_owned(),
+        param: Some(param.to_owned()),
+    })
+}
+
+fn internal_error(message: String) -> HttpResponse {
+    HttpResponse::InternalServerError().json(OpenAiError {
+        message,
+        code: "processing_error".to_owned(),
+        r#type: "internal_error".to_owned(),
+        param: None,
+    })
+}
+
+/// 取得 Image Actor，沒有的話就載入
+async fn get_or_load(
+    config: &ModelConfig,
+    image_pool: &ImagePool,
+) -> Result<Recipient<ProcessImages>, String> {
+    crate::backends::get_or_load::<SimpleDiffusion, _>(config, image_pool).await
+}
+
+#[utoipa::path(
+    request_body = ImagesRequest,
+    responses(
+        (status = OK, description = "Success", body = ImagesResponse, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/images/generations")]
+pub async fn images_generations(
+    req_body: web::Json<ImagesRequest>,
+    image_pool: web::Data<ImagePool>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    cluster: web::Data<Cluster>,
+    req: HttpRequest,
+) -> impl Responder {
+    if !all_configs.contains_key(&req_body.model) {
+        if let Some(peer) = cluster.peer_for(&req_body.model, &req).await {
+            return cluster
+                .forward(&peer, "/v1/images/generations", &req_body.0)
+                .await;
+        }
+    }
+    let Some(config) = all_configs
+        .get(&req_body.model)
+        .filter(|c| c.model_type == ModelType::Image)
+    else {
+        return HttpResponse::BadRequest().json(OpenAiError {
+            message: format!(
+                "The model {} does not exist or is not an image model.",
+                req_body.model
+            ),
+            code: "model_not_found".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some("model".to_owned()),
+        });
+    };
+
+    let diffusion = config.diffusion.clone().unwrap_or_default();
+    if let Some(size) = &req_body.size {
+        if *size != diffusion.size {
+            return bad_request(
+                format!(
+                    "Model {} only supports size {}.",
+                    config.model_name, diffusion.size
+                ),
+                "size",
+            );
+        }
+    }
+    if req_body
+        .response_format
+        .as_deref()
+        .is_some_and(|f| f != "b64_json")
+    {
+        return bad_request(
+            "Only b64_json response_format is supported.".to_owned(),
+            "response_format",
+        );
+    }
+    if req_body.n == 0 || req_body.n > 10 {
+        return bad_request("n should be between 1 and 10.".to_owned(), "n");
+    }
+
+    let recipient = match get_or_load(config, &image_pool).await {
+        Ok(recipient) => recipient,
+        Err(e) => return internal_error(e),
+    };
+
+    let send_future = recipient.send(ProcessImages {
+        prompt: req_body.prompt.clone(),
+        n: req_body.n,
+        steps: req_body.num_inference_steps,
+        seed: req_body.seed,
+    });
+    // NPU 上一張圖要好幾秒
+    let timeout = Duration::from_secs(120 * req_body.n as u64);
+    let images = match actix_web::rt::time::timeout(timeout, send_future).await {
+        Ok(Ok(Ok(images))) => images,
+        Ok(Ok(Err(_))) => return internal_error("Image generation failed.".to_owned()),
+        Ok(Err(e)) => return internal_error(format!("Internal server error:{}", e)),
+        Err(_timeout) => return ApiError::busy("Server Busy.").response(),
+    };
+
+    let created = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    HttpResponse::Ok().json(ImagesResponse {
+        created,
+        data: images
+            .iter()
+            .map(|png| ImageData {
+                b64_json: base64::engine::general_purpose::STANDARD.encode(png),
+            })
+            .collect(),
+    })
+}
@@ -0,0 +1,229 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    chat::{LlmPool, ShutdownPool},
+    sessions::Sessions,
+    utils::ModelConfig,
+    ShutdownMessages,
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a model stays loaded after its last request ends, Ollama's `keep_alive`: seconds or
+/// a duration like `"10m"`, `0` unloads right away and a negative value keeps it loaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeepAlive {
+    /// 不設定就跟以前一樣，等換模型才卸載
+    #[default]
+    Forever,
+    For(Duration),
+}
+
+impl KeepAlive {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let invalid = || format!("Invalid keep_alive \"{}\"", value);
+        let (number, unit) = match value.find(|c: char| c.is_ascii_alphabetic()) {
+            Some(i) => value.split_at(i),
+            None => (value, "s"),
+        };
+        let number: f64 = number.parse().map_err(|_| invalid())?;
+        let secs = match unit {
+            "ms" => number / 1000.0,
+            "s" => number,
+            "m" => number * 60.0,
+            "h" => number * 3600.0,
+            _ => return Err(invalid()),
+        };
+        Ok(Self::from_secs(secs))
+    }
+
+    pub(crate) fn from_secs(secs: f64) -> Self {
+        if secs < 0.0 {
+            KeepAlive::Forever
+        } else {
+            KeepAlive::For(Duration::from_secs_f64(secs))
+        }
+    }
+
+    /// Seconds, `-1` for [`KeepAlive::Forever`].
+    pub(crate) fn secs(&self) -> f64 {
+        match self {
+            KeepAlive::Forever => -1.0,
+            KeepAlive::For(duration) => duration.as_secs_f64(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for KeepAlive {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Secs(f64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Secs(secs) => Ok(KeepAlive::from_secs(secs)),
+            Raw::Text(text) => KeepAlive::parse(&text).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl Serialize for KeepAlive {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            KeepAlive::Forever => serializer.serialize_i64(-1),
+            KeepAlive::For(_) => serializer.serialize_f64(self.secs()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Idle {
+    keep_alive: KeepAlive,
+    since: Instant,
+}
+
+impl Idle {
+    fn expires(&self) -> Option<Instant> {
+        match self.keep_alive {
+            KeepAlive::Forever => None,
+            KeepAlive::For(duration) => Some(self.since + duration),
+        }
+    }
+}
+
+/// Idle time of the loaded LLMs, unloading each one when its `keep_alive` runs out.
+#[derive(Debug, Clone, Default)]
+pub struct KeepAlives(Arc<Mutex<HashMap<String, Idle>>>);
+
+impl KeepAlives {
+    /// The keep-alive of the latest request wins, like Ollama.
+    pub fn set(&self, model: &str, keep_alive: KeepAlive) {
+        self.0.lock().unwrap().insert(
+            model.to_owned(),
+            Idle {
+                keep_alive,
+                since: Instant::now(),
+            },
+        );
+    }
+
+    /// When the model unloads if no request comes in, `None` when it stays loaded.
+    pub fn expires_at(&self, model: &str) -> Option<SystemTime> {
+        let idle = *self.0.lock().unwrap().get(model)?;
+        let left = idle.expires()?.saturating_duration_since(Instant::now());
+        Some(SystemTime::now() + left)
+    }
+
+    /// Loaded models whose keep-alive ran out at `now`. Models with active sessions are not idle,
+    /// their time starts over.
+    fn expired(
+        &self,
+        loaded: &[String],
+        sessions: &Sessions,
+        all_configs: &HashMap<String, ModelConfig>,
+        now: Instant,
+    ) -> Vec<String> {
+        let mut idle = self.0.lock().unwrap();
+        // 已經被換掉的模型不用再記
+        idle.retain(|model, _| loaded.contains(model) || sessions.active(model) > 0);
+        loaded
+            .iter()
+            .filter(|model| {
+                let entry = idle.entry(model.to_string()).or_insert_with(|| Idle {
+                    keep_alive: all_configs
+                        .get(*model)
+                        .and_then(|config| config.keep_alive)
+                        .unwrap_or_default(),
+                    since: now,
+                });
+                if sessions.active(model) > 0 {
+                    entry.since = now;
+                    return false;
+                }
+                entry.expires().is_some_and(|expires| expires <= now)
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn spawn(
+        self,
+        llm_pool: LlmPool,
+        shutdown_pool: ShutdownPool,
+        all_configs: HashMap<String, ModelConfig>,
+        sessions: Sessions,
+    ) {
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let loaded: Vec<String> = llm_pool.lock().unwrap().keys().cloned().collect();
+                for model in self.expired(&loaded, &sessions, &all_configs, Instant::now()) {
+                    log::info!("Keep-alive of {} ran out, unloading", model);
+                    self.0.lock().unwrap().remove(&model);
+                    llm_pool.lock().unwrap().remove(&model);
+                    if let Some(addr) = shutdown_pool.lock().unwrap().remove(&model) {
+                        actix_web::rt::spawn(async move {
+                            let _ = addr.send(ShutdownMessages).await;
+                        });
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unloads_idle_models() {
+        assert_eq!(
+            KeepAlive::parse("5m"),
+            Ok(KeepAlive::For(Duration::from_secs(300)))
+        );
+        assert_eq!(KeepAlive::parse("-1"), Ok(KeepAlive::Forever));
+        assert!(KeepAlive::parse("5 days").is_err());
+        let keep_alive: KeepAlive = serde_json::from_value(serde_json::json!(0)).unwrap();
+        assert_eq!(keep_alive, KeepAlive::For(Duration::ZERO));
+
+        let keep_alives = KeepAlives::default();
+        let sessions = Sessions::default();
+        let configs = HashMap::new();
+        let loaded = ["qwen".to_owned(), "pinned".to_owned()];
+        keep_alives.set("qwen", KeepAlive::For(Duration::from_secs(60)));
+        keep_alives.set("pinned", KeepAlive::Forever);
+        let now = Instant::now();
+        assert!(keep_alives
+            .expired(&loaded, &sessions, &configs, now)
+            .is_empty());
+
+        // 還在回答的模型不算閒置
+        let session = sessions.try_start("qwen", None);
+        let later = now + Duration::from_secs(61);
+        assert!(keep_alives
+            .expired(&loaded, &sessions, &configs, later)
+            .is_empty());
+        drop(session);
+        assert_eq!(
+            keep_alives.expired(
+                &loaded,
+                &sessions,
+                &configs,
+                later + Duration::from_secs(61)
+            ),
+            vec!["qwen".to_owned()]
+        );
+        assert!(keep_alives.expires_at("pinned").is_none());
+    }
+}
@@ -1,10 +1,81 @@
+pub mod admin;
+pub mod aliases;
+pub mod anthropic;
 pub mod asr;
 pub mod audio;
+pub mod audio_input;
+pub mod backends;
+pub mod batches;
+pub mod budgets;
+pub mod capabilities;
 pub mod chat;
+pub mod choices;
+pub mod cluster;
+pub mod coalesce;
+pub mod completions;
+pub mod config_format;
+pub mod crash;
+pub mod debug;
+pub mod diffusion;
+pub mod discover;
+pub mod embedding;
+pub mod embeddings;
+pub mod errors;
+pub mod eviction;
+pub mod export;
+pub mod fallback;
+pub mod files;
+pub mod finish;
+pub mod fingerprint;
+pub mod hub;
+pub mod i18n;
+pub mod keep_alive;
+pub mod image_input;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod images;
+pub mod listing;
 pub mod llm;
+pub mod logprobs;
+pub mod mcp;
 pub mod ollama;
 pub mod openai;
+pub mod plugins;
+pub mod prefill;
+pub mod preload;
+pub mod prompt_cache;
+pub mod proxy;
+pub mod quotas;
+pub mod rag;
+pub mod realtime;
+pub mod reasoning;
+#[cfg(feature = "sqlite")]
+pub mod registry;
+pub mod rerank;
+pub mod sampling;
+pub mod reranker;
+pub mod responses;
+pub mod schedule;
+pub mod sessions;
+pub mod speech;
+pub mod stop;
+#[cfg(feature = "sqlite")]
+pub mod store;
+pub mod structured;
+pub mod tgi;
+pub mod thermal;
+pub mod tools;
+pub mod traffic;
+pub mod transcript;
+pub mod tts;
+pub mod turn_detection;
+pub mod ui;
+pub mod uploads;
+pub mod usage;
 pub mod utils;
+pub mod validate;
+pub mod vector_stores;
+pub mod ws;
 
 use std::{io::Read, pin::Pin};
 
@@ -72,6 +143,21 @@ impl<'de> Deserialize<'de> for Content {
     }
 }
 
+impl Content {
+    /// Text of all parts joined, images are skipped.
+    pub fn text(&self) -> String {
+        match self {
+            Content::String(s) => s.clone(),
+            Content::Array(items) => items.join(""),
+            Content::Parts(parts) => parts
+                .iter()
+                .filter_map(|p| p.text.clone())
+                .collect::<Vec<_>>()
+                .join(""),
+        }
+    }
+}
+
 impl Serialize for Content {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -92,6 +178,17 @@ pub struct ContentPart {
     pub text: Option<String>,
     #[serde(default, deserialize_with = "deserialize_null_image_url")]
     pub image_url: Option<ImageUrl>,
+    /// Audio of an `input_audio` part, transcribed into the prompt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_audio: Option<InputAudio>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct InputAudio {
+    /// Base64 encoded audio
+    pub data: String,
+    /// `wav` or `mp3`
+    pub format: String,
 }
 
 fn deserialize_null_image_url<'de, D>(deserializer: D) -> Result<Option<ImageUrl>, D::Error>
@@ -126,9 +223,12 @@ pub enum Role {
     Assistant,
     #[serde(rename = "developer")]
     Developer,
+    /// Result of a tool call, answering `tool_call_id`
+    #[serde(rename = "tool")]
+    Tool,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, utoipa::ToSchema)]
 pub struct Message {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[schema(value_type = Role)]
@@ -136,12 +236,49 @@ pub struct Message {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[schema(value_type = Content)]
     pub content: Option<Content>,
+    /// Thinking of think-enabled models, taken out of `content`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
+    /// Functions the assistant asked to call
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<MessageToolCall>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
 }
 
-#[derive(actix::Message)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct MessageToolCall {
+    /// Position of the call, only set in streamed deltas
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    pub id: String,
+    pub r#type: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct FunctionCall {
+    pub name: String,
+    /// JSON encoded arguments
+    pub arguments: String,
+}
+
+#[derive(actix::Message, Default)]
 #[rtype(result = "Result<Pin<Box<dyn futures::Stream<Item = String> + Send + 'static>>, ()>")]
 pub struct ProcessMessages {
     pub messages: Vec<Message>,
+    /// Conversation id, selects the prompt cache file
+    pub session: Option<String>,
+    /// Generation ends before any of these
+    pub stop: Vec<String>,
+    /// Generated tokens before the answer is cut off with `finish_reason: "length"`
+    pub max_tokens: Option<usize>,
+    /// Sampling values of the request, the model config fills in the rest
+    pub sampling: crate::sampling::SamplingPreset,
+    /// Asks for a repeatable answer
+    pub seed: Option<i32>,
+    /// Alternatives per token when the answer should carry logprobs
+    pub logprobs: Option<usize>,
 }
 
 #[derive(actix::Message)]
@@ -152,7 +289,64 @@ pub enum ProcessAudio {
 }
 
 pub enum AsrText {
-    SenseVoice(sensevoice_rs::VoiceText),
+    SenseVoice(sensevoice_rs::VoiceText, AudioSpan),
+}
+
+/// Where a recognized segment is in the audio, in milliseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AudioSpan {
+    pub start_ms: u32,
+    pub end_ms: u32,
+}
+
+#[derive(actix::Message)]
+#[rtype(result = "Result<Embeddings, ()>")]
+pub struct ProcessEmbeddings {
+    pub input: Vec<String>,
+}
+
+/// L2-normalized vectors, in the same order as the input.
+pub struct Embeddings {
+    pub data: Vec<Vec<f32>>,
+    pub prompt_tokens: usize,
+}
+
+#[derive(actix::Message)]
+#[rtype(result = "Result<RerankScores, ()>")]
+pub struct ProcessRerank {
+    pub query: String,
+    pub documents: Vec<String>,
+}
+
+/// Relevance score (sigmoid of the cross-encoder logit) of each document, in input order.
+pub struct RerankScores {
+    pub scores: Vec<f32>,
+    pub prompt_tokens: usize,
+}
+
+/// Returns `n` PNG encoded images.
+#[derive(actix::Message)]
+#[rtype(result = "Result<Vec<Vec<u8>>, ()>")]
+pub struct ProcessImages {
+    pub prompt: String,
+    pub n: usize,
+    pub steps: Option<usize>,
+    pub seed: Option<u64>,
+}
+
+/// Returns 16-bit mono samples.
+#[derive(actix::Message)]
+#[rtype(result = "Result<SpeechAudio, ()>")]
+pub struct ProcessSpeech {
+    pub input: String,
+    pub voice: String,
+    /// 1 is normal speed
+    pub speed: f32,
+}
+
+pub struct SpeechAudio {
+    pub samples: Vec<i16>,
+    pub sample_rate: u32,
 }
 
 #[derive(actix::Message)]
@@ -161,10 +355,20 @@ pub struct ShutdownMessages;
 
 pub trait ASR: Actor + Handler<ProcessAudio> + Handler<ShutdownMessages> + AIModel {}
 pub trait LLM: Actor + Handler<ProcessMessages> + Handler<ShutdownMessages> + AIModel {}
+pub trait Embedding:
+    Actor + Handler<ProcessEmbeddings> + Handler<ShutdownMessages> + AIModel
+{
+}
+pub trait Rerank: Actor + Handler<ProcessRerank> + Handler<ShutdownMessages> + AIModel {}
+pub trait Diffusion: Actor + Handler<ProcessImages> + Handler<ShutdownMessages> + AIModel {}
+pub trait TTS: Actor + Handler<ProcessSpeech> + Handler<ShutdownMessages> + AIModel {}
 
 pub trait ModelProgress {
     fn model_load(&mut self, size: usize, filename: &str, start: std::time::Instant);
     fn model_finished(&mut self);
+    /// The next download starts `done` bytes into the `total` bytes of a model made of several
+    /// files, so the progress covers all of them.
+    fn download_offset(&mut self, _done: usize, _total: usize) {}
 }
 
 impl ModelProgress for () {
@@ -0,0 +1,146 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use actix::{Message, Recipient};
+use actix_web::{web, HttpRequest};
+use serde::Deserialize;
+
+use crate::{
+    utils::{ModelConfig, ModelType},
+    ProcessAudio, ProcessEmbeddings, ProcessImages, ProcessMessages, ProcessRerank,
+};
+
+/// Filters and pagination shared by `/v1/models` and `/api/tags`.
+#[derive(Debug, Clone, Default, Deserialize, utoipa::IntoParams)]
+pub struct ModelsQuery {
+    /// `LLM`, `VLM`, `ASR`, `Embedding`, `Rerank`, `Echo` or `Image`
+    pub model_type: Option<String>,
+    /// `rkllm`, `rknn`, `sensevoice`, `piper` or `echo`
+    pub backend: Option<String>,
+    pub loaded: Option<bool>,
+    /// Part of the model name, case insensitive
+    pub name: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+/// Runtime that serves a model type.
+pub fn backend(model_type: &ModelType) -> &'static str {
+    match model_type {
+        ModelType::LLM | ModelType::VLM => "rkllm",
+        ModelType::ASR => "sensevoice",
+        ModelType::Embedding | ModelType::Rerank | ModelType::Image => "rknn",
+        ModelType::Echo => "echo",
+        ModelType::TTS => "piper",
+    }
+}
+
+impl ModelsQuery {
+    fn matches_name(&self, name: &str) -> bool {
+        self.name
+            .as_ref()
+            .is_none_or(|part| name.to_lowercase().contains(&part.to_lowercase()))
+    }
+
+    pub fn matches(&self, name: &str, config: &ModelConfig, loaded: &HashSet<String>) -> bool {
+        self.matches_name(name)
+            && self
+                .model_type
+                .as_ref()
+                .is_none_or(|t| format!("{:?}", config.model_type).eq_ignore_ascii_case(t))
+            && self
+                .backend
+                .as_ref()
+                .is_none_or(|b| backend(&config.model_type).eq_ignore_ascii_case(b))
+            && self
+                .loaded
+                .is_none_or(|l| loaded.contains(&config.model_name) == l)
+    }
+
+    /// 其他節點的模型只知道名稱，有其他條件就不列
+    pub fn matches_peer(&self, name: &str) -> bool {
+        self.model_type.is_none()
+            && self.backend.is_none()
+            && self.loaded.is_none()
+            && self.matches_name(name)
+    }
+
+    pub fn page<T>(&self, items: Vec<T>) -> Vec<T> {
+        items
+            .into_iter()
+            .skip(self.offset.unwrap_or(0))
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+fn pool_keys<M>(req: &HttpRequest) -> Vec<String>
+where
+    M: Message + Send + 'static,
+    M::Result: Send,
+{
+    req.app_data::<web::Data<Arc<Mutex<HashMap<String, Recipient<M>>>>>>()
+        .map(|pool| {
+            pool.lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, recipient)| recipient.connected())
+                .map(|(model, _)| model.clone())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Names of the models currently loaded in any pool.
+pub fn loaded_models(req: &HttpRequest) -> HashSet<String> {
+    pool_keys::<ProcessMessages>(req)
+        .into_iter()
+        .chain(pool_keys::<ProcessAudio>(req))
+        .chain(pool_keys::<ProcessEmbeddings>(req))
+        .chain(pool_keys::<ProcessRerank>(req))
+        .chain(pool_keys::<ProcessImages>(req))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_and_pages() {
+        let qwen = ModelConfig {
+            model_name: "qwen".to_owned(),
+            ..Default::default()
+        };
+        let bge = ModelConfig {
+            model_name: "bge".to_owned(),
+            model_type: ModelType::Embedding,
+            ..Default::default()
+        };
+        let loaded = HashSet::from(["qwen".to_owned()]);
+        let query = ModelsQuery {
+            backend: Some("RKLLM".to_owned()),
+            loaded: Some(true),
+            ..Default::default()
+        };
+        assert!(query.matches("Qwen-Alias", &qwen, &loaded));
+        assert!(!query.matches("bge", &bge, &loaded));
+        assert!(!query.matches_peer("remote"));
+
+        let query = ModelsQuery {
+            model_type: Some("embedding".to_owned()),
+            name: Some("BG".to_owned()),
+            ..Default::default()
+        };
+        assert!(query.matches("bge", &bge, &loaded));
+
+        let query = ModelsQuery {
+            limit: Some(2),
+            offset: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(query.page(vec![1, 2, 3, 4]), [2, 3]);
+    }
+}
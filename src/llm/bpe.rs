@@ -0,0 +1,271 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::Deserialize;
+
+/// Byte-level BPE of a HF `tokenizer.json` (Qwen / Llama 3 / DeepSeek style), only used for
+/// counting and inspecting tokens. Generation itself tokenizes inside RKLLM.
+#[derive(Debug, Clone)]
+pub struct BpeTokenizer {
+    vocab: HashMap<String, u32>,
+    ranks: HashMap<(String, String), usize>,
+    /// 長的排前面，比對時優先
+    added: Vec<(String, u32)>,
+    byte_chars: Vec<char>,
+    /// id -> token，給 logprobs 顯示
+    tokens: HashMap<u32, String>,
+}
+
+#[derive(Deserialize)]
+struct TokenizerJson {
+    model: BpeModel,
+    #[serde(default)]
+    added_tokens: Vec<AddedToken>,
+}
+
+#[derive(Deserialize)]
+struct BpeModel {
+    vocab: HashMap<String, u32>,
+    merges: Vec<Merge>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Merge {
+    String(String),
+    Pair((String, String)),
+}
+
+#[derive(Deserialize)]
+struct AddedToken {
+    id: u32,
+    content: String,
+}
+
+/// GPT-2 的 byte 對 unicode 表，讓每個 byte 都是可見字元
+fn byte_chars() -> Vec<char> {
+    let mut chars = vec!['\0'; 256];
+    let mut extra = 0;
+    for byte in 0..=255u8 {
+        let visible = matches!(byte, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF);
+        chars[byte as usize] = if visible {
+            byte as char
+        } else {
+            extra += 1;
+            char::from_u32(255 + extra).unwrap()
+        };
+    }
+    chars
+}
+
+fn is_letter(c: char) -> bool {
+    c.is_alphabetic()
+}
+
+fn is_number(c: char) -> bool {
+    c.is_numeric()
+}
+
+fn is_newline(c: char) -> bool {
+    c == '\r' || c == '\n'
+}
+
+fn is_punct(c: char) -> bool {
+    !c.is_whitespace() && !is_letter(c) && !is_number(c)
+}
+
+/// Length (in chars) of the next pre-token, following the Qwen2 split regex:
+/// `(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+`
+fn next_pretoken(chars: &[char]) -> usize {
+    let c = chars[0];
+    let next = chars.get(1).copied();
+
+    if c == '\'' {
+        let rest: String = chars[1..chars.len().min(3)]
+            .iter()
+            .collect::<String>()
+            .to_lowercase();
+        for contraction in ["re", "ve", "ll", "s", "t", "m", "d"] {
+            if rest.starts_with(contraction) {
+                return 1 + contraction.len();
+            }
+        }
+    }
+    let letters_from =
+        |start: usize| start + chars[start..].iter().take_while(|c| is_letter(**c)).count();
+    if is_letter(c) {
+        return letters_from(0);
+    }
+    if !is_newline(c) && !is_number(c) && next.is_some_and(is_letter) {
+        return letters_from(1);
+    }
+    if is_number(c) {
+        return 1;
+    }
+    let punct_start = usize::from(c == ' ' && next.is_some_and(is_punct));
+    if punct_start == 1 || is_punct(c) {
+        let end = punct_start
+            + chars[punct_start..]
+                .iter()
+                .take_while(|c| is_punct(**c))
+                .count();
+        return end + chars[end..].iter().take_while(|c| is_newline(**c)).count();
+    }
+
+    let spaces = chars.iter().take_while(|c| c.is_whitespace()).count();
+    if let Some(last_newline) = chars[..spaces].iter().rposition(|c| is_newline(*c)) {
+        return last_newline + 1;
+    }
+    // 最後一個空白留給下一個字
+    if spaces < chars.len() && spaces > 1 {
+        return spaces - 1;
+    }
+    spaces.max(1)
+}
+
+impl BpeTokenizer {
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let json: TokenizerJson = serde_json::from_slice(&std::fs::read(path)?)?;
+        Ok(Self::new(json))
+    }
+
+    fn new(json: TokenizerJson) -> Self {
+        let ranks = json
+            .model
+            .merges
+            .into_iter()
+            .filter_map(|merge| match merge {
+                Merge::Pair(pair) => Some(pair),
+                Merge::String(merge) => merge
+                    .split_once(' ')
+                    .map(|(a, b)| (a.to_owned(), b.to_owned())),
+            })
+            .enumerate()
+            .map(|(rank, pair)| (pair, rank))
+            .collect();
+        let mut added: Vec<(String, u32)> = json
+            .added_tokens
+            .into_iter()
+            .map(|token| (token.content, token.id))
+            .collect();
+        added.sort_by_key(|(content, _)| std::cmp::Reverse(content.len()));
+        let tokens = json
+            .model
+            .vocab
+            .iter()
+            .map(|(token, id)| (*id, token.clone()))
+            .chain(added.iter().map(|(content, id)| (*id, content.clone())))
+            .collect();
+        Self {
+            vocab: json.model.vocab,
+            ranks,
+            added,
+            byte_chars: byte_chars(),
+            tokens,
+        }
+    }
+
+    fn bpe(&self, word: &str, ids: &mut Vec<u32>) {
+        let mut symbols: Vec<String> = word
+            .bytes()
+            .map(|b| self.byte_chars[b as usize].to_string())
+            .collect();
+        loop {
+            let best = symbols
+                .windows(2)
+                .enumerate()
+                .filter_map(|(i, pair)| {
+                    self.ranks
+                        .get(&(pair[0].clone(), pair[1].clone()))
+                        .map(|rank| (*rank, i))
+                })
+                .min();
+            let Some((_, i)) = best else {
+                break;
+            };
+            let merged = symbols.remove(i + 1);
+            symbols[i].push_str(&merged);
+        }
+        ids.extend(symbols.iter().filter_map(|s| self.vocab.get(s)));
+    }
+
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        let mut ids = Vec::new();
+        let mut rest = text;
+        loop {
+            // 先切出 <|im_start|> 這類特殊 token
+            let special = rest.char_indices().find_map(|(pos, _)| {
+                self.added
+                    .iter()
+                    .find(|(content, _)| rest[pos..].starts_with(content.as_str()))
+                    .map(|(content, id)| (pos, content.len(), *id))
+            });
+            let Some((pos, len, id)) = special else {
+                self.encode_plain(rest, &mut ids);
+                return ids;
+            };
+            self.encode_plain(&rest[..pos], &mut ids);
+            ids.push(id);
+            rest = &rest[pos + len..];
+        }
+    }
+
+    fn encode_plain(&self, text: &str, ids: &mut Vec<u32>) {
+        let chars: Vec<char> = text.chars().collect();
+        let mut start = 0;
+        while start < chars.len() {
+            let len = next_pretoken(&chars[start..]);
+            let word: String = chars[start..start + len].iter().collect();
+            self.bpe(&word, ids);
+            start += len;
+        }
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        self.encode(text).len()
+    }
+
+    /// The bytes token `id` stands for.
+    pub fn token_bytes(&self, id: u32) -> Option<Vec<u8>> {
+        let token = self.tokens.get(&id)?;
+        if self.added.iter().any(|(_, added)| *added == id) {
+            return Some(token.as_bytes().to_vec());
+        }
+        token
+            .chars()
+            .map(|c| {
+                self.byte_chars
+                    .iter()
+                    .position(|b| *b == c)
+                    .map(|b| b as u8)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bpe_merges_and_special_tokens() {
+        let json: TokenizerJson = serde_json::from_value(serde_json::json!({
+            "model": {
+                "vocab": { "h": 0, "i": 1, "hi": 2, "Ġ": 3, "Ġhi": 4, "!": 5 },
+                "merges": ["h i", ["Ġ", "hi"]]
+            },
+            "added_tokens": [{ "id": 6, "content": "<|im_end|>" }]
+        }))
+        .unwrap();
+        let tokenizer = BpeTokenizer::new(json);
+        assert_eq!(tokenizer.encode("hi hi!<|im_end|>"), vec![2, 4, 5, 6]);
+        assert_eq!(tokenizer.token_bytes(4).as_deref(), Some(&b" hi"[..]));
+        assert_eq!(
+            tokenizer.token_bytes(6).as_deref(),
+            Some(&b"<|im_end|>"[..])
+        );
+
+        let chars: Vec<char> = "  hello\n\nworld".chars().collect();
+        assert_eq!(next_pretoken(&chars), 1);
+        assert_eq!(next_pretoken(&chars[1..]), 6);
+    }
+}
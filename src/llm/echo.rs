@@ -0,0 +1,336 @@
+use std::pin::Pin;
+use std::time::Duration;
+
+use actix::{Actor, ActorContext};
+use hf_hub::api::Progress;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::chat::FinishReason;
+use crate::utils::{EchoConfig, EchoRule, ModelConfig};
+use crate::{
+    AIModel, Content, Message, ModelProgress, ProcessMessages, Role, ShutdownMessages, LLM,
+};
+
+/// Replays the responses configured in `echo`, no NPU or weights involved.
+#[derive(Debug)]
+pub struct EchoLLM {
+    config: EchoConfig,
+}
+
+impl Actor for EchoLLM {
+    type Context = actix::Context<Self>;
+}
+
+fn last_user_text(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .rev()
+        .find(|m| matches!(m.role, Some(Role::User)))
+        .and_then(|m| m.content.as_ref())
+        .map(Content::text)
+        .unwrap_or_default()
+}
+
+fn pick_rule(config: &EchoConfig, input: &str) -> EchoRule {
+    config
+        .rules
+        .iter()
+        .find(|rule| rule.when.as_deref().is_none_or(|when| input.contains(when)))
+        .cloned()
+        .unwrap_or_else(|| EchoRule {
+            when: None,
+            response: input.to_owned(),
+            error: None,
+        })
+}
+
+/// 以空白切 token，空白留在前一個 token 後面
+fn split_tokens(text: &str) -> Vec<String> {
+    text.split_inclusive(char::is_whitespace)
+        .map(|s| s.to_owned())
+        .collect()
+}
+
+impl actix::Handler<ProcessMessages> for EchoLLM {
+    type Result = Result<Pin<Box<dyn futures::Stream<Item = String> + Send + 'static>>, ()>;
+
+    fn handle(&mut self, msg: ProcessMessages, _ctx: &mut Self::Context) -> Self::Result {
+        let (tx, rx) = tokio::sync::mpsc::channel(64);
+        let prompt = last_user_text(&msg.messages);
+        let rule = pick_rule(&self.config, &prompt);
+        let prompt_tokens = split_tokens(&prompt).len();
+        let delay = Duration::from_millis(self.config.token_delay_ms);
+
+        let mut stop = crate::stop::StopSequences::new(msg.stop);
+        let max_tokens = msg.max_tokens;
+
+        tokio::spawn(async move {
+            let tokens = split_tokens(&rule.response);
+            let truncated = max_tokens.filter(|max| tokens.len() > *max);
+            let mut completion_tokens = 0;
+            for token in tokens.into_iter().take(truncated.unwrap_or(usize::MAX)) {
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+                completion_tokens += 1;
+                let (token, stopped) = stop.push(&token);
+                // 對方斷線就不用再送了
+                if !token.is_empty() && tx.send(token).await.is_err() {
+                    log::info!("Echo stream cancelled by client");
+                    return;
+                }
+                if stopped {
+                    break;
+                }
+            }
+            let rest = stop.flush();
+            if !rest.is_empty() {
+                let _ = tx.send(rest).await;
+            }
+            if truncated.is_some() && !stop.stopped() {
+                let _ = tx.send(crate::finish::message(FinishReason::Length)).await;
+            }
+            if let Some(error) = rule.error {
+                let _ = tx.send(format!("Model error: {}", error)).await;
+            }
+            let _ = tx
+                .send(crate::usage::message(prompt_tokens, completion_tokens))
+                .await;
+            let _ = tx.send(String::new()).await;
+        });
+
+        let stream = ReceiverStream::new(rx);
+        Ok(Box::pin(stream))
+    }
+}
+
+impl actix::Handler<ShutdownMessages> for EchoLLM {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, _: ShutdownMessages, ctx: &mut Self::Context) -> Self::Result {
+        ctx.stop();
+        Ok(())
+    }
+}
+
+impl AIModel for EchoLLM {
+    type Config = ModelConfig;
+
+    fn init_with_progress<P: Progress + ModelProgress + Clone>(
+        config: &Self::Config,
+        p: Option<P>,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(error) = config
+            .echo
+            .as_ref()
+            .and_then(|echo| echo.load_error.clone())
+        {
+            return Err(error.into());
+        }
+        if let Some(mut progress) = p {
+            progress.model_load(0, &config.model_name, std::time::Instant::now());
+            progress.model_finished();
+        }
+        Ok(Self {
+            config: config.echo.clone().unwrap_or_default(),
+        })
+    }
+}
+
+impl LLM for EchoLLM {}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_web::{body::MessageBody, web, App};
+
+    use super::*;
+    use crate::{sessions::Sessions, utils::ModelType};
+
+    fn echo_config(rules: Vec<EchoRule>) -> ModelConfig {
+        ModelConfig {
+            model_name: "echo".to_owned(),
+            model_type: ModelType::Echo,
+            echo: Some(EchoConfig {
+                token_delay_ms: 0,
+                rules,
+                load_error: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// `/v1/chat/completions` 的完整流程，模型是 Echo
+    pub(crate) async fn post_chat(
+        config: ModelConfig,
+        sessions: Sessions,
+        body: serde_json::Value,
+    ) -> actix_web::dev::ServiceResponse {
+        let configs = HashMap::from([(config.model_name.clone(), config)]);
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(crate::chat::LlmPool::default()))
+                .app_data(web::Data::new(crate::chat::ShutdownPool::default()))
+                .app_data(web::Data::new(configs))
+                .app_data(web::Data::new(crate::fallback::FailedModels::default()))
+                .app_data(web::Data::new(crate::cluster::Cluster::new(vec![])))
+                .app_data(web::Data::new(crate::mcp::McpClient::new(vec![])))
+                .app_data(web::Data::new(sessions))
+                .service(web::scope("/v1").service(crate::chat::chat_completions)),
+        )
+        .await;
+        let req = actix_web::test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(body)
+            .to_request();
+        actix_web::test::call_service(&app, req).await
+    }
+
+    #[actix_web::test]
+    async fn stream_ends_with_one_done() {
+        let response = post_chat(
+            echo_config(vec![]),
+            Sessions::default(),
+            serde_json::json!({
+                "model": "echo",
+                "stream": true,
+                "messages": [{ "role": "user", "content": "hello there" }],
+            }),
+        )
+        .await;
+        assert!(response.status().is_success());
+        let body = actix_web::test::read_body(response).await;
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.ends_with(crate::chat::SSE_DONE), "{}", body);
+        assert_eq!(body.matches("[DONE]").count(), 1);
+        assert!(body.contains("hello"));
+    }
+
+    #[actix_web::test]
+    async fn tool_call_rule_becomes_tool_calls() {
+        let rule = EchoRule {
+            when: Some("weather".to_owned()),
+            response: "<tool_call>\n{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Taipei\"}}\n</tool_call>".to_owned(),
+            error: None,
+        };
+        let response = post_chat(
+            echo_config(vec![rule]),
+            Sessions::default(),
+            serde_json::json!({
+                "model": "echo",
+                "messages": [{ "role": "user", "content": "weather in Taipei?" }],
+                "tools": [{ "type": "function", "function": { "name": "get_weather", "parameters": { "type": "object" } } }],
+            }),
+        )
+        .await;
+        assert!(response.status().is_success());
+        let completion: serde_json::Value = actix_web::test::read_body_json(response).await;
+        let choice = &completion["choices"][0];
+        assert_eq!(choice["finish_reason"], "tool_calls");
+        let function = &choice["message"]["tool_calls"][0]["function"];
+        assert_eq!(function["name"], "get_weather");
+        assert_eq!(function["arguments"], "{\"city\":\"Taipei\"}");
+    }
+
+    #[actix_web::test]
+    async fn error_rule_ends_stream_with_error_event() {
+        let rule = EchoRule {
+            when: None,
+            response: "partial".to_owned(),
+            error: Some("exploded".to_owned()),
+        };
+        let response = post_chat(
+            echo_config(vec![rule]),
+            Sessions::default(),
+            serde_json::json!({
+                "model": "echo",
+                "stream": true,
+                "messages": [{ "role": "user", "content": "boom" }],
+            }),
+        )
+        .await;
+        let body = actix_web::test::read_body(response).await;
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("partial"));
+        assert!(body.contains("exploded"), "{}", body);
+        // 出錯的回答不當成正常結束
+        assert!(!body.contains("\"finish_reason\":\"stop\""));
+        assert!(!body.contains("[DONE]"));
+    }
+
+    #[actix_web::test]
+    async fn load_failure_ends_stream_with_error_event() {
+        let mut config = echo_config(vec![]);
+        config.echo.as_mut().unwrap().load_error = Some("no weights".to_owned());
+        let response = post_chat(
+            config,
+            Sessions::default(),
+            serde_json::json!({
+                "model": "echo",
+                "stream": true,
+                "messages": [{ "role": "user", "content": "hi" }],
+            }),
+        )
+        .await;
+        let body = actix_web::test::read_body(response).await;
+        let body = String::from_utf8_lossy(&body);
+        // 連線不會直接斷掉，最後一個事件是錯誤
+        let last = body.trim_end().rsplit("\n\n").next().unwrap_or_default();
+        assert!(last.starts_with("data: {\"error\""), "{}", body);
+        assert!(last.contains("no weights"));
+    }
+
+    #[actix_web::test]
+    async fn client_disconnect_ends_the_session() {
+        let mut config = echo_config(vec![]);
+        config.echo.as_mut().unwrap().token_delay_ms = 20;
+        let sessions = Sessions::default();
+        let response = post_chat(
+            config,
+            sessions.clone(),
+            serde_json::json!({
+                "model": "echo",
+                "stream": true,
+                "messages": [{ "role": "user", "content": "one two three four five six seven eight" }],
+            }),
+        )
+        .await;
+        let mut body = Box::pin(response.into_body());
+        let first = futures::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await;
+        assert!(first.is_some_and(|chunk| chunk.is_ok()));
+        assert_eq!(sessions.active("echo"), 1);
+        drop(body);
+        assert_eq!(sessions.active("echo"), 0);
+    }
+
+    #[test]
+    fn first_matching_rule_wins_and_falls_back_to_echo() {
+        let config = EchoConfig {
+            token_delay_ms: 0,
+            rules: vec![
+                EchoRule {
+                    when: Some("weather".to_owned()),
+                    response: "<tool_call>{}</tool_call>".to_owned(),
+                    error: None,
+                },
+                EchoRule {
+                    when: Some("boom".to_owned()),
+                    response: "partial".to_owned(),
+                    error: Some("exploded".to_owned()),
+                },
+            ],
+            load_error: None,
+        };
+        assert_eq!(
+            pick_rule(&config, "what is the weather").response,
+            "<tool_call>{}</tool_call>"
+        );
+        assert_eq!(
+            pick_rule(&config, "boom").error.as_deref(),
+            Some("exploded")
+        );
+        assert_eq!(pick_rule(&config, "hello there").response, "hello there");
+        assert_eq!(split_tokens("hello there"), vec!["hello ", "there"]);
+    }
+}
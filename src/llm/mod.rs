@@ -1 +1,71 @@
-pub mod simple;
\ No newline at end of file
+pub mod bpe;
+pub mod echo;
+pub mod simple;
+pub mod vision;
+
+use actix::Actor;
+use actix::Recipient;
+use autotokenizer::AutoTokenizer;
+use hf_hub::api::Progress;
+
+use crate::utils::{ModelConfig, ModelType};
+use crate::{AIModel, Message, ModelProgress, ProcessMessages, ShutdownMessages};
+
+/// 依照 model_type 載入對應的 LLM 後端
+pub enum LoadedLLM {
+    Text(simple::SimpleRkLLM),
+    Vision(vision::SimpleRkVLM),
+    Echo(echo::EchoLLM),
+}
+
+impl LoadedLLM {
+    pub fn init_with_progress<P: Progress + ModelProgress + Clone>(
+        config: &ModelConfig,
+        p: Option<P>,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        match config.model_type {
+            ModelType::VLM => Ok(Self::Vision(vision::SimpleRkVLM::init_with_progress(
+                config, p,
+            )?)),
+            ModelType::Echo => Ok(Self::Echo(echo::EchoLLM::init_with_progress(config, p)?)),
+            _ => Ok(Self::Text(simple::SimpleRkLLM::init_with_progress(
+                config, p,
+            )?)),
+        }
+    }
+
+    pub fn init(config: &ModelConfig) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Self::init_with_progress::<()>(config, None)
+    }
+
+    /// 啟動 Actor，回傳對話與關閉用的 Recipient
+    pub fn start(self) -> (Recipient<ProcessMessages>, Recipient<ShutdownMessages>) {
+        match self {
+            Self::Text(llm) => {
+                let addr = llm.start();
+                (addr.clone().recipient(), addr.recipient())
+            }
+            Self::Vision(vlm) => {
+                let addr = vlm.start();
+                (addr.clone().recipient(), addr.recipient())
+            }
+            Self::Echo(echo) => {
+                let addr = echo.start();
+                (addr.clone().recipient(), addr.recipient())
+            }
+        }
+    }
+}
+
+/// The exact prompt the backend of `config` feeds to RKLLM for `messages`.
+pub fn render_prompt(
+    atoken: &AutoTokenizer,
+    config: &ModelConfig,
+    messages: &[Message],
+) -> Result<String, String> {
+    let prompt = match config.model_type {
+        ModelType::VLM => vision::build_prompt(messages).0,
+        _ => simple::prompt_messages(messages),
+    };
+    simple::apply_chat_template(atoken, prompt).map_err(|e| e.to_string())
+}
@@ -1,32 +1,44 @@
 use actix::Actor;
 use actix::ActorContext;
-use hf_hub::api::sync::Api;
 use hf_hub::api::Progress;
-use hf_hub::Cache;
 use hf_hub::Repo;
 use rkllm_rs::prelude::*;
 use serde_variant::to_variant_name;
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::sync::LazyLock;
 use std::sync::Mutex;
 use std::time::Instant;
 use tokio_stream::wrappers::ReceiverStream;
 
 use autotokenizer::AutoTokenizer;
+use autotokenizer::ChatTemplate;
 use autotokenizer::DefaultPromptMessage;
 
+use crate::chat::FinishReason;
+use crate::llm::bpe::BpeTokenizer;
+use crate::sampling::SamplerParams;
 use crate::utils::ModelConfig;
 use crate::AIModel;
+use crate::Message;
 use crate::ModelProgress;
 use crate::ProcessMessages;
 use crate::ShutdownMessages;
 use crate::LLM;
 
-enum TokenizerSource {
-    Local(PathBuf),
-    Remote(String),
+/// 沒給 max_tokens 時 runtime 最多生成這麼多
+pub(crate) const MAX_NEW_TOKENS: i32 = 4096;
+
+/// `max_new_tokens` of the model config, or [`MAX_NEW_TOKENS`].
+pub(crate) fn max_new_tokens(config: &ModelConfig) -> i32 {
+    config
+        .max_new_tokens
+        .filter(|n| *n > 0)
+        .unwrap_or(MAX_NEW_TOKENS)
 }
 
 #[derive(Debug)]
@@ -35,45 +47,74 @@ struct FakeThreadSafeRKLLM(LLMHandle);
 unsafe impl Send for FakeThreadSafeRKLLM {}
 unsafe impl Sync for FakeThreadSafeRKLLM {}
 
+/// The runtime and the sampling it was initialized with.
+#[derive(Debug)]
+struct Loaded {
+    /// `None` after a failed re-initialization, the next request tries again
+    handle: Option<Arc<FakeThreadSafeRKLLM>>,
+    sampler: SamplerParams,
+}
+
 #[derive(Debug)]
 pub struct SimpleRkLLM {
-    handle: Arc<FakeThreadSafeRKLLM>,
+    loaded: Arc<Mutex<Loaded>>,
+    /// 重新初始化時沿用
+    llm_config: Arc<LLMConfig>,
     // 裡面沒資料，純粹用來卡位
     exec_lock: Arc<Mutex<()>>,
-    atoken: AutoTokenizer,
+    atoken: Arc<AutoTokenizer>,
     infer_params: RKLLMInferParam,
     config: ModelConfig,
+    _crash_guard: crate::crash::CleanupGuard,
 }
 
 impl Actor for SimpleRkLLM {
     type Context = actix::Context<Self>;
 }
 
+/// Flatten message content for the chat template.
+pub(crate) fn prompt_messages(messages: &[Message]) -> Vec<DefaultPromptMessage> {
+    messages
+        .iter()
+        .map(|a| {
+            let content = match &a.content {
+                Some(crate::Content::String(s)) => s.clone(),
+                Some(crate::Content::Array(items)) => items.join(""),
+                Some(crate::Content::Parts(parts)) => parts
+                    .iter()
+                    .filter_map(|p| p.text.clone())
+                    .collect::<Vec<_>>()
+                    .join(""),
+                None => "".to_owned(),
+            };
+            DefaultPromptMessage::new(to_variant_name(&a.role).unwrap(), &content)
+        })
+        .collect::<Vec<_>>()
+}
+
+/// Apply the chat template. A final assistant message is a prefill: the prompt ends inside that
+/// turn, without closing it, so the model continues its content.
+pub(crate) fn apply_chat_template(
+    atoken: &AutoTokenizer,
+    mut prompt: Vec<DefaultPromptMessage>,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let prefill = match prompt.last() {
+        Some(last) if last.role == "assistant" => prompt.pop().map(|m| m.content),
+        _ => None,
+    };
+    let input = atoken.apply_chat_template(prompt, true, None)?;
+    Ok(input + prefill.as_deref().unwrap_or_default())
+}
+
 impl actix::Handler<ProcessMessages> for SimpleRkLLM {
     type Result = Result<Pin<Box<dyn futures::Stream<Item = String> + Send + 'static>>, ()>;
 
     fn handle(&mut self, msg: ProcessMessages, _ctx: &mut Self::Context) -> Self::Result {
         let (tx, rx) = tokio::sync::mpsc::channel(64);
         let atoken = self.atoken.clone();
-        let prompt = msg
-            .messages
-            .iter()
-            .map(|a| {
-                let content = match &a.content {
-                    Some(crate::Content::String(s)) => s.clone(),
-                    Some(crate::Content::Array(items)) => items.join(""),
-                    Some(crate::Content::Parts(parts)) => parts
-                        .iter()
-                        .filter_map(|p| p.text.clone())
-                        .collect::<Vec<_>>()
-                        .join(""),
-                    None => "".to_owned(),
-                };
-                DefaultPromptMessage::new(to_variant_name(&a.role).unwrap(), &content)
-            })
-            .collect::<Vec<_>>();
+        let prompt = prompt_messages(&msg.messages);
 
-        let input = match atoken.apply_chat_template(prompt, true, None) {
+        let input = match apply_chat_template(&atoken, prompt) {
             Ok(parsed) => parsed,
             Err(err) => {
                 log::warn!("Failed to apply chat template. Error: {:?}", err);
@@ -83,15 +124,61 @@ impl actix::Handler<ProcessMessages> for SimpleRkLLM {
 
         let think = self.config.think.unwrap_or(false);
 
-        let handle_arc = self.handle.clone();
+        let loaded = self.loaded.clone();
+        let llm_config = self.llm_config.clone();
+        // 沒開 per_request_sampling 就一直用載入時的取樣值，不重新初始化
+        let sampler = self.config.per_request_sampling.then(|| {
+            let sampler = SamplerParams::resolve(&msg.sampling, &self.config);
+            match msg.seed {
+                Some(_) => sampler.seeded(&msg.sampling),
+                None => sampler,
+            }
+        });
 
         let exec_lock = self.exec_lock.clone();
-        let infer_params_cloned = self.infer_params.clone();
+        let mut infer_params_cloned = self.infer_params.clone();
+        if let Some(cache) = infer_params_cloned.prompt_cache_params.as_mut() {
+            if let Some(path) =
+                crate::prompt_cache::cache_file(&self.config, msg.session.as_deref())
+            {
+                cache.prompt_cache_path = path.to_string_lossy().into_owned();
+            }
+        }
+        let config = self.config.clone();
         tokio::task::spawn_blocking(move || {
             let _guard = exec_lock.lock().unwrap();
+            let handle_arc = match handle_for(&loaded, &llm_config, sampler, &atoken, &config) {
+                Ok(handle) => handle,
+                Err(e) => {
+                    log::error!("Failed to re-initialize RKLLM: {}", e);
+                    let _ = tx.blocking_send(format!("Model error: {}", e));
+                    return;
+                }
+            };
+            // 排到了才開始讀 prompt
+            let prefilling = Arc::new(AtomicBool::new(true));
+            let bpe = load_bpe(&config).ok();
+            let prompt_tokens = bpe.as_ref().map(|bpe| bpe.count(&input)).unwrap_or(0);
+            crate::prefill::spawn_ticker(
+                config.model_name.clone(),
+                prompt_tokens,
+                prefilling.clone(),
+                tx.clone(),
+            );
             let handle_for_abort = handle_arc.clone();
             let cb = CallbackSendSelfChannel {
                 sender: Some(tx.clone()),
+                model: config.model_name.clone(),
+                prefilling: prefilling.clone(),
+                prompt_tokens,
+                generated: 0,
+                stop: crate::stop::StopSequences::new(msg.stop),
+                max_tokens: msg.max_tokens,
+                max_context: config.max_context_len.max(0) as usize,
+                max_new_tokens: max_new_tokens(&config) as usize,
+                logprobs: msg.logprobs,
+                bpe,
+                entries: vec![],
                 abort: Box::new(move || {
                     let handle_in_thread = handle_for_abort.clone();
                     std::thread::spawn(move || {
@@ -112,6 +199,7 @@ impl actix::Handler<ProcessMessages> for SimpleRkLLM {
                 Some(infer_params_cloned),
                 cb,
             );
+            prefilling.store(false, Ordering::Relaxed);
             if let Err(e) = result {
                 log::error!("RKLLM execution failed: {}", e);
                 // 發送錯誤訊息字串，這樣 UI 就會顯示出來
@@ -128,6 +216,7 @@ impl actix::Handler<ProcessMessages> for SimpleRkLLM {
             }
 
             drop(tx);
+            crate::prompt_cache::enforce(&config);
         });
 
         // 將 Receiver 轉換為 Stream
@@ -153,29 +242,19 @@ impl AIModel for SimpleRkLLM {
         config: &Self::Config,
         p: Option<P>,
     ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let (model_path, progress) = if let Some(path) = resolve_local_model_path(config) {
-            if path.exists() {
-                log::info!("Using local model: {}", path.display());
-                (path, None::<P>)
-            } else {
-                log::warn!(
-                    "Local model path not found, falling back to remote: {}",
-                    path.display()
-                );
-                download_model(config, p)?
-            }
-        } else {
-            download_model(config, p)?
-        };
+        let (paths, progress) = fetch_files(config, &model_files(config), p)?;
+        let model_path = paths[0].clone();
 
+        let sampler = SamplerParams::resolve(&Default::default(), config);
         let mut llm_config = LLMConfig::default();
         llm_config.model_path = Some(model_path.to_string_lossy().into_owned());
         llm_config.max_context_len = config.max_context_len;
-        llm_config.max_new_tokens = 4096;
-        llm_config.top_k = 40;
-        llm_config.top_p = 0.9;
-        llm_config.temperature = 0.7;
-        llm_config.repeat_penalty = 1.1;
+        llm_config.max_new_tokens = max_new_tokens(config);
+        sampler.apply(&mut llm_config);
+        if let Some((num, mask)) = crate::thermal::profile().enabled_cpus() {
+            llm_config.extend_param.enabled_cpus_num = num;
+            llm_config.extend_param.enabled_cpus_mask = mask;
+        }
 
         let progress = if let Some(mut progress) = progress {
             let meta = fs::metadata(&model_path)?;
@@ -186,7 +265,7 @@ impl AIModel for SimpleRkLLM {
             None
         };
 
-        let handle = match init(llm_config) {
+        let handle = match init(llm_config.clone()) {
             Ok(handle) => handle,
             Err(e) => {
                 return Err(Box::new(std::io::Error::new(
@@ -196,34 +275,11 @@ impl AIModel for SimpleRkLLM {
             }
         };
 
-        let tokenizer_source = if let Some(path) = resolve_local_tokenizer_path(config) {
-            if path.exists() {
-                log::info!("Using local tokenizer: {}", path.display());
-                TokenizerSource::Local(path)
-            } else {
-                log::warn!(
-                    "Local tokenizer path not found, falling back to remote: {}",
-                    path.display()
-                );
-                TokenizerSource::Remote(resolve_tokenizer_repo(config))
-            }
-        } else {
-            TokenizerSource::Remote(resolve_tokenizer_repo(config))
-        };
-
-        let atoken = match tokenizer_source {
-            TokenizerSource::Local(path) => {
-                let tokenizer_file = path.join("tokenizer_config.json");
-                AutoTokenizer::from_file(&tokenizer_file)
-            }
-            TokenizerSource::Remote(repo) => AutoTokenizer::from_pretrained(repo, None),
-        }
-        .map_err(|e| {
-            Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Error loading tokenizer: {:?}", e),
-            ))
-        })?;
+        let atoken = load_tokenizer(config)?;
+        let handle = Arc::new(FakeThreadSafeRKLLM(handle));
+        // 先暖機再讀 prompt cache，暖機的 prompt 不會留在 cache 裡
+        warmup(&handle, &atoken, config);
+        load_prompt_cache(&handle.0, config);
 
         let infer_params = RKLLMInferParam {
             mode: RKLLMInferMode::InferGenerate,
@@ -243,16 +299,308 @@ impl AIModel for SimpleRkLLM {
             progress.model_finished();
         }
 
+        let loaded = Arc::new(Mutex::new(Loaded {
+            handle: Some(handle),
+            sampler,
+        }));
+        // 崩潰時先停掉推論，NPU 不會卡在一半
+        let weak = Arc::downgrade(&loaded);
+        let crash_guard = crate::crash::on_crash(move || {
+            let handle = weak
+                .upgrade()
+                .and_then(|loaded| loaded.try_lock().ok()?.handle.clone());
+            if let Some(handle) = handle {
+                let _ = handle.0.abort();
+            }
+        });
+
         Ok(SimpleRkLLM {
-            handle: Arc::new(FakeThreadSafeRKLLM(handle)),
+            loaded,
+            llm_config: Arc::new(llm_config),
             exec_lock: Arc::new(Mutex::new(())),
             atoken,
             infer_params,
             config: config.clone(),
+            _crash_guard: crash_guard,
         })
     }
 }
 
+/// 暖機時生成的 token 數
+const WARMUP_TOKENS: usize = 8;
+
+/// Counts the tokens of the warmup generation and stops it after [`WARMUP_TOKENS`].
+struct Warmup {
+    generated: usize,
+    abort: Box<dyn FnMut() + Send + Sync + 'static>,
+}
+
+impl RkllmCallbackHandler for Warmup {
+    fn handle(&mut self, result: Option<RKLLMResult<'_>>, state: LLMCallState) {
+        if let (LLMCallState::Normal, Some(_)) = (state, result) {
+            self.generated += 1;
+            if self.generated == WARMUP_TOKENS {
+                (self.abort)();
+            }
+        }
+    }
+}
+
+/// Generate a few tokens of `warmup_prompt` right after init, so the first real request does not
+/// pay for the cold start. A failed warmup is only logged.
+fn warmup(handle: &Arc<FakeThreadSafeRKLLM>, atoken: &AutoTokenizer, config: &ModelConfig) {
+    let Some(prompt) = &config.warmup_prompt else {
+        return;
+    };
+    let messages = [Message {
+        role: Some(crate::Role::User),
+        content: Some(crate::Content::String(prompt.clone())),
+        ..Default::default()
+    }];
+    let input = match apply_chat_template(atoken, prompt_messages(&messages)) {
+        Ok(input) => input,
+        Err(e) => {
+            log::warn!(
+                "Failed to apply chat template for the warmup of {}: {}",
+                config.model_name,
+                e
+            );
+            return;
+        }
+    };
+    let started = Instant::now();
+    let handle_for_abort = handle.clone();
+    let result = handle.0.run(
+        RKLLMInput {
+            input_type: RKLLMInputType::Prompt(input),
+            enable_thinking: false,
+            role: RKLLMInputRole::User,
+        },
+        // 沒有 prompt_cache_params，不會蓋掉存好的 prompt cache
+        Some(RKLLMInferParam::default()),
+        Warmup {
+            generated: 0,
+            abort: Box::new(move || {
+                let handle = handle_for_abort.clone();
+                std::thread::spawn(move || {
+                    if let Err(err) = handle.0.abort() {
+                        log::error!("Failed to abort the warmup: {}", err);
+                    }
+                });
+            }),
+        },
+    );
+    match result {
+        Ok(()) => log::info!("Warmed up {} in {:?}", config.model_name, started.elapsed()),
+        Err(e) => log::warn!("Warmup of {} failed: {}", config.model_name, e),
+    }
+}
+
+/// Load the prompt cache saved by earlier runs, the one of conversations without a session id
+/// when `cache_path` has `{session}`.
+fn load_prompt_cache(handle: &LLMHandle, config: &ModelConfig) {
+    let Some(path) = crate::prompt_cache::cache_file(config, None).filter(|path| path.exists())
+    else {
+        return;
+    };
+    match handle.load_prompt_cache(&path.to_string_lossy()) {
+        Ok(()) => log::info!("Loaded prompt cache {}", path.display()),
+        // 別的模型或舊版 runtime 存的 cache 讀不進來，重新算就好
+        Err(e) => log::warn!("Failed to load prompt cache {}: {}", path.display(), e),
+    }
+}
+
+impl SamplerParams {
+    fn apply(&self, llm_config: &mut LLMConfig) {
+        llm_config.temperature = self.temperature;
+        llm_config.top_p = self.top_p;
+        llm_config.top_k = self.top_k;
+        llm_config.repeat_penalty = self.repeat_penalty;
+        llm_config.presence_penalty = self.presence_penalty;
+        llm_config.frequency_penalty = self.frequency_penalty;
+    }
+}
+
+/// Initialize the runtime with `sampler`, warmed up and with the saved prompt cache like at load.
+fn reinit(
+    llm_config: &LLMConfig,
+    sampler: SamplerParams,
+    atoken: &AutoTokenizer,
+    config: &ModelConfig,
+) -> Result<Arc<FakeThreadSafeRKLLM>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut llm_config = llm_config.clone();
+    sampler.apply(&mut llm_config);
+    let handle = Arc::new(FakeThreadSafeRKLLM(init(llm_config)?));
+    warmup(&handle, atoken, config);
+    load_prompt_cache(&handle.0, config);
+    Ok(handle)
+}
+
+/// The runtime sampling with `sampler`, `None` keeps the loaded values. rkllm only takes sampling
+/// values at init, so one initialized with others is released and initialized again. Called with
+/// `exec_lock` held.
+fn handle_for(
+    loaded: &Mutex<Loaded>,
+    llm_config: &LLMConfig,
+    sampler: Option<SamplerParams>,
+    atoken: &AutoTokenizer,
+    config: &ModelConfig,
+) -> Result<Arc<FakeThreadSafeRKLLM>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut loaded = loaded.lock().unwrap();
+    let previous = loaded.sampler;
+    let sampler = sampler.unwrap_or(previous);
+    if let (Some(handle), true) = (&loaded.handle, previous == sampler) {
+        return Ok(handle.clone());
+    }
+    log::info!(
+        "Re-initializing {:?} with {:?}",
+        llm_config.model_path,
+        sampler
+    );
+    // 先釋放舊的，NPU 記憶體放不下兩份
+    loaded.handle = None;
+    match reinit(llm_config, sampler, atoken, config) {
+        Ok(handle) => {
+            loaded.handle = Some(handle.clone());
+            loaded.sampler = sampler;
+            Ok(handle)
+        }
+        Err(e) => {
+            // 換回原本的取樣值，其他請求照常能用
+            if previous != sampler {
+                match reinit(llm_config, previous, atoken, config) {
+                    Ok(handle) => loaded.handle = Some(handle),
+                    Err(e) => log::error!("Failed to restore {:?}: {}", llm_config.model_path, e),
+                }
+            }
+            Err(e)
+        }
+    }
+}
+
+/// 同一個 tokenizer 檔只載入一次，alias 跟副本共用
+static TOKENIZERS: LazyLock<Mutex<HashMap<PathBuf, Arc<AutoTokenizer>>>> =
+    LazyLock::new(Default::default);
+static BPE_TOKENIZERS: LazyLock<Mutex<HashMap<PathBuf, Arc<BpeTokenizer>>>> =
+    LazyLock::new(Default::default);
+
+/// A tokenizer file from `tokenizer_path`, else `local_repo`, else the hf-hub cache, and only then
+/// the network.
+fn tokenizer_file(
+    config: &ModelConfig,
+    filename: &str,
+) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(path) = &config.tokenizer_path {
+        let path = PathBuf::from(render_local_path_template(path, config));
+        // 指到檔案的話，其他的 tokenizer 檔在同一個目錄
+        let file = match path.parent() {
+            Some(dir) if path.is_file() && path.file_name() != Some(filename.as_ref()) => {
+                dir.join(filename)
+            }
+            _ if path.is_file() => path,
+            _ => path.join(filename),
+        };
+        if !file.exists() {
+            return Err(std::io::Error::other(format!(
+                "tokenizer_path of {}: {} not found",
+                config.model_name,
+                file.display()
+            ))
+            .into());
+        }
+        return Ok(file);
+    }
+    if let Some(path) = resolve_local_tokenizer_path(config) {
+        let file = path.join(filename);
+        if file.exists() {
+            return Ok(file);
+        }
+        log::warn!(
+            "Local tokenizer path not found, falling back to remote: {}",
+            file.display()
+        );
+    }
+    let repo = resolve_tokenizer_repo(config);
+    if let Some(cached) = crate::hub::cache(config)
+        .repo(Repo::model(repo.clone()))
+        .get(filename)
+    {
+        return Ok(cached);
+    }
+    if crate::hub::offline() {
+        return Err(crate::hub::offline_error(&repo, filename).into());
+    }
+    log::info!("Downloading {} of {}", filename, repo);
+    Ok(crate::hub::api(config)?.model(repo).get(filename)?)
+}
+
+pub(crate) fn load_tokenizer(
+    config: &ModelConfig,
+) -> Result<Arc<AutoTokenizer>, Box<dyn std::error::Error + Send + Sync>> {
+    let tokenizer_error = |e| std::io::Error::other(format!("Error loading tokenizer: {:?}", e));
+    let file = tokenizer_file(config, "tokenizer_config.json").map_err(tokenizer_error)?;
+    let atoken = {
+        let mut tokenizers = TOKENIZERS.lock().unwrap();
+        match tokenizers.get(&file) {
+            Some(atoken) => atoken.clone(),
+            None => {
+                log::info!("Using tokenizer: {}", file.display());
+                let atoken = Arc::new(AutoTokenizer::from_file(&file).map_err(tokenizer_error)?);
+                tokenizers.insert(file.clone(), atoken.clone());
+                atoken
+            }
+        }
+    };
+    // 有些轉換過的模型 tokenizer 沒有或是錯的 template，設定檔可以蓋掉
+    let atoken = match chat_template(config)? {
+        Some(template) => {
+            let mut atoken = atoken.as_ref().clone();
+            atoken.chat_template = Some(ChatTemplate::Single(template));
+            Arc::new(atoken)
+        }
+        None => atoken,
+    };
+    if atoken.chat_template.is_none() {
+        return Err(std::io::Error::other(format!(
+            "{} has no chat_template, set chat_template or chat_template_path in the config of {}",
+            file.display(),
+            config.model_name
+        ))
+        .into());
+    }
+    Ok(atoken)
+}
+
+/// `chat_template` of the model config, or the contents of `chat_template_path`.
+fn chat_template(
+    config: &ModelConfig,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(template) = &config.chat_template {
+        return Ok(Some(template.clone()));
+    }
+    let Some(path) = &config.chat_template_path else {
+        return Ok(None);
+    };
+    let path = render_local_path_template(path, config);
+    let template = fs::read_to_string(&path)
+        .map_err(|e| std::io::Error::other(format!("Error reading {}: {}", path, e)))?;
+    Ok(Some(template))
+}
+
+/// `tokenizer.json` next to the tokenizer config, for counting tokens.
+pub(crate) fn load_bpe(
+    config: &ModelConfig,
+) -> Result<Arc<BpeTokenizer>, Box<dyn std::error::Error + Send + Sync>> {
+    let file = tokenizer_file(config, "tokenizer.json")?;
+    let mut tokenizers = BPE_TOKENIZERS.lock().unwrap();
+    if let Some(bpe) = tokenizers.get(&file) {
+        return Ok(bpe.clone());
+    }
+    let bpe = Arc::new(BpeTokenizer::from_file(&file)?);
+    tokenizers.insert(file, bpe.clone());
+    Ok(bpe)
+}
+
 fn resolve_tokenizer_repo(config: &ModelConfig) -> String {
     config
         .tokenizer_repo
@@ -260,15 +608,14 @@ fn resolve_tokenizer_repo(config: &ModelConfig) -> String {
         .unwrap_or_else(|| config.model_repo.clone())
 }
 
-fn render_local_path_template(path: &str, config: &ModelConfig) -> String {
+pub(crate) fn render_local_path_template(path: &str, config: &ModelConfig) -> String {
     path.replace("{model_name}", &config.model_name)
 }
 
-fn resolve_local_model_path(config: &ModelConfig) -> Option<PathBuf> {
+fn resolve_local_file_path(config: &ModelConfig, filename: &str) -> Option<PathBuf> {
     config.local_repo.as_ref().map(|local_repo| {
         let base = PathBuf::from(render_local_path_template(local_repo, config));
-        let model_file = resolve_model_filename(config);
-        base.join(model_file)
+        base.join(filename)
     })
 }
 
@@ -279,7 +626,7 @@ fn resolve_local_tokenizer_path(config: &ModelConfig) -> Option<PathBuf> {
         .map(|local_repo| PathBuf::from(render_local_path_template(local_repo, config)))
 }
 
-fn resolve_model_filename(config: &ModelConfig) -> String {
+pub(crate) fn resolve_model_filename(config: &ModelConfig) -> String {
     let model_file = config
         .model_path
         .clone()
@@ -287,43 +634,278 @@ fn resolve_model_filename(config: &ModelConfig) -> String {
     render_local_path_template(&model_file, config)
 }
 
-fn download_model<P: Progress + ModelProgress + Clone>(
+/// 模型檔，VLM 還有 vision 的 rknn 檔，再來是 `extra_files`
+pub(crate) fn model_files(config: &ModelConfig) -> Vec<String> {
+    let mut files = vec![resolve_model_filename(config)];
+    if let (crate::utils::ModelType::VLM, Some(vision_model_path)) =
+        (&config.model_type, &config.vision_model_path)
+    {
+        files.push(render_local_path_template(vision_model_path, config));
+    }
+    files.extend(
+        config
+            .extra_files
+            .iter()
+            .map(|file| render_local_path_template(file, config)),
+    );
+    files
+}
+
+/// Bytes of the downloaded files of a model, about what it takes in memory once loaded.
+pub(crate) fn footprint(config: &ModelConfig) -> u64 {
+    model_files(config)
+        .iter()
+        .filter_map(|file| cached_file(config, file))
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Where the model file already is, in `local_repo` or the hf-hub cache, without downloading it.
+pub(crate) fn cached_model_file(config: &ModelConfig) -> Option<PathBuf> {
+    cached_file(config, &resolve_model_filename(config))
+}
+
+/// Where a file of the model repository already is, without downloading it.
+pub(crate) fn cached_file(config: &ModelConfig, filename: &str) -> Option<PathBuf> {
+    resolve_local_file_path(config, filename)
+        .filter(|path| path.exists())
+        .or_else(|| {
+            crate::hub::cache(config)
+                .repo(Repo::model(config.model_repo.clone()))
+                .get(filename)
+        })
+}
+
+/// Remove a file of the model from the hf-hub cache, the snapshot link and its blob, and return
+/// the bytes freed. Files in `local_repo` are never touched.
+pub(crate) fn remove_cached_file(config: &ModelConfig, filename: &str) -> std::io::Result<u64> {
+    let Some(link) = crate::hub::cache(config)
+        .repo(Repo::model(config.model_repo.clone()))
+        .get(filename)
+    else {
+        return Ok(0);
+    };
+    let blob = std::fs::canonicalize(&link)?;
+    let size = std::fs::metadata(&blob)?.len();
+    std::fs::remove_file(&link)?;
+    if blob != link {
+        std::fs::remove_file(&blob)?;
+    }
+    log::info!("Removed {} ({} bytes)", link.display(), size);
+    Ok(size)
+}
+
+/// Resolve a file of the model repository, preferring `local_repo` and falling back to hf-hub.
+pub(crate) fn fetch_file<P: Progress + ModelProgress + Clone>(
     config: &ModelConfig,
+    filename: &str,
     p: Option<P>,
 ) -> Result<(PathBuf, Option<P>), Box<dyn std::error::Error + Send + Sync>> {
-    let api = Api::new().unwrap();
-    let repo = api.model(config.model_repo.clone());
-    let filename = resolve_model_filename(config);
-
-    if let Some(progress) = p {
-        if let Some(cached) = Cache::default()
-            .repo(Repo::model(config.model_repo.clone()))
-            .get(&filename)
-        {
-            return Ok((cached, Some(progress)));
+    if let Some(path) = resolve_local_file_path(config, filename) {
+        if path.exists() {
+            log::info!("Using local model: {}", path.display());
+            crate::hub::check_size(config, filename, &path)?;
+            return Ok((path, None::<P>));
+        }
+        log::warn!(
+            "Local model path not found, falling back to remote: {}",
+            path.display()
+        );
+    }
+    download_file(config, filename, p)
+}
+
+/// Bytes still to download of a file, 0 when it is already local or cached.
+fn download_size(config: &ModelConfig, filename: &str) -> usize {
+    if cached_file(config, filename).is_some() || crate::hub::offline() {
+        return 0;
+    }
+    // 拿不到大小時 download_file 會回報真正的錯誤
+    crate::hub::api(config)
+        .and_then(|api| {
+            let repo = api.model(config.model_repo.clone());
+            api.metadata(&repo.url(filename))
+        })
+        .map(|metadata| metadata.size())
+        .unwrap_or(0)
+}
+
+/// Resolve every file of a model, downloading the missing ones with one progress over all of
+/// them. The progress returned is the one of the first file, like [`fetch_file`].
+pub(crate) fn fetch_files<P: Progress + ModelProgress + Clone>(
+    config: &ModelConfig,
+    files: &[String],
+    mut p: Option<P>,
+) -> Result<(Vec<PathBuf>, Option<P>), Box<dyn std::error::Error + Send + Sync>> {
+    let sizes: Vec<usize> = match (&p, files.len()) {
+        (Some(_), 2..) => files.iter().map(|file| download_size(config, file)).collect(),
+        _ => vec![0; files.len()],
+    };
+    let total = sizes.iter().sum();
+    let mut done = 0;
+    let mut paths = Vec::with_capacity(files.len());
+    let mut first_progress = None;
+    for (i, (file, size)) in files.iter().zip(sizes).enumerate() {
+        if let (Some(progress), true) = (&mut p, total > 0) {
+            progress.download_offset(done, total);
+        }
+        let (path, progress) = fetch_file(config, file, p.clone())?;
+        if i == 0 {
+            first_progress = progress;
         }
+        paths.push(path);
+        done += size;
+    }
+    Ok((paths, first_progress))
+}
 
-        let path = repo.download_with_progress(&filename, progress.clone())?;
-        return Ok((path, Some(progress)));
+fn download_file<P: Progress + ModelProgress + Clone>(
+    config: &ModelConfig,
+    filename: &str,
+    p: Option<P>,
+) -> Result<(PathBuf, Option<P>), Box<dyn std::error::Error + Send + Sync>> {
+    let cached = crate::hub::cache(config)
+        .repo(Repo::model(config.model_repo.clone()))
+        .get(filename);
+    if let Some(cached) = cached {
+        crate::hub::check_size(config, filename, &cached)?;
+        return Ok((cached, p));
     }
+    if crate::hub::offline() {
+        return Err(crate::hub::offline_error(&config.model_repo, filename).into());
+    }
+    let api = crate::hub::api(config)?;
+    let repo = api.model(config.model_repo.clone());
+    // 先確定放得下，16 GB 的卡很容易下載到一半就滿了
+    let metadata = api.metadata(&repo.url(filename))?;
+    let cache = crate::hub::cache(config);
+    let mut part = cache
+        .repo(Repo::model(config.model_repo.clone()))
+        .blob_path(metadata.etag());
+    part.set_extension("part");
+    let partial = fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+    crate::hub::check_space(
+        config,
+        filename,
+        cache.path(),
+        metadata.size() as u64,
+        partial,
+    )?;
 
-    let path = repo.get(&filename)?;
-    Ok((path, None))
+    // 沒下載完的 .part 檔會接著下載
+    let path = match &p {
+        Some(progress) => repo.download_with_progress(filename, progress.clone())?,
+        None => repo.get(filename)?,
+    };
+    if let Err(e) = crate::hub::verify_download(config, filename, &path) {
+        // 壞掉的檔案刪掉，下次重新下載
+        let _ = remove_cached_file(config, filename);
+        return Err(e.into());
+    }
+    Ok((path, p))
 }
 
 impl LLM for SimpleRkLLM {}
 
-struct CallbackSendSelfChannel {
-    sender: Option<tokio::sync::mpsc::Sender<String>>,
-    abort: Box<dyn FnMut() + Send + Sync + 'static>,
+pub(crate) struct CallbackSendSelfChannel {
+    pub(crate) sender: Option<tokio::sync::mpsc::Sender<String>>,
+    pub(crate) abort: Box<dyn FnMut() + Send + Sync + 'static>,
+    /// 記錄 prefill 速度用
+    pub(crate) model: String,
+    /// Cleared by the first token, stops the prefill progress
+    pub(crate) prefilling: Arc<AtomicBool>,
+    /// Counted with the tokenizer, only used when the runtime gives no prefill count
+    pub(crate) prompt_tokens: usize,
+    pub(crate) generated: usize,
+    pub(crate) stop: crate::stop::StopSequences,
+    pub(crate) max_tokens: Option<usize>,
+    /// `max_context_len` of the model, the runtime stops when prompt and answer fill it
+    pub(crate) max_context: usize,
+    /// `max_new_tokens` the runtime was initialized with
+    pub(crate) max_new_tokens: usize,
+    /// `top_logprobs` when the request asked for logprobs
+    pub(crate) logprobs: Option<usize>,
+    pub(crate) bpe: Option<Arc<BpeTokenizer>>,
+    /// Entries of tokens not sent yet
+    pub(crate) entries: Vec<serde_json::Value>,
 }
+
+impl CallbackSendSelfChannel {
+    /// Send what is left, why generation ended early and the usage, then close the stream.
+    fn finish(&mut self, prompt_tokens: usize, reason: Option<FinishReason>) {
+        if let Some(sender) = self.sender.take() {
+            let rest = self.stop.flush();
+            if !rest.is_empty() {
+                let _ = sender.blocking_send(self.with_logprobs(rest));
+            }
+            if let Some(reason) = reason {
+                let _ = sender.blocking_send(crate::finish::message(reason));
+            }
+            let usage = crate::usage::message(prompt_tokens, self.generated);
+            let _ = sender.blocking_send(usage);
+        }
+    }
+
+    /// Prompt tokens of the run, taking the perf counters of `perf` like `counted`. The prefill
+    /// speed is recorded and `generated` updated on the way.
+    fn count(&mut self, perf: &RKLLMPerfStatData) -> usize {
+        crate::prefill::record(&self.model, perf);
+        let (prompt_tokens, generated) = counted(perf, self.prompt_tokens, self.generated);
+        self.generated = generated;
+        prompt_tokens
+    }
+
+    /// Keep the logprobs entry of a token, when the runtime gave its logits.
+    fn record_logprobs(&mut self, result: &RKLLMResult<'_>) {
+        let (Some(top), Some(logits)) = (self.logprobs, &result.logits) else {
+            return;
+        };
+        let vocab = logits.vocab_size.max(0) as usize;
+        let all = logits.logits();
+        // 只看最後一個 token 的 logits
+        let row = &all[all.len().saturating_sub(vocab)..];
+        let entry = crate::logprobs::entry(
+            row,
+            result.token_id,
+            &result.text,
+            top,
+            self.bpe.as_deref(),
+        );
+        self.entries.extend(entry);
+    }
+
+    /// `text` with the entries of its tokens attached.
+    fn with_logprobs(&mut self, text: String) -> String {
+        if self.entries.is_empty() {
+            return text;
+        }
+        crate::logprobs::message(&text, &std::mem::take(&mut self.entries))
+    }
+}
+
+/// Prompt and generated tokens of a run, the runtime's perf counters win over our own counts.
+fn counted(perf: &RKLLMPerfStatData, prompt_tokens: usize, generated: usize) -> (usize, usize) {
+    let pick = |counter, ours| if counter > 0 { counter as usize } else { ours };
+    (pick(perf.prefill_tokens, prompt_tokens), pick(perf.generate_tokens, generated))
+}
+
 impl RkllmCallbackHandler for CallbackSendSelfChannel {
     fn handle(&mut self, result: Option<RKLLMResult<'_>>, state: LLMCallState) {
         match state {
             LLMCallState::Normal => {
+                self.prefilling.store(false, Ordering::Relaxed);
                 if let Some(result) = result {
-                    if let Some(sender) = &self.sender {
-                        match sender.blocking_send(result.text.into_owned()) {
+                    if self.stop.stopped() {
+                        return;
+                    }
+                    self.generated += 1;
+                    // 過熱時放慢出字
+                    crate::thermal::pace();
+                    self.record_logprobs(&result);
+                    let (text, stopped) = self.stop.push(&result.text);
+                    if let (Some(sender), false) = (self.sender.clone(), text.is_empty()) {
+                        match sender.blocking_send(self.with_logprobs(text)) {
                             Ok(_) => {
                                 // 發送成功，繼續
                             }
@@ -337,14 +919,38 @@ impl RkllmCallbackHandler for CallbackSendSelfChannel {
                             }
                         }
                     }
+                    // 碰到 stop sequence 或 max_tokens 就不用再生成了
+                    let truncated = self.max_tokens.is_some_and(|max| self.generated >= max);
+                    if (stopped || truncated) && self.sender.is_some() {
+                        let reason = if stopped { None } else { Some(FinishReason::Length) };
+                        let prompt_tokens = self.count(&result.perf);
+                        self.finish(prompt_tokens, reason);
+                        self.stop.halt();
+                        (self.abort)();
+                    }
                 }
             }
             LLMCallState::Waiting => {}
             LLMCallState::Finish => {
-                drop(self.sender.take());
-                self.sender = None;
+                self.prefilling.store(false, Ordering::Relaxed);
+                let prompt_tokens = match result {
+                    Some(result) => self.count(&result.perf),
+                    None => self.prompt_tokens,
+                };
+                // runtime 自己停下來時看是不是 context 或 max_new_tokens 用完了
+                let full = self.generated >= self.max_new_tokens
+                    || (self.max_context > 0 && prompt_tokens + self.generated >= self.max_context);
+                self.finish(prompt_tokens, full.then_some(FinishReason::Length));
+            }
+            LLMCallState::Error => {
+                self.prefilling.store(false, Ordering::Relaxed);
+                // 不送錯誤的話 chat.rs 會當成正常結束
+                if let Some(sender) = self.sender.take() {
+                    let _ = sender.blocking_send(
+                        "Model error: the runtime reported an error during inference.".to_owned(),
+                    );
+                }
             }
-            LLMCallState::Error => {}
             LLMCallState::GetLastHiddenLayer => {}
         }
     }
@@ -362,20 +968,81 @@ mod tests {
             model_type: ModelType::LLM,
             max_context_len: 16384,
             model_path: Some("Qwen2.5-3B-abliterated-16k.rkllm".to_owned()),
-            tokenizer_repo: None,
-            local_repo: None,
-            _asserts_path: String::new(),
-            cache_path: None,
-            think: None,
+            ..Default::default()
         }
     }
 
+    #[test]
+    fn runtime_error_is_sent_as_model_error() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        let mut cb = CallbackSendSelfChannel {
+            sender: Some(tx),
+            abort: Box::new(|| {}),
+            model: "qwen".to_owned(),
+            prefilling: Arc::new(AtomicBool::new(true)),
+            prompt_tokens: 0,
+            generated: 0,
+            stop: crate::stop::StopSequences::new(vec![]),
+            max_tokens: None,
+            max_context: 0,
+            max_new_tokens: 0,
+            logprobs: None,
+            bpe: None,
+            entries: vec![],
+        };
+        cb.handle(None, LLMCallState::Error);
+        assert!(rx.try_recv().unwrap().starts_with("Model error:"));
+        // 送完就關掉，不會再有 usage
+        assert!(rx.try_recv().is_err());
+        assert!(!cb.prefilling.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn perf_counters_win_over_tokenizer_counts() {
+        let perf = |prefill_tokens, generate_tokens| RKLLMPerfStatData {
+            prefill_time_ms: 0.0,
+            prefill_tokens,
+            generate_time_ms: 0.0,
+            generate_tokens,
+            memory_usage_mb: 0.0,
+        };
+        assert_eq!(counted(&perf(120, 30), 118, 29), (120, 30));
+        assert_eq!(counted(&perf(0, 0), 118, 29), (118, 29));
+    }
+
+    #[test]
+    fn model_files_include_extra_files() {
+        let mut config = sample_config();
+        config.model_type = ModelType::VLM;
+        config.vision_model_path = Some("vision.rknn".to_owned());
+        config.extra_files = vec!["{model_name}-00002-of-00002.rkllm".to_owned()];
+        assert_eq!(
+            model_files(&config),
+            [
+                "Qwen2.5-3B-abliterated-16k.rkllm",
+                "vision.rknn",
+                "Qwen2.5-3B-abliterated-00002-of-00002.rkllm"
+            ]
+        );
+    }
+
+    #[test]
+    fn max_new_tokens_defaults_to_4096() {
+        let mut config = sample_config();
+        assert_eq!(max_new_tokens(&config), MAX_NEW_TOKENS);
+        config.max_new_tokens = Some(512);
+        assert_eq!(max_new_tokens(&config), 512);
+        config.max_new_tokens = Some(0);
+        assert_eq!(max_new_tokens(&config), MAX_NEW_TOKENS);
+    }
+
     #[test]
     fn local_repo_derives_model_file_path() {
         let mut config = sample_config();
         config.local_repo = Some("/models/{model_name}".to_owned());
 
-        let resolved = resolve_local_model_path(&config).expect("local model path should resolve");
+        let resolved = resolve_local_file_path(&config, &resolve_model_filename(&config))
+            .expect("local model path should resolve");
         assert_eq!(
             resolved,
             PathBuf::from("/models/Qwen2.5-3B-abliterated/Qwen2.5-3B-abliterated-16k.rkllm")
@@ -407,4 +1074,86 @@ mod tests {
             "Qwen2.5-3B-abliterated.rkllm".to_owned()
         );
     }
+
+    #[test]
+    fn aliases_share_one_tokenizer() {
+        let dir = std::env::temp_dir().join(format!("llmserver-tok-{}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("tokenizer_config.json"),
+            r#"{"chat_template": "{{ messages[0].content }}"}"#,
+        )
+        .unwrap();
+        let mut config = sample_config();
+        config.local_repo = Some(dir.to_string_lossy().into_owned());
+        let mut alias = config.clone();
+        alias.model_name = "alias".to_owned();
+
+        let first = load_tokenizer(&config).unwrap();
+        let second = load_tokenizer(&alias).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tokenizer_path_is_a_dir_or_a_file() {
+        let dir = std::env::temp_dir().join(format!("llmserver-tok-{}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("tokenizer_config.json"), "{}").unwrap();
+        let mut config = sample_config();
+        config.tokenizer_path = Some(dir.to_string_lossy().into_owned());
+        let config_file = dir.join("tokenizer_config.json");
+        assert_eq!(tokenizer_file(&config, "tokenizer_config.json").unwrap(), config_file);
+        config.tokenizer_path = Some(config_file.to_string_lossy().into_owned());
+        assert_eq!(tokenizer_file(&config, "tokenizer_config.json").unwrap(), config_file);
+        // 沒有的檔案不會去網路上抓
+        assert!(tokenizer_file(&config, "tokenizer.json").is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn config_overrides_chat_template() {
+        let dir = std::env::temp_dir().join(format!("llmserver-tok-{}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("tokenizer_config.json"), r#"{"eos_token": "</s>"}"#).unwrap();
+        let mut config = sample_config();
+        config.local_repo = Some(dir.to_string_lossy().into_owned());
+        // tokenizer 沒有 template 就不要載入，不然送給模型的是空字串
+        assert!(load_tokenizer(&config).is_err());
+
+        std::fs::write(
+            dir.join("template.jinja"),
+            "{% for m in messages %}{{ m.content }}{{ eos_token }}{% endfor %}",
+        )
+        .unwrap();
+        let template = dir.join("template.jinja");
+        config.chat_template_path = Some(template.to_string_lossy().into_owned());
+        let atoken = load_tokenizer(&config).unwrap();
+        let user = DefaultPromptMessage::new("user", "Hi");
+        assert_eq!(apply_chat_template(&atoken, vec![user]).unwrap(), "Hi</s>");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn continues_assistant_prefill() {
+        let file =
+            std::env::temp_dir().join(format!("llmserver-tpl-{}.json", rand::random::<u32>()));
+        std::fs::write(
+            &file,
+            r#"{"chat_template": "{% for m in messages %}<{{ m.role }}>{{ m.content }}</{{ m.role }}>{% endfor %}{% if add_generation_prompt %}<assistant>{% endif %}"}"#,
+        )
+        .unwrap();
+        let atoken = AutoTokenizer::from_file(&file).unwrap();
+        std::fs::remove_file(file).unwrap();
+        let user = DefaultPromptMessage::new("user", "Hi");
+        assert_eq!(
+            apply_chat_template(&atoken, vec![user.clone()]).unwrap(),
+            "<user>Hi</user><assistant>"
+        );
+        let prefill = DefaultPromptMessage::new("assistant", "```json");
+        assert_eq!(
+            apply_chat_template(&atoken, vec![user, prefill]).unwrap(),
+            "<user>Hi</user><assistant>```json"
+        );
+    }
 }
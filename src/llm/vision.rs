@@ -0,0 +1,572 @@
+use actix::Actor;
+use actix::ActorContext;
+use autotokenizer::AutoTokenizer;
+use autotokenizer::DefaultPromptMessage;
+use base64::Engine;
+use hf_hub::api::Progress;
+use image::imageops::FilterType;
+use image::Rgb;
+use image::RgbImage;
+use rkllm_rs::prelude::LLMCallState;
+use rkllm_rs::prelude::RKLLMPerfStatData;
+use rkllm_rs::prelude::RKLLMResult;
+use rkllm_rs::prelude::RkllmCallbackHandler;
+use rknn_rs::prelude::Rknn;
+use rknn_rs::prelude::RknnTensorFormat;
+use rknn_rs::prelude::RknnTensorType;
+use serde_variant::to_variant_name;
+use std::borrow::Cow;
+use std::ffi::c_void;
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::fs;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Instant;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::llm::simple::fetch_files;
+use crate::llm::simple::load_tokenizer;
+use crate::llm::simple::CallbackSendSelfChannel;
+use crate::llm::simple::max_new_tokens;
+use crate::llm::simple::model_files;
+use crate::utils::ModelConfig;
+use crate::AIModel;
+use crate::Content;
+use crate::Message;
+use crate::ModelProgress;
+use crate::ProcessMessages;
+use crate::ShutdownMessages;
+use crate::LLM;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Placeholder rkllm expands into `img_start` + image tokens + `img_end`.
+const IMAGE_PLACEHOLDER: &str = "<image>";
+
+/// rkllm-rs does not wrap `RKLLM_INPUT_MULTIMODAL` yet, so the decoder of a vision model is
+/// driven through the raw bindings.
+#[derive(Debug)]
+struct RawRkllm {
+    handle: rkllm_rs::LLMHandle,
+    // rkllm keeps the pointers passed in RKLLMParam, so the strings must outlive the handle.
+    _owned_strings: Vec<CString>,
+}
+
+unsafe impl Send for RawRkllm {}
+unsafe impl Sync for RawRkllm {}
+
+impl Drop for RawRkllm {
+    fn drop(&mut self) {
+        if !self.handle.is_null() {
+            unsafe { rkllm_rs::rkllm_destroy(self.handle) };
+        }
+    }
+}
+
+struct ImageEmbeds {
+    data: Vec<f32>,
+    n_image: usize,
+    n_image_tokens: usize,
+    width: usize,
+    height: usize,
+}
+
+impl RawRkllm {
+    fn init(model_path: &Path, config: &ModelConfig) -> Result<Self, BoxError> {
+        let model_path = CString::new(model_path.to_string_lossy().into_owned())?;
+        let img_start = CString::new(config.img_start.as_deref().unwrap_or("<|vision_start|>"))?;
+        let img_end = CString::new(config.img_end.as_deref().unwrap_or("<|vision_end|>"))?;
+        let img_content = CString::new(config.img_content.as_deref().unwrap_or("<|image_pad|>"))?;
+
+        let defaults = unsafe { rkllm_rs::rkllm_createDefaultParam() };
+        let (enabled_cpus_num, enabled_cpus_mask) = crate::thermal::profile()
+            .enabled_cpus()
+            .unwrap_or((
+                defaults.extend_param.enabled_cpus_num,
+                defaults.extend_param.enabled_cpus_mask,
+            ));
+        // 視覺模型沒有重新初始化，sampling 用模型設定的值，別的值在 chat.rs 就被拒絕了
+        let sampler = crate::sampling::SamplerParams::resolve(&Default::default(), config);
+        let mut param = rkllm_rs::RKLLMParam {
+            model_path: model_path.as_ptr(),
+            max_context_len: config.max_context_len,
+            max_new_tokens: max_new_tokens(config),
+            top_k: sampler.top_k,
+            top_p: sampler.top_p,
+            temperature: sampler.temperature,
+            repeat_penalty: sampler.repeat_penalty,
+            presence_penalty: sampler.presence_penalty,
+            frequency_penalty: sampler.frequency_penalty,
+            img_start: img_start.as_ptr(),
+            img_end: img_end.as_ptr(),
+            img_content: img_content.as_ptr(),
+            extend_param: rkllm_rs::RKLLMExtendParam {
+                // 視覺編碼器跟 LLM 不能放在同一個 domain
+                base_domain_id: 1,
+                enabled_cpus_num,
+                enabled_cpus_mask,
+                ..defaults.extend_param
+            },
+            ..defaults
+        };
+
+        let mut handle = std::ptr::null_mut();
+        let ret =
+            unsafe { rkllm_rs::rkllm_init(&mut handle, &mut param, Some(multimodal_callback)) };
+        if ret != 0 {
+            return Err(format!("rkllm_init returned non-zero: {}", ret).into());
+        }
+        Ok(Self {
+            handle,
+            _owned_strings: vec![model_path, img_start, img_end, img_content],
+        })
+    }
+
+    fn run(
+        &self,
+        prompt: &str,
+        images: Option<&mut ImageEmbeds>,
+        enable_thinking: bool,
+        mut handler: CallbackSendSelfChannel,
+    ) -> Result<(), BoxError> {
+        let role = CString::new("user")?;
+        let prompt = CString::new(prompt)?;
+        let mut input = match images {
+            Some(images) => rkllm_rs::RKLLMInput {
+                role: role.as_ptr(),
+                enable_thinking,
+                input_type: rkllm_rs::RKLLMInputType_RKLLM_INPUT_MULTIMODAL,
+                __bindgen_anon_1: rkllm_rs::RKLLMInput__bindgen_ty_1 {
+                    multimodal_input: rkllm_rs::RKLLMMultiModalInput {
+                        prompt: prompt.as_ptr() as *mut _,
+                        image_embed: images.data.as_mut_ptr(),
+                        n_image_tokens: images.n_image_tokens,
+                        n_image: images.n_image,
+                        image_width: images.width,
+                        image_height: images.height,
+                    },
+                },
+            },
+            None => rkllm_rs::RKLLMInput {
+                role: role.as_ptr(),
+                enable_thinking,
+                input_type: rkllm_rs::RKLLMInputType_RKLLM_INPUT_PROMPT,
+                __bindgen_anon_1: rkllm_rs::RKLLMInput__bindgen_ty_1 {
+                    prompt_input: prompt.as_ptr(),
+                },
+            },
+        };
+        let mut infer_param = rkllm_rs::RKLLMInferParam {
+            mode: 0,
+            lora_params: std::ptr::null_mut(),
+            prompt_cache_params: std::ptr::null_mut(),
+            keep_history: 0,
+        };
+
+        // rkllm_run 是同步的，handler 在整個推論期間都活著
+        let ret = unsafe {
+            rkllm_rs::rkllm_run(
+                self.handle,
+                &mut input,
+                &mut infer_param,
+                &mut handler as *mut CallbackSendSelfChannel as *mut c_void,
+            )
+        };
+        if ret != 0 {
+            return Err(format!("rkllm_run returned non-zero: {}", ret).into());
+        }
+        Ok(())
+    }
+
+    fn abort(&self) -> Result<(), BoxError> {
+        let ret = unsafe { rkllm_rs::rkllm_abort(self.handle) };
+        if ret != 0 {
+            return Err(format!("rkllm_abort returned non-zero: {}", ret).into());
+        }
+        Ok(())
+    }
+}
+
+unsafe extern "C" fn multimodal_callback(
+    result: *mut rkllm_rs::RKLLMResult,
+    userdata: *mut c_void,
+    state: rkllm_rs::LLMCallState,
+) -> i32 {
+    if userdata.is_null() {
+        return 0;
+    }
+    let handler = unsafe { &mut *(userdata as *mut CallbackSendSelfChannel) };
+    let state = match state {
+        0 => LLMCallState::Normal,
+        1 => LLMCallState::Waiting,
+        2 => LLMCallState::Finish,
+        4 => LLMCallState::GetLastHiddenLayer,
+        _ => LLMCallState::Error,
+    };
+    let result = unsafe { result.as_ref() }.map(|raw| RKLLMResult {
+        text: if raw.text.is_null() {
+            Cow::Borrowed("")
+        } else {
+            unsafe { CStr::from_ptr(raw.text) }.to_string_lossy()
+        },
+        token_id: raw.token_id,
+        last_hidden_layer: None,
+        logits: None,
+        perf: RKLLMPerfStatData {
+            prefill_time_ms: raw.perf.prefill_time_ms,
+            prefill_tokens: raw.perf.prefill_tokens,
+            generate_time_ms: raw.perf.generate_time_ms,
+            generate_tokens: raw.perf.generate_tokens,
+            memory_usage_mb: raw.perf.memory_usage_mb,
+        },
+    });
+    handler.handle(result, state);
+    0
+}
+
+/// RKNN vision encoder (the ViT half of a Qwen2-VL style model).
+#[derive(Debug)]
+struct VisionEncoder {
+    rknn: Rknn,
+    width: u32,
+    height: u32,
+    n_image_tokens: usize,
+}
+
+impl VisionEncoder {
+    fn new(path: &Path) -> Result<Self, BoxError> {
+        let rknn = Rknn::new(path)?;
+        let input_attrs = rknn.input_attrs()?;
+        let input = input_attrs
+            .first()
+            .ok_or("Vision encoder does not have any input")?;
+        let (height, width) = match (input.fmt, input.dims.as_slice()) {
+            (RknnTensorFormat::NCHW, [_, _, h, w]) => (*h, *w),
+            (_, [_, h, w, _]) => (*h, *w),
+            _ => return Err(format!("Unexpected vision encoder input: {:?}", input.dims).into()),
+        };
+        let output_attrs = rknn.output_attrs()?;
+        let output = output_attrs
+            .first()
+            .ok_or("Vision encoder does not have any output")?;
+        // [1, n_image_tokens, embed_size] 或 [n_image_tokens, embed_size]
+        let n_image_tokens = match output.dims.as_slice() {
+            [.., tokens, _] => *tokens as usize,
+            _ => return Err(format!("Unexpected vision encoder output: {:?}", output.dims).into()),
+        };
+        Ok(Self {
+            rknn,
+            width,
+            height,
+            n_image_tokens,
+        })
+    }
+
+    fn encode(&self, image: &RgbImage) -> Result<Vec<f32>, BoxError> {
+        let square = expand_to_square(image);
+        let resized =
+            image::imageops::resize(&square, self.width, self.height, FilterType::Triangle);
+        self.rknn.input_set_slice(
+            0,
+            &resized.into_raw(),
+            false,
+            RknnTensorType::Uint8,
+            RknnTensorFormat::NHWC,
+        )?;
+        self.rknn.run()?;
+        let output = self.rknn.outputs_get::<f32>()?;
+        Ok(output.to_vec())
+    }
+
+    fn encode_all(&self, images: &[RgbImage]) -> Result<ImageEmbeds, BoxError> {
+        let mut data = Vec::new();
+        for image in images {
+            data.extend(self.encode(image)?);
+        }
+        Ok(ImageEmbeds {
+            data,
+            n_image: images.len(),
+            n_image_tokens: self.n_image_tokens,
+            width: self.width as usize,
+            height: self.height as usize,
+        })
+    }
+}
+
+/// Pad to a square with the processor mean color, like the rknn-llm multimodal demo does.
+fn expand_to_square(image: &RgbImage) -> RgbImage {
+    let (width, height) = (image.width(), image.height());
+    if width == height {
+        return image.clone();
+    }
+    let size = width.max(height);
+    let mut canvas = RgbImage::from_pixel(size, size, Rgb([127, 127, 127]));
+    image::imageops::overlay(
+        &mut canvas,
+        image,
+        ((size - width) / 2) as i64,
+        ((size - height) / 2) as i64,
+    );
+    canvas
+}
+
+#[derive(Debug)]
+struct FakeThreadSafeEncoder(VisionEncoder);
+
+unsafe impl Send for FakeThreadSafeEncoder {}
+unsafe impl Sync for FakeThreadSafeEncoder {}
+
+#[derive(Debug)]
+pub struct SimpleRkVLM {
+    handle: Arc<RawRkllm>,
+    encoder: Arc<FakeThreadSafeEncoder>,
+    // 視覺編碼器跟 LLM 共用 NPU，同一時間只跑一個請求
+    exec_lock: Arc<Mutex<()>>,
+    atoken: Arc<AutoTokenizer>,
+    config: ModelConfig,
+    _crash_guard: crate::crash::CleanupGuard,
+}
+
+impl Actor for SimpleRkVLM {
+    type Context = actix::Context<Self>;
+}
+
+/// Flatten message content for the chat template, leaving a placeholder at each image position.
+fn content_to_prompt(content: &Option<Content>, image_urls: &mut Vec<String>) -> String {
+    match content {
+        Some(Content::String(s)) => s.clone(),
+        Some(Content::Array(items)) => items.join(""),
+        Some(Content::Parts(parts)) => parts
+            .iter()
+            .filter_map(|p| match p.r#type.as_str() {
+                "image_url" => {
+                    let url = p.image_url.as_ref().and_then(|i| i.url.clone())?;
+                    image_urls.push(url);
+                    Some(IMAGE_PLACEHOLDER.to_owned())
+                }
+                _ => p.text.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(""),
+        None => "".to_owned(),
+    }
+}
+
+pub(crate) fn build_prompt(messages: &[Message]) -> (Vec<DefaultPromptMessage>, Vec<String>) {
+    let mut image_urls = Vec::new();
+    let prompt = messages
+        .iter()
+        .map(|m| {
+            let content = content_to_prompt(&m.content, &mut image_urls);
+            DefaultPromptMessage::new(to_variant_name(&m.role).unwrap(), &content)
+        })
+        .collect();
+    (prompt, image_urls)
+}
+
+/// Decode a `data:image/...;base64,` URL.
+fn decode_data_url(url: &str) -> Result<Vec<u8>, BoxError> {
+    let payload = url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"))
+        .map(|(_, data)| data)
+        .ok_or("Only base64 data URLs are supported for image input")?;
+    Ok(base64::engine::general_purpose::STANDARD.decode(payload.trim())?)
+}
+
+fn load_images(urls: &[String]) -> Result<Vec<RgbImage>, BoxError> {
+    urls.iter()
+        .map(|url| {
+            let bytes = decode_data_url(url)?;
+            Ok(image::load_from_memory(&bytes)?.to_rgb8())
+        })
+        .collect()
+}
+
+impl actix::Handler<ProcessMessages> for SimpleRkVLM {
+    type Result = Result<Pin<Box<dyn futures::Stream<Item = String> + Send + 'static>>, ()>;
+
+    fn handle(&mut self, msg: ProcessMessages, _ctx: &mut Self::Context) -> Self::Result {
+        let (tx, rx) = tokio::sync::mpsc::channel(64);
+        let (prompt, image_urls) = build_prompt(&msg.messages);
+        let (stop, max_tokens) = (msg.stop, msg.max_tokens);
+
+        let input = match super::simple::apply_chat_template(&self.atoken, prompt) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                log::warn!("Failed to apply chat template. Error: {:?}", err);
+                "".to_owned()
+            }
+        };
+
+        let think = self.config.think.unwrap_or(false);
+        let model_name = self.config.model_name.clone();
+        let max_context_len = self.config.max_context_len.max(0) as usize;
+        let max_new_tokens = max_new_tokens(&self.config);
+        let handle_arc = self.handle.clone();
+        let encoder = self.encoder.clone();
+        let exec_lock = self.exec_lock.clone();
+        tokio::task::spawn_blocking(move || {
+            let _guard = exec_lock.lock().unwrap();
+            let handle_for_abort = handle_arc.clone();
+            let cb = CallbackSendSelfChannel {
+                sender: Some(tx.clone()),
+                model: model_name,
+                prefilling: Default::default(),
+                prompt_tokens: 0,
+                generated: 0,
+                stop: crate::stop::StopSequences::new(stop),
+                max_tokens,
+                max_context: max_context_len,
+                max_new_tokens: max_new_tokens as usize,
+                logprobs: None,
+                bpe: None,
+                entries: vec![],
+                abort: Box::new(move || {
+                    let handle_in_thread = handle_for_abort.clone();
+                    std::thread::spawn(move || {
+                        if let Err(err) = handle_in_thread.abort() {
+                            log::error!("Failed to abort RKLLM execution: {}", err);
+                        }
+                    });
+                }),
+            };
+
+            let result = load_images(&image_urls).and_then(|images| {
+                if images.is_empty() {
+                    handle_arc.run(&input, None, think, cb)
+                } else {
+                    let mut embeds = encoder.0.encode_all(&images)?;
+                    handle_arc.run(&input, Some(&mut embeds), think, cb)
+                }
+            });
+            if let Err(e) = result {
+                log::error!("RKLLM multimodal execution failed: {}", e);
+                let error_msg = format!("Model error: execution failed. Details: {}", e);
+                if let Err(e) = tx.blocking_send(error_msg) {
+                    log::error!("blocking_send failed: {}", e);
+                }
+                if let Err(e) = tx.blocking_send(String::new()) {
+                    log::error!("blocking_send failed: {}", e);
+                }
+            }
+
+            drop(tx);
+        });
+
+        let stream = ReceiverStream::new(rx);
+        Ok(Box::pin(stream))
+    }
+}
+
+impl actix::Handler<ShutdownMessages> for SimpleRkVLM {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, _: ShutdownMessages, ctx: &mut Self::Context) -> Self::Result {
+        let _guard = self.exec_lock.lock().unwrap();
+        ctx.stop();
+        Ok(())
+    }
+}
+
+impl AIModel for SimpleRkVLM {
+    type Config = ModelConfig;
+    fn init_with_progress<P: Progress + ModelProgress + Clone>(
+        config: &Self::Config,
+        p: Option<P>,
+    ) -> Result<Self, BoxError> {
+        if config.vision_model_path.is_none() {
+            return Err(format!(
+                "Model \"{}\" is a VLM but vision_model_path is not set",
+                config.model_name
+            )
+            .into());
+        }
+        // model_files 裡第一個是模型檔，第二個是 vision encoder
+        let (paths, progress) = fetch_files(config, &model_files(config), p)?;
+        let (model_path, encoder_path) = (paths[0].clone(), paths[1].clone());
+
+        let progress = if let Some(mut progress) = progress {
+            let meta = fs::metadata(&model_path)?;
+            let filename = model_path.file_name().unwrap().to_string_lossy();
+            progress.model_load(meta.len().try_into().unwrap(), &filename, Instant::now());
+            Some(progress)
+        } else {
+            None
+        };
+
+        let encoder = VisionEncoder::new(&encoder_path)
+            .map_err(|e| format!("Error initializing vision encoder: {}", e))?;
+        let handle = RawRkllm::init(&model_path, config)
+            .map_err(|e| format!("Error initializing RKLLM: {}", e))?;
+        let atoken = load_tokenizer(config)?;
+
+        if let Some(mut progress) = progress {
+            progress.model_finished();
+        }
+
+        let handle = Arc::new(handle);
+        let weak = Arc::downgrade(&handle);
+        let crash_guard = crate::crash::on_crash(move || {
+            if let Some(handle) = weak.upgrade() {
+                let _ = handle.abort();
+            }
+        });
+
+        Ok(SimpleRkVLM {
+            handle,
+            encoder: Arc::new(FakeThreadSafeEncoder(encoder)),
+            exec_lock: Arc::new(Mutex::new(())),
+            atoken,
+            config: config.clone(),
+            _crash_guard: crash_guard,
+        })
+    }
+}
+
+impl LLM for SimpleRkVLM {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ContentPart, ImageUrl, Role};
+
+    #[test]
+    fn image_parts_become_placeholders() {
+        let messages = vec![Message {
+            role: Some(Role::User),
+            content: Some(Content::Parts(vec![
+                ContentPart {
+                    r#type: "image_url".to_owned(),
+                    text: None,
+                    image_url: Some(ImageUrl {
+                        url: Some("data:image/png;base64,AAAA".to_owned()),
+                        detail: None,
+                    }),
+                    input_audio: None,
+                },
+                ContentPart {
+                    r#type: "text".to_owned(),
+                    text: Some("What is this?".to_owned()),
+                    image_url: None,
+                    input_audio: None,
+                },
+            ])),
+            ..Default::default()
+        }];
+
+        let (prompt, urls) = build_prompt(&messages);
+        assert_eq!(prompt[0].content, "<image>What is this?");
+        assert_eq!(urls, vec!["data:image/png;base64,AAAA".to_owned()]);
+    }
+
+    #[test]
+    fn data_url_is_decoded() {
+        let bytes = decode_data_url("data:image/png;base64,aGVsbG8=").expect("should decode");
+        assert_eq!(bytes, b"hello");
+        assert!(decode_data_url("https://example.com/cat.png").is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use serde_json::{json, Value};
+
+use crate::{
+    chat::ChatCompletionsRequest,
+    llm::bpe::BpeTokenizer,
+    utils::{ModelConfig, ModelType},
+    OpenAiError,
+};
+
+/// Marks text sent together with the log probabilities of its tokens, like `usage:`.
+const PREFIX: &str = "\u{0}logprobs:";
+pub const MAX_TOP_LOGPROBS: usize = 20;
+
+fn invalid(message: &str, param: &str) -> OpenAiError {
+    OpenAiError {
+        message: message.to_owned(),
+        code: "invalid_value".to_owned(),
+        r#type: "invalid_request_error".to_owned(),
+        param: Some(param.to_owned()),
+    }
+}
+
+/// Only the rkllm text backend hands out logits, other models leave `logprobs` null.
+pub fn supported(config: &ModelConfig) -> bool {
+    config.model_type == ModelType::LLM
+}
+
+/// Alternatives to list per token when `logprobs` is on.
+pub fn requested(request: &ChatCompletionsRequest) -> Result<Option<usize>, OpenAiError> {
+    let top = request.top_logprobs.unwrap_or(0);
+    if top > MAX_TOP_LOGPROBS {
+        return Err(invalid(
+            "top_logprobs must be between 0 and 20.",
+            "top_logprobs",
+        ));
+    }
+    match request.logprobs {
+        Some(true) => Ok(Some(top)),
+        _ if request.top_logprobs.is_some() => Err(invalid(
+            "logprobs must be true when top_logprobs is set.",
+            "logprobs",
+        )),
+        _ => Ok(None),
+    }
+}
+
+fn token(id: usize, logprob: f32, bpe: Option<&BpeTokenizer>) -> Value {
+    let bytes = bpe.and_then(|bpe| bpe.token_bytes(id as u32));
+    let text = bytes
+        .as_deref()
+        .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+        .unwrap_or_default();
+    json!({ "token": text, "logprob": logprob, "bytes": bytes })
+}
+
+/// Entry of the sampled token from the logits of one step, with the `top` most likely
+/// alternatives.
+pub fn entry(
+    logits: &[f32],
+    token_id: i32,
+    text: &str,
+    top: usize,
+    bpe: Option<&BpeTokenizer>,
+) -> Option<Value> {
+    let sampled = *logits.get(usize::try_from(token_id).ok()?)?;
+    // log softmax
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let norm = max + logits.iter().map(|l| (l - max).exp()).sum::<f32>().ln();
+    let mut ranked: Vec<(usize, f32)> = logits.iter().copied().enumerate().collect();
+    let top = top.min(ranked.len());
+    if top > 0 {
+        ranked.select_nth_unstable_by(top - 1, |a, b| b.1.total_cmp(&a.1));
+        ranked.truncate(top);
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+    }
+    let alternatives: Vec<Value> = ranked
+        .into_iter()
+        .take(top)
+        .map(|(id, logit)| token(id, logit - norm, bpe))
+        .collect();
+    Some(json!({
+        "token": text,
+        "logprob": sampled - norm,
+        "bytes": text.as_bytes(),
+        "top_logprobs": alternatives,
+    }))
+}
+
+pub fn message(text: &str, entries: &[Value]) -> String {
+    format!("{}{}", PREFIX, json!({ "text": text, "content": entries }))
+}
+
+/// Text and token entries of a logprobs message, `None` for model output.
+pub fn parse(text: &str) -> Option<(String, Vec<Value>)> {
+    let mut message: Value = serde_json::from_str(text.strip_prefix(PREFIX)?).ok()?;
+    let content = serde_json::from_value(message["content"].take()).ok()?;
+    Some((message["text"].as_str()?.to_owned(), content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_softmax_of_logits() {
+        let entry = entry(&[2.0, 1.0, 0.0], 1, "b", 2, None).unwrap();
+        let logprob = entry["logprob"].as_f64().unwrap();
+        assert!((logprob - (-1.4076)).abs() < 1e-3);
+        let top = entry["top_logprobs"].as_array().unwrap();
+        assert_eq!(top.len(), 2);
+        assert!(top[0]["logprob"].as_f64().unwrap() > top[1]["logprob"].as_f64().unwrap());
+        assert!(super::entry(&[0.0], 3, "x", 0, None).is_none());
+
+        let (text, content) = parse(&message("Hi", &[entry])).unwrap();
+        assert_eq!((text.as_str(), content.len()), ("Hi", 1));
+        assert_eq!(parse("Hi"), None);
+    }
+}
@@ -1,48 +1,70 @@
 use actix::{Actor, Recipient};
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, Command};
 use std::{
     collections::HashMap,
-    fs,
-    io::Read,
     net::Ipv4Addr,
+    path::Path,
     sync::{Arc, Mutex},
     time::Duration,
 };
 
-use actix_web::{head, middleware::Logger, App, HttpServer, Result};
+use actix_web::{dev::Service, head, middleware::Logger, App, HttpServer, Result};
 use llmserver_rs::{
-    utils::ModelConfig, AIModel, OpenAiError, ProcessAudio, ProcessMessages, ShutdownMessages,
+    utils::ModelConfig,
+    validate::{self, Problem},
+    AIModel, OpenAiError, ProcessAudio, ProcessEmbeddings, ProcessImages, ProcessMessages,
+    ProcessRerank, ProcessSpeech, ShutdownMessages,
 };
 use utoipa_actix_web::{scope, AppExt};
 use utoipa_swagger_ui::SwaggerUi;
 
-fn load_model_configs() -> Result<HashMap<String, ModelConfig>, Box<dyn std::error::Error>> {
-    let dir_path = "assets/config";
-    let entries = fs::read_dir(dir_path).map_err(|e| e.to_string())?;
+const MODEL_MAP_PATH: &str = "assets/model_map.json";
+const DEFAULT_CONFIG_DIR: &str = "assets/config";
+const CONFIG_DIR_ENV: &str = "LLMSERVER_CONFIG_DIR";
 
-    let mut configs: HashMap<String, ModelConfig> = HashMap::new();
-
-    for entry in entries {
-        let entry = entry.map_err(|e| e.to_string())?;
-        let path = entry.path();
-
-        if path.is_file() && path.extension().and_then(|s| s.to_str()) == Some("json") {
-            let mut file = fs::File::open(&path).map_err(|e| e.to_string())?;
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)
-                .map_err(|e| e.to_string())?;
+/// Configs of the config directory and `model_map.json`, with everything wrong in them and in
+/// `assets/server.json`.
+fn check_configs(dir_path: &str) -> (HashMap<String, ModelConfig>, Vec<Problem>) {
+    let (mut configs, mut problems) = validate::check_dir(Path::new(dir_path));
+    // OpenAI 模型名稱對應表，可選
+    problems.extend(validate::check_model_map(Path::new(MODEL_MAP_PATH), &mut configs));
+    problems.extend(validate::check_server_config(
+        Path::new(llmserver_rs::preload::SERVER_CONFIG_PATH),
+        &configs,
+    ));
+    (configs, problems)
+}
 
-            let mut config: ModelConfig =
-                serde_json::from_str(&contents).map_err(|e| e.to_string())?;
-            log::info!("Loaded model config: {:?}", path.display());
-            config._asserts_path = path.to_string_lossy().to_string();
-            configs.insert(config.model_name.clone(), config);
+fn load_model_configs(
+    dir_path: &str,
+) -> Result<HashMap<String, ModelConfig>, Box<dyn std::error::Error>> {
+    let (configs, problems) = check_configs(dir_path);
+    for problem in &problems {
+        if problem.is_error() {
+            log::error!("{}", problem);
+        } else {
+            log::warn!("{}", problem);
         }
     }
-
+    let errors = problems.iter().filter(|problem| problem.is_error()).count();
+    if errors > 0 {
+        return Err(format!(
+            "{} errors in the model configs, run `llmserver validate-config` to list them",
+            errors
+        )
+        .into());
+    }
+    log::info!("Loaded {} model configs", configs.len());
     Ok(configs)
 }
 
+/// `--config-dir` 優先，其次環境變數
+fn config_dir(arg: Option<&String>) -> String {
+    arg.cloned()
+        .or_else(|| std::env::var(CONFIG_DIR_ENV).ok())
+        .unwrap_or_else(|| DEFAULT_CONFIG_DIR.to_owned())
+}
+
 /// Get health of the API.
 #[utoipa::path(
     responses(
@@ -63,39 +85,318 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = Command::new("rkllm")
         .about("Lightweight RKLLM inference web server")
         .version(VERSION)
-        .arg(Arg::new("model_name"))
+        .arg(
+            Arg::new("model_name")
+                .action(ArgAction::Append)
+                .help("Models to load before the server starts, like --preload"),
+        )
+        .arg(
+            Arg::new("config_dir")
+                .long("config-dir")
+                .help("Directory of the model configs, e.g. /etc/llmserver/models.d. Also read from LLMSERVER_CONFIG_DIR [default: assets/config]"),
+        )
+        .arg(
+            Arg::new("preload")
+                .long("preload")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Models to load before the server starts, comma separated or repeated. Also read from the preload array of assets/server.json"),
+        )
+        .arg(
+            Arg::new("peer")
+                .long("peer")
+                .action(ArgAction::Append)
+                .help("Base URL of a peer llmserver, e.g. http://10.0.0.2:8080. Can be repeated."),
+        )
+        .arg(
+            Arg::new("trusted_proxy")
+                .long("trusted-proxy")
+                .action(ArgAction::Append)
+                .help("Address or CIDR of a reverse proxy whose X-Forwarded-For / X-Real-IP are trusted, e.g. 127.0.0.1. Can be repeated."),
+        )
+        .arg(
+            Arg::new("mcp")
+                .long("mcp")
+                .action(ArgAction::Append)
+                .help("URL of an MCP server whose tools local models may call. Can be repeated."),
+        )
+        .arg(
+            Arg::new("grpc_port")
+                .long("grpc-port")
+                .value_parser(clap::value_parser!(u16))
+                .help("Also serve the gRPC API on this port (needs the grpc feature)."),
+        )
+        .arg(
+            Arg::new("store")
+                .long("store")
+                .help("SQLite file to record conversations, pulled models and aliases in (needs the sqlite feature)."),
+        )
+        .arg(
+            Arg::new("export")
+                .long("export")
+                .help("Directory to append finished completions and transcriptions to, as rotating JSONL files."),
+        )
+        .arg(
+            Arg::new("lang")
+                .long("lang")
+                .value_parser(["en", "zh"])
+                .help("Language of progress messages when the client sends no Accept-Language [default: zh]"),
+        )
+        .arg(
+            Arg::new("power_profile")
+                .long("power-profile")
+                .value_parser(["performance", "balanced", "quiet"])
+                .help("Fewer CPU cores and token pacing at lower SoC temperatures for fanless boxes [default: performance]"),
+        )
+        .arg(
+            Arg::new("coalesce_ms")
+                .long("coalesce-ms")
+                .value_parser(clap::value_parser!(u64))
+                .help("Merge streamed tokens into one SSE event at least every N ms [default: off]"),
+        )
+        .arg(
+            Arg::new("coalesce_tokens")
+                .long("coalesce-tokens")
+                .value_parser(clap::value_parser!(usize))
+                .help("Merge up to N streamed tokens into one SSE event [default: off]"),
+        )
+        .arg(
+            Arg::new("session_budget")
+                .long("session-budget")
+                .value_parser(clap::value_parser!(u64))
+                .help("Tokens each session (X-Session-Id or user) may generate, e.g. for demo kiosks [default: unlimited]"),
+        )
+        .arg(
+            Arg::new("memory_budget")
+                .long("memory-budget")
+                .value_parser(clap::value_parser!(u64))
+                .help("MB the loaded LLMs may take together, the least recently used are unloaded to make room [default: one LLM at a time]"),
+        )
+        .arg(
+            Arg::new("load_timeout")
+                .long("load-timeout")
+                .value_parser(clap::value_parser!(u64))
+                .help("Seconds a non-streaming request waits for its model to load before getting 503 with Retry-After, the load goes on [default: wait until loaded]"),
+        )
+        .arg(
+            Arg::new("offline")
+                .long("offline")
+                .action(ArgAction::SetTrue)
+                .help("Never download, models and tokenizers must be in local_repo, tokenizer_path or the hf-hub cache (also HF_HUB_OFFLINE=1)"),
+        )
+        .arg(
+            Arg::new("discover_owner")
+                .long("discover-owner")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Hugging Face owners whose repositories a chat request may name without a config, e.g. kautism [default: none, only configured models]"),
+        )
+        .arg(
+            Arg::new("user_daily_tokens")
+                .long("user-daily-tokens")
+                .value_parser(clap::value_parser!(u64))
+                .help("Prompt and generated tokens each user of the chat requests may use per UTC day [default: unlimited]"),
+        )
+        .arg(
+            Arg::new("user_monthly_tokens")
+                .long("user-monthly-tokens")
+                .value_parser(clap::value_parser!(u64))
+                .help("Prompt and generated tokens each user of the chat requests may use per UTC month [default: unlimited]"),
+        )
+        .subcommand(
+            Command::new("usage-export")
+                .about("Print token usage per day, API key and model from a --store database")
+                .arg(Arg::new("store").long("store").required(true))
+                .arg(Arg::new("start").long("start").help("First day, YYYY-MM-DD (UTC)"))
+                .arg(Arg::new("end").long("end").help("Last day, YYYY-MM-DD (UTC)"))
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["csv", "json"])
+                        .default_value("csv"),
+                ),
+        )
+        .subcommand(
+            Command::new("validate-config")
+                .about("Check the model configs, model_map.json and server.json, then exit")
+                .arg(
+                    Arg::new("config_dir")
+                        .long("config-dir")
+                        .help("Directory of the model configs [default: assets/config]"),
+                ),
+        )
         .get_matches();
 
+    if let Some(("validate-config", args)) = matches.subcommand() {
+        let config_dir = config_dir(args.get_one::<String>("config_dir"));
+        let (configs, problems) = check_configs(&config_dir);
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        let errors = problems.iter().filter(|problem| problem.is_error()).count();
+        println!(
+            "{}: {} model configs, {} errors, {} warnings",
+            config_dir,
+            configs.len(),
+            errors,
+            problems.len() - errors
+        );
+        if errors > 0 {
+            return Err(format!("{} errors in the model configs", errors).into());
+        }
+        return Ok(());
+    }
+
+    if let Some(("usage-export", args)) = matches.subcommand() {
+        #[cfg(feature = "sqlite")]
+        {
+            let start = args.get_one::<String>("start").map(String::as_str);
+            let end = args.get_one::<String>("end").map(String::as_str);
+            if start
+                .into_iter()
+                .chain(end)
+                .any(|day| !llmserver_rs::store::is_day(day))
+            {
+                return Err("--start and --end must be YYYY-MM-DD dates".into());
+            }
+            let store = llmserver_rs::store::Store::open(args.get_one::<String>("store").unwrap())?;
+            let rows = store.usage_report(start, end)?;
+            if args.get_one::<String>("format").map(String::as_str) == Some("json") {
+                println!("{}", serde_json::to_string_pretty(&rows)?);
+            } else {
+                print!("{}", llmserver_rs::store::usage_csv(&rows));
+            }
+            return Ok(());
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            let _ = args;
+            return Err("usage-export needs the sqlite feature".into());
+        }
+    }
+
+    if let Some(lang) = matches
+        .get_one::<String>("lang")
+        .and_then(|lang| llmserver_rs::i18n::Lang::parse(lang))
+    {
+        llmserver_rs::i18n::set_default_lang(lang);
+    }
+
+    if let Some(profile) = matches
+        .get_one::<String>("power_profile")
+        .and_then(|profile| llmserver_rs::thermal::PowerProfile::parse(profile))
+    {
+        llmserver_rs::thermal::set_profile(profile);
+    }
+
+    let offline = matches.get_flag("offline")
+        || llmserver_rs::hub::offline_from_env(
+            std::env::var(llmserver_rs::hub::OFFLINE_ENV).ok().as_deref(),
+        );
+    if offline {
+        log::info!("Offline mode, nothing is downloaded");
+        llmserver_rs::hub::set_offline(true);
+    }
+
     //初始化模型
-    let model_name_opt = matches.get_one::<String>("model_name");
+    let server_config =
+        llmserver_rs::preload::ServerConfig::load(llmserver_rs::preload::SERVER_CONFIG_PATH)?;
+    llmserver_rs::hub::set_defaults(server_config.hub.clone());
+    let preload = server_config.preload_list(
+        matches
+            .get_many::<String>("model_name")
+            .into_iter()
+            .flatten()
+            .chain(matches.get_many::<String>("preload").into_iter().flatten())
+            .cloned(),
+    );
 
     // Text type LLM
     let llm_recipients = Arc::new(Mutex::new(
         HashMap::<String, Recipient<ProcessMessages>>::new(),
     ));
     let audio_recipients = Arc::new(Mutex::new(HashMap::<String, Recipient<ProcessAudio>>::new()));
+    let embedding_recipients = Arc::new(Mutex::new(
+        HashMap::<String, Recipient<ProcessEmbeddings>>::new(),
+    ));
+    let rerank_recipients = Arc::new(Mutex::new(
+        HashMap::<String, Recipient<ProcessRerank>>::new(),
+    ));
+    let image_recipients = Arc::new(Mutex::new(
+        HashMap::<String, Recipient<ProcessImages>>::new(),
+    ));
+    let speech_recipients = Arc::new(Mutex::new(
+        HashMap::<String, Recipient<ProcessSpeech>>::new(),
+    ));
+    let failed_models: llmserver_rs::fallback::FailedModels = Default::default();
+    let sessions = llmserver_rs::sessions::Sessions::default();
     let shutdown_recipients = Arc::new(Mutex::new(
         HashMap::<String, Recipient<ShutdownMessages>>::new(),
     ));
 
-    let model_config_table = load_model_configs()?;
+    llmserver_rs::crash::install(
+        llmserver_rs::crash::CRASH_DIR,
+        llm_recipients.clone(),
+        sessions.clone(),
+    );
 
-    if let Some(model_name) = model_name_opt {
+    let config_dir = config_dir(matches.get_one::<String>("config_dir"));
+    log::info!("Loading model configs from {}", config_dir);
+    let model_config_table = load_model_configs(&config_dir)?;
+
+    // 每個模型都在 HTTP server 開始前載好
+    for model_name in &preload {
+        log::info!("Preloading {}", model_name);
         if let Some(config) = model_config_table.get(model_name) {
-            if config.model_type == llmserver_rs::utils::ModelType::LLM {
-                let llm = llmserver_rs::llm::simple::SimpleRkLLM::init(&config);
+            if config.model_type == llmserver_rs::utils::ModelType::LLM
+                || config.model_type == llmserver_rs::utils::ModelType::VLM
+                || config.model_type == llmserver_rs::utils::ModelType::Echo
+            {
+                let llm = llmserver_rs::llm::LoadedLLM::init(&config);
                 let model_name = config.model_name.clone();
 
-                let addr = llm.unwrap().start(); // 啟動 Actor，一次即可
-                llm_recipients.lock().unwrap().insert(
-                    model_name.clone(),
-                    addr.clone().recipient::<ProcessMessages>(),
-                );
+                let (recipient, shutdown) = llm.unwrap().start(); // 啟動 Actor，一次即可
+                llm_recipients
+                    .lock()
+                    .unwrap()
+                    .insert(model_name.clone(), recipient);
                 shutdown_recipients
                     .clone()
                     .lock()
                     .unwrap()
-                    .insert(model_name, addr.clone().recipient::<ShutdownMessages>());
+                    .insert(model_name, shutdown);
+            } else if config.model_type == llmserver_rs::utils::ModelType::Embedding {
+                let model = llmserver_rs::embedding::simple::SimpleEmbedding::init(&config);
+                let addr = model.unwrap().start();
+                embedding_recipients.lock().unwrap().insert(
+                    config.model_name.clone(),
+                    addr.clone().recipient::<ProcessEmbeddings>(),
+                );
+                llmserver_rs::backends::register(&config.model_name, addr.recipient());
+            } else if config.model_type == llmserver_rs::utils::ModelType::Rerank {
+                let model = llmserver_rs::reranker::simple::SimpleRerank::init(&config);
+                let addr = model.unwrap().start();
+                rerank_recipients
+                    .lock()
+                    .unwrap()
+                    .insert(config.model_name.clone(), addr.clone().recipient::<ProcessRerank>());
+                llmserver_rs::backends::register(&config.model_name, addr.recipient());
+            } else if config.model_type == llmserver_rs::utils::ModelType::Image {
+                let model = llmserver_rs::diffusion::simple::SimpleDiffusion::init(&config);
+                let addr = model.unwrap().start();
+                image_recipients
+                    .lock()
+                    .unwrap()
+                    .insert(config.model_name.clone(), addr.clone().recipient::<ProcessImages>());
+                llmserver_rs::backends::register(&config.model_name, addr.recipient());
+            } else if config.model_type == llmserver_rs::utils::ModelType::TTS {
+                let model = llmserver_rs::tts::simple::SimpleTTS::init(&config);
+                let addr = model.unwrap().start();
+                speech_recipients
+                    .lock()
+                    .unwrap()
+                    .insert(config.model_name.clone(), addr.clone().recipient::<ProcessSpeech>());
+                llmserver_rs::backends::register(&config.model_name, addr.recipient());
             } else if config.model_type == llmserver_rs::utils::ModelType::ASR {
                 // let (llm, model_name) = match (*model_name).as_str() {
                 //     "happyme531/SenseVoiceSmall-RKNN2" => {
@@ -120,10 +421,137 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 //     .insert(model_name, addr.clone().recipient::<ShutdownMessages>());
             }
         } else {
-            panic!("Model \"{}\" not found in the configuration!", model_name);
+            let message = format!("Model \"{}\" not found in the configuration!", model_name);
+            return Err(message.into());
         }
     }
 
+    let peers = matches
+        .get_many::<String>("peer")
+        .map(|peers| peers.cloned().collect())
+        .unwrap_or_default();
+    let cluster = llmserver_rs::cluster::Cluster::new(peers);
+    cluster.spawn_refresh();
+
+    let trusted_proxy: Vec<String> = matches
+        .get_many::<String>("trusted_proxy")
+        .map(|proxies| proxies.cloned().collect())
+        .unwrap_or_default();
+    let trusted_proxies = llmserver_rs::proxy::TrustedProxies::parse(&trusted_proxy)?;
+
+    let mcp_servers = matches
+        .get_many::<String>("mcp")
+        .map(|servers| servers.cloned().collect())
+        .unwrap_or_default();
+    let plugins = llmserver_rs::plugins::Plugins::load(llmserver_rs::plugins::PLUGINS_PATH)?;
+    if !plugins.is_empty() {
+        log::info!("Loaded {} plugin tools", plugins.tools().len());
+    }
+    let mcp = llmserver_rs::mcp::McpClient::new(mcp_servers).with_plugins(plugins);
+    mcp.spawn_refresh();
+
+    let keep_alives = llmserver_rs::keep_alive::KeepAlives::default();
+    keep_alives.clone().spawn(
+        llm_recipients.clone(),
+        shutdown_recipients.clone(),
+        model_config_table.clone(),
+        sessions.clone(),
+    );
+
+    let rag = llmserver_rs::rag::RagStore::open(llmserver_rs::rag::RAG_DIR)?;
+    let files = llmserver_rs::files::FileStore::new(llmserver_rs::files::FILES_DIR);
+    let batches = llmserver_rs::batches::BatchStore::new(llmserver_rs::batches::BATCHES_DIR);
+    let uploads = llmserver_rs::uploads::UploadStore::new(llmserver_rs::uploads::UPLOADS_DIR);
+    let coalesce = llmserver_rs::coalesce::Coalesce {
+        window_ms: matches.get_one::<u64>("coalesce_ms").copied().unwrap_or(0),
+        max_tokens: matches.get_one::<usize>("coalesce_tokens").copied(),
+    };
+    let budgets =
+        llmserver_rs::budgets::Budgets::new(matches.get_one::<u64>("session_budget").copied());
+    let quotas = llmserver_rs::quotas::Quotas::new(
+        matches.get_one::<u64>("user_daily_tokens").copied(),
+        matches.get_one::<u64>("user_monthly_tokens").copied(),
+    );
+    let aliases = llmserver_rs::aliases::Aliases::new(MODEL_MAP_PATH);
+    let discovered = llmserver_rs::discover::Discovered::new(
+        matches
+            .get_many::<String>("discover_owner")
+            .map(|owners| owners.cloned().collect())
+            .unwrap_or_default(),
+    );
+    let loads = llmserver_rs::chat::Loads::default();
+    let memory_budget = llmserver_rs::eviction::MemoryBudget::new(
+        matches.get_one::<u64>("memory_budget").copied(),
+    );
+    let schedule = llmserver_rs::schedule::Schedule::load(llmserver_rs::schedule::SCHEDULE_PATH)?;
+    if !schedule.is_empty() {
+        log::info!("Loaded {} warm windows", schedule.windows.len());
+    }
+    schedule.spawn(
+        llm_recipients.clone(),
+        shutdown_recipients.clone(),
+        model_config_table.clone(),
+        sessions.clone(),
+        memory_budget.clone(),
+        loads.clone(),
+    );
+    let load_timeout = llmserver_rs::chat::LoadTimeout(
+        matches
+            .get_one::<u64>("load_timeout")
+            .map(|secs| Duration::from_secs(*secs)),
+    );
+    let exporter = matches.get_one::<String>("export").map(|dir| {
+        llmserver_rs::export::Exporter::new(dir, llmserver_rs::export::DEFAULT_MAX_BYTES)
+    });
+
+    if let Some(port) = matches.get_one::<u16>("grpc_port") {
+        #[cfg(feature = "grpc")]
+        actix_web::rt::spawn(llmserver_rs::grpc::serve(
+            (Ipv4Addr::UNSPECIFIED, *port).into(),
+            llmserver_rs::grpc::GrpcService {
+                llm_pool: llm_recipients.clone(),
+                embedding_pool: embedding_recipients.clone(),
+                audio_pool: audio_recipients.clone(),
+                all_configs: model_config_table.clone(),
+            },
+        ));
+        #[cfg(not(feature = "grpc"))]
+        log::warn!(
+            "--grpc-port {} is ignored, build with `--features grpc` to enable gRPC",
+            port
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    let store = match matches.get_one::<String>("store") {
+        Some(path) => Some(llmserver_rs::store::Store::open(path)?),
+        None => None,
+    };
+    #[cfg(feature = "sqlite")]
+    let registry = match matches.get_one::<String>("store") {
+        Some(path) => Some(llmserver_rs::registry::Registry::open(path)?),
+        None => None,
+    };
+    // 重開前的別名、keep_alive 跟最近使用時間
+    #[cfg(feature = "sqlite")]
+    if let Some(registry) = &registry {
+        let loaded: Vec<String> = llm_recipients.lock().unwrap().keys().cloned().collect();
+        registry.restore(
+            &loaded,
+            &model_config_table,
+            &aliases,
+            &keep_alives,
+            &memory_budget,
+        )?;
+    }
+    #[cfg(not(feature = "sqlite"))]
+    if let Some(path) = matches.get_one::<String>("store") {
+        log::warn!(
+            "--store {} is ignored, build with `--features sqlite` to enable it",
+            path
+        );
+    }
+
     let shutdown_recipients_cloned = shutdown_recipients.clone();
     HttpServer::new(move || {
         let shutdown_for_data = shutdown_recipients_cloned.clone();
@@ -143,19 +571,129 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 )
                 .into()
             });
+        let v1 = scope::scope("/v1")
+            .service(llmserver_rs::chat::chat_completions)
+            .service(llmserver_rs::completions::completions)
+            .service(llmserver_rs::responses::responses)
+            .service(llmserver_rs::openai::models)
+            .service(llmserver_rs::audio::audio_transcriptions)
+            .service(llmserver_rs::speech::audio_speech)
+            .service(llmserver_rs::embeddings::embeddings)
+            .service(llmserver_rs::rerank::rerank)
+            .service(llmserver_rs::images::images_generations)
+            .service(llmserver_rs::realtime::realtime)
+            .service(llmserver_rs::rag::list)
+            .service(llmserver_rs::rag::ingest)
+            .service(llmserver_rs::rag::search)
+            .service(llmserver_rs::rag::delete_document)
+            .service(llmserver_rs::rag::delete_knowledge_base)
+            .service(llmserver_rs::prompt_cache::list_caches)
+            .service(llmserver_rs::prompt_cache::delete_caches)
+            .service(llmserver_rs::budgets::get_budget)
+            .service(llmserver_rs::budgets::set_budget)
+            .service(llmserver_rs::budgets::delete_budget)
+            .service(llmserver_rs::quotas::get_quota)
+            .service(llmserver_rs::files::upload)
+            .service(llmserver_rs::files::list)
+            .service(llmserver_rs::files::retrieve)
+            .service(llmserver_rs::files::content)
+            .service(llmserver_rs::files::remove)
+            .service(llmserver_rs::batches::create)
+            .service(llmserver_rs::batches::list)
+            .service(llmserver_rs::batches::retrieve)
+            .service(llmserver_rs::batches::cancel)
+            .service(llmserver_rs::uploads::create)
+            .service(llmserver_rs::uploads::retrieve)
+            .service(llmserver_rs::uploads::append)
+            .service(llmserver_rs::uploads::remove)
+            .service(llmserver_rs::vector_stores::create)
+            .service(llmserver_rs::vector_stores::list)
+            .service(llmserver_rs::vector_stores::retrieve)
+            .service(llmserver_rs::vector_stores::modify)
+            .service(llmserver_rs::vector_stores::remove)
+            .service(llmserver_rs::vector_stores::create_file)
+            .service(llmserver_rs::vector_stores::list_files)
+            .service(llmserver_rs::vector_stores::remove_file)
+            .service(llmserver_rs::vector_stores::search);
+        // 有指定 --store 才記錄對話
+        #[cfg(feature = "sqlite")]
+        let v1 = match &store {
+            Some(store) => v1
+                .app_data(actix_web::web::Data::new(store.clone()))
+                .service(llmserver_rs::store::list_completions)
+                .service(llmserver_rs::store::get_completion)
+                .service(llmserver_rs::store::get_completion_messages)
+                .service(llmserver_rs::store::delete_completion)
+                .service(llmserver_rs::store::history)
+                .service(llmserver_rs::store::usage)
+                .service(llmserver_rs::store::usage_export),
+            None => v1,
+        };
+        // 有指定 --export 才匯出
+        let v1 = match &exporter {
+            Some(exporter) => v1.app_data(actix_web::web::Data::new(exporter.clone())),
+            None => v1,
+        };
         let (app, api) = App::new()
             .app_data(json_config)
             .app_data(actix_web::web::Data::new(llm_recipients.clone()))
             .app_data(actix_web::web::Data::new(audio_recipients.clone()))
+            .app_data(actix_web::web::Data::new(embedding_recipients.clone()))
+            .app_data(actix_web::web::Data::new(rerank_recipients.clone()))
+            .app_data(actix_web::web::Data::new(image_recipients.clone()))
+            .app_data(actix_web::web::Data::new(speech_recipients.clone()))
             .app_data(actix_web::web::Data::new(model_config_table.clone()))
+            .app_data(actix_web::web::Data::new(failed_models.clone()))
+            .app_data(actix_web::web::Data::new(sessions.clone()))
+            .app_data(actix_web::web::Data::new(cluster.clone()))
+            .app_data(actix_web::web::Data::new(coalesce))
+            .app_data(actix_web::web::Data::new(load_timeout))
+            .app_data(actix_web::web::Data::new(memory_budget.clone()))
+            .app_data(actix_web::web::Data::new(loads.clone()))
+            .app_data(actix_web::web::Data::new(budgets.clone()))
+            .app_data(actix_web::web::Data::new(quotas.clone()))
+            .app_data(actix_web::web::Data::new(aliases.clone()))
+            .app_data(actix_web::web::Data::new(discovered.clone()))
+            .app_data(actix_web::web::Data::new(keep_alives.clone()))
+            .app_data(actix_web::web::Data::new(mcp.clone()))
+            .app_data(actix_web::web::Data::new(rag.clone()))
+            .app_data(actix_web::web::Data::new(files.clone()))
+            .app_data(actix_web::web::Data::new(batches.clone()))
+            .app_data(actix_web::web::Data::new(uploads.clone()))
             .app_data(actix_web::web::Data::new(shutdown_for_data))
+            .app_data(actix_web::web::Data::new(trusted_proxies.clone()))
             .into_utoipa_app()
-            .map(|app| app.wrap(Logger::default()))
+            .map(|app| {
+                // 跟預設格式一樣，只是 IP 換成 proxy 後面的客戶端
+                let proxies = trusted_proxies.clone();
+                let logger =
+                    Logger::new("%{client}xi \"%r\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" %T")
+                        .custom_request_replace("client", move |req| {
+                            proxies
+                                .client_ip(req.peer_addr(), req.headers())
+                                .map_or_else(|| "-".to_owned(), |ip| ip.to_string())
+                        });
+                app.wrap(logger).wrap_fn(|req, srv| {
+                    // 崩潰報告裡列出處理中的請求
+                    let guard = llmserver_rs::crash::track(req.method().as_str(), req.path());
+                    let response = srv.call(req);
+                    async move {
+                        let response = response.await;
+                        drop(guard);
+                        response
+                    }
+                })
+            })
+            .service(v1)
+            .service(scope::scope("/debug").service(llmserver_rs::debug::render))
+            .service(
+                scope::scope("/admin")
+                    .service(llmserver_rs::admin::list_cache)
+                    .service(llmserver_rs::admin::delete_cache),
+            )
             .service(
-                scope::scope("/v1")
-                    .service(llmserver_rs::chat::chat_completions)
-                    .service(llmserver_rs::openai::models)
-                    .service(llmserver_rs::audio::audio_transcriptions),
+                // Anthropic Messages API compatible
+                scope::scope("/anthropic/v1").service(llmserver_rs::anthropic::messages),
             )
             .service(
                 // Some Ollama compatible APIs
@@ -163,10 +701,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .service(llmserver_rs::ollama::version)
                     .service(llmserver_rs::ollama::push)
                     .service(llmserver_rs::ollama::pull)
+                    .service(llmserver_rs::ollama::chat)
+                    .service(llmserver_rs::ollama::delete)
+                    .service(llmserver_rs::ollama::copy)
+                    .service(llmserver_rs::ollama::tags)
                     .service(llmserver_rs::ollama::ps),
             )
+            .service(llmserver_rs::ws::ws_chat)
+            // Text Generation Inference compatible
+            .service(llmserver_rs::tgi::generate)
+            .service(llmserver_rs::tgi::generate_stream)
+            .service(llmserver_rs::ui::index)
             .service(health)
             .split_for_parts();
+        #[cfg(feature = "sqlite")]
+        let app = match &registry {
+            Some(registry) => app.app_data(actix_web::web::Data::new(registry.clone())),
+            None => app,
+        };
 
         app.service(SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-docs/openapi.json", api))
     })
@@ -193,6 +745,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     tokio::spawn(async {
         futures::future::join_all(shutdowns).await;
+        llmserver_rs::backends::unload_all().await;
     })
     .await?;
     Ok(())
@@ -0,0 +1,521 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use actix_web::{web, HttpResponse};
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    chat::{
+        append_system_prompt, create_sse_chunk_data, sse_frame, status_event,
+        ChatCompletionsRequest, ToolChoice, REQUEST_ID_HEADER, SSE_DONE,
+    },
+    i18n::{self, MessageKey},
+    plugins::Plugins,
+    ws::{chunk_text, sse_data, ChatContext},
+    Content, Message, Role,
+};
+
+const PROTOCOL_VERSION: &str = "2025-03-26";
+const SESSION_HEADER: &str = "Mcp-Session-Id";
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+const CALL_TIMEOUT: Duration = Duration::from_secs(30);
+/// 避免模型一直呼叫工具停不下來
+const MAX_ROUNDS: usize = 5;
+pub(crate) const TOOL_CALL_OPEN: &str = "<tool_call>";
+const TOOL_CALL_CLOSE: &str = "</tool_call>";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct McpTool {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default, rename = "inputSchema")]
+    pub input_schema: Value,
+}
+
+/// A `<tool_call>` emitted by the model.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ToolCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+/// MCP servers (streamable HTTP transport) and server-side plugins whose tools are offered to
+/// local models.
+#[derive(Debug, Clone, Default)]
+pub struct McpClient {
+    servers: Arc<Vec<String>>,
+    plugins: Arc<Plugins>,
+    /// (server URL, tool), in advertising order
+    tools: Arc<Mutex<Vec<(String, McpTool)>>>,
+    /// server URL -> Mcp-Session-Id
+    sessions: Arc<Mutex<Vec<(String, String)>>>,
+    next_id: Arc<AtomicU64>,
+    client: reqwest::Client,
+}
+
+impl McpClient {
+    pub fn new(servers: Vec<String>) -> Self {
+        Self {
+            servers: Arc::new(servers),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_plugins(mut self, plugins: Plugins) -> Self {
+        self.plugins = Arc::new(plugins);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.servers.is_empty() && self.plugins.is_empty()
+    }
+
+    /// 本機 plugin 優先，同名的 MCP 工具會被蓋掉
+    pub fn tools(&self) -> Vec<McpTool> {
+        let mut tools = self.plugins.tools();
+        tools.extend(
+            self.tools
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, tool)| !self.plugins.has(&tool.name))
+                .map(|(_, tool)| tool.clone()),
+        );
+        tools
+    }
+
+    fn session(&self, server: &str) -> Option<String> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(url, _)| url == server)
+            .map(|(_, session)| session.clone())
+    }
+
+    async fn post(&self, server: &str, message: Value) -> Result<reqwest::Response, String> {
+        let mut request = self
+            .client
+            .post(server)
+            .header(
+                reqwest::header::ACCEPT,
+                "application/json, text/event-stream",
+            )
+            .timeout(CALL_TIMEOUT)
+            .json(&message);
+        if let Some(session) = self.session(server) {
+            request = request.header(SESSION_HEADER, session);
+        }
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        if let Some(session) = response
+            .headers()
+            .get(SESSION_HEADER)
+            .and_then(|v| v.to_str().ok())
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            sessions.retain(|(url, _)| url != server);
+            sessions.push((server.to_owned(), session.to_owned()));
+        }
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status()));
+        }
+        Ok(response)
+    }
+
+    /// JSON-RPC request, the server may answer with plain JSON or an SSE stream.
+    async fn rpc(&self, server: &str, method: &str, params: Value) -> Result<Value, String> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let response = self
+            .post(
+                server,
+                json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }),
+            )
+            .await?;
+        let is_sse = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/event-stream"));
+        let text = response.text().await.map_err(|e| e.to_string())?;
+        let message = if is_sse {
+            text.lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .filter_map(|data| serde_json::from_str::<Value>(data.trim()).ok())
+                .find(|message| message["id"] == id)
+        } else {
+            serde_json::from_str::<Value>(&text).ok()
+        }
+        .ok_or_else(|| format!("Invalid response to {}", method))?;
+        if let Some(error) = message.get("error") {
+            return Err(error["message"]
+                .as_str()
+                .map(str::to_owned)
+                .unwrap_or_else(|| error.to_string()));
+        }
+        Ok(message["result"].clone())
+    }
+
+    async fn connect(&self, server: &str) -> Result<Vec<McpTool>, String> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .retain(|(url, _)| url != server);
+        self.rpc(
+            server,
+            "initialize",
+            json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": {},
+                "clientInfo": { "name": "llmserver-rs", "version": env!("CARGO_PKG_VERSION") },
+            }),
+        )
+        .await?;
+        self.post(
+            server,
+            json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+        )
+        .await?;
+
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let result = self.rpc(server, "tools/list", params).await?;
+            let page: Vec<McpTool> =
+                serde_json::from_value(result["tools"].clone()).map_err(|e| e.to_string())?;
+            tools.extend(page);
+            cursor = result["nextCursor"].as_str().map(str::to_owned);
+            if cursor.is_none() {
+                break;
+            }
+        }
+        Ok(tools)
+    }
+
+    /// Reconnect to every server and reload its tools. Unreachable servers contribute nothing.
+    pub async fn refresh(&self) {
+        let mut tools: Vec<(String, McpTool)> = Vec::new();
+        for server in self.servers.iter() {
+            match self.connect(server).await {
+                Ok(server_tools) => {
+                    for tool in server_tools {
+                        if tools.iter().any(|(_, t)| t.name == tool.name) {
+                            log::warn!("MCP tool {} of {} is shadowed", tool.name, server);
+                            continue;
+                        }
+                        tools.push((server.clone(), tool));
+                    }
+                }
+                Err(e) => log::warn!("MCP server {} is unreachable: {}", server, e),
+            }
+        }
+        *self.tools.lock().unwrap() = tools;
+    }
+
+    /// 背景定期更新工具清單
+    pub fn spawn_refresh(&self) {
+        if self.servers.is_empty() {
+            return;
+        }
+        let mcp = self.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(REFRESH_INTERVAL);
+            loop {
+                interval.tick().await;
+                mcp.refresh().await;
+            }
+        });
+    }
+
+    /// Run a tool call. Failures are returned as text so the model can react to them.
+    pub async fn call(&self, call: &ToolCall) -> String {
+        if self.plugins.has(&call.name) {
+            return self.plugins.call(call).await;
+        }
+        let server = self
+            .tools
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, tool)| tool.name == call.name)
+            .map(|(server, _)| server.clone());
+        let Some(server) = server else {
+            return format!("Error: unknown tool {}", call.name);
+        };
+        let arguments = if call.arguments.is_object() {
+            call.arguments.clone()
+        } else {
+            json!({})
+        };
+        let result = match self
+            .rpc(
+                &server,
+                "tools/call",
+                json!({ "name": call.name, "arguments": arguments }),
+            )
+            .await
+        {
+            Ok(result) => result,
+            Err(e) => return format!("Error: {}", e),
+        };
+        let text: Vec<&str> = result["content"]
+            .as_array()
+            .map(|content| {
+                content
+                    .iter()
+                    .filter_map(|part| part["text"].as_str())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let text = text.join("\n");
+        if result["isError"].as_bool() == Some(true) {
+            format!("Error: {}", text)
+        } else {
+            text
+        }
+    }
+}
+
+/// Hermes style tool description, the format Qwen chat templates use.
+pub(crate) fn tools_prompt(tools: &[McpTool]) -> String {
+    let mut prompt = "# Tools\n\nYou may call one or more functions to assist with the user query.\n\nYou are provided with function signatures within <tools></tools> XML tags:\n<tools>".to_owned();
+    for tool in tools {
+        let function = json!({
+            "type": "function",
+            "function": {
+                "name": tool.name,
+                "description": tool.description,
+                "parameters": tool.input_schema,
+            }
+        });
+        prompt.push('\n');
+        prompt.push_str(&function.to_string());
+    }
+    prompt.push_str("\n</tools>\n\nFor each function call, return a json object with function name and arguments within <tool_call></tool_call> XML tags:\n<tool_call>\n{\"name\": <function-name>, \"arguments\": <args-json-object>}\n</tool_call>");
+    prompt
+}
+
+/// 把工具說明加進 system prompt，沒有 system 就新增一則
+fn with_tools(mut messages: Vec<Message>, tools: &[McpTool]) -> Vec<Message> {
+    append_system_prompt(&mut messages, &tools_prompt(tools));
+    messages
+}
+
+pub(crate) fn parse_tool_calls(text: &str) -> Vec<ToolCall> {
+    text.split(TOOL_CALL_OPEN)
+        .skip(1)
+        .filter_map(|call| {
+            let call = call.split(TOOL_CALL_CLOSE).next().unwrap_or(call);
+            serde_json::from_str(call.trim()).ok()
+        })
+        .collect()
+}
+
+/// Length of the end of `text` that could still grow into `<tool_call>`.
+pub(crate) fn partial_tag_len(text: &str) -> usize {
+    (1..TOOL_CALL_OPEN.len())
+        .rev()
+        .find(|&len| text.ends_with(&TOOL_CALL_OPEN[..len]))
+        .unwrap_or(0)
+}
+
+pub(crate) fn is_finish_chunk(data: &str) -> bool {
+    serde_json::from_str::<Value>(data)
+        .is_ok_and(|chunk| !chunk["choices"][0]["finish_reason"].is_null())
+}
+
+/// 每一輪都是新的 chat request，chunk 改用第一輪的 id
+fn sse(data: String, id: &str) -> web::Bytes {
+    match serde_json::from_str::<Value>(&data) {
+        Ok(mut chunk) if chunk.get("choices").is_some() => {
+            chunk["id"] = json!(id);
+            web::Bytes::from(sse_frame(&chunk.to_string()))
+        }
+        _ => web::Bytes::from(sse_frame(&data)),
+    }
+}
+
+/// Chat with the MCP and plugin tools offered to the model: tool calls are executed and the results fed
+/// back for another round until the model answers in plain text.
+pub(crate) async fn agent_chat(
+    body: ChatCompletionsRequest,
+    ctx: ChatContext,
+    mcp: web::Data<McpClient>,
+) -> HttpResponse {
+    let tools = mcp.tools();
+    // 客戶端自己帶 tools 的話交給客戶端處理
+    let disabled = matches!(&body.tool_choice, Some(ToolChoice::Mode(mode)) if mode == "none");
+    if tools.is_empty() || body.tools.is_some() || disabled {
+        return ctx.chat(body).await;
+    }
+
+    let mut body = body;
+    body.messages = with_tools(body.messages, &tools);
+    let first = ctx.chat(body.clone()).await;
+    if !first.status().is_success() {
+        return first;
+    }
+
+    let mut builder = HttpResponse::Ok();
+    for (name, value) in first.headers() {
+        builder.insert_header((name.clone(), value.clone()));
+    }
+    let lang = i18n::request_lang(&ctx.req);
+    let id = first
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .map(str::to_owned)
+        .unwrap_or_else(|| format!("chatcmpl-{:016x}", rand::random::<u64>()));
+    let created = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let events = async_stream::stream! {
+        let mut response = first;
+        for round in 0..MAX_ROUNDS {
+            // 最後一輪不再執行工具，原樣輸出
+            let allow_tools = round + 1 < MAX_ROUNDS;
+            let mut text = String::new();
+            let mut held: Vec<String> = Vec::new();
+            let mut finish: Vec<String> = Vec::new();
+            let mut calling = false;
+
+            let mut chunks = Box::pin(sse_data(response));
+            while let Some(data) = chunks.next().await {
+                match chunk_text(&data) {
+                    Ok(Some(delta)) => {
+                        text.push_str(&delta);
+                        if calling {
+                            continue;
+                        }
+                        if allow_tools && text.contains(TOOL_CALL_OPEN) {
+                            calling = true;
+                            held.clear();
+                            continue;
+                        }
+                        held.push(data);
+                        if !allow_tools || partial_tag_len(&text) == 0 {
+                            for data in held.drain(..) {
+                                yield Ok::<_, actix_web::Error>(sse(data, &id));
+                            }
+                        }
+                    }
+                    Ok(None) if is_finish_chunk(&data) => finish.push(data),
+                    Ok(None) => yield Ok(sse(data, &id)),
+                    Err(_) => {
+                        yield Ok(sse(data, &id));
+                        return;
+                    }
+                }
+            }
+
+            let calls = if calling { parse_tool_calls(&text) } else { vec![] };
+            if calls.is_empty() {
+                if calling {
+                    // 格式壞掉的 tool_call 就當成一般回答
+                    let rest = text[text.find(TOOL_CALL_OPEN).unwrap_or(0)..].to_owned();
+                    yield Ok(web::Bytes::from(create_sse_chunk_data(
+                        &id, created, &body.model, None, Some(Content::String(rest)),
+                    )));
+                }
+                for data in held.drain(..).chain(finish) {
+                    yield Ok(sse(data, &id));
+                }
+                yield Ok(web::Bytes::from(SSE_DONE));
+                return;
+            }
+
+            body.messages.push(Message {
+                role: Some(Role::Assistant),
+                content: Some(Content::String(text.clone())),
+                ..Default::default()
+            });
+            let mut results = Vec::new();
+            for call in &calls {
+                log::info!("Calling tool {} {}", call.name, call.arguments);
+                let status = MessageKey::ToolCalling {
+                    name: call.name.clone(),
+                    arguments: call.arguments.to_string(),
+                };
+                yield Ok(web::Bytes::from(status_event(&status.text(lang), false)));
+                let result = mcp.call(call).await;
+                results.push(format!("<tool_response>\n{}\n</tool_response>", result));
+            }
+            body.messages.push(Message {
+                role: Some(Role::User),
+                content: Some(Content::String(results.join("\n"))),
+                ..Default::default()
+            });
+
+            response = ctx.chat(body.clone()).await;
+            if !response.status().is_success() {
+                let bytes = actix_web::body::to_bytes(response.into_body())
+                    .await
+                    .unwrap_or_default();
+                yield Ok(sse(format!("{{\"error\": {}}}", String::from_utf8_lossy(&bytes)), &id));
+                return;
+            }
+        }
+    };
+
+    builder.content_type("text/event-stream").streaming(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tool_calls_are_parsed_and_advertised() {
+        let text = "Let me check.<tool_call>\n{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Taipei\"}}\n</tool_call>";
+        assert_eq!(
+            parse_tool_calls(text),
+            vec![ToolCall {
+                name: "get_weather".to_owned(),
+                arguments: json!({ "city": "Taipei" }),
+            }]
+        );
+        assert_eq!(partial_tag_len("Sure <tool_"), 6);
+        assert_eq!(partial_tag_len("Sure"), 0);
+
+        let tools = vec![McpTool {
+            name: "get_weather".to_owned(),
+            description: Some("Weather by city".to_owned()),
+            input_schema: json!({ "type": "object" }),
+        }];
+        let messages = with_tools(
+            vec![Message {
+                role: Some(Role::User),
+                content: Some(Content::String("Hi".to_owned())),
+                ..Default::default()
+            }],
+            &tools,
+        );
+        assert_eq!(messages.len(), 2);
+        assert!(
+            matches!(&messages[0].content, Some(Content::String(s)) if s.contains("\"name\":\"get_weather\""))
+        );
+    }
+
+    #[test]
+    fn rounds_keep_the_first_id() {
+        let chunk = sse(r#"{"id":"chatcmpl-2","choices":[]}"#.to_owned(), "chatcmpl-1");
+        assert!(String::from_utf8_lossy(&chunk).contains("\"id\":\"chatcmpl-1\""));
+        let error = sse(r#"{"error":"busy"}"#.to_owned(), "chatcmpl-1");
+        assert!(!String::from_utf8_lossy(&error).contains("chatcmpl-1"));
+    }
+}
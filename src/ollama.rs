@@ -1,14 +1,27 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Instant};
 
-use actix::Recipient;
 use actix_web::{
-    get, post,
+    delete, get, post,
     web::{self, Json},
-    HttpResponse, Responder,
+    HttpRequest, HttpResponse, Responder,
 };
+use base64::Engine;
 use serde::{Deserialize, Serialize};
 
-use crate::{ProcessMessages, utils::ModelConfig};
+use futures::StreamExt;
+use serde_json::{json, Value};
+
+use crate::{
+    aliases::Aliases,
+    chat::{ChatCompletionsRequest, JsonSchemaFormat, LlmPool, ResponseFormat, ShutdownPool, Stop},
+    errors::relay_status,
+    keep_alive::KeepAlives,
+    listing::{loaded_models, ModelsQuery},
+    llm::simple::{cached_file, fetch_file, model_files, remove_cached_file},
+    utils::{ModelConfig, ModelType, OpenWebUIProgress},
+    ws::{chunk_text, sse_data, ChatContext},
+    Content, ContentPart, ImageUrl, Message, OpenAiError, Role, ShutdownMessages,
+};
 
 #[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
 pub struct Version {
@@ -55,6 +68,57 @@ pub fn default_true() -> bool {
     true
 }
 
+/// 只留英數字跟小數點比較，`Qwen3-1.7B` 跟 `qwen3:1.7b` 一樣
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '.')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// The config an Ollama style `name:tag` stands for. `:latest` is the model itself, other tags
+/// pick the model whose name spells out base and tag (`qwen3:1.7b` is `Qwen3-1.7B`) or, among
+/// models named like the base, the one with that parameter size or quantization
+/// (`qwen3:w8a8`).
+pub fn config_name(
+    name: &str,
+    all_configs: &HashMap<String, ModelConfig>,
+    aliases: &Aliases,
+) -> Option<String> {
+    let exact = |name: &str| {
+        let name = aliases.resolve(name);
+        all_configs.contains_key(&name).then_some(name)
+    };
+    let (base, tag) = match name.rsplit_once(':') {
+        Some((base, tag)) => (base, tag),
+        None => return exact(name).or_else(|| exact(&format!("{}:latest", name))),
+    };
+    if let Some(name) = exact(name) {
+        return Some(name);
+    }
+    if tag.eq_ignore_ascii_case("latest") {
+        return exact(base);
+    }
+
+    let mut names: Vec<&String> = all_configs.keys().collect();
+    names.sort();
+    let spelled = normalize(&format!("{}{}", base, tag));
+    if let Some(name) = names.iter().find(|name| normalize(name) == spelled) {
+        return Some(name.to_string());
+    }
+    let base = normalize(base);
+    names
+        .into_iter()
+        .filter(|name| normalize(name).starts_with(&base))
+        .find(|name| {
+            let config = &all_configs[*name];
+            parameter_size(config).is_some_and(|size| size.eq_ignore_ascii_case(tag))
+                || crate::openai::quantization(config)
+                    .is_some_and(|quantization| quantization.eq_ignore_ascii_case(tag))
+        })
+        .cloned()
+}
+
 #[utoipa::path(
     request_body = PullPushRequest,
     responses(
@@ -71,22 +135,268 @@ pub async fn push(_body: Json<PullPushRequest>) -> impl Responder {
     })
 }
 
+/// Ollama style pull progress: `pulling manifest`, one line per percent of each file, `success`.
+fn pull_progress(config: ModelConfig) -> impl futures::Stream<Item = serde_json::Value> {
+    async_stream::stream! {
+        yield json!({ "status": "pulling manifest" });
+        for file in model_files(&config) {
+            let (progress_tx, mut progress_rx) = tokio::sync::mpsc::channel(64);
+            let fetch_config = config.clone();
+            let fetch_file_name = file.clone();
+            let join_handle = tokio::task::spawn_blocking(move || {
+                let progress = OpenWebUIProgress::new(progress_tx);
+                fetch_file(&fetch_config, &fetch_file_name, Some(progress))
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            });
+
+            let mut percent = None;
+            while let Some(msg) = progress_rx.recv().await {
+                if msg.download_done || msg.total == 0 {
+                    continue;
+                }
+                let current = msg.current * 100 / msg.total;
+                if percent == Some(current) {
+                    continue;
+                }
+                percent = Some(current);
+                yield json!({
+                    "status": format!("pulling {}", file),
+                    "digest": file,
+                    "total": msg.total,
+                    "completed": msg.current,
+                });
+            }
+
+            match join_handle.await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    yield json!({ "error": e });
+                    return;
+                }
+                Err(e) => {
+                    yield json!({ "error": format!("Join err: {}", e) });
+                    return;
+                }
+            }
+        }
+        yield json!({ "status": "success" });
+    }
+}
+
 #[utoipa::path(
     request_body = PullPushRequest,
     responses(
-        (status = OK, description = "Success", body = Status, content_type = "application/json")
+        (status = OK, description = "Success", body = Status, content_type = "application/x-ndjson")
     ),
     security(
         ("api_key" = [])
     ),
 )]
 #[post("/pull")]
-pub async fn pull(_body: Json<PullPushRequest>) -> impl Responder {
-    HttpResponse::Ok().json(Status {
-        status: "not implemented".to_string(),
+pub async fn pull(
+    body: Json<PullPushRequest>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    aliases: web::Data<Aliases>,
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))] req: HttpRequest,
+) -> impl Responder {
+    let Some(config) = config_name(&body.model, &all_configs, &aliases)
+        .and_then(|name| all_configs.get(&name))
+        .filter(|c| matches!(c.model_type, ModelType::LLM | ModelType::VLM))
+        .cloned()
+    else {
+        return HttpResponse::NotFound().json(json!({
+            "error": format!("model \"{}\" not found", body.model)
+        }));
+    };
+
+    let progress = pull_progress(config.clone());
+    #[cfg(feature = "sqlite")]
+    let progress =
+        crate::registry::record_pull(progress, crate::registry::from_request(&req), config);
+    if !body.stream {
+        let Some(last) = progress.collect::<Vec<_>>().await.pop() else {
+            return HttpResponse::InternalServerError().finish();
+        };
+        if last.get("error").is_some() {
+            return HttpResponse::InternalServerError().json(last);
+        }
+        return HttpResponse::Ok().json(last);
+    }
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(
+            progress.map(|line| Ok::<_, actix_web::Error>(web::Bytes::from(format!("{}\n", line)))),
+        )
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[schema(example = json!({ "source": "DeepSeek-R1-Distill-Qwen-1.5B", "destination": "assistant:latest" }))]
+pub struct CopyRequest {
+    pub source: String,
+    pub destination: String,
+}
+
+/// Another name for a model, the backing config and files stay the same.
+#[utoipa::path(
+    request_body = CopyRequest,
+    responses(
+        (status = OK, description = "Success", body = Status, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/copy")]
+pub async fn copy(
+    body: Json<CopyRequest>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    aliases: web::Data<Aliases>,
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))] req: HttpRequest,
+) -> impl Responder {
+    let Some(source) = config_name(&body.source, &all_configs, &aliases) else {
+        return HttpResponse::NotFound().json(json!({
+            "error": format!("model \"{}\" not found", body.source)
+        }));
+    };
+    match aliases.copy(&source, &body.destination, &all_configs) {
+        Ok(()) => {
+            #[cfg(feature = "sqlite")]
+            if let Some(registry) = crate::registry::from_request(&req) {
+                let source = aliases.resolve(&source);
+                if let Err(e) = registry.alias(&body.destination, &source) {
+                    log::warn!("Failed to register alias {}: {}", body.destination, e);
+                }
+            }
+            HttpResponse::Ok().json(Status {
+                status: "success".to_owned(),
+            })
+        }
+        Err(e) => HttpResponse::BadRequest().json(json!({ "error": e })),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[schema(example = json!({ "model": "DeepSeek-R1-Distill-Qwen-1.5B", "remove_files": true }))]
+pub struct DeleteRequest {
+    pub model: String,
+    /// Also remove the downloaded model files from the hf-hub cache
+    #[serde(default)]
+    pub remove_files: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct DeleteStatus {
+    pub status: String,
+    pub unloaded: bool,
+    /// Bytes removed from the hf-hub cache
+    pub freed: u64,
+}
+
+/// Unload a model, and with `remove_files` free the storage its cached files take.
+#[utoipa::path(
+    request_body = DeleteRequest,
+    responses(
+        (status = OK, description = "Success", body = DeleteStatus, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[delete("/delete")]
+pub async fn delete(
+    body: Json<DeleteRequest>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    llm_pool: web::Data<LlmPool>,
+    shutdown_pool: web::Data<ShutdownPool>,
+    aliases: web::Data<Aliases>,
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))] req: HttpRequest,
+) -> impl Responder {
+    let Some(config) = config_name(&body.model, &all_configs, &aliases)
+        .and_then(|name| all_configs.get(&name).cloned())
+    else {
+        return HttpResponse::NotFound().json(json!({
+            "error": format!("model \"{}\" not found", body.model)
+        }));
+    };
+    if body.remove_files {
+        let mut loaded: Vec<String> = shutdown_pool.lock().unwrap().keys().cloned().collect();
+        loaded.extend(crate::backends::loaded());
+        let sharing = sharing_files(&config, &all_configs, &loaded);
+        if !sharing.is_empty() {
+            return HttpResponse::Conflict().json(json!({
+                "error": format!(
+                    "the files of \"{}\" are used by loaded models {}, unload them first",
+                    body.model,
+                    sharing.join(", ")
+                )
+            }));
+        }
+    }
+
+    llm_pool.lock().unwrap().remove(&config.model_name);
+    let shutdown = shutdown_pool.lock().unwrap().remove(&config.model_name);
+    let mut unloaded = shutdown.is_some();
+    if let Some(addr) = shutdown {
+        log::info!("Unloading {}", config.model_name);
+        // 等 actor 放掉 NPU 跟檔案再刪
+        if let Err(e) = addr.send(ShutdownMessages).await {
+            log::warn!("Actor is already dead, skipping shutdown signal: {}", e);
+        }
+    }
+    unloaded |= crate::backends::unload(&config.model_name).await;
+
+    let mut freed = 0;
+    if body.remove_files {
+        #[cfg(feature = "sqlite")]
+        if let Some(registry) = crate::registry::from_request(&req) {
+            if let Err(e) = registry.forget(&config.model_name) {
+                log::warn!("Failed to unregister {}: {}", config.model_name, e);
+            }
+        }
+        let removed = tokio::task::spawn_blocking(move || {
+            model_files(&config)
+                .iter()
+                .map(|file| remove_cached_file(&config, file))
+                .sum::<std::io::Result<u64>>()
+        })
+        .await
+        .map_err(|e| format!("Join err: {}", e))
+        .and_then(|r| r.map_err(|e| e.to_string()));
+        match removed {
+            Ok(bytes) => freed = bytes,
+            Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e })),
+        }
+    }
+
+    HttpResponse::Ok().json(DeleteStatus {
+        status: "success".to_owned(),
+        unloaded,
+        freed,
     })
 }
 
+/// Loaded models other than `config` that read the files of its repository, aliases, replicas and
+/// variants among them.
+fn sharing_files(
+    config: &ModelConfig,
+    all_configs: &HashMap<String, ModelConfig>,
+    loaded: &[String],
+) -> Vec<String> {
+    let mut models: Vec<String> = loaded
+        .iter()
+        .filter(|name| **name != config.model_name)
+        .filter(|name| {
+            all_configs
+                .get(*name)
+                .is_some_and(|other| other.model_repo == config.model_repo)
+        })
+        .cloned()
+        .collect();
+    models.sort();
+    models
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
 pub struct ModelDetail {
     pub format: String,
@@ -97,18 +407,25 @@ pub struct ModelDetail {
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
-struct OllamaModel {
+pub struct OllamaModel {
     pub name: String,
+    pub model: String,
+    /// Newest modified time of the downloaded files, empty before the first pull
     #[serde(default)]
     pub modified_at: String,
+    /// Bytes of the downloaded files
     #[serde(default)]
-    pub size: String,
+    pub size: u64,
     #[serde(default)]
     pub digest: String,
     pub details: Option<ModelDetail>,
+    /// Loaded in the NPU right now
+    #[serde(default)]
+    pub loaded: bool,
 }
 
 #[utoipa::path(
+    params(ModelsQuery),
     responses(
         (status = OK, description = "Success", body = OllamaModel, content_type = "application/json")
     ),
@@ -117,24 +434,125 @@ struct OllamaModel {
     ),
 )]
 #[get("/tags")]
-pub async fn tags(all_configs: web::Data<HashMap<String, ModelConfig>>) -> impl Responder {
-    HttpResponse::Ok().json(
-        all_configs
-            .keys()
-            .map(|config| OllamaModel {
-                name: config.clone(),
-                modified_at: "".to_string(),
-                size: "".to_string(),
-                digest: "".to_string(),
-                details: None,
-            })
-            .collect::<Vec<OllamaModel>>(),
+pub async fn tags(
+    query: web::Query<ModelsQuery>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    aliases: web::Data<Aliases>,
+    req: HttpRequest,
+) -> impl Responder {
+    let loaded = loaded_models(&req);
+    let copies = aliases.names();
+    let mut names: Vec<(&String, &ModelConfig)> = all_configs
+        .iter()
+        .chain(copies.iter().filter_map(|alias| {
+            all_configs
+                .get(&aliases.resolve(alias))
+                .map(|config| (alias, config))
+        }))
+        .filter(|(name, config)| query.matches(name, config, &loaded))
+        .collect();
+    names.sort_by_key(|(name, _)| *name);
+    let models = query
+        .page(names)
+        .into_iter()
+        .map(|(name, config)| {
+            let (modified_at, size, digest) =
+                recorded_pull(&req, config).unwrap_or_else(|| scan_files(config));
+            OllamaModel {
+                name: name.clone(),
+                model: name.clone(),
+                modified_at,
+                size,
+                digest,
+                details: Some(details(config)),
+                loaded: loaded.contains(&config.model_name),
+            }
+        })
+        .collect::<Vec<OllamaModel>>();
+    HttpResponse::Ok().json(json!({ "models": models }))
+}
+
+/// The files of a model that are already downloaded.
+fn cached_files(config: &ModelConfig) -> Vec<std::fs::Metadata> {
+    model_files(config)
+        .iter()
+        .filter_map(|file| cached_file(config, file))
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .collect()
+}
+
+/// `(modified_at, size, digest)` of the downloaded files.
+fn scan_files(config: &ModelConfig) -> (String, u64, String) {
+    let files = cached_files(config);
+    let modified_at = files
+        .iter()
+        .filter_map(|metadata| metadata.modified().ok())
+        .max()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|time| crate::utils::rfc3339(time.as_secs()))
+        .unwrap_or_default();
+    (
+        modified_at,
+        files.iter().map(|metadata| metadata.len()).sum(),
+        crate::fingerprint::model_digest(config).unwrap_or_default(),
     )
 }
 
+/// The last pull of the model in the `--store` database, instead of looking at its files.
+#[cfg(feature = "sqlite")]
+fn recorded_pull(req: &HttpRequest, config: &ModelConfig) -> Option<(String, u64, String)> {
+    let registry = crate::registry::from_request(req)?;
+    let (pulled_at, size, digest) = registry.get(&config.model_name).ok()??.pull()?;
+    Some((crate::utils::rfc3339(pulled_at), size, digest))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn recorded_pull(_req: &HttpRequest, _config: &ModelConfig) -> Option<(String, u64, String)> {
+    None
+}
+
+/// Parameter count from the model name, like `1.5B` of `DeepSeek-R1-Distill-Qwen-1.5B`.
+fn parameter_size(config: &ModelConfig) -> Option<String> {
+    [config.model_name.as_str(), config.model_repo.as_str()]
+        .iter()
+        .find_map(|name| {
+            name.split(['-', '_', '/', ':']).find_map(|part| {
+                let count = part.strip_suffix(['B', 'b', 'M', 'm'])?;
+                count.parse::<f32>().ok()?;
+                Some(part.to_uppercase())
+            })
+        })
+}
+
+fn details(config: &ModelConfig) -> ModelDetail {
+    ModelDetail {
+        format: crate::listing::backend(&config.model_type).to_owned(),
+        family: String::new(),
+        families: Vec::new(),
+        parameter_size: parameter_size(config).unwrap_or_default(),
+        quantization_level: crate::openai::quantization(config).unwrap_or_default(),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct RunningModel {
+    pub name: String,
+    pub model: String,
+    pub size: u64,
+    pub digest: String,
+    pub details: ModelDetail,
+    pub expires_at: String,
+    /// NPU memory estimate: the weights are mapped into the shared DRAM the NPU reads from
+    pub size_vram: u64,
+}
+
+/// `keep_alive: -1` 的模型不會自動卸載
+const NEVER_EXPIRES: &str = "9999-12-31T23:59:59Z";
+
+/// Models loaded in the NPU right now.
 #[utoipa::path(
     responses(
-        (status = OK, description = "Success", body = OllamaModel, content_type = "application/json")
+        (status = OK, description = "Success", body = RunningModel, content_type = "application/json")
     ),
     security(
         ("api_key" = [])
@@ -142,18 +560,435 @@ pub async fn tags(all_configs: web::Data<HashMap<String, ModelConfig>>) -> impl
 )]
 #[get("/ps")]
 pub async fn ps(
-    llm_pool: web::Data<HashMap<String, Vec<Recipient<ProcessMessages>>>>,
+    llm_pool: web::Data<LlmPool>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    keep_alives: web::Data<KeepAlives>,
+    req: HttpRequest,
 ) -> impl Responder {
-    HttpResponse::Ok().json(
-        llm_pool
-            .keys()
-            .map(|config| OllamaModel {
-                name: config.clone(),
-                modified_at: "".to_string(),
-                size: "".to_string(),
-                digest: "".to_string(),
-                details: None,
-            })
-            .collect::<Vec<OllamaModel>>(),
+    let mut names: Vec<String> = llm_pool.lock().unwrap().keys().cloned().collect();
+    names.sort();
+    let models = names
+        .into_iter()
+        .filter_map(|name| all_configs.get(&name).map(|config| (name, config.clone())))
+        .map(|(name, config)| {
+            let size = crate::llm::simple::footprint(&config);
+            let expires_at = keep_alives
+                .expires_at(&name)
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|time| crate::utils::rfc3339(time.as_secs()))
+                .unwrap_or_else(|| NEVER_EXPIRES.to_owned());
+            RunningModel {
+                model: name.clone(),
+                name,
+                size,
+                digest: recorded_pull(&req, &config)
+                    .map(|(_, _, digest)| digest)
+                    .unwrap_or_else(|| {
+                        crate::fingerprint::model_digest(&config).unwrap_or_default()
+                    }),
+                details: details(&config),
+                expires_at,
+                size_vram: size,
+            }
+        })
+        .collect::<Vec<_>>();
+    HttpResponse::Ok().json(json!({ "models": models }))
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct OllamaOptions {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub top_k: Option<i32>,
+    /// Tokens to generate, like `max_tokens`
+    pub num_predict: Option<i32>,
+    pub seed: Option<i32>,
+    #[serde(default)]
+    pub stop: Vec<String>,
+    pub repeat_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct OllamaMessage {
+    #[schema(value_type = Role)]
+    pub role: Role,
+    #[serde(default)]
+    pub content: String,
+    /// Base64 encoded images, without a `data:` prefix
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[schema(example = json!({
+    "model": "Qwen2.5-3B-abliterated",
+    "messages": [{ "role": "user", "content": "Why is the sky blue?" }]
+}))]
+pub struct OllamaChatRequest {
+    pub model: String,
+    #[serde(default)]
+    pub messages: Vec<OllamaMessage>,
+    #[serde(default = "default_true")]
+    pub stream: bool,
+    /// `"json"` or a JSON schema the answer must follow
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub format: Option<Value>,
+    #[serde(default)]
+    pub options: OllamaOptions,
+    #[serde(default)]
+    #[schema(value_type = Option<String>)]
+    pub keep_alive: Option<crate::keep_alive::KeepAlive>,
+}
+
+impl OllamaMessage {
+    fn into_message(self) -> Result<Message, String> {
+        let content = if self.images.is_empty() {
+            Content::String(self.content)
+        } else {
+            let mut parts = vec![ContentPart {
+                r#type: "text".to_owned(),
+                text: Some(self.content),
+                image_url: None,
+                input_audio: None,
+            }];
+            for image in self.images {
+                let url = base64::engine::general_purpose::STANDARD
+                    .decode(image.trim())
+                    .ok()
+                    .and_then(|bytes| crate::image_input::data_url(&bytes))
+                    .ok_or("Images must be base64 encoded PNG or JPEG.")?;
+                parts.push(ContentPart {
+                    r#type: "image_url".to_owned(),
+                    text: None,
+                    image_url: Some(ImageUrl {
+                        url: Some(url),
+                        detail: None,
+                    }),
+                    input_audio: None,
+                });
+            }
+            Content::Parts(parts)
+        };
+        Ok(Message {
+            role: Some(self.role),
+            content: Some(content),
+            ..Default::default()
+        })
+    }
+}
+
+impl OllamaChatRequest {
+    fn into_chat_request(self) -> Result<ChatCompletionsRequest, String> {
+        let response_format = match self.format {
+            None | Some(Value::Null) => None,
+            Some(Value::String(format)) if format == "json" => Some(ResponseFormat {
+                r#type: "json_object".to_owned(),
+                json_schema: None,
+            }),
+            Some(schema @ Value::Object(_)) => Some(ResponseFormat {
+                r#type: "json_schema".to_owned(),
+                json_schema: Some(JsonSchemaFormat {
+                    name: "format".to_owned(),
+                    description: None,
+                    schema: Some(schema),
+                    strict: Some(true),
+                }),
+            }),
+            Some(format) => return Err(format!("Unsupported format {}", format)),
+        };
+        let options = self.options;
+        Ok(crate::chat::streamed(ChatCompletionsRequest {
+            model: self.model,
+            messages: self
+                .messages
+                .into_iter()
+                .map(OllamaMessage::into_message)
+                .collect::<Result<_, _>>()?,
+            temperature: options.temperature,
+            top_p: options.top_p,
+            top_k: options.top_k,
+            max_tokens: options.num_predict.filter(|n| *n > 0),
+            seed: options.seed,
+            stop: (!options.stop.is_empty()).then_some(Stop::Array(options.stop)),
+            repeat_penalty: options.repeat_penalty,
+            presence_penalty: options.presence_penalty,
+            frequency_penalty: options.frequency_penalty,
+            response_format,
+            keep_alive: self.keep_alive,
+            ..Default::default()
+        }))
+    }
+}
+
+fn now() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    crate::utils::rfc3339(secs)
+}
+
+/// Counts for the last line of an answer.
+#[derive(Debug, Default)]
+struct ChatStats {
+    prompt_eval_count: i32,
+    eval_count: i32,
+    done_reason: &'static str,
+    timings: crate::usage::Timings,
+}
+
+impl ChatStats {
+    /// Take the usage and finish reason of a chat chunk.
+    fn read(&mut self, data: &str) {
+        if let Some(usage) = crate::usage::from_chunk(data) {
+            self.prompt_eval_count = usage.prompt_tokens;
+            self.eval_count = usage.completion_tokens;
+        }
+        if let Some(timings) = crate::usage::timings_from_chunk(data) {
+            self.timings = timings;
+        }
+        let Ok(chunk) = serde_json::from_str::<Value>(data) else {
+            return;
+        };
+        match chunk["choices"][0]["finish_reason"].as_str() {
+            Some("length") => self.done_reason = "length",
+            Some(_) => self.done_reason = "stop",
+            None => {}
+        }
+    }
+
+    fn done(&self, model: &str, content: String, thinking: String, started: Instant) -> Value {
+        let mut line = chat_line(model, content, thinking);
+        line["done"] = json!(true);
+        line["done_reason"] = json!(if self.done_reason.is_empty() {
+            "stop"
+        } else {
+            self.done_reason
+        });
+        line["total_duration"] = json!(started.elapsed().as_nanos() as u64);
+        // Ollama 的時間都是奈秒
+        let nanos = |ms: f64| (ms * 1_000_000.0) as u64;
+        line["load_duration"] = json!(nanos(self.timings.load_ms));
+        line["prompt_eval_count"] = json!(self.prompt_eval_count);
+        line["prompt_eval_duration"] = json!(nanos(self.timings.prompt_ms));
+        line["eval_count"] = json!(self.eval_count);
+        line["eval_duration"] = json!(nanos(self.timings.generate_ms));
+        line
+    }
+}
+
+fn chat_line(model: &str, content: String, thinking: String) -> Value {
+    let mut message = json!({ "role": "assistant", "content": content });
+    if !thinking.is_empty() {
+        message["thinking"] = json!(thinking);
+    }
+    json!({ "model": model, "created_at": now(), "message": message, "done": false })
+}
+
+/// `reasoning_content` of a chat chunk.
+fn chunk_thinking(data: &str) -> Option<String> {
+    let chunk: Value = serde_json::from_str(data).ok()?;
+    Some(
+        chunk["choices"][0]["delta"]["reasoning_content"]
+            .as_str()?
+            .to_owned(),
     )
 }
+
+/// Ollama chat, streamed as NDJSON lines ending with a `done` line carrying the statistics.
+#[utoipa::path(
+    request_body = OllamaChatRequest,
+    responses(
+        (status = OK, description = "Success", content_type = "application/x-ndjson")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/chat")]
+pub async fn chat(body: Json<OllamaChatRequest>, ctx: ChatContext) -> impl Responder {
+    let started = Instant::now();
+    let mut body = body.into_inner();
+    let model = body.model.clone();
+    if let Some(name) = ctx
+        .req
+        .app_data::<web::Data<Aliases>>()
+        .and_then(|aliases| config_name(&body.model, &ctx.all_configs, aliases))
+    {
+        body.model = name;
+    }
+    let stream = body.stream;
+    let chat_request = match body.into_chat_request() {
+        Ok(chat_request) => chat_request,
+        Err(e) => return HttpResponse::BadRequest().json(json!({ "error": e })),
+    };
+
+    let response = ctx.chat(chat_request).await;
+    if !response.status().is_success() {
+        let mut builder = relay_status(&response);
+        let bytes = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap_or_default();
+        let message = serde_json::from_slice::<OpenAiError>(&bytes)
+            .map(|e| e.message)
+            .unwrap_or_else(|_| String::from_utf8_lossy(&bytes).to_string());
+        return builder.json(json!({ "error": message }));
+    }
+    let mut chunks = Box::pin(sse_data(response));
+
+    if !stream {
+        let mut stats = ChatStats::default();
+        let (mut content, mut thinking) = (String::new(), String::new());
+        while let Some(data) = chunks.next().await {
+            stats.read(&data);
+            thinking.extend(chunk_thinking(&data));
+            match chunk_text(&data) {
+                Ok(Some(delta)) => content.push_str(&delta),
+                Ok(None) => {}
+                Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e })),
+            }
+        }
+        return HttpResponse::Ok().json(stats.done(&model, content, thinking, started));
+    }
+
+    let lines = async_stream::stream! {
+        let mut stats = ChatStats::default();
+        while let Some(data) = chunks.next().await {
+            stats.read(&data);
+            let thinking = chunk_thinking(&data).unwrap_or_default();
+            match chunk_text(&data) {
+                Ok(content) if content.is_some() || !thinking.is_empty() => {
+                    let line = chat_line(&model, content.unwrap_or_default(), thinking);
+                    yield Ok::<_, actix_web::Error>(web::Bytes::from(format!("{}\n", line)));
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    yield Ok(web::Bytes::from(format!("{}\n", json!({ "error": e }))));
+                    return;
+                }
+            }
+        }
+        let done = stats.done(&model, String::new(), String::new(), started);
+        yield Ok(web::Bytes::from(format!("{}\n", done)));
+    };
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_loaded_models_sharing_files() {
+        let config = |name: &str, repo: &str| ModelConfig {
+            model_name: name.to_owned(),
+            model_repo: repo.to_owned(),
+            ..Default::default()
+        };
+        let all_configs = HashMap::from([
+            ("qwen".to_owned(), config("qwen", "kautism/qwen")),
+            ("qwen-fast".to_owned(), config("qwen-fast", "kautism/qwen")),
+            ("other".to_owned(), config("other", "kautism/other")),
+        ]);
+        let loaded = [
+            "qwen".to_owned(),
+            "qwen-fast".to_owned(),
+            "other".to_owned(),
+        ];
+        assert_eq!(
+            sharing_files(&all_configs["qwen"], &all_configs, &loaded),
+            ["qwen-fast"]
+        );
+        assert!(sharing_files(&all_configs["qwen"], &all_configs, &loaded[..1]).is_empty());
+    }
+
+    #[test]
+    fn ollama_chat_maps_to_chat() {
+        let body: OllamaChatRequest = serde_json::from_value(json!({
+            "model": "qwen",
+            "messages": [
+                { "role": "system", "content": "Be brief." },
+                { "role": "user", "content": "What is this?", "images": ["iVBORw0KGgoAAAANSUhEUg=="] }
+            ],
+            "format": "json",
+            "options": { "num_predict": 64, "stop": ["\n"], "top_k": 20 }
+        }))
+        .unwrap();
+        assert!(body.stream);
+        let request = body.into_chat_request().unwrap();
+        assert!(request.stream);
+        assert_eq!(request.max_tokens, Some(64));
+        assert_eq!(request.top_k, Some(20));
+        assert_eq!(request.response_format.unwrap().r#type, "json_object");
+        let Some(Content::Parts(parts)) = &request.messages[1].content else {
+            panic!("images should become parts");
+        };
+        assert!(parts[1]
+            .image_url
+            .as_ref()
+            .and_then(|image| image.url.as_deref())
+            .is_some_and(|url| url.starts_with("data:image/png;base64,")));
+
+        let mut stats = ChatStats::default();
+        stats.read(r#"{"choices":[{"index":0,"delta":{},"finish_reason":"length"}]}"#);
+        stats.read(
+            r#"{"choices":[],"usage":{"prompt_tokens":5,"completion_tokens":7,"total_tokens":12},"timings":{"load_ms":0.0,"prompt_ms":1.5,"generate_ms":20.0}}"#,
+        );
+        let done = stats.done("qwen", String::new(), String::new(), Instant::now());
+        assert_eq!(done["done"], true);
+        assert_eq!(done["done_reason"], "length");
+        assert_eq!(done["eval_count"], 7);
+        assert_eq!(done["eval_duration"], 20_000_000);
+    }
+
+    #[test]
+    fn tags_pick_configs() {
+        let config = |model_name: &str, model_path: &str| {
+            (
+                model_name.to_owned(),
+                ModelConfig {
+                    model_name: model_name.to_owned(),
+                    model_path: Some(model_path.to_owned()),
+                    ..Default::default()
+                },
+            )
+        };
+        let all_configs = HashMap::from([
+            config("Qwen3-1.7B", "qwen3-1.7b-w8a8.rkllm"),
+            config("Qwen3-4B", "qwen3-4b-w4a16.rkllm"),
+            config("llama", "llama.rkllm"),
+        ]);
+        let aliases = Aliases::default();
+        let name = |name| config_name(name, &all_configs, &aliases);
+        assert_eq!(name("llama"), Some("llama".to_owned()));
+        assert_eq!(name("llama:latest"), Some("llama".to_owned()));
+        assert_eq!(name("qwen3:1.7b"), Some("Qwen3-1.7B".to_owned()));
+        assert_eq!(name("qwen3:w4a16"), Some("Qwen3-4B".to_owned()));
+        assert_eq!(name("qwen3:8b"), None);
+
+        aliases
+            .copy("llama", "assistant:latest", &all_configs)
+            .unwrap();
+        assert_eq!(name("assistant"), Some("llama".to_owned()));
+    }
+
+    #[test]
+    fn parameter_size_from_name() {
+        let config = |model_name: &str| ModelConfig {
+            model_name: model_name.to_owned(),
+            model_repo: "kautism/model".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(
+            parameter_size(&config("DeepSeek-R1-Distill-Qwen-1.5B")),
+            Some("1.5B".to_owned())
+        );
+        assert_eq!(parameter_size(&config("qwen3:4b")), Some("4B".to_owned()));
+        assert_eq!(parameter_size(&config("bge-small-zh-v1.5")), None);
+    }
+}
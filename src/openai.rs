@@ -3,11 +3,16 @@ use std::collections::HashMap;
 use actix_web::{
     get,
     web::{self},
-    HttpResponse, Responder,
+    HttpRequest, HttpResponse, Responder,
 };
 use serde::{Deserialize, Serialize};
 
-use crate::utils::ModelConfig;
+use crate::{
+    capabilities::{capabilities, Capability},
+    cluster::Cluster,
+    listing::{loaded_models, ModelsQuery},
+    utils::ModelConfig,
+};
 
 #[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
 struct ListModel {
@@ -24,9 +29,47 @@ struct Model {
     pub created: u32,
     #[serde(default)]
     pub owned_by: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<Capability>,
+    /// `max_context_len` of the model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<i32>,
+    /// Like `w8a8` or `w4a16_g128`, read from the model file name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantization: Option<String>,
+    /// Same as the `system_fingerprint` of its answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_fingerprint: Option<String>,
+}
+
+/// RKLLM 轉檔時的量化方式，寫在檔名裡
+pub(crate) fn quantization(config: &ModelConfig) -> Option<String> {
+    let names = [
+        config.model_path.as_deref().unwrap_or_default(),
+        &config.model_repo,
+    ];
+    names.iter().find_map(|name| {
+        let name = name.to_ascii_lowercase();
+        let parts: Vec<&str> = name.split(['-', '.', '/', '_']).collect();
+        parts.iter().enumerate().find_map(|(i, part)| {
+            let (weights, activations) = part.strip_prefix('w')?.split_once('a')?;
+            let digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+            if !digits(weights) || !digits(activations) {
+                return None;
+            }
+            // w4a16_g128 的 group size
+            match parts.get(i + 1) {
+                Some(group) if group.strip_prefix('g').is_some_and(digits) => {
+                    Some(format!("{}_{}", part, group))
+                }
+                _ => Some(part.to_string()),
+            }
+        })
+    })
 }
 
 #[utoipa::path(
+    params(ModelsQuery),
     responses(
         (status = OK, description = "Success", body = Model, content_type = "application/json")
     ),
@@ -35,16 +78,78 @@ struct Model {
     ),
 )]
 #[get("/models")]
-pub async fn models(all_configs: web::Data<HashMap<String, ModelConfig>>) -> impl Responder {
-    
-    HttpResponse::Ok().json(ListModel {
-        object: "list".to_string(),
-        data: all_configs.iter().map(|(_,config)| Model {
-                id: config.model_name.clone(),
+pub async fn models(
+    query: web::Query<ModelsQuery>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    cluster: web::Data<Cluster>,
+    req: HttpRequest,
+) -> impl Responder {
+    let loaded = loaded_models(&req);
+    // 用 key 列出，對應表裡的 OpenAI 名稱也會出現
+    let mut data = all_configs
+        .iter()
+        .filter(|(id, config)| query.matches(id, config, &loaded))
+        .map(|(id, config)| Model {
+            id: id.clone(),
+            object: "model".to_string(),
+            created: 0,
+            owned_by: "llmserver-rs".to_string(),
+            capabilities: capabilities(config),
+            context_length: Some(config.max_context_len),
+            quantization: quantization(config),
+            system_fingerprint: Some(crate::fingerprint::system_fingerprint(config)),
+        })
+        .collect::<Vec<Model>>();
+    // 其他節點的模型也列出來，整個 cluster 看起來像一台
+    data.extend(
+        cluster
+            .peer_models(&req)
+            .into_iter()
+            .filter(|id| !all_configs.contains_key(id) && query.matches_peer(id))
+            .map(|id| Model {
+                id,
                 object: "model".to_string(),
                 created: 0,
-                owned_by: "llmserver-rs".to_string(),
-            })
-            .collect::<Vec<Model>>(),
+                owned_by: "llmserver-rs-peer".to_string(),
+                capabilities: vec![],
+                context_length: None,
+                quantization: None,
+                system_fingerprint: None,
+            }),
+    );
+    // 分頁要固定順序
+    data.sort_by(|a, b| a.id.cmp(&b.id));
+
+    HttpResponse::Ok().json(ListModel {
+        object: "list".to_string(),
+        data: query.page(data),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantization_from_file_name() {
+        let config = |model_path: &str| ModelConfig {
+            model_repo: "kautism/Qwen3-4B-RKLLM".to_owned(),
+            model_path: Some(model_path.to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            quantization(&config(
+                "Qwen3-4B-Instruct-2507-rk3588-w8a8_g512-opt-1-hybrid-ratio-1.0.rkllm"
+            )),
+            Some("w8a8_g512".to_owned())
+        );
+        assert_eq!(
+            quantization(&config("qwen-W4A16.rkllm")),
+            Some("w4a16".to_owned())
+        );
+        assert_eq!(
+            quantization(&config("Qwen2.5-3B-abliterated-16k-rk3588.rkllm")),
+            None
+        );
+    }
+}
@@ -0,0 +1,440 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::mcp::{McpTool, ToolCall};
+
+pub const PLUGINS_PATH: &str = "assets/plugins.json";
+const DEFAULT_MAX_BYTES: usize = 8000;
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+/// 標籤去掉後文字少很多，HTML 多讀一些
+const HTML_READ_FACTOR: usize = 8;
+
+fn default_max_bytes() -> usize {
+    DEFAULT_MAX_BYTES
+}
+
+fn default_timeout_secs() -> u64 {
+    DEFAULT_TIMEOUT_SECS
+}
+
+/// Built-in `fetch` tool.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FetchPlugin {
+    /// URL 前綴白名單，空的話不提供這個工具
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: usize,
+}
+
+/// A fixed program the model may run. `{name}` arguments are filled from the tool call,
+/// nothing goes through a shell.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CommandPlugin {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub parameters: Option<Value>,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: usize,
+}
+
+/// Home automation style webhook, the tool arguments are sent as JSON body (or query for GET).
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookPlugin {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub url: String,
+    #[serde(default)]
+    pub method: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub parameters: Option<Value>,
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: usize,
+}
+
+/// Tools executed by the server itself, loaded from `assets/plugins.json`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Plugins {
+    #[serde(default)]
+    pub fetch: Option<FetchPlugin>,
+    #[serde(default)]
+    pub commands: Vec<CommandPlugin>,
+    #[serde(default)]
+    pub webhooks: Vec<WebhookPlugin>,
+    #[serde(skip)]
+    client: reqwest::Client,
+}
+
+/// `{host}` 這種整段的參數
+fn placeholder(arg: &str) -> Option<&str> {
+    arg.strip_prefix('{')?.strip_suffix('}')
+}
+
+fn truncate(mut text: String, max_bytes: usize) -> String {
+    if text.len() > max_bytes {
+        let mut end = max_bytes;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str("\n[truncated]");
+    }
+    text
+}
+
+/// A response body, reading stops after `limit` bytes so a huge page is never held in memory. One
+/// byte more is kept for `truncate` to notice.
+async fn read_limited<B: AsRef<[u8]>, E: std::fmt::Display>(
+    body: impl Stream<Item = Result<B, E>>,
+    limit: usize,
+) -> Result<String, String> {
+    let mut body = std::pin::pin!(body);
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.next().await {
+        bytes.extend_from_slice(chunk.map_err(|e| e.to_string())?.as_ref());
+        if bytes.len() > limit {
+            bytes.truncate(limit + 1);
+            break;
+        }
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// 粗略去掉 HTML 標籤，模型只需要文字
+fn html_text(html: &str) -> String {
+    // 只轉 ASCII 大小寫，位置跟原文一樣
+    let lower = html.to_ascii_lowercase();
+    let mut text = String::new();
+    let mut pos = 0;
+    while let Some(start) = html[pos..].find('<').map(|i| pos + i) {
+        text.push_str(&html[pos..start]);
+        let tag = &lower[start..];
+        let skip_to = if tag.starts_with("<script") {
+            "</script>"
+        } else if tag.starts_with("<style") {
+            "</style>"
+        } else {
+            ">"
+        };
+        pos = match tag.find(skip_to) {
+            Some(end) => start + end + skip_to.len(),
+            None => html.len(),
+        };
+        text.push(' ');
+    }
+    text.push_str(&html[pos..]);
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+impl CommandPlugin {
+    fn schema(&self) -> Value {
+        if let Some(parameters) = &self.parameters {
+            return parameters.clone();
+        }
+        let names: Vec<&str> = self.args.iter().filter_map(|a| placeholder(a)).collect();
+        let properties: serde_json::Map<String, Value> = names
+            .iter()
+            .map(|name| (name.to_string(), json!({ "type": "string" })))
+            .collect();
+        json!({ "type": "object", "properties": properties, "required": names })
+    }
+
+    fn argv(&self, arguments: &Value) -> Result<Vec<String>, String> {
+        self.args
+            .iter()
+            .map(|arg| match placeholder(arg) {
+                Some(name) => match &arguments[name] {
+                    Value::String(s) => Ok(s.clone()),
+                    Value::Null => Err(format!("missing argument {}", name)),
+                    other => Ok(other.to_string()),
+                },
+                None => Ok(arg.clone()),
+            })
+            .collect()
+    }
+
+    fn run(&self, arguments: &Value) -> Result<String, String> {
+        let argv = self.argv(arguments)?;
+        let mut child = Command::new(&self.command)
+            .args(&argv)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("{}: {}", self.command, e))?;
+        // 另開執行緒讀輸出，避免 pipe 滿了卡住
+        let readers: Vec<_> = [
+            child
+                .stdout
+                .take()
+                .map(|r| Box::new(r) as Box<dyn Read + Send>),
+            child
+                .stderr
+                .take()
+                .map(|r| Box::new(r) as Box<dyn Read + Send>),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|mut reader| {
+            std::thread::spawn(move || {
+                let mut buf = Vec::new();
+                let _ = reader.read_to_end(&mut buf);
+                buf
+            })
+        })
+        .collect();
+        let deadline = Instant::now() + Duration::from_secs(self.timeout_secs);
+        let status = loop {
+            if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+                break status;
+            }
+            if Instant::now() > deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("{} timed out", self.command));
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        };
+        let output: Vec<u8> = readers
+            .into_iter()
+            .flat_map(|reader| reader.join().unwrap_or_default())
+            .collect();
+        let output = String::from_utf8_lossy(&output).into_owned();
+        let output = truncate(output, self.max_bytes);
+        if status.success() {
+            Ok(output)
+        } else {
+            Err(format!("exit status {}\n{}", status, output))
+        }
+    }
+}
+
+impl Plugins {
+    pub fn load(path: &str) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| format!("{}: {}", path, e)),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fetch_plugin().is_none() && self.commands.is_empty() && self.webhooks.is_empty()
+    }
+
+    /// The `fetch` tool, only offered with a non-empty `allow` list.
+    fn fetch_plugin(&self) -> Option<&FetchPlugin> {
+        self.fetch.as_ref().filter(|fetch| !fetch.allow.is_empty())
+    }
+
+    pub fn tools(&self) -> Vec<McpTool> {
+        let mut tools = Vec::new();
+        if self.fetch_plugin().is_some() {
+            tools.push(McpTool {
+                name: "fetch".to_owned(),
+                description: Some("Fetch a web page or API and return its text.".to_owned()),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": { "url": { "type": "string" } },
+                    "required": ["url"],
+                }),
+            });
+        }
+        for command in &self.commands {
+            tools.push(McpTool {
+                name: command.name.clone(),
+                description: command.description.clone(),
+                input_schema: command.schema(),
+            });
+        }
+        for webhook in &self.webhooks {
+            tools.push(McpTool {
+                name: webhook.name.clone(),
+                description: webhook.description.clone(),
+                input_schema: webhook
+                    .parameters
+                    .clone()
+                    .unwrap_or_else(|| json!({ "type": "object" })),
+            });
+        }
+        tools
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        (name == "fetch" && self.fetch_plugin().is_some())
+            || self.commands.iter().any(|c| c.name == name)
+            || self.webhooks.iter().any(|w| w.name == name)
+    }
+
+    async fn fetch(&self, fetch: &FetchPlugin, arguments: &Value) -> Result<String, String> {
+        let url = arguments["url"].as_str().ok_or("missing argument url")?;
+        let parsed = reqwest::Url::parse(url).map_err(|e| format!("{}: {}", url, e))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err("only http and https URLs are supported".to_owned());
+        }
+        if !crate::image_input::allowed(&parsed, &fetch.allow) {
+            return Err(format!("{} is not allowed", url));
+        }
+        // 白名單的網域也可能解析或轉址到內網
+        let response = crate::image_input::pinned_client(&parsed, false)
+            .await?
+            .get(parsed)
+            .timeout(Duration::from_secs(DEFAULT_TIMEOUT_SECS))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let status = response.status();
+        let is_html = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("html"));
+        let limit = if is_html {
+            fetch.max_bytes.saturating_mul(HTML_READ_FACTOR)
+        } else {
+            fetch.max_bytes
+        };
+        let body = read_limited(response.bytes_stream(), limit).await?;
+        let body = if is_html { html_text(&body) } else { body };
+        if !status.is_success() {
+            return Err(format!(
+                "HTTP {}\n{}",
+                status,
+                truncate(body, fetch.max_bytes)
+            ));
+        }
+        Ok(truncate(body, fetch.max_bytes))
+    }
+
+    async fn webhook(&self, webhook: &WebhookPlugin, arguments: &Value) -> Result<String, String> {
+        let method = webhook
+            .method
+            .as_deref()
+            .unwrap_or("POST")
+            .to_ascii_uppercase();
+        let method = reqwest::Method::from_bytes(method.as_bytes()).map_err(|e| e.to_string())?;
+        let mut request = self
+            .client
+            .request(method.clone(), &webhook.url)
+            .timeout(Duration::from_secs(DEFAULT_TIMEOUT_SECS));
+        for (name, value) in &webhook.headers {
+            request = request.header(name, value);
+        }
+        request = if method == reqwest::Method::GET {
+            let query: Vec<(String, String)> = arguments
+                .as_object()
+                .map(|args| {
+                    args.iter()
+                        .map(|(k, v)| {
+                            (
+                                k.clone(),
+                                v.as_str()
+                                    .map(str::to_owned)
+                                    .unwrap_or_else(|| v.to_string()),
+                            )
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            request.query(&query)
+        } else {
+            request.json(arguments)
+        };
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        let status = response.status();
+        let body = truncate(
+            read_limited(response.bytes_stream(), webhook.max_bytes).await?,
+            webhook.max_bytes,
+        );
+        if status.is_success() {
+            Ok(if body.is_empty() {
+                format!("HTTP {}", status)
+            } else {
+                body
+            })
+        } else {
+            Err(format!("HTTP {}\n{}", status, body))
+        }
+    }
+
+    /// Run a plugin tool. Like MCP tools, failures are returned as text for the model.
+    pub async fn call(&self, call: &ToolCall) -> String {
+        let result = if let Some(fetch) = self.fetch_plugin().filter(|_| call.name == "fetch") {
+            self.fetch(fetch, &call.arguments).await
+        } else if let Some(command) = self.commands.iter().find(|c| c.name == call.name) {
+            let command = command.clone();
+            let arguments = call.arguments.clone();
+            tokio::task::spawn_blocking(move || command.run(&arguments))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+        } else if let Some(webhook) = self.webhooks.iter().find(|w| w.name == call.name) {
+            self.webhook(webhook, &call.arguments).await
+        } else {
+            Err(format!("unknown tool {}", call.name))
+        };
+        result.unwrap_or_else(|e| format!("Error: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn reading_stops_at_the_limit() {
+        let chunks = (0..1000).map(|_| Ok::<_, String>(vec![b'a'; 1000]));
+        let body = read_limited(futures::stream::iter(chunks), 2500)
+            .await
+            .unwrap();
+        assert_eq!(body.len(), 2501);
+        assert!(truncate(body, 2500).ends_with("[truncated]"));
+
+        let short = futures::stream::iter([Ok::<_, String>("hello")]);
+        assert_eq!(read_limited(short, 2500).await.unwrap(), "hello");
+    }
+
+    #[test]
+    fn commands_and_html() {
+        let plugins: Plugins = serde_json::from_value(json!({
+            "commands": [{ "name": "say", "command": "echo", "args": ["-n", "{text}"] }]
+        }))
+        .unwrap();
+        let tools = plugins.tools();
+        assert_eq!(tools[0].input_schema["required"], json!(["text"]));
+        assert!(plugins.has("say") && !plugins.has("fetch"));
+
+        let command = &plugins.commands[0];
+        assert_eq!(
+            command.argv(&json!({ "text": "a; rm -rf /" })).unwrap(),
+            vec!["-n", "a; rm -rf /"]
+        );
+        assert!(command.argv(&json!({})).is_err());
+
+        let open: Plugins = serde_json::from_value(json!({ "fetch": {} })).unwrap();
+        assert!(open.is_empty() && !open.has("fetch"));
+
+        assert_eq!(
+            html_text("<p>Hi <b>there</b></p><script>x()</script>!"),
+            "Hi there !"
+        );
+        assert_eq!(html_text("a<STYLE>p{}</Style>b<BR>ü"), "a b ü");
+        assert_eq!(truncate("你好".to_owned(), 4), "你\n[truncated]");
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use rkllm_rs::prelude::RKLLMPerfStatData;
+
+/// 短的 prompt 一下就讀完，不用回報進度
+pub const MIN_TOKENS: usize = 256;
+const INTERVAL: Duration = Duration::from_secs(1);
+/// Marks a progress message in the text stream of a model, like the `Model error:` prefix.
+const PREFIX: &str = "\u{0}prefill:";
+
+/// Prefill tokens per millisecond measured on the last run of each model.
+static RATES: LazyLock<Mutex<HashMap<String, f32>>> = LazyLock::new(Default::default);
+
+pub fn record(model: &str, perf: &RKLLMPerfStatData) {
+    if perf.prefill_tokens > 0 && perf.prefill_time_ms > 0.0 {
+        let rate = perf.prefill_tokens as f32 / perf.prefill_time_ms;
+        RATES.lock().unwrap().insert(model.to_owned(), rate);
+    }
+}
+
+/// Tokens probably read after `elapsed`, never the whole prompt before the first token arrives.
+pub fn estimate(model: &str, total: usize, elapsed: Duration) -> usize {
+    let rate = RATES.lock().unwrap().get(model).copied().unwrap_or(0.0);
+    ((elapsed.as_millis() as f32 * rate) as usize).min(total.saturating_sub(1))
+}
+
+pub fn message(current: usize, total: usize) -> String {
+    format!("{}{}/{}", PREFIX, current, total)
+}
+
+/// `(current, total)` of a progress message, `None` for model output.
+pub fn parse(text: &str) -> Option<(usize, usize)> {
+    let (current, total) = text.strip_prefix(PREFIX)?.split_once('/')?;
+    Some((current.parse().ok()?, total.parse().ok()?))
+}
+
+/// Send estimated progress every second until `prefilling` is cleared by the first token or the
+/// end of the run.
+pub fn spawn_ticker(
+    model: String,
+    total: usize,
+    prefilling: Arc<AtomicBool>,
+    tx: tokio::sync::mpsc::Sender<String>,
+) {
+    if total < MIN_TOKENS {
+        prefilling.store(false, Ordering::Relaxed);
+        return;
+    }
+    std::thread::spawn(move || {
+        let started = Instant::now();
+        let mut current = 0;
+        loop {
+            if !prefilling.load(Ordering::Relaxed) {
+                return;
+            }
+            if tx.blocking_send(message(current, total)).is_err() {
+                return;
+            }
+            std::thread::sleep(INTERVAL);
+            current = estimate(&model, total, started.elapsed());
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_from_last_run() {
+        assert_eq!(parse(&message(120, 8000)), Some((120, 8000)));
+        assert_eq!(parse("prefill:1/2"), None);
+
+        assert_eq!(estimate("prefill-test", 8000, Duration::from_secs(2)), 0);
+        record(
+            "prefill-test",
+            &RKLLMPerfStatData {
+                prefill_time_ms: 1000.0,
+                prefill_tokens: 500,
+                generate_time_ms: 0.0,
+                generate_tokens: 0,
+                memory_usage_mb: 0.0,
+            },
+        );
+        assert_eq!(estimate("prefill-test", 8000, Duration::from_secs(2)), 1000);
+        assert_eq!(estimate("prefill-test", 800, Duration::from_secs(2)), 799);
+    }
+}
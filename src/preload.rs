@@ -0,0 +1,50 @@
+use serde::Deserialize;
+
+pub const SERVER_CONFIG_PATH: &str = "assets/server.json";
+
+/// Server settings of `assets/server.json`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ServerConfig {
+    /// Models loaded before the HTTP server starts, like `--preload`
+    #[serde(default)]
+    pub preload: Vec<String>,
+    /// Hugging Face endpoint, cache and token of all models
+    #[serde(default)]
+    pub hub: crate::hub::HubConfig,
+}
+
+impl ServerConfig {
+    pub fn load(path: &str) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| format!("{}: {}", path, e)),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// Models named on the command line first, then the ones of the file, each once.
+    pub fn preload_list(&self, cli: impl IntoIterator<Item = String>) -> Vec<String> {
+        let mut models: Vec<String> = Vec::new();
+        for model in cli.into_iter().chain(self.preload.iter().cloned()) {
+            if !models.contains(&model) {
+                models.push(model);
+            }
+        }
+        models
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_models_come_first() {
+        let config: ServerConfig =
+            serde_json::from_str(r#"{ "preload": ["bge-small-zh-v1.5", "qwen"] }"#).unwrap();
+        assert_eq!(
+            config.preload_list(["qwen".to_owned(), "sensevoice".to_owned()]),
+            vec!["qwen", "sensevoice", "bge-small-zh-v1.5"]
+        );
+        assert!(ServerConfig::default().preload_list([]).is_empty());
+    }
+}
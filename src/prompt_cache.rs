@@ -0,0 +1,284 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use actix_web::{delete, get, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{utils::ModelConfig, OpenAiError};
+
+/// Header naming the conversation, used for `{session}` in `cache_path`.
+pub const SESSION_HEADER: &str = "X-Session-Id";
+const SESSION_PLACEHOLDER: &str = "{session}";
+const DEFAULT_SESSION: &str = "default";
+
+/// Limits of the prompt cache files of a model.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PromptCachePolicy {
+    /// 超過就從最舊的開始刪
+    pub max_bytes: Option<u64>,
+    pub max_age_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct CacheFile {
+    pub model: String,
+    pub session: String,
+    pub filename: String,
+    pub bytes: u64,
+    pub modified_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ListCacheFiles {
+    pub object: String,
+    pub data: Vec<CacheFile>,
+}
+
+/// 檔名安全的 id 原樣用，其他的用整個 id 的 sha256，不同的 id 不會共用一個檔案。雜湊有 `.`，
+/// 不會跟原樣的 id 撞名
+fn sanitize(session: &str) -> String {
+    let safe = session
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if session.is_empty() {
+        DEFAULT_SESSION.to_owned()
+    } else if safe && session.len() <= 64 {
+        session.to_owned()
+    } else {
+        let digest = Sha256::digest(session.as_bytes());
+        let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("sha256.{}", hex)
+    }
+}
+
+/// Cache file of a conversation. Without `{session}` in `cache_path` all conversations share one.
+pub fn cache_file(config: &ModelConfig, session: Option<&str>) -> Option<PathBuf> {
+    let template = config.cache_path.as_deref()?;
+    let session = sanitize(session.unwrap_or(DEFAULT_SESSION));
+    Some(PathBuf::from(
+        template.replace(SESSION_PLACEHOLDER, &session),
+    ))
+}
+
+/// `(directory, file name prefix, suffix)` the cache files of `config` match.
+fn pattern(config: &ModelConfig) -> Option<(PathBuf, String, String)> {
+    let template = Path::new(config.cache_path.as_deref()?);
+    let dir = template
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+        .to_path_buf();
+    let name = template.file_name()?.to_string_lossy().into_owned();
+    Some(match name.split_once(SESSION_PLACEHOLDER) {
+        Some((prefix, suffix)) => (dir, prefix.to_owned(), suffix.to_owned()),
+        None => (dir, name, String::new()),
+    })
+}
+
+pub fn list(config: &ModelConfig) -> Vec<(PathBuf, CacheFile)> {
+    let Some((dir, prefix, suffix)) = pattern(config) else {
+        return vec![];
+    };
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return vec![];
+    };
+    let mut files: Vec<(PathBuf, CacheFile)> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let filename = entry.file_name().to_string_lossy().into_owned();
+            let session = filename
+                .strip_prefix(prefix.as_str())?
+                .strip_suffix(suffix.as_str())?;
+            let session = if session.is_empty() {
+                DEFAULT_SESSION
+            } else {
+                session
+            };
+            let meta = entry.metadata().ok().filter(|m| m.is_file())?;
+            let modified_at = meta
+                .modified()
+                .ok()?
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .ok()?
+                .as_secs();
+            Some((
+                entry.path(),
+                CacheFile {
+                    model: config.model_name.clone(),
+                    session: session.to_owned(),
+                    filename: filename.clone(),
+                    bytes: meta.len(),
+                    modified_at,
+                },
+            ))
+        })
+        .collect();
+    // 新的排前面
+    files.sort_by_key(|(_, file)| std::cmp::Reverse(file.modified_at));
+    files
+}
+
+/// Delete expired files, then the oldest ones until the total fits `max_bytes`.
+pub fn enforce(config: &ModelConfig) -> usize {
+    let Some(policy) = &config.cache_policy else {
+        return 0;
+    };
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut deleted = 0;
+    let mut total = 0;
+    for (path, file) in list(config) {
+        let expired = policy.max_age_secs.is_some_and(|max_age| {
+            now.saturating_sub(Duration::from_secs(file.modified_at)) > Duration::from_secs(max_age)
+        });
+        let over = policy.max_bytes.is_some_and(|max| total + file.bytes > max);
+        if expired || over {
+            if std::fs::remove_file(&path).is_ok() {
+                log::info!("Removed prompt cache {}", path.display());
+                deleted += 1;
+            }
+        } else {
+            total += file.bytes;
+        }
+    }
+    deleted
+}
+
+pub fn delete(config: &ModelConfig, session: Option<&str>) -> usize {
+    let session = session.map(sanitize);
+    list(config)
+        .into_iter()
+        .filter(|(_, file)| session.as_ref().is_none_or(|s| &file.session == s))
+        .filter(|(path, _)| std::fs::remove_file(path).is_ok())
+        .count()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CacheQuery {
+    pub model: Option<String>,
+    pub session: Option<String>,
+}
+
+fn model_not_found(model: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(OpenAiError {
+        message: format!("Model \"{}\" does not exist.", model),
+        code: "model_not_found".to_owned(),
+        r#type: "invalid_request_error".to_owned(),
+        param: Some("model".to_owned()),
+    })
+}
+
+/// List prompt cache files.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", body = ListCacheFiles, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/prompt_caches")]
+pub async fn list_caches(
+    query: web::Query<CacheQuery>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+) -> impl Responder {
+    if let Some(model) = query
+        .model
+        .as_deref()
+        .filter(|m| !all_configs.contains_key(*m))
+    {
+        return model_not_found(model);
+    }
+    let mut configs: Vec<&ModelConfig> = all_configs
+        .values()
+        .filter(|c| query.model.as_ref().is_none_or(|m| &c.model_name == m))
+        .collect();
+    configs.sort_by(|a, b| a.model_name.cmp(&b.model_name));
+    let data = configs
+        .into_iter()
+        .flat_map(|config| {
+            enforce(config);
+            list(config)
+        })
+        .map(|(_, file)| file)
+        .filter(|file| query.session.as_ref().is_none_or(|s| &file.session == s))
+        .collect();
+    HttpResponse::Ok().json(ListCacheFiles {
+        object: "list".to_owned(),
+        data,
+    })
+}
+
+/// Delete the prompt cache of a conversation, or all of a model without `session`.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[delete("/prompt_caches")]
+pub async fn delete_caches(
+    query: web::Query<CacheQuery>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+) -> impl Responder {
+    let Some(model) = &query.model else {
+        return HttpResponse::BadRequest().json(OpenAiError {
+            message: "model is required.".to_owned(),
+            code: "invalid_request_error".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some("model".to_owned()),
+        });
+    };
+    let Some(config) = all_configs.get(model) else {
+        return model_not_found(model);
+    };
+    let deleted = delete(config, query.session.as_deref());
+    HttpResponse::Ok().json(serde_json::json!({ "object": "list", "deleted": deleted }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_and_limits() {
+        let dir = std::env::temp_dir().join(format!("llmserver-cache-{}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = ModelConfig {
+            model_name: "qwen".to_owned(),
+            cache_path: Some(
+                dir.join("qwen-{session}.bin")
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            cache_policy: Some(PromptCachePolicy {
+                max_bytes: Some(10),
+                max_age_secs: None,
+            }),
+            ..Default::default()
+        };
+        let path = cache_file(&config, Some("chat/1")).unwrap();
+        assert!(path.ends_with(format!("qwen-{}.bin", sanitize("chat/1"))));
+        assert!(sanitize("chat/1").starts_with("sha256."));
+        assert_eq!(sanitize("chat_1"), "chat_1");
+        assert_ne!(sanitize("chat/1"), sanitize("chat:1"));
+        assert_ne!(sanitize(&"a".repeat(65)), sanitize(&"a".repeat(66)));
+        std::fs::write(&path, "123456").unwrap();
+        std::fs::write(cache_file(&config, None).unwrap(), "123456").unwrap();
+        std::fs::write(dir.join("other.bin"), "x").unwrap();
+        assert_eq!(list(&config).len(), 2);
+
+        assert_eq!(enforce(&config), 1);
+        assert_eq!(list(&config).len(), 1);
+        assert_eq!(delete(&config, None), 1);
+        assert!(list(&config).is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
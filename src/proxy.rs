@@ -0,0 +1,129 @@
+use std::net::{IpAddr, SocketAddr};
+
+use actix_web::{http::header::HeaderMap, web, HttpRequest};
+
+/// Reverse proxies (nginx, Caddy, ...) whose `X-Forwarded-For` / `X-Real-IP` are believed.
+/// Without `--trusted-proxy` the headers are ignored, anyone could send them.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl TrustedProxies {
+    /// Addresses like `127.0.0.1`, `10.0.0.0/8` or `fd00::/8`.
+    pub fn parse<S: AsRef<str>>(entries: &[S]) -> Result<Self, String> {
+        let networks = entries
+            .iter()
+            .map(|entry| {
+                let entry = entry.as_ref().trim();
+                let (addr, prefix) = entry.split_once('/').unwrap_or((entry, ""));
+                let addr: IpAddr = addr
+                    .parse()
+                    .map_err(|_| format!("Invalid trusted proxy \"{}\"", entry))?;
+                let max = if addr.is_ipv4() { 32 } else { 128 };
+                let prefix = match prefix {
+                    "" => max,
+                    prefix => prefix
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|p| *p <= max)
+                        .ok_or_else(|| format!("Invalid prefix length in \"{}\"", entry))?,
+                };
+                Ok((addr, prefix))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { networks })
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.networks
+            .iter()
+            .any(|(network, prefix)| match (ip, network) {
+                (IpAddr::V4(ip), IpAddr::V4(network)) => {
+                    let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                    u32::from(ip) & mask == u32::from(*network) & mask
+                }
+                (IpAddr::V6(ip), IpAddr::V6(network)) => {
+                    let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                    u128::from(ip) & mask == u128::from(*network) & mask
+                }
+                _ => false,
+            })
+    }
+
+    /// The client behind the trusted proxies in front of `peer`.
+    pub fn client_ip(&self, peer: Option<SocketAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let peer = peer?.ip().to_canonical();
+        if !self.is_trusted(peer) {
+            return Some(peer);
+        }
+        let forwarded: Vec<IpAddr> = headers
+            .get_all("x-forwarded-for")
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|ip| ip.trim().parse().ok())
+            .collect();
+        // 從最後一個往前找，跳過自己的 proxy，第一個不認識的就是客戶端
+        if let Some(ip) = forwarded
+            .iter()
+            .rev()
+            .map(|ip| ip.to_canonical())
+            .find(|ip| !self.is_trusted(*ip))
+        {
+            return Some(ip);
+        }
+        let real_ip = headers
+            .get("x-real-ip")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<IpAddr>().ok());
+        Some(
+            real_ip
+                .or_else(|| forwarded.first().copied())
+                .unwrap_or(peer),
+        )
+    }
+}
+
+/// Client address of a request for logs and the request history.
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    let default = TrustedProxies::default();
+    let proxies = req
+        .app_data::<web::Data<TrustedProxies>>()
+        .map(|proxies| proxies.get_ref())
+        .unwrap_or(&default);
+    proxies
+        .client_ip(req.peer_addr(), req.headers())
+        .map(|ip| ip.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    #[test]
+    fn trusts_only_configured_proxies() {
+        let proxies = TrustedProxies::parse(&["127.0.0.1", "10.0.0.0/8"]).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("x-forwarded-for"),
+            HeaderValue::from_static("1.2.3.4, 203.0.113.9, 10.0.0.5"),
+        );
+        let client = |peer: &str| {
+            proxies
+                .client_ip(Some(peer.parse().unwrap()), &headers)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(client("127.0.0.1:5000"), "203.0.113.9");
+        assert_eq!(client("[::ffff:10.1.2.3]:5000"), "203.0.113.9");
+        // 不是自己的 proxy，header 可能是偽造的
+        assert_eq!(client("198.51.100.7:5000"), "198.51.100.7");
+
+        assert!(TrustedProxies::parse(&["10.0.0.0/33"]).is_err());
+        assert!(TrustedProxies::parse(&["fd00::/8"])
+            .unwrap()
+            .is_trusted("fd12::1".parse().unwrap()));
+    }
+}
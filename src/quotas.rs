@@ -0,0 +1,174 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Serialize;
+
+use crate::errors::ApiError;
+
+/// Tokens of the current UTC day and month.
+#[derive(Debug, Clone, Copy, Default)]
+struct Usage {
+    day: u64,
+    daily: u64,
+    month: u64,
+    monthly: u64,
+}
+
+impl Usage {
+    /// 換日、換月就從零開始算
+    fn at(mut self, now: u64) -> Self {
+        let (day, month) = periods(now);
+        if self.day != day {
+            (self.day, self.daily) = (day, 0);
+        }
+        if self.month != month {
+            (self.month, self.monthly) = (month, 0);
+        }
+        self
+    }
+}
+
+/// Day and month of a unix time, UTC.
+fn periods(secs: u64) -> (u64, u64) {
+    let day = secs / 86400;
+    let (year, month, _) = crate::utils::civil_date(day as i64);
+    (day, (year * 12) as u64 + month as u64 - 1)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Prompt and generated tokens per `user` of the chat requests, limited per UTC day and month by
+/// `--user-daily-tokens` and `--user-monthly-tokens`. Kept in memory, a restart resets the counts.
+#[derive(Debug, Clone, Default)]
+pub struct Quotas {
+    daily_limit: Option<u64>,
+    monthly_limit: Option<u64>,
+    users: Arc<Mutex<HashMap<String, Usage>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct UserQuota {
+    pub user: String,
+    pub daily_limit: Option<u64>,
+    pub daily_used: u64,
+    pub monthly_limit: Option<u64>,
+    pub monthly_used: u64,
+}
+
+impl Quotas {
+    pub fn new(daily_limit: Option<u64>, monthly_limit: Option<u64>) -> Self {
+        Self {
+            daily_limit,
+            monthly_limit,
+            ..Default::default()
+        }
+    }
+
+    fn get_at(&self, user: &str, now: u64) -> UserQuota {
+        let usage = self
+            .users
+            .lock()
+            .unwrap()
+            .get(user)
+            .copied()
+            .unwrap_or_default()
+            .at(now);
+        UserQuota {
+            user: user.to_owned(),
+            daily_limit: self.daily_limit,
+            daily_used: usage.daily,
+            monthly_limit: self.monthly_limit,
+            monthly_used: usage.monthly,
+        }
+    }
+
+    pub fn get(&self, user: &str) -> UserQuota {
+        self.get_at(user, now())
+    }
+
+    fn add_at(&self, user: &str, tokens: u64, now: u64) {
+        let mut users = self.users.lock().unwrap();
+        let usage = users.entry(user.to_owned()).or_default();
+        *usage = usage.at(now);
+        usage.daily += tokens;
+        usage.monthly += tokens;
+    }
+
+    pub fn add(&self, user: &str, tokens: u64) {
+        if self.daily_limit.is_some() || self.monthly_limit.is_some() {
+            self.add_at(user, tokens, now());
+        }
+    }
+
+    fn check_at(&self, user: &str, now: u64) -> Result<(), ApiError> {
+        let quota = self.get_at(user, now);
+        let used_up = |limit: Option<u64>, used| limit.filter(|limit| used >= *limit);
+        if let Some(limit) = used_up(quota.daily_limit, quota.daily_used) {
+            return Err(ApiError::Quota(format!(
+                "User \"{}\" used the daily quota of {} tokens.",
+                user, limit
+            )));
+        }
+        if let Some(limit) = used_up(quota.monthly_limit, quota.monthly_used) {
+            return Err(ApiError::Quota(format!(
+                "User \"{}\" used the monthly quota of {} tokens.",
+                user, limit
+            )));
+        }
+        Ok(())
+    }
+
+    /// Refuse the request once the user used up the quota of the day or month.
+    pub fn check(&self, user: &str) -> Result<(), ApiError> {
+        self.check_at(user, now())
+    }
+}
+
+/// Quota and usage of a `user`.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", body = UserQuota, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/users/{user}/quota")]
+pub async fn get_quota(user: web::Path<String>, quotas: web::Data<Quotas>) -> impl Responder {
+    HttpResponse::Ok().json(quotas.get(&user))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resets_each_day_and_month() {
+        // 2025-01-31 12:00 UTC
+        let jan_31 = 1738324800;
+        assert_eq!(periods(jan_31).1, 2025 * 12);
+        assert_eq!(periods(jan_31 + 86400).1, 2025 * 12 + 1);
+
+        let quotas = Quotas::new(Some(100), Some(150));
+        quotas.add_at("alice", 100, jan_31);
+        let error = quotas.check_at("alice", jan_31).unwrap_err();
+        assert!(error.message().contains("daily"));
+        assert!(quotas.check_at("bob", jan_31).is_ok());
+
+        // 隔天是二月，兩個額度都重新算
+        assert!(quotas.check_at("alice", jan_31 + 86400).is_ok());
+        quotas.add_at("alice", 60, jan_31 - 86400);
+        let error = quotas.check_at("alice", jan_31 - 86400).unwrap_err();
+        assert!(error.message().contains("monthly"));
+        assert_eq!(quotas.get_at("alice", jan_31 + 86400).monthly_used, 0);
+    }
+}
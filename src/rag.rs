@@ -0,0 +1,725 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chat::{append_system_prompt, ChatCompletionsRequest},
+    embeddings::{embed, EmbeddingPool},
+    utils::{ModelConfig, ModelType},
+    ws::ChatContext,
+    OpenAiError, Role,
+};
+
+pub const RAG_DIR: &str = "assets/rag";
+const DEFAULT_TOP_K: usize = 4;
+pub(crate) const DEFAULT_CHUNK_SIZE: usize = 800;
+pub(crate) const DEFAULT_CHUNK_OVERLAP: usize = 100;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Chunk {
+    pub document_id: String,
+    pub text: String,
+    pub vector: Vec<f32>,
+}
+
+/// A knowledge base, stored as `assets/rag/<id>.json`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct KnowledgeBase {
+    /// Embedding model, every chunk must come from the same one
+    pub model: String,
+    pub created: u64,
+    pub chunks: Vec<Chunk>,
+    /// Set by `/v1/vector_stores`
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+}
+
+impl KnowledgeBase {
+    /// 向量都已正規化，內積就是 cosine similarity
+    pub fn search(&self, query: &[f32], top_k: usize) -> Vec<(f32, &Chunk)> {
+        let mut scored: Vec<(f32, &Chunk)> = self
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let score = chunk.vector.iter().zip(query).map(|(a, b)| a * b).sum();
+                (score, chunk)
+            })
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.truncate(top_k);
+        scored
+    }
+
+    pub fn remove_document(&mut self, document_id: &str) -> usize {
+        let before = self.chunks.len();
+        self.chunks.retain(|chunk| chunk.document_id != document_id);
+        before - self.chunks.len()
+    }
+
+    pub fn documents(&self) -> usize {
+        self.chunks
+            .iter()
+            .map(|chunk| chunk.document_id.as_str())
+            .collect::<HashSet<_>>()
+            .len()
+    }
+}
+
+/// 所有 knowledge base 都放在記憶體，改動時寫回磁碟
+#[derive(Clone, Default)]
+pub struct RagStore {
+    dir: PathBuf,
+    bases: Arc<Mutex<HashMap<String, KnowledgeBase>>>,
+}
+
+impl RagStore {
+    pub fn open(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        let mut bases = HashMap::new();
+        if dir.is_dir() {
+            for entry in std::fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.extension().is_none_or(|ext| ext != "json") {
+                    continue;
+                }
+                let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+                match serde_json::from_slice::<KnowledgeBase>(&std::fs::read(&path)?) {
+                    Ok(kb) => {
+                        bases.insert(id.to_owned(), kb);
+                    }
+                    Err(e) => log::warn!("Skip knowledge base {}: {}", path.display(), e),
+                }
+            }
+        }
+        Ok(Self {
+            dir,
+            bases: Arc::new(Mutex::new(bases)),
+        })
+    }
+
+    /// 在鎖裡修改，之後寫回檔案
+    pub fn update<R>(
+        &self,
+        id: &str,
+        f: impl FnOnce(&mut KnowledgeBase) -> R,
+    ) -> std::io::Result<R> {
+        let (result, json) = {
+            let mut bases = self.bases.lock().unwrap();
+            let kb = bases.entry(id.to_owned()).or_default();
+            let result = f(kb);
+            (result, serde_json::to_vec(kb)?)
+        };
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("{}.json", id));
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)?;
+        std::fs::rename(tmp, path)?;
+        Ok(result)
+    }
+
+    pub fn read<R>(&self, id: &str, f: impl FnOnce(&KnowledgeBase) -> R) -> Option<R> {
+        self.bases.lock().unwrap().get(id).map(f)
+    }
+
+    pub fn remove(&self, id: &str) -> std::io::Result<bool> {
+        if self.bases.lock().unwrap().remove(id).is_none() {
+            return Ok(false);
+        }
+        let path = self.dir.join(format!("{}.json", id));
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(true)
+    }
+
+    /// 依 id 排序
+    pub fn list<R>(&self, f: impl Fn(&str, &KnowledgeBase) -> R) -> Vec<R> {
+        let bases = self.bases.lock().unwrap();
+        let mut ids: Vec<&String> = bases.keys().collect();
+        ids.sort();
+        ids.into_iter().map(|id| f(id, &bases[id])).collect()
+    }
+
+    fn info(&self, id: &str) -> Option<KnowledgeBaseInfo> {
+        self.read(id, |kb| KnowledgeBaseInfo::new(id, kb))
+    }
+}
+
+/// 只允許安全的檔名
+pub(crate) fn valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// 以段落切塊，超過長度的段落再硬切，相鄰塊保留 `overlap` 個字
+pub fn split_chunks(text: &str, size: usize, overlap: usize) -> Vec<String> {
+    let size = size.max(1);
+    let overlap = overlap.min(size / 2);
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for paragraph in text.split("\n\n").map(str::trim).filter(|p| !p.is_empty()) {
+        if !current.is_empty() && current.chars().count() + paragraph.chars().count() + 2 > size {
+            chunks.push(std::mem::take(&mut current));
+        }
+        let chars: Vec<char> = paragraph.chars().collect();
+        if chars.len() > size {
+            let mut start = 0;
+            while start < chars.len() {
+                let end = (start + size).min(chars.len());
+                chunks.push(chars[start..end].iter().collect());
+                if end == chars.len() {
+                    break;
+                }
+                start = end - overlap;
+            }
+            continue;
+        }
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(paragraph);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+pub(crate) fn error(
+    status: actix_web::http::StatusCode,
+    message: String,
+    code: &str,
+) -> HttpResponse {
+    HttpResponse::build(status).json(OpenAiError {
+        message,
+        code: code.to_owned(),
+        r#type: if status.is_server_error() {
+            "internal_error".to_owned()
+        } else {
+            "invalid_request_error".to_owned()
+        },
+        param: None,
+    })
+}
+
+fn not_found(id: &str) -> HttpResponse {
+    error(
+        actix_web::http::StatusCode::NOT_FOUND,
+        format!("Knowledge base {} does not exist.", id),
+        "knowledge_base_not_found",
+    )
+}
+
+/// 沒指定時用第一個 embedding 模型
+pub(crate) fn embedding_config<'a>(
+    all_configs: &'a HashMap<String, ModelConfig>,
+    model: Option<&str>,
+) -> Result<&'a ModelConfig, String> {
+    match model {
+        Some(model) => all_configs
+            .get(model)
+            .filter(|c| c.model_type == ModelType::Embedding)
+            .ok_or_else(|| {
+                format!(
+                    "The model {} does not exist or is not an embedding model.",
+                    model
+                )
+            }),
+        None => {
+            let mut names: Vec<&String> = all_configs
+                .iter()
+                .filter(|(_, c)| c.model_type == ModelType::Embedding)
+                .map(|(name, _)| name)
+                .collect();
+            names.sort();
+            names
+                .first()
+                .map(|name| &all_configs[*name])
+                .ok_or_else(|| "No embedding model is configured.".to_owned())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct Document {
+    /// Re-ingesting an id replaces the old chunks
+    #[serde(default)]
+    pub id: Option<String>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[schema(example = json!({
+    "model": "bge-m3",
+    "documents": [{ "id": "manual", "text": "The reset button is on the back of the device." }]
+}))]
+pub struct IngestRequest {
+    /// Embedding model, defaults to the model of the knowledge base
+    #[serde(default)]
+    pub model: Option<String>,
+    pub documents: Vec<Document>,
+    /// Chunk length in characters
+    #[serde(default)]
+    pub chunk_size: Option<usize>,
+    #[serde(default)]
+    pub chunk_overlap: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct KnowledgeBaseInfo {
+    pub id: String,
+    pub object: String,
+    pub model: String,
+    pub created: u64,
+    pub documents: usize,
+    pub chunks: usize,
+}
+
+impl KnowledgeBaseInfo {
+    fn new(id: &str, kb: &KnowledgeBase) -> Self {
+        Self {
+            id: id.to_owned(),
+            object: "knowledge_base".to_owned(),
+            model: kb.model.clone(),
+            created: kb.created,
+            documents: kb.documents(),
+            chunks: kb.chunks.len(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct ListKnowledgeBases {
+    pub object: String,
+    pub data: Vec<KnowledgeBaseInfo>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct QueryRequest {
+    pub query: String,
+    #[serde(default)]
+    pub top_k: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct QueryResult {
+    pub document_id: String,
+    pub text: String,
+    pub score: f32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct QueryResponse {
+    pub object: String,
+    pub data: Vec<QueryResult>,
+}
+
+/// Embed `query` with the model of the knowledge base and return the nearest chunks.
+pub(crate) async fn query(
+    rag: &RagStore,
+    id: &str,
+    query: &str,
+    top_k: usize,
+    all_configs: &HashMap<String, ModelConfig>,
+    embedding_pool: &EmbeddingPool,
+) -> Result<Option<Vec<QueryResult>>, String> {
+    let Some(model) = rag.read(id, |kb| kb.model.clone()) else {
+        return Ok(None);
+    };
+    let config = embedding_config(all_configs, Some(&model))?;
+    let vector = embed(config, embedding_pool, vec![query.to_owned()])
+        .await
+        .map_err(|e| e.to_string())?
+        .data
+        .pop()
+        .unwrap_or_default();
+    Ok(rag.read(id, |kb| {
+        kb.search(&vector, top_k)
+            .into_iter()
+            .map(|(score, chunk)| QueryResult {
+                document_id: chunk.document_id.clone(),
+                text: chunk.text.clone(),
+                score,
+            })
+            .collect()
+    }))
+}
+
+/// Chunk and embed `(document_id, text)` pairs into a knowledge base, creating it if needed.
+pub(crate) async fn add_documents(
+    rag: &RagStore,
+    id: &str,
+    model: Option<&str>,
+    documents: Vec<(String, String)>,
+    (size, overlap): (usize, usize),
+    all_configs: &HashMap<String, ModelConfig>,
+    embedding_pool: &EmbeddingPool,
+) -> Result<KnowledgeBaseInfo, HttpResponse> {
+    let bad_request = |message: String| {
+        error(
+            actix_web::http::StatusCode::BAD_REQUEST,
+            message,
+            "invalid_request_error",
+        )
+    };
+    let existing = rag.read(id, |kb| kb.model.clone());
+    let model = match (&existing, model) {
+        (Some(existing), Some(model)) if existing != model => {
+            return Err(bad_request(format!(
+                "Knowledge base {} uses embedding model {}, not {}.",
+                id, existing, model
+            )));
+        }
+        (Some(existing), _) => Some(existing.as_str()),
+        (None, model) => model,
+    };
+    let config = embedding_config(all_configs, model).map_err(bad_request)?;
+
+    let mut pending = Vec::new();
+    for (document_id, text) in documents {
+        for text in split_chunks(&text, size, overlap) {
+            pending.push((document_id.clone(), text));
+        }
+    }
+
+    let vectors = if pending.is_empty() {
+        vec![]
+    } else {
+        let input = pending.iter().map(|(_, text)| text.clone()).collect();
+        embed(config, embedding_pool, input)
+            .await
+            .map_err(|e| e.response())?
+            .data
+    };
+
+    let model_name = config.model_name.clone();
+    rag.update(id, move |kb| {
+        if kb.model.is_empty() {
+            kb.model = model_name;
+            kb.created = now();
+        }
+        for (document_id, _) in &pending {
+            kb.remove_document(document_id);
+        }
+        kb.chunks.extend(
+            pending
+                .into_iter()
+                .zip(vectors)
+                .map(|((document_id, text), vector)| Chunk {
+                    document_id,
+                    text,
+                    vector,
+                }),
+        );
+        KnowledgeBaseInfo::new(id, kb)
+    })
+    .map_err(|e| {
+        error(
+            actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+            e.to_string(),
+            "processing_error",
+        )
+    })
+}
+
+/// List knowledge bases.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", body = ListKnowledgeBases, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/knowledge_bases")]
+pub async fn list(rag: web::Data<RagStore>) -> impl Responder {
+    HttpResponse::Ok().json(ListKnowledgeBases {
+        object: "list".to_owned(),
+        data: rag.list(KnowledgeBaseInfo::new),
+    })
+}
+
+/// Chunk, embed and store documents. The knowledge base is created on first use.
+#[utoipa::path(
+    request_body = IngestRequest,
+    responses(
+        (status = OK, description = "Success", body = KnowledgeBaseInfo, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/knowledge_bases/{id}/documents")]
+pub async fn ingest(
+    path: web::Path<String>,
+    body: web::Json<IngestRequest>,
+    rag: web::Data<RagStore>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    embedding_pool: web::Data<EmbeddingPool>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let body = body.into_inner();
+    let bad_request = |message: String| {
+        error(
+            actix_web::http::StatusCode::BAD_REQUEST,
+            message,
+            "invalid_request_error",
+        )
+    };
+    if !valid_id(&id) {
+        return bad_request(
+            "Knowledge base id may only contain letters, digits, - and _.".to_owned(),
+        );
+    }
+    if body.documents.is_empty() {
+        return bad_request("documents should not be empty.".to_owned());
+    }
+
+    let documents = body
+        .documents
+        .into_iter()
+        .map(|document| {
+            let document_id = document
+                .id
+                .unwrap_or_else(|| format!("doc-{:016x}", rand::random::<u64>()));
+            (document_id, document.text)
+        })
+        .collect();
+    let chunking = (
+        body.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE),
+        body.chunk_overlap.unwrap_or(DEFAULT_CHUNK_OVERLAP),
+    );
+    match add_documents(
+        &rag,
+        &id,
+        body.model.as_deref(),
+        documents,
+        chunking,
+        &all_configs,
+        &embedding_pool,
+    )
+    .await
+    {
+        Ok(info) => HttpResponse::Ok().json(info),
+        Err(response) => response,
+    }
+}
+
+/// Retrieve the chunks closest to a query.
+#[utoipa::path(
+    request_body = QueryRequest,
+    responses(
+        (status = OK, description = "Success", body = QueryResponse, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/knowledge_bases/{id}/query")]
+pub async fn search(
+    path: web::Path<String>,
+    body: web::Json<QueryRequest>,
+    rag: web::Data<RagStore>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    embedding_pool: web::Data<EmbeddingPool>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let top_k = body.top_k.unwrap_or(DEFAULT_TOP_K);
+    match query(&rag, &id, &body.query, top_k, &all_configs, &embedding_pool).await {
+        Ok(Some(data)) => HttpResponse::Ok().json(QueryResponse {
+            object: "list".to_owned(),
+            data,
+        }),
+        Ok(None) => not_found(&id),
+        Err(e) => error(
+            actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+            e,
+            "processing_error",
+        ),
+    }
+}
+
+/// Delete a document from a knowledge base.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", body = KnowledgeBaseInfo, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[delete("/knowledge_bases/{id}/documents/{document_id}")]
+pub async fn delete_document(
+    path: web::Path<(String, String)>,
+    rag: web::Data<RagStore>,
+) -> impl Responder {
+    let (id, document_id) = path.into_inner();
+    if rag.info(&id).is_none() {
+        return not_found(&id);
+    }
+    let info_id = id.clone();
+    match rag.update(&id, move |kb| {
+        kb.remove_document(&document_id);
+        KnowledgeBaseInfo::new(&info_id, kb)
+    }) {
+        Ok(info) => HttpResponse::Ok().json(info),
+        Err(e) => error(
+            actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+            e.to_string(),
+            "processing_error",
+        ),
+    }
+}
+
+/// Delete a knowledge base.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[delete("/knowledge_bases/{id}")]
+pub async fn delete_knowledge_base(
+    path: web::Path<String>,
+    rag: web::Data<RagStore>,
+) -> impl Responder {
+    let id = path.into_inner();
+    match rag.remove(&id) {
+        Ok(true) => HttpResponse::Ok().json(serde_json::json!({
+            "id": id,
+            "object": "knowledge_base.deleted",
+            "deleted": true,
+        })),
+        Ok(false) => not_found(&id),
+        Err(e) => error(
+            actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+            e.to_string(),
+            "processing_error",
+        ),
+    }
+}
+
+fn context_prompt(results: &[QueryResult]) -> String {
+    let mut prompt = "Use the following context to answer. If it does not contain the answer, say you don't know.\n\n<context>".to_owned();
+    for (i, result) in results.iter().enumerate() {
+        prompt.push_str(&format!("\n[{}] {}", i + 1, result.text));
+    }
+    prompt.push_str("\n</context>");
+    prompt
+}
+
+/// 請求帶了 `knowledge_base` 時，把檢索結果放進 system prompt
+pub(crate) async fn augment(
+    mut body: ChatCompletionsRequest,
+    ctx: &ChatContext,
+) -> Result<ChatCompletionsRequest, HttpResponse> {
+    let Some(id) = body.knowledge_base.take() else {
+        return Ok(body);
+    };
+    let missing = |id: &str| {
+        HttpResponse::BadRequest().json(OpenAiError {
+            message: format!("Knowledge base {} does not exist.", id),
+            code: "knowledge_base_not_found".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some("knowledge_base".to_owned()),
+        })
+    };
+    let (Some(rag), Some(embedding_pool)) = (
+        ctx.req.app_data::<web::Data<RagStore>>(),
+        ctx.req.app_data::<web::Data<EmbeddingPool>>(),
+    ) else {
+        return Err(missing(&id));
+    };
+    let question = body
+        .messages
+        .iter()
+        .rev()
+        .find(|m| matches!(m.role, Some(Role::User)))
+        .and_then(|m| m.content.as_ref())
+        .map(|content| content.text())
+        .unwrap_or_default();
+    if question.is_empty() {
+        return Ok(body);
+    }
+
+    match query(
+        rag,
+        &id,
+        &question,
+        DEFAULT_TOP_K,
+        &ctx.all_configs,
+        embedding_pool,
+    )
+    .await
+    {
+        Ok(Some(results)) => {
+            if !results.is_empty() {
+                append_system_prompt(&mut body.messages, &context_prompt(&results));
+            }
+            Ok(body)
+        }
+        Ok(None) => Err(missing(&id)),
+        Err(e) => Err(error(
+            actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+            e,
+            "processing_error",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_and_search() {
+        let chunks = split_chunks("aaaa\n\nbbbb\n\n0123456789", 6, 2);
+        assert_eq!(chunks, vec!["aaaa", "bbbb", "012345", "456789"]);
+
+        let chunk = |id: &str, vector: Vec<f32>| Chunk {
+            document_id: id.to_owned(),
+            text: id.to_owned(),
+            vector,
+        };
+        let mut kb = KnowledgeBase {
+            model: "bge".to_owned(),
+            created: 0,
+            chunks: vec![
+                chunk("x", vec![1.0, 0.0]),
+                chunk("y", vec![0.0, 1.0]),
+                chunk("xy", vec![0.6, 0.8]),
+            ],
+            ..Default::default()
+        };
+        let top: Vec<&str> = kb
+            .search(&[0.0, 1.0], 2)
+            .iter()
+            .map(|(_, c)| c.document_id.as_str())
+            .collect();
+        assert_eq!(top, vec!["y", "xy"]);
+        assert_eq!(kb.remove_document("y"), 1);
+        assert_eq!(kb.documents(), 2);
+        assert!(!valid_id("../etc"));
+    }
+}
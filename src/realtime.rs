@@ -0,0 +1,803 @@
+use std::{
+    cell::Cell,
+    io::Cursor,
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use actix_web::{get, rt::task::JoinHandle, web, HttpRequest, HttpResponse};
+use actix_ws::Session;
+use base64::Engine;
+use futures::StreamExt;
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Value};
+
+use crate::{
+    audio::AudioPool,
+    chat::ChatCompletionsRequest,
+    speech::{self, SpeechPool},
+    turn_detection::{ServerVad, ServerVadConfig, TurnEvent},
+    utils::{ModelConfig, ModelType},
+    ws::{chunk_text, sse_data, ChatContext},
+    Content, Message, OpenAiError, Role,
+};
+
+/// SenseVoice 只吃 16k
+pub(crate) const ASR_SAMPLE_RATE: u32 = 16000;
+/// Realtime API 的 pcm16 是 24k mono
+const INPUT_SAMPLE_RATE: u32 = 24000;
+
+#[derive(Debug, Deserialize)]
+pub struct RealtimeQuery {
+    pub model: String,
+}
+
+/// 分得出「沒給」跟「給 null」
+fn explicit<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct SessionUpdate {
+    instructions: Option<String>,
+    input_audio_transcription: Option<TranscriptionConfig>,
+    modalities: Option<Vec<String>>,
+    voice: Option<String>,
+    /// `null` turns it off
+    #[serde(default, deserialize_with = "explicit")]
+    turn_detection: Option<Option<ServerVadConfig>>,
+    /// Extension: the TTS model speaking the answers
+    speech_model: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TranscriptionConfig {
+    model: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConversationItem {
+    role: Role,
+    #[serde(default)]
+    content: Vec<ItemContent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ItemContent {
+    text: Option<String>,
+    transcript: Option<String>,
+}
+
+/// The subset of client events we understand.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+enum ClientEvent {
+    #[serde(rename = "session.update")]
+    SessionUpdate { session: SessionUpdate },
+    #[serde(rename = "input_audio_buffer.append")]
+    AudioAppend { audio: String },
+    #[serde(rename = "input_audio_buffer.commit")]
+    AudioCommit,
+    #[serde(rename = "input_audio_buffer.clear")]
+    AudioClear,
+    #[serde(rename = "conversation.item.create")]
+    ItemCreate { item: ConversationItem },
+    #[serde(rename = "response.create")]
+    ResponseCreate,
+    #[serde(rename = "response.cancel")]
+    ResponseCancel,
+}
+
+/// Settings of one connection, changed by `session.update`.
+struct SessionState {
+    model: String,
+    instructions: Option<String>,
+    transcription_model: Option<String>,
+    modalities: Vec<String>,
+    voice: String,
+    speech_model: Option<String>,
+    vad: Option<ServerVad>,
+}
+
+impl SessionState {
+    fn new(model: String) -> Self {
+        Self {
+            model,
+            instructions: None,
+            transcription_model: None,
+            modalities: vec!["text".to_owned()],
+            voice: "alloy".to_owned(),
+            speech_model: None,
+            vad: None,
+        }
+    }
+
+    fn update(&mut self, session: SessionUpdate) {
+        if session.instructions.is_some() {
+            self.instructions = session.instructions;
+        }
+        if let Some(transcription) = session.input_audio_transcription {
+            self.transcription_model = transcription.model;
+        }
+        if let Some(modalities) = session.modalities {
+            self.modalities = modalities;
+        }
+        if let Some(voice) = session.voice {
+            self.voice = voice;
+        }
+        if session.speech_model.is_some() {
+            self.speech_model = session.speech_model;
+        }
+        if let Some(turn_detection) = session.turn_detection {
+            self.vad = turn_detection.map(|config| ServerVad::new(config, INPUT_SAMPLE_RATE));
+        }
+    }
+
+    fn info(&self) -> Value {
+        json!({ "session": {
+            "object": "realtime.session",
+            "model": self.model,
+            "modalities": self.modalities,
+            "instructions": self.instructions.clone().unwrap_or_default(),
+            "voice": self.voice,
+            "input_audio_format": "pcm16",
+            "output_audio_format": "pcm16",
+            "turn_detection": self.vad.as_ref().map(|vad| json!({
+                "type": "server_vad",
+                "threshold": vad.config.threshold,
+                "prefix_padding_ms": vad.config.prefix_padding_ms,
+                "silence_duration_ms": vad.config.silence_duration_ms,
+                "create_response": vad.config.create_response,
+                "interrupt_response": vad.config.interrupt_response,
+            })),
+        }})
+    }
+
+    /// The TTS model answers are spoken with, when audio output is on.
+    fn speech_config(
+        &self,
+        configs: &std::collections::HashMap<String, ModelConfig>,
+    ) -> Option<ModelConfig> {
+        if !self.modalities.iter().any(|m| m == "audio") {
+            return None;
+        }
+        let mut speech_models: Vec<_> = configs
+            .values()
+            .filter(|config| config.model_type == ModelType::TTS)
+            .collect();
+        speech_models.sort_by(|a, b| a.model_name.cmp(&b.model_name));
+        match &self.speech_model {
+            Some(name) => speech_models.into_iter().find(|c| c.model_name == *name),
+            None => speech_models.into_iter().next(),
+        }
+        .cloned()
+    }
+}
+
+/// Sends server events, numbering them per connection.
+#[derive(Clone)]
+struct Events {
+    session: Session,
+    counter: Rc<Cell<usize>>,
+}
+
+impl Events {
+    async fn send(&mut self, r#type: &str, mut event: Value) -> bool {
+        self.counter.set(self.counter.get() + 1);
+        event["type"] = json!(r#type);
+        event["event_id"] = json!(format!("event_{}", self.counter.get()));
+        self.session.text(event.to_string()).await.is_ok()
+    }
+
+    async fn error(&mut self, message: String) -> bool {
+        self.send(
+            "error",
+            json!({ "error": { "type": "invalid_request_error", "message": message } }),
+        )
+        .await
+    }
+}
+
+/// 線性插值重新取樣
+pub(crate) fn resample(samples: &[i16], from: u32, to: u32) -> Vec<i16> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let len = (samples.len() as u64 * to as u64 / from as u64) as usize;
+    (0..len)
+        .map(|i| {
+            let pos = i as f64 * from as f64 / to as f64;
+            let index = pos as usize;
+            let frac = pos - index as f64;
+            let a = samples[index] as f64;
+            let b = *samples.get(index + 1).unwrap_or(&samples[index]) as f64;
+            (a + (b - a) * frac).round() as i16
+        })
+        .collect()
+}
+
+pub(crate) fn to_wav(samples: &[i16]) -> Result<Vec<u8>, hound::Error> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: ASR_SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut cursor = Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut cursor, spec)?;
+    for sample in samples {
+        writer.write_sample(*sample)?;
+    }
+    writer.finalize()?;
+    Ok(cursor.into_inner())
+}
+
+async fn transcribe(
+    audio_pool: &AudioPool,
+    model: Option<&str>,
+    samples: &[i16],
+) -> Result<String, String> {
+    let wav = to_wav(&resample(samples, INPUT_SAMPLE_RATE, ASR_SAMPLE_RATE))
+        .map_err(|e| format!("Invalid audio: {}", e))?;
+    crate::audio::transcribe(audio_pool, model, wav).await
+}
+
+/// Transcribe a committed turn into a user message of the conversation.
+async fn commit(
+    events: &mut Events,
+    audio_pool: &AudioPool,
+    state: &SessionState,
+    conversation: &Mutex<Vec<Message>>,
+    item_id: String,
+    samples: Vec<i16>,
+) {
+    events
+        .send(
+            "input_audio_buffer.committed",
+            json!({ "item_id": item_id }),
+        )
+        .await;
+    match transcribe(audio_pool, state.transcription_model.as_deref(), &samples).await {
+        Ok(transcript) => {
+            conversation.lock().unwrap().push(Message {
+                role: Some(Role::User),
+                content: Some(Content::String(transcript.clone())),
+                ..Default::default()
+            });
+            events
+                .send(
+                    "conversation.item.input_audio_transcription.completed",
+                    json!({ "item_id": item_id, "content_index": 0, "transcript": transcript }),
+                )
+                .await;
+        }
+        Err(e) => {
+            events
+                .send(
+                    "conversation.item.input_audio_transcription.failed",
+                    json!({ "item_id": item_id, "content_index": 0, "error": { "message": e } }),
+                )
+                .await;
+        }
+    }
+}
+
+/// The complete sentences at the start of `text`, taken out so they can be spoken while the
+/// rest is generated.
+fn take_sentences(text: &mut String) -> Option<String> {
+    let end = text
+        .char_indices()
+        .rfind(|(_, c)| matches!(c, '。' | '！' | '？' | '；' | '.' | '!' | '?' | ';' | '\n'))
+        .map(|(i, c)| i + c.len_utf8())?;
+    let rest = text.split_off(end);
+    Some(std::mem::replace(text, rest))
+}
+
+/// The voice of an answer.
+#[derive(Clone)]
+struct Speech {
+    config: ModelConfig,
+    pool: SpeechPool,
+    voice: String,
+}
+
+/// Speak the sentences coming from `sentences` as `response.audio.delta` events.
+async fn speak(
+    mut events: Events,
+    speech: Speech,
+    mut sentences: tokio::sync::mpsc::UnboundedReceiver<String>,
+    response_id: String,
+    item_id: String,
+) {
+    while let Some(sentence) = sentences.recv().await {
+        if sentence.trim().is_empty() {
+            continue;
+        }
+        let audio = match speech::synthesize(
+            &speech.config,
+            &speech.pool,
+            sentence,
+            speech.voice.clone(),
+            1.0,
+        )
+        .await
+        {
+            Ok(audio) => audio,
+            Err(e) => {
+                events
+                    .error(format!("Speech synthesis failed: {}", e))
+                    .await;
+                return;
+            }
+        };
+        let pcm: Vec<u8> = resample(&audio.samples, audio.sample_rate, INPUT_SAMPLE_RATE)
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let event = json!({
+            "response_id": response_id,
+            "item_id": item_id,
+            "output_index": 0,
+            "content_index": 0,
+            "delta": base64::engine::general_purpose::STANDARD.encode(pcm),
+        });
+        if !events.send("response.audio.delta", event).await {
+            return;
+        }
+    }
+}
+
+/// One `response.create`: run the conversation through the chat pipeline and stream text deltas,
+/// plus the spoken answer when `speech` is set.
+async fn run_response(
+    mut events: Events,
+    ctx: ChatContext,
+    model: String,
+    messages: Vec<Message>,
+    conversation: Arc<Mutex<Vec<Message>>>,
+    response_id: String,
+    speech: Option<Speech>,
+) {
+    let item_id = format!("item_{}", response_id);
+    events
+        .send(
+            "response.created",
+            json!({ "response": { "id": response_id, "object": "realtime.response", "status": "in_progress" } }),
+        )
+        .await;
+
+    let response = ctx
+        .chat(crate::chat::streamed(ChatCompletionsRequest {
+            model,
+            messages,
+            ..Default::default()
+        }))
+        .await;
+    if !response.status().is_success() {
+        let bytes = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap_or_default();
+        let message = serde_json::from_slice::<OpenAiError>(&bytes)
+            .map(|e| e.message)
+            .unwrap_or_else(|_| String::from_utf8_lossy(&bytes).to_string());
+        events.error(message).await;
+        events
+            .send(
+                "response.done",
+                json!({ "response": { "id": response_id, "object": "realtime.response", "status": "failed" } }),
+            )
+            .await;
+        return;
+    }
+
+    // 有聲音時文字是 audio_transcript
+    let (text_event, done_event) = match speech {
+        Some(_) => (
+            "response.audio_transcript.delta",
+            "response.audio_transcript.done",
+        ),
+        None => ("response.text.delta", "response.text.done"),
+    };
+    let (sentence_tx, sentence_rx) = tokio::sync::mpsc::unbounded_channel();
+    let spoken = match speech.clone() {
+        Some(speech) => futures::future::Either::Left(speak(
+            events.clone(),
+            speech,
+            sentence_rx,
+            response_id.clone(),
+            item_id.clone(),
+        )),
+        None => futures::future::Either::Right(std::future::ready(())),
+    };
+    let written = async {
+        let mut text = String::new();
+        let mut unspoken = String::new();
+        let mut status = "completed";
+        let mut chunks = std::pin::pin!(sse_data(response));
+        while let Some(data) = chunks.next().await {
+            let content = match chunk_text(&data) {
+                Ok(Some(content)) => content,
+                Ok(None) => continue,
+                Err(e) => {
+                    events.error(e).await;
+                    status = "failed";
+                    break;
+                }
+            };
+            text.push_str(&content);
+            unspoken.push_str(&content);
+            if let Some(sentences) = take_sentences(&mut unspoken) {
+                let _ = sentence_tx.send(sentences);
+            }
+            let event = json!({
+                "response_id": response_id,
+                "item_id": item_id,
+                "output_index": 0,
+                "content_index": 0,
+                "delta": content,
+            });
+            if !events.send(text_event, event).await {
+                return None;
+            }
+        }
+        let _ = sentence_tx.send(unspoken);
+        drop(sentence_tx);
+        Some((text, status))
+    };
+    // 文字繼續生成，同時把完整的句子念出來
+    let (written, ()) = futures::join!(written, spoken);
+    let Some((text, status)) = written else {
+        return;
+    };
+
+    if speech.is_some() {
+        events
+            .send(
+                "response.audio.done",
+                json!({ "response_id": response_id, "item_id": item_id, "output_index": 0, "content_index": 0 }),
+            )
+            .await;
+    }
+    let field = if speech.is_some() {
+        "transcript"
+    } else {
+        "text"
+    };
+    let mut done = json!({ "response_id": response_id, "item_id": item_id, "output_index": 0, "content_index": 0 });
+    done[field] = json!(text);
+    events.send(done_event, done).await;
+    conversation.lock().unwrap().push(Message {
+        role: Some(Role::Assistant),
+        content: Some(Content::String(text.clone())),
+        ..Default::default()
+    });
+    let content = match speech {
+        Some(_) => json!({ "type": "audio", "transcript": text }),
+        None => json!({ "type": "text", "text": text }),
+    };
+    events
+        .send(
+            "response.done",
+            json!({
+                "response": {
+                    "id": response_id,
+                    "object": "realtime.response",
+                    "status": status,
+                    "output": [{
+                        "id": item_id,
+                        "type": "message",
+                        "role": "assistant",
+                        "content": [content],
+                    }],
+                }
+            }),
+        )
+        .await;
+}
+
+/// Subset of the OpenAI Realtime API.
+///
+/// Audio (`pcm16`, 24kHz mono) is buffered with `input_audio_buffer.append`, transcribed by the
+/// loaded ASR model on `input_audio_buffer.commit` or when `server_vad` turn detection hears the
+/// end of a turn, and `response.create` streams the LLM answer as `response.text.delta` events.
+/// With the `audio` modality the answer is also spoken by a TTS model as `response.audio.delta`.
+#[utoipa::path(
+    params(
+        ("model" = String, Query, description = "Chat model answering the conversation")
+    ),
+    responses(
+        (status = 101, description = "Switching protocols")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/realtime")]
+pub async fn realtime(
+    req: HttpRequest,
+    payload: web::Payload,
+    query: web::Query<RealtimeQuery>,
+    ctx: ChatContext,
+    audio_pool: web::Data<AudioPool>,
+    speech_pool: web::Data<SpeechPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !ctx.all_configs.contains_key(&query.model) {
+        return Ok(HttpResponse::BadRequest().json(OpenAiError {
+            message: format!("Model \"{}\" does not exist.", query.model),
+            code: "model_not_found".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some("model".to_owned()),
+        }));
+    }
+    let (response, session, mut msg_stream) = actix_ws::handle(&req, payload)?;
+    let model = query.into_inner().model;
+
+    actix_web::rt::spawn(async move {
+        let mut events = Events {
+            session,
+            counter: Rc::new(Cell::new(0)),
+        };
+        let mut state = SessionState::new(model.clone());
+        let mut audio: Vec<i16> = Vec::new();
+        let conversation = Arc::new(Mutex::new(Vec::<Message>::new()));
+        let mut running: Option<JoinHandle<()>> = None;
+        let mut response_count = 0;
+
+        events.send("session.created", state.info()).await;
+
+        while let Some(Ok(msg)) = msg_stream.recv().await {
+            let text = match msg {
+                actix_ws::Message::Text(text) => text,
+                actix_ws::Message::Ping(bytes) => {
+                    let _ = events.session.pong(&bytes).await;
+                    continue;
+                }
+                actix_ws::Message::Close(_) => break,
+                _ => continue,
+            };
+            let event = match serde_json::from_str::<ClientEvent>(&text) {
+                Ok(event) => event,
+                Err(e) => {
+                    events.error(format!("Unsupported event: {}", e)).await;
+                    continue;
+                }
+            };
+
+            // server_vad 聽到一句話結束就自己 commit
+            let mut turn_ended = false;
+            let mut create_response = false;
+            match event {
+                ClientEvent::SessionUpdate { session } => {
+                    state.update(session);
+                    events.send("session.updated", state.info()).await;
+                }
+                ClientEvent::AudioAppend { audio: chunk } => {
+                    let samples: Vec<i16> =
+                        match base64::engine::general_purpose::STANDARD.decode(chunk) {
+                            Ok(bytes) => bytes
+                                .chunks_exact(2)
+                                .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                                .collect(),
+                            Err(e) => {
+                                events.error(format!("Invalid base64 audio: {}", e)).await;
+                                continue;
+                            }
+                        };
+                    audio.extend(&samples);
+                    let turns = state
+                        .vad
+                        .as_mut()
+                        .map(|vad| vad.push(&samples))
+                        .unwrap_or_default();
+                    for turn in turns {
+                        let item_id = format!("item_input_{}", response_count + 1);
+                        match turn {
+                            TurnEvent::SpeechStarted { audio_start_ms } => {
+                                events
+                                    .send("input_audio_buffer.speech_started", json!({ "audio_start_ms": audio_start_ms, "item_id": item_id }))
+                                    .await;
+                                let interrupt = state
+                                    .vad
+                                    .as_ref()
+                                    .is_some_and(|vad| vad.config.interrupt_response);
+                                if let Some(task) = running
+                                    .take()
+                                    .filter(|task| interrupt && !task.is_finished())
+                                {
+                                    task.abort();
+                                    events
+                                        .send(
+                                            "response.done",
+                                            json!({ "response": { "object": "realtime.response", "status": "cancelled" } }),
+                                        )
+                                        .await;
+                                }
+                            }
+                            TurnEvent::SpeechStopped { audio_end_ms } => {
+                                events
+                                    .send(
+                                        "input_audio_buffer.speech_stopped",
+                                        json!({ "audio_end_ms": audio_end_ms, "item_id": item_id }),
+                                    )
+                                    .await;
+                                turn_ended = true;
+                            }
+                        }
+                    }
+                }
+                ClientEvent::AudioClear => {
+                    audio.clear();
+                    if let Some(vad) = state.vad.as_mut() {
+                        vad.reset();
+                    }
+                    events.send("input_audio_buffer.cleared", json!({})).await;
+                }
+                ClientEvent::AudioCommit => {
+                    if audio.is_empty() {
+                        events
+                            .error("Input audio buffer is empty.".to_owned())
+                            .await;
+                        continue;
+                    }
+                    turn_ended = true;
+                }
+                ClientEvent::ItemCreate { item } => {
+                    let text: String = item
+                        .content
+                        .into_iter()
+                        .filter_map(|c| c.text.or(c.transcript))
+                        .collect();
+                    conversation.lock().unwrap().push(Message {
+                        role: Some(item.role),
+                        content: Some(Content::String(text)),
+                        ..Default::default()
+                    });
+                    events.send("conversation.item.created", json!({})).await;
+                }
+                ClientEvent::ResponseCreate => create_response = true,
+                ClientEvent::ResponseCancel => {
+                    if let Some(task) = running.take() {
+                        task.abort();
+                        events
+                            .send(
+                                "response.done",
+                                json!({ "response": { "object": "realtime.response", "status": "cancelled" } }),
+                            )
+                            .await;
+                    }
+                }
+            }
+
+            if turn_ended {
+                // 只留說話開始前 prefix_padding_ms 的聲音
+                let start = state
+                    .vad
+                    .as_ref()
+                    .and_then(|vad| vad.turn_start_ms())
+                    .map_or(0, |ms| {
+                        (ms as usize * INPUT_SAMPLE_RATE as usize / 1000).min(audio.len())
+                    });
+                let samples = audio.split_off(start);
+                audio.clear();
+                if let Some(vad) = state.vad.as_mut() {
+                    create_response = create_response || vad.config.create_response;
+                    vad.reset();
+                }
+                response_count += 1;
+                let item_id = format!("item_input_{}", response_count);
+                commit(
+                    &mut events,
+                    &audio_pool,
+                    &state,
+                    &conversation,
+                    item_id,
+                    samples,
+                )
+                .await;
+            }
+
+            if create_response {
+                if running.as_ref().is_some_and(|task| !task.is_finished()) {
+                    events
+                        .error("A response is already in progress.".to_owned())
+                        .await;
+                    continue;
+                }
+                let speech = match state.speech_config(&ctx.all_configs) {
+                    Some(config) => Some(Speech {
+                        config,
+                        pool: speech_pool.get_ref().clone(),
+                        voice: state.voice.clone(),
+                    }),
+                    None if state.modalities.iter().any(|m| m == "audio") => {
+                        events
+                            .error(
+                                "No speech model is configured, answering with text only."
+                                    .to_owned(),
+                            )
+                            .await;
+                        None
+                    }
+                    None => None,
+                };
+                response_count += 1;
+                let mut messages: Vec<Message> = state
+                    .instructions
+                    .iter()
+                    .map(|instructions| Message {
+                        role: Some(Role::System),
+                        content: Some(Content::String(instructions.clone())),
+                        ..Default::default()
+                    })
+                    .collect();
+                messages.extend(conversation.lock().unwrap().iter().cloned());
+                running = Some(actix_web::rt::spawn(run_response(
+                    events.clone(),
+                    ctx.clone(),
+                    model.clone(),
+                    messages,
+                    conversation.clone(),
+                    format!("resp_{}", response_count),
+                    speech,
+                )));
+            }
+        }
+        if let Some(task) = running.take() {
+            task.abort();
+        }
+    });
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resample_24k_to_16k() {
+        let samples: Vec<i16> = (0..24).map(|i| i * 100).collect();
+        let resampled = resample(&samples, INPUT_SAMPLE_RATE, ASR_SAMPLE_RATE);
+        assert_eq!(resampled.len(), 16);
+        assert_eq!(resampled[0], 0);
+        assert_eq!(resampled[2], 300);
+    }
+
+    #[test]
+    fn client_events_parse() {
+        let event: ClientEvent =
+            serde_json::from_str(r#"{"type":"input_audio_buffer.append","audio":"AAA="}"#).unwrap();
+        assert!(matches!(event, ClientEvent::AudioAppend { .. }));
+        let event: ClientEvent =
+            serde_json::from_str(r#"{"type":"response.create","event_id":"e1","response":{}}"#)
+                .unwrap();
+        assert!(matches!(event, ClientEvent::ResponseCreate));
+
+        let update = |session: Value| -> SessionUpdate { serde_json::from_value(session).unwrap() };
+        let mut state = SessionState::new("qwen".to_owned());
+        state.update(update(
+            json!({ "turn_detection": { "type": "server_vad" }, "modalities": ["text", "audio"] }),
+        ));
+        assert!(state.vad.is_some());
+        state.update(update(json!({ "voice": "nova" })));
+        assert!(state.vad.is_some());
+        state.update(update(json!({ "turn_detection": null })));
+        assert!(state.vad.is_none());
+        assert_eq!(state.info()["session"]["modalities"][1], "audio");
+    }
+
+    #[test]
+    fn speaks_whole_sentences() {
+        let mut text = "你好。今天天".to_owned();
+        assert_eq!(take_sentences(&mut text).as_deref(), Some("你好。"));
+        assert_eq!(text, "今天天");
+        assert_eq!(take_sentences(&mut text), None);
+        text.push_str("氣很好! Bye");
+        assert_eq!(take_sentences(&mut text).as_deref(), Some("今天天氣很好!"));
+        assert_eq!(text, " Bye");
+    }
+}
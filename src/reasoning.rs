@@ -0,0 +1,103 @@
+const OPEN: &str = "<think>";
+const CLOSE: &str = "</think>";
+
+/// Splits the output of a think-enabled model into its reasoning and the answer, like the
+/// `reasoning_content` of DeepSeek and vLLM. The output starts in the reasoning, with or without
+/// a `<think>` of its own (many chat templates already open it), and the answer is what follows
+/// `</think>`. Text that could be the start of a tag is held back until the next token decides.
+#[derive(Debug, Clone)]
+pub struct Reasoning {
+    pending: String,
+    opened: bool,
+    answering: bool,
+    /// 區段開頭的空白不送
+    trim: bool,
+}
+
+impl Default for Reasoning {
+    fn default() -> Self {
+        Self {
+            pending: String::new(),
+            opened: false,
+            answering: false,
+            trim: true,
+        }
+    }
+}
+
+impl Reasoning {
+    fn trimmed(&mut self, text: String) -> String {
+        if !self.trim {
+            return text;
+        }
+        let text = text.trim_start();
+        self.trim = text.is_empty();
+        text.to_owned()
+    }
+
+    /// The reasoning and answer text that can be sent for the next token.
+    pub fn push(&mut self, text: &str) -> (String, String) {
+        if self.answering {
+            return (String::new(), self.trimmed(text.to_owned()));
+        }
+        self.pending.push_str(text);
+        if !self.opened {
+            let start = self.pending.trim_start();
+            if start.len() < OPEN.len() && OPEN.starts_with(start) {
+                return Default::default();
+            }
+            if let Some(rest) = start.strip_prefix(OPEN) {
+                self.pending = rest.to_owned();
+            }
+            self.opened = true;
+        }
+        if let Some(at) = self.pending.find(CLOSE) {
+            let answer = self.pending.split_off(at + CLOSE.len());
+            self.pending.truncate(at);
+            let reasoning = std::mem::take(&mut self.pending);
+            let reasoning = self.trimmed(reasoning).trim_end().to_owned();
+            self.answering = true;
+            self.trim = true;
+            return (reasoning, self.trimmed(answer));
+        }
+        let held = (1..CLOSE.len())
+            .rev()
+            .find(|len| self.pending.ends_with(&CLOSE[..*len]))
+            .unwrap_or(0);
+        let rest = self.pending.split_off(self.pending.len() - held);
+        let reasoning = std::mem::replace(&mut self.pending, rest);
+        (self.trimmed(reasoning), String::new())
+    }
+
+    /// What was held back when the model finished, still reasoning if `</think>` never came.
+    pub fn flush(&mut self) -> String {
+        let reasoning = std::mem::take(&mut self.pending);
+        self.trimmed(reasoning)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_reasoning_from_answer() {
+        let mut reasoning = Reasoning::default();
+        let pieces: Vec<_> = ["<th", "ink>\n", "Let me", " think.</th", "ink>\n\n", "Hello", "!"]
+            .into_iter()
+            .map(|text| reasoning.push(text))
+            .collect();
+        let text = |pick: fn(&(String, String)) -> &String| {
+            pieces.iter().map(pick).cloned().collect::<String>()
+        };
+        assert_eq!(text(|p| &p.0), "Let me think.");
+        assert_eq!(text(|p| &p.1), "Hello!");
+        assert_eq!(pieces[4], (String::new(), String::new()));
+
+        // chat template 已經開了 <think>，輸出只有結尾
+        let mut reasoning = Reasoning::default();
+        assert_eq!(reasoning.push("Hmm"), ("Hmm".to_owned(), String::new()));
+        assert_eq!(reasoning.push(" <"), (" ".to_owned(), String::new()));
+        assert_eq!(reasoning.flush(), "<");
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use actix_web::{web, HttpRequest};
+use futures::StreamExt;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::Value;
+
+use crate::{
+    aliases::Aliases,
+    eviction::MemoryBudget,
+    keep_alive::{KeepAlive, KeepAlives},
+    llm::simple::{cached_file, model_files},
+    utils::ModelConfig,
+};
+
+const SCHEMA: &str = "
+PRAGMA journal_mode = WAL;
+CREATE TABLE IF NOT EXISTS models (
+    name TEXT PRIMARY KEY,
+    repo TEXT NOT NULL,
+    digest TEXT,
+    size INTEGER,
+    pulled_at INTEGER,
+    last_used INTEGER,
+    keep_alive REAL
+);
+CREATE TABLE IF NOT EXISTS aliases (
+    alias TEXT PRIMARY KEY,
+    model TEXT NOT NULL
+);
+";
+
+const COLUMNS: &str = "name, repo, digest, size, pulled_at, last_used, keep_alive";
+
+/// What the registry remembers of a model.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RegisteredModel {
+    pub name: String,
+    pub repo: String,
+    /// Digest of the model file when it was pulled
+    pub digest: Option<String>,
+    /// Bytes of the pulled files
+    pub size: Option<u64>,
+    /// Unix time of the last finished pull, `None` once the files are removed
+    pub pulled_at: Option<u64>,
+    /// Unix time of the last request
+    pub last_used: Option<u64>,
+    /// `keep_alive` of the last request
+    pub keep_alive: Option<KeepAlive>,
+}
+
+impl RegisteredModel {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        let keep_alive: Option<f64> = row.get(6)?;
+        Ok(Self {
+            name: row.get(0)?,
+            repo: row.get(1)?,
+            digest: row.get(2)?,
+            size: row.get::<_, Option<i64>>(3)?.map(|size| size as u64),
+            pulled_at: row.get::<_, Option<i64>>(4)?.map(|time| time as u64),
+            last_used: row.get::<_, Option<i64>>(5)?.map(|time| time as u64),
+            keep_alive: keep_alive.map(KeepAlive::from_secs),
+        })
+    }
+
+    /// `(pulled_at, size, digest)` when the files are downloaded.
+    pub fn pull(&self) -> Option<(u64, u64, String)> {
+        Some((self.pulled_at?, self.size?, self.digest.clone()?))
+    }
+}
+
+fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+/// Pulled models, aliases and last use in the `--store` database, so `/api/tags`, `/api/ps` and
+/// keep-alive survive restarts without scanning the hf-hub cache for every model.
+#[derive(Clone)]
+pub struct Registry {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Registry {
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    pub fn pulled(&self, config: &ModelConfig, digest: &str, size: u64) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO models (name, repo, digest, size, pulled_at) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (name) DO UPDATE SET repo = excluded.repo, digest = excluded.digest,
+                 size = excluded.size, pulled_at = excluded.pulled_at",
+            params![
+                config.model_name,
+                config.model_repo,
+                digest,
+                size as i64,
+                now_secs()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// A request for the model came in.
+    pub fn used(&self, config: &ModelConfig, keep_alive: KeepAlive) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO models (name, repo, last_used, keep_alive) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (name) DO UPDATE SET last_used = excluded.last_used,
+                 keep_alive = excluded.keep_alive",
+            params![
+                config.model_name,
+                config.model_repo,
+                now_secs(),
+                keep_alive.secs()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The files of the model were removed.
+    pub fn forget(&self, name: &str) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE models SET digest = NULL, size = NULL, pulled_at = NULL WHERE name = ?1",
+            params![name],
+        )?;
+        Ok(())
+    }
+
+    /// The repository was removed from the hf-hub cache.
+    pub fn forget_repo(&self, repo: &str) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE models SET digest = NULL, size = NULL, pulled_at = NULL WHERE repo = ?1",
+            params![repo],
+        )?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> rusqlite::Result<Option<RegisteredModel>> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                &format!("SELECT {} FROM models WHERE name = ?1", COLUMNS),
+                params![name],
+                RegisteredModel::from_row,
+            )
+            .optional()
+    }
+
+    pub fn list(&self) -> rusqlite::Result<Vec<RegisteredModel>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM models ORDER BY name", COLUMNS))?;
+        let rows = stmt.query_map([], RegisteredModel::from_row)?;
+        rows.collect()
+    }
+
+    pub fn alias(&self, alias: &str, model: &str) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO aliases (alias, model) VALUES (?1, ?2)",
+            params![alias, model],
+        )?;
+        Ok(())
+    }
+
+    pub fn aliases(&self) -> rusqlite::Result<HashMap<String, String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT alias, model FROM aliases")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    /// Record the files of a finished pull.
+    pub fn record_pull(&self, config: &ModelConfig) {
+        let size = model_files(config)
+            .iter()
+            .filter_map(|file| cached_file(config, file))
+            .filter_map(|path| std::fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        let digest = crate::fingerprint::model_digest(config).unwrap_or_default();
+        if let Err(e) = self.pulled(config, &digest, size) {
+            log::warn!("Failed to register {}: {}", config.model_name, e);
+        }
+    }
+
+    /// Bring back the aliases, and the keep-alive and last use of the models loaded at startup.
+    pub fn restore(
+        &self,
+        loaded: &[String],
+        configs: &HashMap<String, ModelConfig>,
+        aliases: &Aliases,
+        keep_alives: &KeepAlives,
+        memory_budget: &MemoryBudget,
+    ) -> rusqlite::Result<()> {
+        aliases.restore(self.aliases()?, configs);
+        let now = now_secs() as u64;
+        for model in self.list()? {
+            if !loaded.contains(&model.name) {
+                continue;
+            }
+            // 閒置時間從開機重新算，預載的模型不會一開機就被卸載
+            if let Some(keep_alive) = model.keep_alive {
+                keep_alives.set(&model.name, keep_alive);
+            }
+            if let Some(last_used) = model.last_used {
+                memory_budget.restore(
+                    &model.name,
+                    Duration::from_secs(now.saturating_sub(last_used)),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn from_request(req: &HttpRequest) -> Option<web::Data<Registry>> {
+    req.app_data::<web::Data<Registry>>().cloned()
+}
+
+/// Pass the pull progress through and register the model once it reports `success`.
+pub(crate) fn record_pull(
+    progress: impl futures::Stream<Item = Value>,
+    registry: Option<web::Data<Registry>>,
+    config: ModelConfig,
+) -> impl futures::Stream<Item = Value> {
+    progress.map(move |line| {
+        if let (Some(registry), Some("success")) = (&registry, line["status"].as_str()) {
+            registry.record_pull(&config);
+        }
+        line
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_round_trip() {
+        let registry = Registry::open(":memory:").unwrap();
+        let config = ModelConfig {
+            model_name: "qwen".to_owned(),
+            model_repo: "kautism/qwen".to_owned(),
+            ..Default::default()
+        };
+        registry.pulled(&config, "abc", 7).unwrap();
+        registry
+            .used(&config, KeepAlive::For(Duration::from_secs(300)))
+            .unwrap();
+        let model = registry.get("qwen").unwrap().unwrap();
+        assert_eq!(model.repo, "kautism/qwen");
+        assert_eq!(
+            model.pull().map(|(_, size, digest)| (size, digest)),
+            Some((7, "abc".to_owned()))
+        );
+        assert_eq!(
+            model.keep_alive,
+            Some(KeepAlive::For(Duration::from_secs(300)))
+        );
+        assert!(model.last_used.is_some());
+
+        registry.used(&config, KeepAlive::Forever).unwrap();
+        assert_eq!(
+            registry.get("qwen").unwrap().unwrap().keep_alive,
+            Some(KeepAlive::Forever)
+        );
+        // 刪掉檔案後只留下用量
+        registry.forget_repo("kautism/qwen").unwrap();
+        let model = registry.get("qwen").unwrap().unwrap();
+        assert!(model.pull().is_none());
+        assert!(model.last_used.is_some());
+
+        registry.alias("assistant:latest", "qwen").unwrap();
+        registry.alias("assistant:latest", "qwen").unwrap();
+        assert_eq!(
+            registry.aliases().unwrap(),
+            HashMap::from([("assistant:latest".to_owned(), "qwen".to_owned())])
+        );
+        assert_eq!(registry.list().unwrap().len(), 1);
+    }
+}
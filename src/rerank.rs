@@ -0,0 +1,160 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use actix::Recipient;
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cluster::Cluster,
+    errors::ApiError,
+    reranker::simple::SimpleRerank,
+    utils::{ModelConfig, ModelType},
+    OpenAiError, ProcessRerank,
+};
+
+pub type RerankPool = Arc<Mutex<HashMap<String, Recipient<ProcessRerank>>>>;
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct RerankRequest {
+    pub model: String,
+    pub query: String,
+    pub documents: Vec<String>,
+    #[serde(default)]
+    pub top_n: Option<usize>,
+    #[serde(default)]
+    pub return_documents: bool,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct RerankDocument {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct RerankResult {
+    pub index: usize,
+    pub relevance_score: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document: Option<RerankDocument>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct RerankUsage {
+    pub total_tokens: usize,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct RerankResponse {
+    pub model: String,
+    pub results: Vec<RerankResult>,
+    pub usage: RerankUsage,
+}
+
+fn internal_error(message: String) -> HttpResponse {
+    HttpResponse::InternalServerError().json(OpenAiError {
+        message,
+        code: "processing_error".to_owned(),
+        r#type: "internal_error".to_owned(),
+        param: None,
+    })
+}
+
+/// 取得 Rerank Actor，沒有的話就載入
+async fn get_or_load(
+    config: &ModelConfig,
+    rerank_pool: &RerankPool,
+) -> Result<Recipient<ProcessRerank>, String> {
+    crate::backends::get_or_load::<SimpleRerank, _>(config, rerank_pool).await
+}
+
+#[utoipa::path(
+    request_body = RerankRequest,
+    responses(
+        (status = OK, description = "Success", body = RerankResponse, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/rerank")]
+pub async fn rerank(
+    req_body: web::Json<RerankRequest>,
+    rerank_pool: web::Data<RerankPool>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    cluster: web::Data<Cluster>,
+    req: HttpRequest,
+) -> impl Responder {
+    if !all_configs.contains_key(&req_body.model) {
+        if let Some(peer) = cluster.peer_for(&req_body.model, &req).await {
+            return cluster.forward(&peer, "/v1/rerank", &req_body.0).await;
+        }
+    }
+    let Some(config) = all_configs
+        .get(&req_body.model)
+        .filter(|c| c.model_type == ModelType::Rerank)
+    else {
+        return HttpResponse::BadRequest().json(OpenAiError {
+            message: format!(
+                "The model {} does not exist or is not a rerank model.",
+                req_body.model
+            ),
+            code: "model_not_found".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some("model".to_owned()),
+        });
+    };
+
+    if req_body.documents.is_empty() {
+        return HttpResponse::BadRequest().json(OpenAiError {
+            message: "Documents should not be empty.".to_owned(),
+            code: "invalid_request_error".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some("documents".to_owned()),
+        });
+    }
+
+    let recipient = match get_or_load(config, &rerank_pool).await {
+        Ok(recipient) => recipient,
+        Err(e) => return internal_error(e),
+    };
+
+    let send_future = recipient.send(ProcessRerank {
+        query: req_body.query.clone(),
+        documents: req_body.documents.clone(),
+    });
+    let result = match actix_web::rt::time::timeout(Duration::from_secs(60), send_future).await {
+        Ok(Ok(Ok(result))) => result,
+        Ok(Ok(Err(_))) => return internal_error("Rerank inference failed.".to_owned()),
+        Ok(Err(e)) => return internal_error(format!("Internal server error:{}", e)),
+        Err(_timeout) => return ApiError::busy("Server Busy.").response(),
+    };
+
+    let mut results: Vec<RerankResult> = result
+        .scores
+        .into_iter()
+        .enumerate()
+        .map(|(index, relevance_score)| RerankResult {
+            index,
+            relevance_score,
+            document: req_body.return_documents.then(|| RerankDocument {
+                text: req_body.documents[index].clone(),
+            }),
+        })
+        .collect();
+    results.sort_by(|a, b| b.relevance_score.total_cmp(&a.relevance_score));
+    if let Some(top_n) = req_body.top_n {
+        results.truncate(top_n);
+    }
+
+    HttpResponse::Ok().json(RerankResponse {
+        model: req_body.model.clone(),
+        results,
+        usage: RerankUsage {
+            total_tokens: result.prompt_tokens,
+        },
+    })
+}
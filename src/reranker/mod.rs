@@ -0,0 +1 @@
+pub mod simple;
\ No newline at end of file
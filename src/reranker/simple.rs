@@ -0,0 +1,139 @@
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use actix::{Actor, ActorContext};
+use hf_hub::api::Progress;
+
+use crate::embedding::simple::Encoder;
+use crate::embedding::wordpiece::WordPiece;
+use crate::llm::simple::{fetch_file, render_local_path_template};
+use crate::utils::ModelConfig;
+use crate::{AIModel, ModelProgress, ProcessRerank, Rerank, RerankScores, ShutdownMessages};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Cross-encoder reranker (bge-reranker style) converted to RKNN, output is one logit per pair.
+pub struct SimpleRerank {
+    encoder: Arc<Mutex<Encoder>>,
+    tokenizer: Arc<WordPiece>,
+    max_batch_pairs: usize,
+}
+
+impl Actor for SimpleRerank {
+    type Context = actix::Context<Self>;
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+impl actix::Handler<ProcessRerank> for SimpleRerank {
+    type Result = actix::ResponseFuture<Result<RerankScores, ()>>;
+
+    fn handle(&mut self, msg: ProcessRerank, _ctx: &mut Self::Context) -> Self::Result {
+        let encoder = self.encoder.clone();
+        let tokenizer = self.tokenizer.clone();
+        let max_batch_pairs = self.max_batch_pairs;
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                let encoder = encoder.lock().unwrap();
+                let (ids, type_ids): (Vec<_>, Vec<_>) = msg
+                    .documents
+                    .iter()
+                    .map(|doc| tokenizer.encode_pair(&msg.query, doc, encoder.seq_len))
+                    .unzip();
+                let prompt_tokens = ids.iter().map(|i: &Vec<i64>| i.len()).sum();
+
+                let mut scores = Vec::with_capacity(ids.len());
+                for (ids, type_ids) in ids
+                    .chunks(max_batch_pairs)
+                    .zip(type_ids.chunks(max_batch_pairs))
+                {
+                    match encoder.run(ids, Some(type_ids), tokenizer.pad_id()) {
+                        Ok(logits) => scores.extend(
+                            (0..ids.len()).map(|row| sigmoid(logits[row * encoder.hidden])),
+                        ),
+                        Err(e) => {
+                            log::error!("Rerank inference failed: {}", e);
+                            return Err(());
+                        }
+                    }
+                }
+                Ok(RerankScores {
+                    scores,
+                    prompt_tokens,
+                })
+            })
+            .await
+            .map_err(|e| log::error!("Rerank task failed: {}", e))?
+        })
+    }
+}
+
+impl actix::Handler<ShutdownMessages> for SimpleRerank {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, _: ShutdownMessages, ctx: &mut Self::Context) -> Self::Result {
+        let _guard = self.encoder.lock().unwrap();
+        ctx.stop();
+        Ok(())
+    }
+}
+
+impl AIModel for SimpleRerank {
+    type Config = ModelConfig;
+
+    fn init_with_progress<P: Progress + ModelProgress + Clone>(
+        config: &Self::Config,
+        p: Option<P>,
+    ) -> Result<Self, BoxError> {
+        let model_file = render_local_path_template(
+            config.model_path.as_deref().unwrap_or("model.rknn"),
+            config,
+        );
+        let (model_path, progress) = fetch_file(config, &model_file, p)?;
+        let (vocab_path, _) = fetch_file(
+            config,
+            config.vocab_path.as_deref().unwrap_or("vocab.txt"),
+            None::<P>,
+        )?;
+
+        let mut progress = progress;
+        if let Some(progress) = progress.as_mut() {
+            let size = std::fs::metadata(&model_path)?.len();
+            let filename = model_path.file_name().unwrap().to_string_lossy();
+            progress.model_load(size.try_into().unwrap(), &filename, Instant::now());
+        }
+
+        let encoder = Encoder::new(&model_path)
+            .map_err(|e| format!("Error initializing rerank model: {}", e))?;
+        // RKNN 的 batch 是固定的，一次最多只能塞 batch 組
+        let max_batch_pairs = config.max_batch_pairs.unwrap_or(encoder.batch);
+        if max_batch_pairs == 0 || max_batch_pairs > encoder.batch {
+            return Err(format!(
+                "max_batch_pairs should be between 1 and the model batch size {}",
+                encoder.batch
+            )
+            .into());
+        }
+        let tokenizer = WordPiece::from_file(&vocab_path, true)?;
+        log::info!(
+            "Rerank model {} loaded, {} pairs per batch, seq_len {}",
+            config.model_name,
+            max_batch_pairs,
+            encoder.seq_len
+        );
+
+        if let Some(mut progress) = progress {
+            progress.model_finished();
+        }
+
+        Ok(Self {
+            encoder: Arc::new(Mutex::new(encoder)),
+            tokenizer: Arc::new(tokenizer),
+            max_batch_pairs,
+        })
+    }
+}
+
+impl Rerank for SimpleRerank {}
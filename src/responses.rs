@@ -0,0 +1,565 @@
+use std::{collections::HashMap, time::SystemTime};
+
+use actix_web::{post, web, HttpResponse, Responder};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    chat::{ChatCompletionsRequest, Function, Tool, ToolChoice, ToolChoiceFunction, Usage},
+    ws::{chunk_text, sse_data, ChatContext},
+    Content, ContentPart, FunctionCall, ImageUrl, Message, MessageToolCall, OpenAiError, Role,
+};
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(untagged)]
+pub enum ResponseInput {
+    String(String),
+    Items(Vec<InputItem>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(untagged)]
+pub enum ItemContent {
+    String(String),
+    Parts(Vec<InputPart>),
+}
+
+/// `input_text`, `output_text` or `input_image`.
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct InputPart {
+    pub r#type: String,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub image_url: Option<String>,
+}
+
+/// A `message` (the `type` can be left out), `function_call` or `function_call_output` item.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct InputItem {
+    #[serde(default)]
+    pub r#type: Option<String>,
+    #[serde(default)]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub content: Option<ItemContent>,
+    #[serde(default)]
+    pub call_id: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub arguments: Option<String>,
+    #[serde(default)]
+    pub output: Option<String>,
+}
+
+/// Responses API function tool, the definition is not nested under `function`.
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct ResponseTool {
+    pub r#type: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub parameters: Option<Value>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[schema(example = json!({
+    "model": "Qwen2.5-3B-abliterated",
+    "instructions": "You are a helpful assistant.",
+    "input": "Hello"
+}))]
+pub struct ResponsesRequest {
+    pub model: String,
+    pub input: ResponseInput,
+    /// System prompt
+    #[serde(default)]
+    pub instructions: Option<String>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default)]
+    pub max_output_tokens: Option<i32>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub tools: Option<Vec<ResponseTool>>,
+    /// `auto`, `none`, `required` or `{"type": "function", "name": ...}`
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub tool_choice: Option<Value>,
+    #[serde(default)]
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+fn role(role: Option<&str>) -> Result<Role, String> {
+    match role.unwrap_or("user") {
+        "user" => Ok(Role::User),
+        "assistant" => Ok(Role::Assistant),
+        "system" => Ok(Role::System),
+        "developer" => Ok(Role::Developer),
+        role => Err(format!("Unknown role {}", role)),
+    }
+}
+
+impl ItemContent {
+    fn into_content(self) -> Content {
+        let parts = match self {
+            ItemContent::String(text) => return Content::String(text),
+            ItemContent::Parts(parts) => parts,
+        };
+        if parts.iter().all(|part| part.image_url.is_none()) {
+            return Content::String(parts.into_iter().filter_map(|part| part.text).collect());
+        }
+        // 有圖片時轉成 chat 的 image_url parts
+        Content::Parts(
+            parts
+                .into_iter()
+                .map(|part| match part.image_url {
+                    Some(url) => ContentPart {
+                        r#type: "image_url".to_owned(),
+                        text: None,
+                        image_url: Some(ImageUrl {
+                            url: Some(url),
+                            detail: None,
+                        }),
+                        input_audio: None,
+                    },
+                    None => ContentPart {
+                        r#type: "text".to_owned(),
+                        text: part.text,
+                        image_url: None,
+                        input_audio: None,
+                    },
+                })
+                .collect(),
+        )
+    }
+}
+
+fn tool_choice(choice: &Value) -> Option<ToolChoice> {
+    match choice {
+        Value::String(mode) => Some(ToolChoice::Mode(mode.clone())),
+        choice => Some(ToolChoice::Function {
+            r#type: "function".to_owned(),
+            function: ToolChoiceFunction {
+                name: choice["name"].as_str()?.to_owned(),
+            },
+        }),
+    }
+}
+
+impl ResponsesRequest {
+    fn into_chat_request(self) -> Result<ChatCompletionsRequest, String> {
+        let mut messages: Vec<Message> = self
+            .instructions
+            .map(|instructions| Message {
+                role: Some(Role::System),
+                content: Some(Content::String(instructions)),
+                ..Default::default()
+            })
+            .into_iter()
+            .collect();
+        let items = match self.input {
+            ResponseInput::String(text) => vec![InputItem {
+                content: Some(ItemContent::String(text)),
+                ..Default::default()
+            }],
+            ResponseInput::Items(items) => items,
+        };
+        for item in items {
+            match item.r#type.as_deref().unwrap_or("message") {
+                "message" => messages.push(Message {
+                    role: Some(role(item.role.as_deref())?),
+                    content: item.content.map(ItemContent::into_content),
+                    ..Default::default()
+                }),
+                "function_call" => {
+                    let call = MessageToolCall {
+                        index: None,
+                        id: item.call_id.unwrap_or_default(),
+                        r#type: "function".to_owned(),
+                        function: FunctionCall {
+                            name: item.name.unwrap_or_default(),
+                            arguments: item.arguments.unwrap_or_default(),
+                        },
+                    };
+                    // 連續的 function_call 是同一個回合
+                    match messages.last_mut() {
+                        Some(Message {
+                            role: Some(Role::Assistant),
+                            tool_calls: Some(calls),
+                            ..
+                        }) => calls.push(call),
+                        _ => messages.push(Message {
+                            role: Some(Role::Assistant),
+                            tool_calls: Some(vec![call]),
+                            ..Default::default()
+                        }),
+                    }
+                }
+                "function_call_output" => messages.push(Message {
+                    role: Some(Role::Tool),
+                    content: Some(Content::String(item.output.unwrap_or_default())),
+                    tool_call_id: item.call_id,
+                    ..Default::default()
+                }),
+                other => return Err(format!("Unsupported input item type {}", other)),
+            }
+        }
+        let tools = self.tools.map(|tools| {
+            tools
+                .into_iter()
+                .filter(|tool| tool.r#type == "function")
+                .filter_map(|tool| {
+                    Some(Tool {
+                        r#type: "function".to_owned(),
+                        function: Function {
+                            name: tool.name?,
+                            description: tool.description,
+                            parameters: tool.parameters,
+                        },
+                    })
+                })
+                .collect()
+        });
+        Ok(crate::chat::streamed(ChatCompletionsRequest {
+            model: self.model,
+            messages,
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_completion_tokens: self.max_output_tokens,
+            tools,
+            tool_choice: self.tool_choice.as_ref().and_then(tool_choice),
+            metadata: self.metadata,
+            ..Default::default()
+        }))
+    }
+}
+
+fn random_id(prefix: &str) -> String {
+    format!("{}_{:016x}", prefix, rand::random::<u64>())
+}
+
+/// The answer as it is built up from the chat chunks.
+struct Output {
+    id: String,
+    model: String,
+    created_at: u64,
+    message_id: String,
+    text: String,
+    calls: Vec<MessageToolCall>,
+    usage: Option<Usage>,
+    truncated: bool,
+    sequence: usize,
+}
+
+impl Output {
+    fn new(model: String) -> Self {
+        Self {
+            id: random_id("resp"),
+            model,
+            created_at: SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            message_id: random_id("msg"),
+            text: String::new(),
+            calls: vec![],
+            usage: None,
+            truncated: false,
+            sequence: 0,
+        }
+    }
+
+    /// Take what a chat chunk adds, returns its text delta.
+    fn push(&mut self, data: &str) -> Result<Option<String>, String> {
+        if let Some(usage) = crate::usage::from_chunk(data) {
+            self.usage = Some(usage);
+            return Ok(None);
+        }
+        let delta = chunk_text(data)?;
+        let chunk: Value = serde_json::from_str(data).unwrap_or_default();
+        let choice = &chunk["choices"][0];
+        if choice["finish_reason"] == "length" {
+            self.truncated = true;
+        }
+        if let Ok(calls) =
+            serde_json::from_value::<Vec<MessageToolCall>>(choice["delta"]["tool_calls"].clone())
+        {
+            self.calls.extend(calls);
+        }
+        if let Some(delta) = &delta {
+            self.text.push_str(delta);
+        }
+        Ok(delta)
+    }
+
+    fn text_part(&self) -> Value {
+        json!({ "type": "output_text", "text": self.text, "annotations": [] })
+    }
+
+    fn message_item(&self, status: &str) -> Value {
+        let content = if status == "completed" {
+            vec![self.text_part()]
+        } else {
+            vec![]
+        };
+        json!({
+            "type": "message",
+            "id": self.message_id,
+            "status": status,
+            "role": "assistant",
+            "content": content,
+        })
+    }
+
+    fn call_item(call: &MessageToolCall) -> Value {
+        json!({
+            "type": "function_call",
+            "id": format!("fc_{}", call.id.trim_start_matches("call_")),
+            "call_id": call.id,
+            "name": call.function.name,
+            "arguments": call.function.arguments,
+            "status": "completed",
+        })
+    }
+
+    fn items(&self) -> Vec<Value> {
+        let message = (!self.text.is_empty() || self.calls.is_empty())
+            .then(|| self.message_item("completed"));
+        message
+            .into_iter()
+            .chain(self.calls.iter().map(Self::call_item))
+            .collect()
+    }
+
+    fn response(&self, status: &str) -> Value {
+        let done = status != "in_progress";
+        let status = if done && self.truncated {
+            "incomplete"
+        } else {
+            status
+        };
+        let usage = self.usage.as_ref().filter(|_| done).map(|usage| {
+            json!({
+                "input_tokens": usage.prompt_tokens,
+                "output_tokens": usage.completion_tokens,
+                "total_tokens": usage.total_tokens,
+            })
+        });
+        json!({
+            "id": self.id,
+            "object": "response",
+            "created_at": self.created_at,
+            "status": status,
+            "model": self.model,
+            "output": if done { self.items() } else { vec![] },
+            "incomplete_details": (status == "incomplete").then(|| json!({ "reason": "max_output_tokens" })),
+            "error": null,
+            "usage": usage,
+        })
+    }
+
+    /// A semantic event, numbered in order.
+    fn event(&mut self, kind: &str, mut data: Value) -> web::Bytes {
+        data["type"] = json!(kind);
+        data["sequence_number"] = json!(self.sequence);
+        self.sequence += 1;
+        web::Bytes::from(format!("event: {}\ndata: {}\n\n", kind, data))
+    }
+}
+
+async fn complete(chunks: impl Stream<Item = String>, mut output: Output) -> HttpResponse {
+    let mut chunks = Box::pin(chunks);
+    while let Some(data) = chunks.next().await {
+        if let Err(e) = output.push(&data) {
+            return crate::errors::ApiError::internal(e).response();
+        }
+    }
+    HttpResponse::Ok().json(output.response("completed"))
+}
+
+fn events(
+    chunks: impl Stream<Item = String> + 'static,
+    mut output: Output,
+) -> impl Stream<Item = Result<web::Bytes, actix_web::Error>> {
+    async_stream::stream! {
+        let mut chunks = Box::pin(chunks);
+        let response = output.response("in_progress");
+        yield Ok(output.event("response.created", json!({ "response": response })));
+        yield Ok(output.event("response.in_progress", json!({ "response": response })));
+        // 第一段文字來了才開 message item，只有 tool call 時就不用
+        let mut started = false;
+        while let Some(data) = chunks.next().await {
+            let delta = match output.push(&data) {
+                Ok(Some(delta)) => delta,
+                Ok(None) => continue,
+                Err(e) => {
+                    let mut response = output.response("failed");
+                    response["error"] = json!({ "code": "server_error", "message": e });
+                    yield Ok(output.event("response.failed", json!({ "response": response })));
+                    return;
+                }
+            };
+            if !started {
+                started = true;
+                let item = output.message_item("in_progress");
+                yield Ok(output.event("response.output_item.added", json!({ "output_index": 0, "item": item })));
+                let item_id = output.message_id.clone();
+                yield Ok(output.event("response.content_part.added", json!({
+                    "item_id": item_id,
+                    "output_index": 0,
+                    "content_index": 0,
+                    "part": { "type": "output_text", "text": "", "annotations": [] },
+                })));
+            }
+            let item_id = output.message_id.clone();
+            yield Ok(output.event("response.output_text.delta", json!({
+                "item_id": item_id,
+                "output_index": 0,
+                "content_index": 0,
+                "delta": delta,
+            })));
+        }
+
+        let mut output_index = 0;
+        if started {
+            let (item_id, text, part) = (output.message_id.clone(), output.text.clone(), output.text_part());
+            yield Ok(output.event("response.output_text.done", json!({
+                "item_id": item_id,
+                "output_index": 0,
+                "content_index": 0,
+                "text": text,
+            })));
+            yield Ok(output.event("response.content_part.done", json!({
+                "item_id": item_id,
+                "output_index": 0,
+                "content_index": 0,
+                "part": part,
+            })));
+            let item = output.message_item("completed");
+            yield Ok(output.event("response.output_item.done", json!({ "output_index": 0, "item": item })));
+            output_index = 1;
+        }
+        let calls: Vec<Value> = output.calls.iter().map(Output::call_item).collect();
+        for item in calls {
+            let mut added = item.clone();
+            added["status"] = json!("in_progress");
+            yield Ok(output.event("response.output_item.added", json!({ "output_index": output_index, "item": added })));
+            yield Ok(output.event("response.function_call_arguments.done", json!({
+                "item_id": item["id"],
+                "output_index": output_index,
+                "arguments": item["arguments"],
+            })));
+            yield Ok(output.event("response.output_item.done", json!({ "output_index": output_index, "item": item })));
+            output_index += 1;
+        }
+        let response = output.response("completed");
+        let kind = if output.truncated { "response.incomplete" } else { "response.completed" };
+        yield Ok(output.event(kind, json!({ "response": response })));
+    }
+}
+
+/// OpenAI Responses API on top of the chat completions pipeline.
+#[utoipa::path(
+    request_body = ResponsesRequest,
+    responses(
+        (status = OK, description = "Success", content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/responses")]
+pub async fn responses(body: web::Json<ResponsesRequest>, ctx: ChatContext) -> impl Responder {
+    let body = body.into_inner();
+    let output = Output::new(body.model.clone());
+    let stream = body.stream;
+    let chat_request = match body.into_chat_request() {
+        Ok(chat_request) => chat_request,
+        Err(message) => {
+            return HttpResponse::BadRequest().json(OpenAiError {
+                message,
+                code: "invalid_value".to_owned(),
+                r#type: "invalid_request_error".to_owned(),
+                param: Some("input".to_owned()),
+            })
+        }
+    };
+
+    let response = if crate::tools::wants_tool_calls(&chat_request) {
+        crate::tools::relay(ctx.chat(chat_request).await)
+    } else {
+        ctx.chat(chat_request).await
+    };
+    // 錯誤本來就是 OpenAI 格式
+    if !response.status().is_success() {
+        return response;
+    }
+    let chunks = sse_data(response);
+    if !stream {
+        return complete(chunks, output).await;
+    }
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .streaming(events(chunks, output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn input_items_map_to_chat() {
+        let body: ResponsesRequest = serde_json::from_value(json!({
+            "model": "qwen",
+            "instructions": "Be brief.",
+            "max_output_tokens": 64,
+            "input": [
+                { "role": "user", "content": [{ "type": "input_text", "text": "Weather?" }] },
+                { "type": "function_call", "call_id": "call_1", "name": "weather", "arguments": "{}" },
+                { "type": "function_call_output", "call_id": "call_1", "output": "22°C" }
+            ],
+            "tools": [{ "type": "function", "name": "weather", "parameters": { "type": "object" } }]
+        }))
+        .unwrap();
+        let chat = body.into_chat_request().unwrap();
+        assert!(chat.stream);
+        assert_eq!(chat.max_completion_tokens, Some(64));
+        assert_eq!(chat.messages.len(), 4);
+        assert!(
+            matches!(chat.messages[1].content, Some(Content::String(ref s)) if s == "Weather?")
+        );
+        assert_eq!(
+            chat.messages[2].tool_calls.as_ref().unwrap()[0].id,
+            "call_1"
+        );
+        assert_eq!(chat.messages[3].tool_call_id.as_deref(), Some("call_1"));
+        assert_eq!(chat.tools.unwrap()[0].function.name, "weather");
+
+        let chunk = |content: &str| {
+            let data = crate::chat::create_sse_chunk_data(
+                "chatcmpl-1",
+                1,
+                "qwen",
+                None,
+                Some(Content::String(content.to_owned())),
+            );
+            data.trim_start_matches("data: ").trim().to_owned()
+        };
+        let chunks = futures::stream::iter(vec![chunk("Hel"), chunk("lo")]);
+        let response = complete(chunks, Output::new("qwen".to_owned())).await;
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let response: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response["status"], "completed");
+        assert_eq!(response["output"][0]["content"][0]["text"], "Hello");
+    }
+}
@@ -0,0 +1,383 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chat::ChatCompletionsRequest,
+    utils::{ModelConfig, ModelType},
+    OpenAiError,
+};
+
+/// Named bundle of sampling values, picked with `preset` on a request.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct SamplingPreset {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub top_k: Option<i32>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    /// rkllm 原生的 repeat penalty，1 是關閉
+    #[serde(default)]
+    pub repeat_penalty: Option<f32>,
+}
+
+/// Presets every model has unless its config redefines them.
+pub fn builtin(name: &str) -> Option<SamplingPreset> {
+    let preset = match name {
+        "precise" => SamplingPreset {
+            temperature: Some(0.2),
+            top_p: Some(0.8),
+            top_k: Some(20),
+            ..Default::default()
+        },
+        "creative" => SamplingPreset {
+            temperature: Some(1.0),
+            top_p: Some(0.95),
+            top_k: Some(100),
+            presence_penalty: Some(0.3),
+            ..Default::default()
+        },
+        // 每次都挑機率最高的 token
+        "deterministic" => SamplingPreset {
+            temperature: Some(0.0),
+            top_p: Some(1.0),
+            top_k: Some(1),
+            ..Default::default()
+        },
+        _ => return None,
+    };
+    Some(preset)
+}
+
+pub fn preset(config: &ModelConfig, name: &str) -> Option<SamplingPreset> {
+    config
+        .sampling_presets
+        .get(name)
+        .cloned()
+        .or_else(|| builtin(name))
+}
+
+/// Fill the sampling fields the request left out from its `preset`.
+pub fn apply(
+    request: &mut ChatCompletionsRequest,
+    config: &ModelConfig,
+) -> Result<(), OpenAiError> {
+    let Some(name) = &request.preset else {
+        return Ok(());
+    };
+    let Some(preset) = preset(config, name) else {
+        let mut names: Vec<&str> = ["precise", "creative", "deterministic"]
+            .into_iter()
+            .chain(config.sampling_presets.keys().map(String::as_str))
+            .collect();
+        names.sort();
+        names.dedup();
+        return Err(OpenAiError {
+            message: format!(
+                "Unknown preset \"{}\", expected one of {}.",
+                name,
+                names.join(", ")
+            ),
+            code: "invalid_preset".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some("preset".to_owned()),
+        });
+    };
+    request.temperature = request.temperature.or(preset.temperature);
+    request.top_p = request.top_p.or(preset.top_p);
+    request.top_k = request.top_k.or(preset.top_k);
+    request.presence_penalty = request.presence_penalty.or(preset.presence_penalty);
+    request.frequency_penalty = request.frequency_penalty.or(preset.frequency_penalty);
+    request.repeat_penalty = request.repeat_penalty.or(preset.repeat_penalty);
+    Ok(())
+}
+
+/// Sampling values of a chat request, before the model defaults are filled in.
+pub fn requested(request: &ChatCompletionsRequest) -> SamplingPreset {
+    SamplingPreset {
+        temperature: request.temperature,
+        top_p: request.top_p,
+        top_k: request.top_k,
+        presence_penalty: request.presence_penalty,
+        frequency_penalty: request.frequency_penalty,
+        repeat_penalty: request.repeat_penalty,
+    }
+}
+
+fn out_of_range(param: &str, range: &str) -> OpenAiError {
+    OpenAiError {
+        message: format!("{} must be {}.", param, range),
+        code: "invalid_value".to_owned(),
+        r#type: "invalid_request_error".to_owned(),
+        param: Some(param.to_owned()),
+    }
+}
+
+/// Reject values rkllm cannot sample with, OpenAI ranges.
+pub fn validate(request: &ChatCompletionsRequest) -> Result<(), OpenAiError> {
+    if request
+        .temperature
+        .is_some_and(|t| !(0.0..=2.0).contains(&t))
+    {
+        return Err(out_of_range("temperature", "between 0 and 2"));
+    }
+    if request.top_p.is_some_and(|p| !(0.0..=1.0).contains(&p)) {
+        return Err(out_of_range("top_p", "between 0 and 1"));
+    }
+    if request.top_k.is_some_and(|k| k < 0) {
+        return Err(out_of_range("top_k", "at least 0"));
+    }
+    for (param, penalty) in [
+        ("presence_penalty", request.presence_penalty),
+        ("frequency_penalty", request.frequency_penalty),
+    ] {
+        if penalty.is_some_and(|p| !(-2.0..=2.0).contains(&p)) {
+            return Err(out_of_range(param, "between -2 and 2"));
+        }
+    }
+    if request.repeat_penalty.is_some_and(|p| p <= 0.0) {
+        return Err(out_of_range("repeat_penalty", "greater than 0"));
+    }
+    Ok(())
+}
+
+/// The first sampling parameter of `request` whose value is not the one `config` loads with.
+pub fn differing(request: &ChatCompletionsRequest, config: &ModelConfig) -> Option<&'static str> {
+    let loaded = SamplerParams::resolve(&SamplingPreset::default(), config);
+    let asked = SamplerParams::resolve(&requested(request), config);
+    let differs = [
+        ("temperature", asked.temperature != loaded.temperature),
+        ("top_p", asked.top_p != loaded.top_p),
+        ("top_k", asked.top_k != loaded.top_k),
+        (
+            "repeat_penalty",
+            asked.repeat_penalty != loaded.repeat_penalty,
+        ),
+        (
+            "presence_penalty",
+            asked.presence_penalty != loaded.presence_penalty,
+        ),
+        (
+            "frequency_penalty",
+            asked.frequency_penalty != loaded.frequency_penalty,
+        ),
+    ];
+    differs
+        .into_iter()
+        .find(|(_, differs)| *differs)
+        .map(|(param, _)| param)
+}
+
+/// Reject sampling values other than the model's when switching would re-initialize the runtime,
+/// unless the config sets `per_request_sampling`. A `seed` asks for greedy values, so it is
+/// rejected too.
+pub fn fixed(request: &ChatCompletionsRequest, config: &ModelConfig) -> Result<(), OpenAiError> {
+    if config.model_type != ModelType::LLM || config.per_request_sampling {
+        return Ok(());
+    }
+    let param = request
+        .seed
+        .map(|_| "seed")
+        .or_else(|| differing(request, config));
+    match param {
+        Some(param) => Err(OpenAiError {
+            message: format!(
+                "Model \"{}\" samples with the values of its config ({:?}), set per_request_sampling in its config to change {} per request.",
+                request.model,
+                SamplerParams::resolve(&SamplingPreset::default(), config),
+                param
+            ),
+            code: "sampling_fixed".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some(param.to_owned()),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// The values rkllm samples with. They are fixed when the runtime is initialized, a request asking
+/// for others re-initializes it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplerParams {
+    pub temperature: f32,
+    pub top_p: f32,
+    pub top_k: i32,
+    pub repeat_penalty: f32,
+    /// 跟 OpenAI 一樣的意思，rkllm 直接支援
+    pub presence_penalty: f32,
+    pub frequency_penalty: f32,
+}
+
+impl Default for SamplerParams {
+    fn default() -> Self {
+        Self {
+            temperature: 0.7,
+            top_p: 0.9,
+            top_k: 40,
+            repeat_penalty: 1.1,
+            presence_penalty: 0.0,
+            frequency_penalty: 0.0,
+        }
+    }
+}
+
+impl SamplerParams {
+    /// rkllm has no way to seed its sampler, greedy decoding is the only repeatable one. Values
+    /// the request set itself are kept.
+    pub fn seeded(self, requested: &SamplingPreset) -> Self {
+        Self {
+            temperature: requested.temperature.map_or(0.0, |_| self.temperature),
+            top_p: requested.top_p.map_or(1.0, |_| self.top_p),
+            top_k: requested.top_k.map_or(1, |_| self.top_k),
+            ..self
+        }
+    }
+
+    /// Values of the request, then of the model config, then the defaults.
+    pub fn resolve(requested: &SamplingPreset, config: &ModelConfig) -> Self {
+        let defaults = Self::default();
+        Self {
+            temperature: requested
+                .temperature
+                .or(config.sampling.temperature)
+                .unwrap_or(defaults.temperature),
+            top_p: requested
+                .top_p
+                .or(config.sampling.top_p)
+                .unwrap_or(defaults.top_p),
+            top_k: requested
+                .top_k
+                .or(config.sampling.top_k)
+                .unwrap_or(defaults.top_k),
+            repeat_penalty: requested
+                .repeat_penalty
+                .or(config.sampling.repeat_penalty)
+                .unwrap_or(defaults.repeat_penalty),
+            presence_penalty: requested
+                .presence_penalty
+                .or(config.sampling.presence_penalty)
+                .unwrap_or(defaults.presence_penalty),
+            frequency_penalty: requested
+                .frequency_penalty
+                .or(config.sampling.frequency_penalty)
+                .unwrap_or(defaults.frequency_penalty),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_values_win() {
+        let mut config = ModelConfig::default();
+        config.sampling_presets.insert(
+            "kiosk".to_owned(),
+            SamplingPreset {
+                temperature: Some(0.5),
+                top_k: Some(10),
+                ..Default::default()
+            },
+        );
+        let mut request: ChatCompletionsRequest = serde_json::from_value(serde_json::json!({
+            "model": "qwen", "messages": [], "preset": "kiosk", "top_k": 3
+        }))
+        .unwrap();
+        apply(&mut request, &config).unwrap();
+        assert_eq!(request.temperature, Some(0.5));
+        assert_eq!(request.top_k, Some(3));
+
+        request.preset = Some("deterministic".to_owned());
+        request.temperature = None;
+        apply(&mut request, &config).unwrap();
+        assert_eq!(request.temperature, Some(0.0));
+
+        request.preset = Some("wild".to_owned());
+        let error = apply(&mut request, &config).unwrap_err();
+        assert_eq!(
+            error.message,
+            "Unknown preset \"wild\", expected one of creative, deterministic, kiosk, precise."
+        );
+    }
+
+    #[test]
+    fn sampler_falls_back_to_model_defaults() {
+        let mut config = ModelConfig::default();
+        config.sampling.top_p = Some(0.5);
+        let request: ChatCompletionsRequest = serde_json::from_value(serde_json::json!({
+            "model": "qwen", "messages": [], "temperature": 0.1
+        }))
+        .unwrap();
+        let sampler = SamplerParams::resolve(&requested(&request), &config);
+        assert_eq!(
+            sampler,
+            SamplerParams {
+                temperature: 0.1,
+                top_p: 0.5,
+                ..Default::default()
+            }
+        );
+
+        let request: ChatCompletionsRequest = serde_json::from_value(serde_json::json!({
+            "model": "qwen", "messages": [], "temperature": 3.0
+        }))
+        .unwrap();
+        assert_eq!(
+            validate(&request).unwrap_err().param.as_deref(),
+            Some("temperature")
+        );
+
+        let request: ChatCompletionsRequest = serde_json::from_value(serde_json::json!({
+            "model": "qwen", "messages": [], "presence_penalty": 0.6, "repeat_penalty": 1.3
+        }))
+        .unwrap();
+        assert!(validate(&request).is_ok());
+        let sampler = SamplerParams::resolve(&requested(&request), &config);
+        assert_eq!(
+            (sampler.presence_penalty, sampler.repeat_penalty),
+            (0.6, 1.3)
+        );
+    }
+
+    #[test]
+    fn seed_keeps_explicit_values() {
+        let config = ModelConfig::default();
+        let requested = SamplingPreset {
+            temperature: Some(0.7),
+            ..Default::default()
+        };
+        let seeded = SamplerParams::resolve(&requested, &config).seeded(&requested);
+        assert_eq!(seeded.temperature, 0.7);
+        assert_eq!((seeded.top_p, seeded.top_k), (1.0, 1));
+    }
+
+    #[test]
+    fn fixed_sampling_rejects_other_values() {
+        let mut config = ModelConfig::default();
+        config.sampling.temperature = Some(0.3);
+        let request = |body: serde_json::Value| -> ChatCompletionsRequest {
+            serde_json::from_value(body).unwrap()
+        };
+        // 跟設定檔一樣的值不用重新初始化
+        let same = request(serde_json::json!({
+            "model": "qwen", "messages": [], "temperature": 0.3, "top_k": 40
+        }));
+        assert!(fixed(&same, &config).is_ok());
+        let other = request(serde_json::json!({
+            "model": "qwen", "messages": [], "top_p": 0.5
+        }));
+        assert_eq!(
+            fixed(&other, &config).unwrap_err().param.as_deref(),
+            Some("top_p")
+        );
+        let seeded = request(serde_json::json!({
+            "model": "qwen", "messages": [], "temperature": 0.3, "seed": 7
+        }));
+        assert_eq!(
+            fixed(&seeded, &config).unwrap_err().param.as_deref(),
+            Some("seed")
+        );
+        config.per_request_sampling = true;
+        assert!(fixed(&other, &config).is_ok());
+        assert!(fixed(&seeded, &config).is_ok());
+    }
+}
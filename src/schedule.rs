@@ -0,0 +1,263 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    chat::{LlmPool, Loads, ShutdownPool},
+    eviction::MemoryBudget,
+    sessions::Sessions,
+    utils::{ModelConfig, ModelType},
+    ShutdownMessages,
+};
+
+pub const SCHEDULE_PATH: &str = "assets/schedule.json";
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// Keep `model` resident between `start` and `end` (`HH:MM`, the end is exclusive). A window
+/// may cross midnight, e.g. `22:00`-`08:00`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WarmWindow {
+    /// `null` 就是這段時間卸載所有 LLM
+    pub model: Option<String>,
+    pub start: String,
+    pub end: String,
+    /// `mon`..`sun`，空的話每天
+    #[serde(default)]
+    pub days: Vec<String>,
+}
+
+/// Preload/unload schedule, loaded from `assets/schedule.json`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Schedule {
+    /// 例如 `+08:00`，預設 UTC
+    #[serde(default)]
+    pub utc_offset: Option<String>,
+    #[serde(default)]
+    pub windows: Vec<WarmWindow>,
+}
+
+fn parse_minutes(time: &str) -> Result<i64, String> {
+    let (hours, minutes) = time
+        .split_once(':')
+        .ok_or_else(|| format!("invalid time {}, expected HH:MM", time))?;
+    match (hours.parse::<i64>(), minutes.parse::<i64>()) {
+        (Ok(h), Ok(m)) if (0..24).contains(&h) && (0..60).contains(&m) => Ok(h * 60 + m),
+        _ => Err(format!("invalid time {}, expected HH:MM", time)),
+    }
+}
+
+fn parse_offset(offset: &str) -> Result<i64, String> {
+    let (sign, time) = match offset.split_at_checked(1) {
+        Some(("+", time)) => (1, time),
+        Some(("-", time)) => (-1, time),
+        _ => return Err(format!("invalid utc_offset {}, expected +HH:MM", offset)),
+    };
+    Ok(sign * parse_minutes(time)?)
+}
+
+impl WarmWindow {
+    fn contains(&self, minute: i64, weekday: usize) -> bool {
+        if !self.days.is_empty()
+            && !self
+                .days
+                .iter()
+                .any(|day| day.eq_ignore_ascii_case(DAYS[weekday]))
+        {
+            return false;
+        }
+        let (Ok(start), Ok(end)) = (parse_minutes(&self.start), parse_minutes(&self.end)) else {
+            return false;
+        };
+        if start <= end {
+            start == end || (start..end).contains(&minute)
+        } else {
+            minute >= start || minute < end
+        }
+    }
+}
+
+impl Schedule {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let schedule: Self = match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                serde_json::from_str(&contents).map_err(|e| format!("{}: {}", path, e))?
+            }
+            Err(_) => return Ok(Self::default()),
+        };
+        schedule.offset_minutes()?;
+        for window in &schedule.windows {
+            parse_minutes(&window.start)?;
+            parse_minutes(&window.end)?;
+            if let Some(day) = window
+                .days
+                .iter()
+                .find(|day| !DAYS.contains(&day.to_ascii_lowercase().as_str()))
+            {
+                return Err(format!("{}: unknown day {}", path, day));
+            }
+        }
+        Ok(schedule)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    fn offset_minutes(&self) -> Result<i64, String> {
+        self.utc_offset.as_deref().map_or(Ok(0), parse_offset)
+    }
+
+    /// The model the first matching window wants at `unix_secs`, `None` outside every window.
+    pub fn desired(&self, unix_secs: u64) -> Option<Option<&str>> {
+        let local = unix_secs as i64 / 60 + self.offset_minutes().unwrap_or(0);
+        let minute = local.rem_euclid(24 * 60);
+        // 1970-01-01 是星期四
+        let weekday = (local.div_euclid(24 * 60) + 3).rem_euclid(7) as usize;
+        self.windows
+            .iter()
+            .find(|window| window.contains(minute, weekday))
+            .map(|window| window.model.as_deref())
+    }
+
+    /// Check the schedule every minute. It only acts when the desired model changes, so a
+    /// request for another model inside a window is not undone until the next window starts.
+    /// Models are loaded like for a request, within `budget` and sharing a running load.
+    pub fn spawn(
+        self,
+        llm_pool: LlmPool,
+        shutdown_pool: ShutdownPool,
+        all_configs: HashMap<String, ModelConfig>,
+        sessions: Sessions,
+        budget: MemoryBudget,
+        loads: Loads,
+    ) {
+        if self.is_empty() {
+            return;
+        }
+        actix_web::rt::spawn(async move {
+            let mut applied: Option<Option<String>> = None;
+            let mut interval = actix_web::rt::time::interval(CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let now = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let Some(target) = self.desired(now).map(|m| m.map(str::to_owned)) else {
+                    applied = None;
+                    continue;
+                };
+                if applied.as_ref() == Some(&target) {
+                    continue;
+                }
+                // 有請求在跑就等下一分鐘
+                let busy = llm_pool
+                    .lock()
+                    .unwrap()
+                    .keys()
+                    .any(|model| sessions.active(model) > 0);
+                if busy {
+                    log::debug!("Schedule: models are busy, retry later");
+                    continue;
+                }
+                match &target {
+                    Some(model) => {
+                        warm(
+                            model,
+                            &llm_pool,
+                            &shutdown_pool,
+                            &all_configs,
+                            &budget,
+                            &loads,
+                        )
+                        .await
+                    }
+                    None => {
+                        log::info!("Schedule: unloading all models");
+                        unload_all(&llm_pool, &shutdown_pool);
+                    }
+                }
+                applied = Some(target);
+            }
+        });
+    }
+}
+
+/// Unload the LLMs, the other models in the shutdown pool are left alone.
+fn unload_all(llm_pool: &LlmPool, shutdown_pool: &ShutdownPool) {
+    let mut pool = llm_pool.lock().unwrap();
+    let mut shutdown = shutdown_pool.lock().unwrap();
+    let tasks: Vec<_> = pool
+        .drain()
+        .filter_map(|(model, _)| shutdown.remove(&model))
+        .map(|addr| async move {
+            let _ = addr.send(ShutdownMessages).await;
+        })
+        .collect();
+    actix_web::rt::spawn(futures::future::join_all(tasks));
+}
+
+async fn warm(
+    model: &str,
+    llm_pool: &LlmPool,
+    shutdown_pool: &ShutdownPool,
+    all_configs: &HashMap<String, ModelConfig>,
+    budget: &MemoryBudget,
+    loads: &Loads,
+) {
+    let Some(config) = all_configs.get(model).filter(|c| {
+        matches!(
+            c.model_type,
+            ModelType::LLM | ModelType::VLM | ModelType::Echo
+        )
+    }) else {
+        log::warn!("Schedule: {} is not a configured LLM", model);
+        return;
+    };
+    if llm_pool.lock().unwrap().contains_key(model) {
+        return;
+    }
+    log::info!("Schedule: loading {}", model);
+    let (_, load) = crate::chat::spawn_load(
+        config.clone(),
+        llm_pool,
+        shutdown_pool,
+        Some(budget),
+        all_configs,
+        Some(loads),
+    );
+    if let Err(e) = load.await {
+        log::error!("Schedule: failed to load {}: {}", model, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows() {
+        let schedule: Schedule = serde_json::from_value(serde_json::json!({
+            "utc_offset": "+08:00",
+            "windows": [
+                { "model": "big", "start": "08:00", "end": "22:00", "days": ["mon"] },
+                { "model": "small", "start": "22:00", "end": "08:00" },
+            ]
+        }))
+        .unwrap();
+        // 2024-01-01 (Mon) 00:00 UTC = 08:00 +08:00
+        let monday = 1_704_067_200;
+        assert_eq!(schedule.desired(monday), Some(Some("big")));
+        assert_eq!(schedule.desired(monday - 60), Some(Some("small")));
+        assert_eq!(schedule.desired(monday + 14 * 3600), Some(Some("small")));
+        // 星期二白天沒有符合的
+        assert_eq!(schedule.desired(monday + 24 * 3600), None);
+
+        assert_eq!(parse_offset("-05:30"), Ok(-330));
+        assert!(parse_minutes("24:00").is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Queued or running chat sessions per model name.
+#[derive(Debug, Clone, Default)]
+pub struct Sessions(Arc<Mutex<HashMap<String, usize>>>);
+
+/// One session of a model, it ends when the guard is dropped (the response stream finished or
+/// the client went away).
+#[derive(Debug)]
+pub struct SessionGuard {
+    sessions: Sessions,
+    model: String,
+}
+
+impl Sessions {
+    /// `None` when `limit` sessions of the model are already active.
+    pub fn try_start(&self, model: &str, limit: Option<usize>) -> Option<SessionGuard> {
+        let mut active = self.0.lock().unwrap();
+        let count = active.entry(model.to_owned()).or_default();
+        if limit.is_some_and(|limit| *count >= limit) {
+            return None;
+        }
+        *count += 1;
+        Some(SessionGuard {
+            sessions: self.clone(),
+            model: model.to_owned(),
+        })
+    }
+
+    pub fn active(&self, model: &str) -> usize {
+        self.0.lock().unwrap().get(model).copied().unwrap_or(0)
+    }
+
+    /// Active sessions per model, `None` if the lock is held (used from the panic hook).
+    pub fn try_snapshot(&self) -> Option<Vec<(String, usize)>> {
+        let active = self.0.try_lock().ok()?;
+        let mut snapshot: Vec<(String, usize)> = active
+            .iter()
+            .filter(|(_, count)| **count > 0)
+            .map(|(model, count)| (model.clone(), *count))
+            .collect();
+        snapshot.sort();
+        Some(snapshot)
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        let mut active = self.sessions.0.lock().unwrap();
+        if let Some(count) = active.get_mut(&self.model) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit_and_release() {
+        let sessions = Sessions::default();
+        let first = sessions.try_start("qwen", Some(2)).unwrap();
+        let _second = sessions.try_start("qwen", Some(2)).unwrap();
+        assert!(sessions.try_start("qwen", Some(2)).is_none());
+        assert!(sessions.try_start("other", Some(2)).is_some());
+
+        drop(first);
+        assert_eq!(sessions.active("qwen"), 1);
+        assert!(sessions.try_start("qwen", Some(2)).is_some());
+    }
+}
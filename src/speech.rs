@@ -0,0 +1,263 @@
+use std::{
+    collections::HashMap,
+    io::{Cursor, Write},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use actix::Recipient;
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cluster::Cluster,
+    errors::ApiError,
+    tts::simple::SimpleTTS,
+    utils::{ModelConfig, ModelType},
+    OpenAiError, ProcessSpeech, SpeechAudio,
+};
+
+pub type SpeechPool = Arc<Mutex<HashMap<String, Recipient<ProcessSpeech>>>>;
+
+/// OpenAI 的上限
+const MAX_INPUT_CHARS: usize = 4096;
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct SpeechRequest {
+    pub model: String,
+    /// Text to speak, up to 4096 characters
+    pub input: String,
+    /// OpenAI voice or piper speaker name, unknown voices use the default speaker
+    #[serde(default)]
+    pub voice: String,
+    /// `wav` (default), `pcm`, or `mp3`/`opus`/`aac`/`flac` when ffmpeg is installed
+    #[serde(default)]
+    pub response_format: Option<String>,
+    /// 0.25 to 4
+    #[serde(default)]
+    pub speed: Option<f32>,
+}
+
+fn bad_request(message: String, param: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(OpenAiError {
+        message,
+        code: "invalid_request_error".to_owned(),
+        r#type: "invalid_request_error".to_owned(),
+        param: Some(param.to_owned()),
+    })
+}
+
+fn internal_error(message: String) -> HttpResponse {
+    HttpResponse::InternalServerError().json(OpenAiError {
+        message,
+        code: "processing_error".to_owned(),
+        r#type: "internal_error".to_owned(),
+        param: None,
+    })
+}
+
+/// Content type and ffmpeg muxer of a `response_format`, `None` muxer for the ones written here.
+fn format(name: &str) -> Option<(&'static str, Option<&'static str>)> {
+    match name {
+        "wav" => Some(("audio/wav", None)),
+        "pcm" => Some(("audio/pcm", None)),
+        "mp3" => Some(("audio/mpeg", Some("mp3"))),
+        "opus" => Some(("audio/ogg", Some("opus"))),
+        "aac" => Some(("audio/aac", Some("adts"))),
+        "flac" => Some(("audio/flac", Some("flac"))),
+        _ => None,
+    }
+}
+
+fn pcm(audio: &SpeechAudio) -> Vec<u8> {
+    audio.samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+}
+
+fn wav(audio: &SpeechAudio) -> Result<Vec<u8>, hound::Error> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: audio.sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut wav = Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut wav, spec)?;
+    for sample in &audio.samples {
+        writer.write_sample(*sample)?;
+    }
+    writer.finalize()?;
+    Ok(wav.into_inner())
+}
+
+/// 沒有內建的壓縮格式交給 ffmpeg
+fn transcode(audio: &SpeechAudio, muxer: &str) -> Result<Vec<u8>, String> {
+    let mut child = Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-f", "s16le", "-ac", "1", "-ar"])
+        .arg(audio.sample_rate.to_string())
+        .args(["-i", "pipe:0", "-f", muxer, "pipe:1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("ffmpeg: {}", e))?;
+    let input = pcm(audio);
+    let mut stdin = child.stdin.take().ok_or("ffmpeg: no stdin")?;
+    // 另開執行緒寫入，避免 stdout 滿了卡住
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    let _ = writer.join();
+    if !output.status.success() {
+        return Err(format!(
+            "ffmpeg exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// 取得 TTS Actor，沒有的話就載入
+async fn get_or_load(
+    config: &ModelConfig,
+    speech_pool: &SpeechPool,
+) -> Result<Recipient<ProcessSpeech>, String> {
+    crate::backends::get_or_load::<SimpleTTS, _>(config, speech_pool).await
+}
+
+/// Speak `input` with the TTS model of `config`, loading it first if needed.
+pub(crate) async fn synthesize(
+    config: &ModelConfig,
+    speech_pool: &SpeechPool,
+    input: String,
+    voice: String,
+    speed: f32,
+) -> Result<SpeechAudio, ApiError> {
+    let recipient = get_or_load(config, speech_pool)
+        .await
+        .map_err(ApiError::internal)?;
+    let send_future = recipient.send(ProcessSpeech {
+        input,
+        voice,
+        speed,
+    });
+    match actix_web::rt::time::timeout(Duration::from_secs(120), send_future).await {
+        Ok(Ok(Ok(audio))) => Ok(audio),
+        Ok(Ok(Err(_))) => Err(ApiError::internal("Speech synthesis failed.")),
+        Ok(Err(e)) => Err(ApiError::internal(format!("Internal server error:{}", e))),
+        Err(_timeout) => Err(ApiError::busy("Server Busy.")),
+    }
+}
+
+#[utoipa::path(
+    request_body = SpeechRequest,
+    responses(
+        (status = OK, description = "Success", body = Vec<u8>, content_type = "audio/wav")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/audio/speech")]
+pub async fn audio_speech(
+    req_body: web::Json<SpeechRequest>,
+    speech_pool: web::Data<SpeechPool>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    cluster: web::Data<Cluster>,
+    req: HttpRequest,
+) -> impl Responder {
+    if !all_configs.contains_key(&req_body.model) {
+        if let Some(peer) = cluster.peer_for(&req_body.model, &req).await {
+            return cluster
+                .forward(&peer, "/v1/audio/speech", &req_body.0)
+                .await;
+        }
+    }
+    let Some(config) = all_configs
+        .get(&req_body.model)
+        .filter(|c| c.model_type == ModelType::TTS)
+    else {
+        return HttpResponse::BadRequest().json(OpenAiError {
+            message: format!(
+                "The model {} does not exist or is not a speech model.",
+                req_body.model
+            ),
+            code: "model_not_found".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some("model".to_owned()),
+        });
+    };
+
+    let format_name = req_body.response_format.as_deref().unwrap_or("wav");
+    let Some((content_type, muxer)) = format(format_name) else {
+        return bad_request(
+            format!("Unsupported response_format {}.", format_name),
+            "response_format",
+        );
+    };
+    if req_body.input.trim().is_empty() || req_body.input.chars().count() > MAX_INPUT_CHARS {
+        return bad_request(
+            format!("input should be 1 to {} characters.", MAX_INPUT_CHARS),
+            "input",
+        );
+    }
+    let speed = req_body.speed.unwrap_or(1.0);
+    if !(0.25..=4.0).contains(&speed) {
+        return bad_request("speed should be between 0.25 and 4.".to_owned(), "speed");
+    }
+
+    let audio = match synthesize(
+        config,
+        &speech_pool,
+        req_body.input.clone(),
+        req_body.voice.clone(),
+        speed,
+    )
+    .await
+    {
+        Ok(audio) => audio,
+        Err(e) => return e.response(),
+    };
+
+    // pcm 沒有 header，用這個告訴客戶端取樣率
+    let sample_rate = audio.sample_rate;
+    let body = match (format_name, muxer) {
+        ("pcm", _) => Ok(pcm(&audio)),
+        (_, Some(muxer)) => {
+            let muxer = muxer.to_owned();
+            web::block(move || transcode(&audio, &muxer))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|r| r)
+        }
+        _ => wav(&audio).map_err(|e| e.to_string()),
+    };
+    match body {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(content_type)
+            .insert_header(("X-Sample-Rate", sample_rate.to_string()))
+            .body(body),
+        Err(e) => internal_error(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_wav_and_pcm() {
+        let audio = SpeechAudio {
+            samples: vec![0, 1000, -1000],
+            sample_rate: 22050,
+        };
+        assert_eq!(pcm(&audio), vec![0, 0, 0xe8, 0x03, 0x18, 0xfc]);
+        let wav = wav(&audio).unwrap();
+        let mut reader = hound::WavReader::new(Cursor::new(wav)).unwrap();
+        assert_eq!(reader.spec().sample_rate, 22050);
+        let samples: Vec<i16> = reader.samples().map(|s| s.unwrap()).collect();
+        assert_eq!(samples, audio.samples);
+        assert_eq!(format("mp3"), Some(("audio/mpeg", Some("mp3"))));
+        assert_eq!(format("ogg"), None);
+    }
+}
@@ -0,0 +1,104 @@
+use crate::chat::Stop;
+
+/// `stop` of a request as a list, empty strings dropped.
+pub fn sequences(stop: Option<&Stop>) -> Vec<String> {
+    let stops = match stop {
+        Some(Stop::String(stop)) => vec![stop.clone()],
+        Some(Stop::Array(stops)) => stops.clone(),
+        None => vec![],
+    };
+    stops.into_iter().filter(|stop| !stop.is_empty()).collect()
+}
+
+/// Cuts the generated text at the first stop sequence. Text that could be the start of a stop
+/// sequence is held back until the next token decides.
+#[derive(Debug, Clone, Default)]
+pub struct StopSequences {
+    stops: Vec<String>,
+    pending: String,
+    stopped: bool,
+}
+
+impl StopSequences {
+    pub fn new(stops: Vec<String>) -> Self {
+        Self {
+            stops,
+            ..Default::default()
+        }
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// The text that can be sent for the next token, and whether generation should stop.
+    pub fn push(&mut self, text: &str) -> (String, bool) {
+        if self.stopped {
+            return (String::new(), true);
+        }
+        if self.stops.is_empty() {
+            return (text.to_owned(), false);
+        }
+        self.pending.push_str(text);
+        if let Some(at) = self
+            .stops
+            .iter()
+            .filter_map(|stop| self.pending.find(stop.as_str()))
+            .min()
+        {
+            self.stopped = true;
+            let mut text = std::mem::take(&mut self.pending);
+            text.truncate(at);
+            return (text, true);
+        }
+        let held = self
+            .stops
+            .iter()
+            .filter_map(|stop| {
+                (1..stop.len())
+                    .rev()
+                    .filter(|len| stop.is_char_boundary(*len))
+                    .find(|len| self.pending.ends_with(&stop[..*len]))
+            })
+            .max()
+            .unwrap_or(0);
+        let rest = self.pending.split_off(self.pending.len() - held);
+        (std::mem::replace(&mut self.pending, rest), false)
+    }
+
+    /// Drop the rest of the generation, e.g. after `max_tokens`.
+    pub fn halt(&mut self) {
+        self.pending.clear();
+        self.stopped = true;
+    }
+
+    /// What was held back when the model finished on its own.
+    pub fn flush(&mut self) -> String {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cuts_at_first_stop() {
+        let mut stop = StopSequences::new(sequences(Some(&Stop::Array(vec![
+            "\nUser:".to_owned(),
+            "END".to_owned(),
+            String::new(),
+        ]))));
+        assert_eq!(stop.push("Hello"), ("Hello".to_owned(), false));
+        assert_eq!(stop.push(" world\nUs"), (" world".to_owned(), false));
+        assert_eq!(stop.push("ually"), ("\nUsually".to_owned(), false));
+        assert_eq!(stop.push(" E"), (" ".to_owned(), false));
+        assert_eq!(stop.push("ND more"), (String::new(), true));
+        assert!(stop.stopped());
+        assert_eq!(stop.push("ignored"), (String::new(), true));
+
+        let mut stop = StopSequences::new(vec![]);
+        assert_eq!(stop.push("E"), ("E".to_owned(), false));
+        assert_eq!(stop.flush(), "");
+    }
+}
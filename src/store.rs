@@ -0,0 +1,806 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use actix_web::{delete, get, http::header, web, HttpRequest, HttpResponse, Responder};
+use futures::StreamExt;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    chat::ChatCompletionsRequest,
+    ws::{chunk_text, take_sse_events},
+    Content, Message, OpenAiError, Role,
+};
+
+const SCHEMA: &str = "
+PRAGMA journal_mode = WAL;
+CREATE TABLE IF NOT EXISTS completions (
+    id TEXT PRIMARY KEY,
+    created INTEGER NOT NULL,
+    model TEXT NOT NULL,
+    user TEXT,
+    messages TEXT NOT NULL,
+    content TEXT NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL,
+    metadata TEXT,
+    api_key TEXT,
+    client_ip TEXT
+);
+CREATE INDEX IF NOT EXISTS completions_user ON completions (user, created);
+";
+
+/// 舊的資料庫沒有後來加的欄位
+const MIGRATIONS: [&str; 2] = [
+    "ALTER TABLE completions ADD COLUMN api_key TEXT",
+    "ALTER TABLE completions ADD COLUMN client_ip TEXT",
+];
+
+/// One answered chat completion.
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub id: String,
+    pub created: i64,
+    pub model: String,
+    pub user: Option<String>,
+    pub messages: Vec<Message>,
+    pub content: String,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub metadata: Option<HashMap<String, String>>,
+    /// Masked key of the caller, see [`api_key_id`]
+    pub api_key: Option<String>,
+    /// Address of the caller behind any trusted proxy
+    pub client_ip: Option<String>,
+}
+
+impl Completion {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        let messages: String = row.get(4)?;
+        let metadata: Option<String> = row.get(8)?;
+        Ok(Self {
+            id: row.get(0)?,
+            created: row.get(1)?,
+            model: row.get(2)?,
+            user: row.get(3)?,
+            messages: serde_json::from_str(&messages).unwrap_or_default(),
+            content: row.get(5)?,
+            prompt_tokens: row.get(6)?,
+            completion_tokens: row.get(7)?,
+            metadata: metadata.and_then(|m| serde_json::from_str(&m).ok()),
+            api_key: row.get(9)?,
+            client_ip: row.get(10)?,
+        })
+    }
+
+    /// Same shape as a non-streamed chat completions response.
+    fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "object": "chat.completion",
+            "created": self.created,
+            "model": self.model,
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": self.content },
+                "finish_reason": "stop",
+            }],
+            "usage": {
+                "prompt_tokens": self.prompt_tokens,
+                "completion_tokens": self.completion_tokens,
+                "total_tokens": self.prompt_tokens + self.completion_tokens,
+            },
+            "metadata": self.metadata,
+        })
+    }
+}
+
+const COLUMNS: &str =
+    "id, created, model, user, messages, content, prompt_tokens, completion_tokens, metadata, api_key, client_ip";
+
+/// Usage of one API key and model on one (UTC) day.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UsageRow {
+    pub day: String,
+    pub api_key: Option<String>,
+    pub model: String,
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+}
+
+/// SQLite conversation log, 每次寫入都馬上落地，當機重開也不會丟失對話
+#[derive(Clone)]
+pub struct Store {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Store {
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        for migration in MIGRATIONS {
+            // 欄位已經存在時會失敗，忽略即可
+            let _ = conn.execute(migration, []);
+        }
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    pub fn insert(&self, completion: &Completion) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            &format!(
+                "INSERT OR REPLACE INTO completions ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                COLUMNS
+            ),
+            params![
+                completion.id,
+                completion.created,
+                completion.model,
+                completion.user,
+                serde_json::to_string(&completion.messages).unwrap_or_default(),
+                completion.content,
+                completion.prompt_tokens,
+                completion.completion_tokens,
+                completion
+                    .metadata
+                    .as_ref()
+                    .map(|m| serde_json::to_string(m).unwrap_or_default()),
+                completion.api_key,
+                completion.client_ip,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> rusqlite::Result<Option<Completion>> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                &format!("SELECT {} FROM completions WHERE id = ?1", COLUMNS),
+                [id],
+                Completion::from_row,
+            )
+            .optional()
+    }
+
+    pub fn delete(&self, id: &str) -> rusqlite::Result<bool> {
+        let deleted = self
+            .conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM completions WHERE id = ?1", [id])?;
+        Ok(deleted > 0)
+    }
+
+    /// Newest first. `after` is the id of the last completion of the previous page.
+    pub fn list(
+        &self,
+        model: Option<&str>,
+        after: Option<&str>,
+        limit: usize,
+    ) -> rusqlite::Result<Vec<Completion>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM completions
+             WHERE (?1 IS NULL OR model = ?1)
+               AND (?2 IS NULL OR created < (SELECT created FROM completions WHERE id = ?2)
+                    OR (created = (SELECT created FROM completions WHERE id = ?2) AND id < ?2))
+             ORDER BY created DESC, id DESC LIMIT ?3",
+            COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![model, after, limit as i64], Completion::from_row)?;
+        rows.collect()
+    }
+
+    /// The last `limit` turns of `user`, oldest first.
+    pub fn history(&self, user: &str, limit: usize) -> rusqlite::Result<Vec<Completion>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM completions WHERE user = ?1 ORDER BY created DESC, id DESC LIMIT ?2",
+            COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![user, limit as i64], Completion::from_row)?;
+        let mut turns = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        turns.reverse();
+        Ok(turns)
+    }
+
+    /// (model, requests, prompt_tokens, completion_tokens) per model, only counting completions
+    /// whose metadata has all `tags`.
+    pub fn usage(
+        &self,
+        tags: &[(String, String)],
+    ) -> rusqlite::Result<Vec<(String, i64, i64, i64)>> {
+        let mut sql = "SELECT model, COUNT(*), SUM(prompt_tokens), SUM(completion_tokens)
+             FROM completions"
+            .to_owned();
+        let mut values = Vec::new();
+        for (i, (key, value)) in tags.iter().enumerate() {
+            sql.push_str(if i == 0 { " WHERE " } else { " AND " });
+            // 用 json_each 比對，key 不用拼進 JSON path
+            sql.push_str(&format!(
+                "EXISTS (SELECT 1 FROM json_each(metadata) WHERE key = ?{} AND value = ?{})",
+                2 * i + 1,
+                2 * i + 2
+            ));
+            values.push(key);
+            values.push(value);
+        }
+        sql.push_str(" GROUP BY model ORDER BY model");
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(values), |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?;
+        rows.collect()
+    }
+
+    /// Usage per day, API key and model between the `start` and `end` days (`YYYY-MM-DD`,
+    /// inclusive).
+    pub fn usage_report(
+        &self,
+        start: Option<&str>,
+        end: Option<&str>,
+    ) -> rusqlite::Result<Vec<UsageRow>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT date(created, 'unixepoch') AS day, api_key, model,
+                    COUNT(*), SUM(prompt_tokens), SUM(completion_tokens)
+             FROM completions
+             WHERE (?1 IS NULL OR created >= CAST(strftime('%s', ?1) AS INTEGER))
+               AND (?2 IS NULL OR created < CAST(strftime('%s', ?2, '+1 day') AS INTEGER))
+             GROUP BY day, api_key, model ORDER BY day, api_key, model",
+        )?;
+        let rows = stmt.query_map(params![start, end], |row| {
+            Ok(UsageRow {
+                day: row.get(0)?,
+                api_key: row.get(1)?,
+                model: row.get(2)?,
+                requests: row.get(3)?,
+                prompt_tokens: row.get(4)?,
+                completion_tokens: row.get(5)?,
+            })
+        })?;
+        rows.collect()
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+pub fn usage_csv(rows: &[UsageRow]) -> String {
+    let mut csv =
+        "day,api_key,model,requests,prompt_tokens,completion_tokens,total_tokens\n".to_owned();
+    for row in rows {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{}\n",
+            row.day,
+            csv_field(row.api_key.as_deref().unwrap_or_default()),
+            csv_field(&row.model),
+            row.requests,
+            row.prompt_tokens,
+            row.completion_tokens,
+            row.prompt_tokens + row.completion_tokens
+        ));
+    }
+    csv
+}
+
+/// `YYYY-MM-DD`
+pub fn is_day(day: &str) -> bool {
+    day.len() == 10
+        && day.char_indices().all(|(i, c)| {
+            if i == 4 || i == 7 {
+                c == '-'
+            } else {
+                c.is_ascii_digit()
+            }
+        })
+}
+
+/// Bearer token or `x-api-key` of the request, masked like `sk-...abcd` so the database never
+/// holds the secret.
+pub fn api_key_id(req: &HttpRequest) -> Option<String> {
+    let headers = req.headers();
+    let key = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get("x-api-key").and_then(|v| v.to_str().ok()))?
+        .trim();
+    let chars: Vec<char> = key.chars().collect();
+    if chars.is_empty() {
+        return None;
+    }
+    // 太短的 key 只留最後兩碼
+    let (head, tail) = if chars.len() >= 12 {
+        (3, 4)
+    } else {
+        (0, 2.min(chars.len()))
+    };
+    Some(format!(
+        "{}...{}",
+        chars[..head].iter().collect::<String>(),
+        chars[chars.len() - tail..].iter().collect::<String>()
+    ))
+}
+
+/// `metadata[key]=value` query parameters as sorted `(key, value)` pairs.
+pub fn metadata_filters(query: &HashMap<String, String>) -> Vec<(String, String)> {
+    let mut tags: Vec<(String, String)> = query
+        .iter()
+        .filter_map(|(name, value)| {
+            let key = name.strip_prefix("metadata[")?.strip_suffix(']')?;
+            Some((key.to_owned(), value.clone()))
+        })
+        .collect();
+    tags.sort();
+    tags
+}
+
+pub(crate) fn from_request(req: &HttpRequest) -> Option<web::Data<Store>> {
+    req.app_data::<web::Data<Store>>().cloned()
+}
+
+/// Pass the SSE response through untouched and store the answer once it is complete.
+pub(crate) fn record(
+    response: HttpResponse,
+    store: web::Data<Store>,
+    request: ChatCompletionsRequest,
+    api_key: Option<String>,
+    client_ip: Option<String>,
+) -> HttpResponse {
+    if !response.status().is_success() {
+        return response;
+    }
+    let mut builder = HttpResponse::build(response.status());
+    for (name, value) in response.headers() {
+        builder.insert_header((name.clone(), value.clone()));
+    }
+    let mut body = Box::pin(response.into_body());
+    let created = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+
+    let stream = async_stream::stream! {
+        let mut buf = Vec::new();
+        let mut id = None;
+        let mut model = request.model.clone();
+        let mut content = String::new();
+        let mut prompt_tokens = 0;
+        let mut completion_tokens = 0;
+        while let Some(chunk) = futures::future::poll_fn(|cx| {
+            actix_web::body::MessageBody::poll_next(body.as_mut(), cx)
+        })
+        .await
+        {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    yield Err(actix_web::error::ErrorInternalServerError(e.to_string()));
+                    return;
+                }
+            };
+            buf.extend_from_slice(&chunk);
+            for data in take_sse_events(&mut buf) {
+                let Ok(parsed) = serde_json::from_str::<Value>(&data) else {
+                    continue;
+                };
+                if let (None, Some(chunk_id)) = (&id, parsed["id"].as_str()) {
+                    id = Some(chunk_id.to_owned());
+                }
+                if let Some(chunk_model) = parsed["model"].as_str() {
+                    model = chunk_model.to_owned();
+                }
+                if let Ok(Some(delta)) = chunk_text(&data) {
+                    content.push_str(&delta);
+                    completion_tokens += 1;
+                }
+                // 有 usage 就以模型算的為準
+                if let Some(usage) = crate::usage::from_chunk(&data) {
+                    prompt_tokens = usage.prompt_tokens as i64;
+                    completion_tokens = usage.completion_tokens as i64;
+                }
+            }
+            yield Ok(chunk);
+        }
+
+        if let Some(id) = id {
+            let completion = Completion {
+                id,
+                created,
+                model,
+                user: request.user.clone(),
+                messages: request.messages.clone(),
+                content,
+                prompt_tokens,
+                completion_tokens,
+                metadata: request.metadata.clone(),
+                api_key,
+                client_ip,
+            };
+            if let Err(e) = store.insert(&completion) {
+                log::error!("Failed to store completion {}: {}", completion.id, e);
+            }
+        }
+    };
+    builder.streaming(stream)
+}
+
+fn store_error(e: rusqlite::Error) -> HttpResponse {
+    HttpResponse::InternalServerError().json(OpenAiError {
+        message: format!("Storage error: {}", e),
+        code: "storage_error".to_owned(),
+        r#type: "internal_error".to_owned(),
+        param: None,
+    })
+}
+
+fn not_found(id: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(OpenAiError {
+        message: format!("No chat completion found with id {}", id),
+        code: "not_found".to_owned(),
+        r#type: "invalid_request_error".to_owned(),
+        param: None,
+    })
+}
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+pub struct ListQuery {
+    pub model: Option<String>,
+    pub after: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// List stored chat completions, newest first.
+#[utoipa::path(
+    params(ListQuery),
+    responses(
+        (status = OK, description = "Success", content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/chat/completions")]
+pub async fn list_completions(
+    query: web::Query<ListQuery>,
+    store: web::Data<Store>,
+) -> impl Responder {
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    // 多拿一筆判斷 has_more
+    let mut completions =
+        match store.list(query.model.as_deref(), query.after.as_deref(), limit + 1) {
+            Ok(completions) => completions,
+            Err(e) => return store_error(e),
+        };
+    let has_more = completions.len() > limit;
+    completions.truncate(limit);
+    HttpResponse::Ok().json(json!({
+        "object": "list",
+        "data": completions.iter().map(Completion::to_json).collect::<Vec<_>>(),
+        "first_id": completions.first().map(|c| &c.id),
+        "last_id": completions.last().map(|c| &c.id),
+        "has_more": has_more,
+    }))
+}
+
+/// Retrieve a stored chat completion.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/chat/completions/{id}")]
+pub async fn get_completion(id: web::Path<String>, store: web::Data<Store>) -> impl Responder {
+    match store.get(&id) {
+        Ok(Some(completion)) => HttpResponse::Ok().json(completion.to_json()),
+        Ok(None) => not_found(&id),
+        Err(e) => store_error(e),
+    }
+}
+
+/// Messages of a stored chat completion request.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/chat/completions/{id}/messages")]
+pub async fn get_completion_messages(
+    id: web::Path<String>,
+    store: web::Data<Store>,
+) -> impl Responder {
+    match store.get(&id) {
+        Ok(Some(completion)) => HttpResponse::Ok().json(json!({
+            "object": "list",
+            "data": completion.messages,
+        })),
+        Ok(None) => not_found(&id),
+        Err(e) => store_error(e),
+    }
+}
+
+/// Delete a stored chat completion.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[delete("/chat/completions/{id}")]
+pub async fn delete_completion(id: web::Path<String>, store: web::Data<Store>) -> impl Responder {
+    match store.delete(&id) {
+        Ok(true) => HttpResponse::Ok().json(json!({
+            "object": "chat.completion.deleted",
+            "id": id.as_str(),
+            "deleted": true,
+        })),
+        Ok(false) => not_found(&id),
+        Err(e) => store_error(e),
+    }
+}
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+pub struct HistoryQuery {
+    pub limit: Option<usize>,
+}
+
+/// Dialog history of a `user`, ready to be sent back as `messages` after a restart.
+#[utoipa::path(
+    params(HistoryQuery),
+    responses(
+        (status = OK, description = "Success", content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/chat/history/{user}")]
+pub async fn history(
+    user: web::Path<String>,
+    query: web::Query<HistoryQuery>,
+    store: web::Data<Store>,
+) -> impl Responder {
+    let turns = match store.history(&user, query.limit.unwrap_or(10)) {
+        Ok(turns) => turns,
+        Err(e) => return store_error(e),
+    };
+    // 每一輪取最後一則使用者訊息跟回答
+    let mut messages = Vec::new();
+    for turn in turns {
+        let last_user = turn
+            .messages
+            .iter()
+            .rev()
+            .find(|m| matches!(m.role, Some(Role::User)))
+            .cloned();
+        messages.extend(last_user);
+        messages.push(Message {
+            role: Some(Role::Assistant),
+            content: Some(Content::String(turn.content)),
+            ..Default::default()
+        });
+    }
+    HttpResponse::Ok().json(json!({ "object": "list", "data": messages }))
+}
+
+/// Requests and tokens per model, filtered by `metadata[key]=value` tags.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/usage")]
+pub async fn usage(
+    query: web::Query<HashMap<String, String>>,
+    store: web::Data<Store>,
+) -> impl Responder {
+    let usage = match store.usage(&metadata_filters(&query)) {
+        Ok(usage) => usage,
+        Err(e) => return store_error(e),
+    };
+    HttpResponse::Ok().json(json!({
+        "object": "list",
+        "data": usage
+            .into_iter()
+            .map(|(model, requests, prompt_tokens, completion_tokens)| json!({
+                "model": model,
+                "requests": requests,
+                "prompt_tokens": prompt_tokens,
+                "completion_tokens": completion_tokens,
+            }))
+            .collect::<Vec<_>>(),
+    }))
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+pub struct UsageExportQuery {
+    /// First day, `YYYY-MM-DD` (UTC)
+    pub start: Option<String>,
+    /// Last day, `YYYY-MM-DD` (UTC)
+    pub end: Option<String>,
+    #[serde(default)]
+    #[param(inline)]
+    pub format: ExportFormat,
+}
+
+/// Requests and tokens per day, API key and model over a period, for chargeback.
+#[utoipa::path(
+    params(UsageExportQuery),
+    responses(
+        (status = OK, description = "Success", content_type = "text/csv")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/usage/export")]
+pub async fn usage_export(
+    query: web::Query<UsageExportQuery>,
+    store: web::Data<Store>,
+) -> impl Responder {
+    for (param, day) in [("start", &query.start), ("end", &query.end)] {
+        if day.as_deref().is_some_and(|day| !is_day(day)) {
+            return HttpResponse::BadRequest().json(OpenAiError {
+                message: format!("{} must be a YYYY-MM-DD date.", param),
+                code: "invalid_request_error".to_owned(),
+                r#type: "invalid_request_error".to_owned(),
+                param: Some(param.to_owned()),
+            });
+        }
+    }
+    let rows = match store.usage_report(query.start.as_deref(), query.end.as_deref()) {
+        Ok(rows) => rows,
+        Err(e) => return store_error(e),
+    };
+    match query.format {
+        ExportFormat::Csv => HttpResponse::Ok()
+            .content_type("text/csv")
+            .insert_header((
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"usage.csv\"",
+            ))
+            .body(usage_csv(&rows)),
+        ExportFormat::Json => HttpResponse::Ok().json(json!({ "object": "list", "data": rows })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completion(id: &str, created: i64, user: &str) -> Completion {
+        Completion {
+            id: id.to_owned(),
+            created,
+            model: "qwen".to_owned(),
+            user: Some(user.to_owned()),
+            messages: vec![Message {
+                role: Some(Role::User),
+                content: Some(Content::String(format!("question {}", id))),
+                ..Default::default()
+            }],
+            content: format!("answer {}", id),
+            prompt_tokens: 3,
+            completion_tokens: 5,
+            metadata: None,
+            api_key: None,
+            client_ip: None,
+        }
+    }
+
+    #[test]
+    fn store_round_trip() {
+        let store = Store::open(":memory:").unwrap();
+        store.insert(&completion("a", 1, "speaker")).unwrap();
+        store.insert(&completion("b", 2, "speaker")).unwrap();
+        store.insert(&completion("c", 3, "other")).unwrap();
+
+        assert_eq!(store.get("a").unwrap().unwrap().content, "answer a");
+        let page: Vec<String> = store
+            .list(None, Some("c"), 10)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.id)
+            .collect();
+        assert_eq!(page, ["b", "a"]);
+        let history: Vec<String> = store
+            .history("speaker", 10)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.id)
+            .collect();
+        assert_eq!(history, ["a", "b"]);
+        assert_eq!(store.usage(&[]).unwrap(), [("qwen".to_owned(), 3, 9, 15)]);
+        assert!(store.delete("a").unwrap());
+        assert!(store.get("a").unwrap().is_none());
+    }
+
+    #[test]
+    fn usage_by_metadata() {
+        let store = Store::open(":memory:").unwrap();
+        let mut kitchen = completion("a", 1, "speaker");
+        kitchen.metadata = Some(HashMap::from([(
+            "app".to_owned(),
+            "kitchen-display".to_owned(),
+        )]));
+        store.insert(&kitchen).unwrap();
+        store.insert(&completion("b", 2, "speaker")).unwrap();
+
+        let query = HashMap::from([
+            ("metadata[app]".to_owned(), "kitchen-display".to_owned()),
+            ("limit".to_owned(), "10".to_owned()),
+        ]);
+        let tags = metadata_filters(&query);
+        assert_eq!(tags, [("app".to_owned(), "kitchen-display".to_owned())]);
+        assert_eq!(store.usage(&tags).unwrap(), [("qwen".to_owned(), 1, 3, 5)]);
+        assert!(store
+            .usage(&[("app".to_owned(), "tv".to_owned())])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn usage_export_per_key_and_day() {
+        let store = Store::open(":memory:").unwrap();
+        let day = 1767225600; // 2026-01-01
+        for (id, created, key) in [
+            ("a", day, Some("sk-...abcd")),
+            ("b", day + 60, Some("sk-...abcd")),
+            ("c", day + 86400, None),
+        ] {
+            let mut c = completion(id, created, "speaker");
+            c.api_key = key.map(str::to_owned);
+            store.insert(&c).unwrap();
+        }
+        let rows = store
+            .usage_report(Some("2026-01-01"), Some("2026-01-01"))
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].requests, 2);
+        assert_eq!(
+            usage_csv(&rows),
+            "day,api_key,model,requests,prompt_tokens,completion_tokens,total_tokens\n\
+             2026-01-01,sk-...abcd,qwen,2,6,10,16\n"
+        );
+        assert_eq!(store.usage_report(None, None).unwrap().len(), 2);
+        assert!(is_day("2026-01-31") && !is_day("2026-1-31"));
+
+        let req = actix_web::test::TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "Bearer sk-1234567890abcd"))
+            .to_http_request();
+        assert_eq!(api_key_id(&req).as_deref(), Some("sk-...abcd"));
+    }
+}
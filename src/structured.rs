@@ -0,0 +1,379 @@
+use actix_web::{http::header, HttpResponse};
+use serde_json::Value;
+
+use crate::{
+    chat::{collect, ChatCompletionsRequest, ChatCompletionsResponse},
+    errors::ApiError,
+    ws::ChatContext,
+    Content, Message, Role,
+};
+
+const JSON_INSTRUCTION: &str = "Respond only with a single valid JSON object. Do not wrap it in markdown code fences and do not add any text before or after it.";
+/// 含第一次，模型通常重問一次就會修好
+const MAX_ATTEMPTS: usize = 3;
+
+/// `response_format` is `json_object` or `json_schema`.
+pub fn json_mode(request: &ChatCompletionsRequest) -> bool {
+    request
+        .response_format
+        .as_ref()
+        .is_some_and(|format| matches!(format.r#type.as_str(), "json_object" | "json_schema"))
+}
+
+/// Schema of `response_format: {"type": "json_schema", ...}`.
+pub fn schema(request: &ChatCompletionsRequest) -> Option<&Value> {
+    let format = request.response_format.as_ref()?;
+    if format.r#type != "json_schema" {
+        return None;
+    }
+    format.json_schema.as_ref()?.schema.as_ref()
+}
+
+/// System prompt lines asking the model for JSON only.
+pub fn instruction(request: &ChatCompletionsRequest) -> Option<String> {
+    if !json_mode(request) {
+        return None;
+    }
+    let mut instruction = JSON_INSTRUCTION.to_owned();
+    if let Some(schema) = schema(request) {
+        instruction.push_str("\nThe object must conform to this JSON schema:\n");
+        instruction.push_str(&schema.to_string());
+    }
+    Some(instruction)
+}
+
+fn type_matches(value: &Value, name: &str) -> bool {
+    match name {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+/// `$ref` inside the same document, like `#/$defs/address`.
+fn resolve<'a>(schema: &'a Value, root: &'a Value) -> &'a Value {
+    match schema["$ref"].as_str().and_then(|r| r.strip_prefix('#')) {
+        Some(pointer) => root.pointer(pointer).unwrap_or(schema),
+        None => schema,
+    }
+}
+
+/// Check `value` against the subset of JSON schema structured outputs use. The error names the
+/// offending path, e.g. `$.tags[1]`.
+pub fn validate(value: &Value, schema: &Value) -> Result<(), String> {
+    check(value, schema, schema, "$")
+}
+
+fn check(value: &Value, schema: &Value, root: &Value, path: &str) -> Result<(), String> {
+    let schema = resolve(schema, root);
+    let types: Vec<&str> = match &schema["type"] {
+        Value::String(name) => vec![name.as_str()],
+        Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    };
+    if !types.is_empty() && !types.iter().any(|name| type_matches(value, name)) {
+        return Err(format!("{} must be of type {}", path, types.join(" or ")));
+    }
+    if let Some(options) = schema["enum"].as_array() {
+        if !options.contains(value) {
+            return Err(format!(
+                "{} must be one of {}",
+                path,
+                Value::from(options.clone())
+            ));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if value != expected {
+            return Err(format!("{} must be {}", path, expected));
+        }
+    }
+    if let Some(options) = schema["anyOf"].as_array().or(schema["oneOf"].as_array()) {
+        if !options
+            .iter()
+            .any(|option| check(value, option, root, path).is_ok())
+        {
+            return Err(format!("{} does not match any allowed schema", path));
+        }
+    }
+    match value {
+        Value::Object(object) => {
+            let properties = schema["properties"].as_object();
+            for name in schema["required"].as_array().into_iter().flatten() {
+                if let Some(name) = name.as_str().filter(|name| !object.contains_key(*name)) {
+                    return Err(format!(
+                        "{} is missing required property \"{}\"",
+                        path, name
+                    ));
+                }
+            }
+            for (name, item) in object {
+                let path = format!("{}.{}", path, name);
+                match (
+                    properties.and_then(|p| p.get(name)),
+                    &schema["additionalProperties"],
+                ) {
+                    (Some(property), _) => check(item, property, root, &path)?,
+                    (None, Value::Bool(false)) => {
+                        return Err(format!("{} is not an allowed property", path))
+                    }
+                    (None, additional @ Value::Object(_)) => check(item, additional, root, &path)?,
+                    (None, _) => {}
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(min) = schema["minItems"]
+                .as_u64()
+                .filter(|min| (items.len() as u64) < *min)
+            {
+                return Err(format!("{} must have at least {} items", path, min));
+            }
+            if let Some(max) = schema["maxItems"]
+                .as_u64()
+                .filter(|max| items.len() as u64 > *max)
+            {
+                return Err(format!("{} must have at most {} items", path, max));
+            }
+            if schema["items"].is_object() {
+                for (i, item) in items.iter().enumerate() {
+                    check(item, &schema["items"], root, &format!("{}[{}]", path, i))?;
+                }
+            }
+        }
+        Value::String(text) => {
+            let len = text.chars().count() as u64;
+            if schema["minLength"].as_u64().is_some_and(|min| len < min) {
+                return Err(format!("{} is too short", path));
+            }
+            if schema["maxLength"].as_u64().is_some_and(|max| len > max) {
+                return Err(format!("{} is too long", path));
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            if let Some(min) = schema["minimum"].as_f64().filter(|min| number < *min) {
+                return Err(format!("{} must be at least {}", path, min));
+            }
+            if let Some(max) = schema["maximum"].as_f64().filter(|max| number > *max) {
+                return Err(format!("{} must be at most {}", path, max));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Close the strings, objects and arrays a cut off answer left open.
+fn close(text: &str) -> String {
+    let mut open = Vec::new();
+    let (mut in_string, mut escaped) = (false, false);
+    for c in text.chars() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => open.push('}'),
+            '[' => open.push(']'),
+            '}' | ']' => {
+                open.pop();
+            }
+            _ => {}
+        }
+    }
+    let mut closed = text.to_owned();
+    if in_string {
+        closed.push('"');
+    }
+    for c in open.into_iter().rev() {
+        let trimmed = closed.trim_end().trim_end_matches(',').len();
+        closed.truncate(trimmed);
+        closed.push(c);
+    }
+    closed
+}
+
+/// The JSON object in a model answer: code fences, reasoning and chatter around it are dropped,
+/// a truncated object is closed. `None` when there is nothing to save.
+pub fn repair(text: &str) -> Option<String> {
+    let text = text.rsplit("</think>").next().unwrap_or(text).trim();
+    if serde_json::from_str::<Value>(text).is_ok_and(|v| v.is_object()) {
+        return Some(text.to_owned());
+    }
+    let start = text.find('{')?;
+    let candidates = [
+        text.rfind('}')
+            .filter(|end| *end > start)
+            .map(|end| text[start..=end].to_owned()),
+        Some(close(&text[start..])),
+    ];
+    candidates.into_iter().flatten().find_map(|candidate| {
+        serde_json::from_str::<Value>(&candidate)
+            .ok()
+            .filter(Value::is_object)
+            .map(|value| value.to_string())
+    })
+}
+
+async fn read(
+    response: HttpResponse,
+) -> Result<(HttpResponse, ChatCompletionsResponse), HttpResponse> {
+    if !response.status().is_success() {
+        return Err(response);
+    }
+    let mut builder = HttpResponse::build(response.status());
+    for (name, value) in response.headers() {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            builder.insert_header((name.clone(), value.clone()));
+        }
+    }
+    let completion = match actix_web::body::to_bytes(response.into_body()).await {
+        Ok(body) => serde_json::from_slice::<ChatCompletionsResponse>(&body).ok(),
+        Err(_) => None,
+    };
+    match completion {
+        Some(completion) => Ok((builder.finish(), completion)),
+        None => Err(ApiError::internal("Failed to read the model answer.").response()),
+    }
+}
+
+/// Repair the answer and check it against `schema`, the text of the answer on failure.
+fn conform(
+    completion: &mut ChatCompletionsResponse,
+    schema: Option<&Value>,
+) -> Result<(), (String, String)> {
+    for message in completion
+        .choices
+        .iter_mut()
+        .filter_map(|c| c.message.as_mut())
+    {
+        // 工具呼叫沒有內容
+        let Some(content) = &message.content else {
+            continue;
+        };
+        let text = content.text();
+        let Some(json) = repair(&text) else {
+            return Err((text, "the answer is not a JSON object".to_owned()));
+        };
+        if let Some(schema) = schema {
+            let value: Value = serde_json::from_str(&json).unwrap_or_default();
+            validate(&value, schema).map_err(|problem| (json.clone(), problem))?;
+        }
+        message.content = Some(Content::String(json));
+    }
+    Ok(())
+}
+
+/// Make the message of a non-stream completion valid JSON, or fail the request. With a
+/// `json_schema` the model is asked to fix a non-conforming answer, `retry` is the context
+/// and request to ask again with.
+pub(crate) async fn enforce(
+    response: HttpResponse,
+    retry: Option<(ChatContext, ChatCompletionsRequest)>,
+) -> HttpResponse {
+    let (mut head, mut completion) = match read(response).await {
+        Ok(answer) => answer,
+        Err(response) => return response,
+    };
+    let mut retry = retry.filter(|(_, body)| schema(body).is_some());
+    let mut attempt = 1;
+    loop {
+        let schema = retry.as_ref().and_then(|(_, body)| schema(body)).cloned();
+        let (text, problem) = match conform(&mut completion, schema.as_ref()) {
+            Ok(()) => {
+                let mut builder = HttpResponse::build(head.status());
+                for (name, value) in head.headers() {
+                    builder.insert_header((name.clone(), value.clone()));
+                }
+                return builder.json(completion);
+            }
+            Err(failure) => failure,
+        };
+        let Some((ctx, body)) = retry.as_mut().filter(|_| attempt < MAX_ATTEMPTS) else {
+            let message = match schema {
+                Some(_) => format!(
+                    "The model answer does not match the JSON schema: {}",
+                    problem
+                ),
+                None => "The model did not return valid JSON.".to_owned(),
+            };
+            return ApiError::internal(message).response();
+        };
+        log::debug!("Structured output attempt {} failed: {}", attempt, problem);
+        attempt += 1;
+        body.messages.push(Message {
+            role: Some(Role::Assistant),
+            content: Some(Content::String(text)),
+            ..Default::default()
+        });
+        body.messages.push(Message {
+            role: Some(Role::User),
+            content: Some(Content::String(format!(
+                "That answer is invalid: {}. Reply with the corrected JSON object only.",
+                problem
+            ))),
+            ..Default::default()
+        });
+        (head, completion) = match read(collect(ctx.chat(body.clone()).await).await).await {
+            Ok(answer) => answer,
+            Err(response) => return response,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repairs_model_json() {
+        assert_eq!(repair(r#"{"a": 1}"#).as_deref(), Some(r#"{"a": 1}"#));
+        assert_eq!(
+            repair("<think>hmm</think>\n```json\n{\"a\": [1, 2]}\n```").as_deref(),
+            Some(r#"{"a":[1,2]}"#)
+        );
+        assert_eq!(
+            repair(r#"Sure! {"name": "Taipei", "tags": ["city", "cap"#).as_deref(),
+            Some(r#"{"name":"Taipei","tags":["city","cap"]}"#)
+        );
+        assert_eq!(repair(r#"{"a": 1,"#).as_deref(), Some(r#"{"a":1}"#));
+        assert_eq!(repair("no json here"), None);
+    }
+
+    #[test]
+    fn validates_schema() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "city": { "type": "string" },
+                "tags": { "type": "array", "items": { "$ref": "#/$defs/tag" } }
+            },
+            "required": ["city"],
+            "additionalProperties": false,
+            "$defs": { "tag": { "type": "string", "enum": ["capital", "port"] } }
+        });
+        let check = |value: Value| validate(&value, &schema);
+        assert!(check(serde_json::json!({ "city": "Taipei", "tags": ["capital"] })).is_ok());
+        assert_eq!(
+            check(serde_json::json!({ "tags": [] })).unwrap_err(),
+            "$ is missing required property \"city\""
+        );
+        assert_eq!(
+            check(serde_json::json!({ "city": "Taipei", "tags": ["capital", 1] })).unwrap_err(),
+            "$.tags[1] must be of type string"
+        );
+        assert!(check(serde_json::json!({ "city": "Taipei", "zip": 100 })).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    chat::{ChatCompletionsRequest, Stop},
+    errors::relay_status,
+    utils::ModelType,
+    ws::{chunk_text, sse_data, ChatContext},
+    Content, Message, OpenAiError, Role,
+};
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct GenerateParameters {
+    pub max_new_tokens: Option<i32>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub stop: Vec<String>,
+    pub seed: Option<i32>,
+    #[serde(default)]
+    pub return_full_text: bool,
+    #[serde(default)]
+    pub details: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[schema(example = json!({
+    "inputs": "What is Deep Learning?",
+    "parameters": { "max_new_tokens": 64 }
+}))]
+pub struct GenerateRequest {
+    pub inputs: String,
+    #[serde(default)]
+    pub parameters: GenerateParameters,
+    /// Extension: TGI serves a single model, default to the loaded one
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+fn tgi_error(
+    mut response: actix_web::HttpResponseBuilder,
+    error_type: &str,
+    message: String,
+) -> HttpResponse {
+    response.json(json!({ "error": message, "error_type": error_type }))
+}
+
+impl GenerateRequest {
+    /// 沒指定模型就用已載入的，再不然用第一個 LLM
+    fn resolve_model(&self, ctx: &ChatContext) -> Option<String> {
+        if let Some(model) = &self.model {
+            return Some(model.clone());
+        }
+        if let Some(model) = ctx.llm_pool.lock().unwrap().keys().next() {
+            return Some(model.clone());
+        }
+        let mut models: Vec<&String> = ctx
+            .all_configs
+            .values()
+            .filter(|c| c.model_type == ModelType::LLM)
+            .map(|c| &c.model_name)
+            .collect();
+        models.sort();
+        models.first().map(|m| (*m).clone())
+    }
+
+    fn into_chat_request(self, model: String) -> ChatCompletionsRequest {
+        // inputs 會經過模型的 chat template
+        crate::chat::streamed(ChatCompletionsRequest {
+            model,
+            messages: vec![Message {
+                role: Some(Role::User),
+                content: Some(Content::String(self.inputs)),
+                ..Default::default()
+            }],
+            max_tokens: self.parameters.max_new_tokens,
+            temperature: self.parameters.temperature,
+            top_p: self.parameters.top_p,
+            seed: self.parameters.seed,
+            stop: (!self.parameters.stop.is_empty()).then_some(Stop::Array(self.parameters.stop)),
+            ..Default::default()
+        })
+    }
+}
+
+fn details(generated_tokens: usize, seed: Option<i32>, truncated: bool) -> Value {
+    let finish_reason = if truncated { "length" } else { "eos_token" };
+    json!({ "finish_reason": finish_reason, "generated_tokens": generated_tokens, "seed": seed })
+}
+
+/// The chat chunk ends the answer at `max_new_tokens`.
+fn is_truncated(data: &str) -> bool {
+    serde_json::from_str::<Value>(data)
+        .is_ok_and(|chunk| chunk["choices"][0]["finish_reason"] == "length")
+}
+
+async fn start(
+    body: GenerateRequest,
+    ctx: &ChatContext,
+) -> Result<(HttpResponse, GenerateParameters, String), HttpResponse> {
+    let Some(model) = body.resolve_model(ctx) else {
+        return Err(tgi_error(
+            HttpResponse::BadRequest(),
+            "validation",
+            "No LLM is configured.".to_owned(),
+        ));
+    };
+    let parameters = body.parameters.clone();
+    let inputs = body.inputs.clone();
+    let response = ctx.chat(body.into_chat_request(model)).await;
+    if !response.status().is_success() {
+        let builder = relay_status(&response);
+        let bytes = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap_or_default();
+        let message = serde_json::from_slice::<OpenAiError>(&bytes)
+            .map(|e| e.message)
+            .unwrap_or_else(|_| String::from_utf8_lossy(&bytes).to_string());
+        return Err(tgi_error(builder, "validation", message));
+    }
+    Ok((response, parameters, inputs))
+}
+
+/// Text Generation Inference compatible generation.
+#[utoipa::path(
+    request_body = GenerateRequest,
+    responses(
+        (status = OK, description = "Success", content_type = "application/json")
+    ),
+)]
+#[post("/generate")]
+pub async fn generate(body: web::Json<GenerateRequest>, ctx: ChatContext) -> impl Responder {
+    let (response, parameters, inputs) = match start(body.into_inner(), &ctx).await {
+        Ok(started) => started,
+        Err(response) => return response,
+    };
+
+    let mut chunks = Box::pin(sse_data(response));
+    let mut text = String::new();
+    let mut generated_tokens = 0;
+    let mut truncated = false;
+    while let Some(data) = chunks.next().await {
+        truncated |= is_truncated(&data);
+        match chunk_text(&data) {
+            Ok(Some(delta)) => {
+                text.push_str(&delta);
+                generated_tokens += 1;
+            }
+            Ok(None) => {}
+            Err(e) => return tgi_error(HttpResponse::InternalServerError(), "generation", e),
+        }
+    }
+    if parameters.return_full_text {
+        text = inputs + &text;
+    }
+
+    let mut result = json!({ "generated_text": text });
+    if parameters.details {
+        result["details"] = details(generated_tokens, parameters.seed, truncated);
+    }
+    HttpResponse::Ok().json(result)
+}
+
+/// Text Generation Inference compatible token streaming.
+#[utoipa::path(
+    request_body = GenerateRequest,
+    responses(
+        (status = OK, description = "Success", content_type = "text/event-stream")
+    ),
+)]
+#[post("/generate_stream")]
+pub async fn generate_stream(body: web::Json<GenerateRequest>, ctx: ChatContext) -> impl Responder {
+    let (response, parameters, inputs) = match start(body.into_inner(), &ctx).await {
+        Ok(started) => started,
+        Err(response) => return response,
+    };
+
+    let mut chunks = Box::pin(sse_data(response));
+    let events = async_stream::stream! {
+        let mut text = String::new();
+        let mut generated_tokens = 0;
+        let mut truncated = false;
+        while let Some(data) = chunks.next().await {
+            truncated |= is_truncated(&data);
+            match chunk_text(&data) {
+                Ok(Some(delta)) => {
+                    text.push_str(&delta);
+                    let token = json!({
+                        "token": { "id": generated_tokens, "text": delta, "logprob": 0.0, "special": false },
+                        "generated_text": null,
+                        "details": null,
+                    });
+                    generated_tokens += 1;
+                    yield Ok::<_, actix_web::Error>(web::Bytes::from(format!("data:{}\n\n", token)));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    let error = json!({ "error": e, "error_type": "generation" });
+                    yield Ok(web::Bytes::from(format!("data:{}\n\n", error)));
+                    return;
+                }
+            }
+        }
+        // 最後一個 token 帶完整文字跟 details
+        if parameters.return_full_text {
+            text = inputs + &text;
+        }
+        let last = json!({
+            "token": { "id": generated_tokens, "text": "", "logprob": 0.0, "special": true },
+            "generated_text": text,
+            "details": details(generated_tokens, parameters.seed, truncated),
+        });
+        yield Ok(web::Bytes::from(format!("data:{}\n\n", last)));
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .streaming(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tgi_request_maps_to_chat() {
+        let body: GenerateRequest = serde_json::from_str(
+            r#"{"inputs": "Hello", "parameters": {"max_new_tokens": 20, "stop": ["\n"]}}"#,
+        )
+        .unwrap();
+        let chat = body.into_chat_request("qwen".to_owned());
+        assert_eq!(chat.max_tokens, Some(20));
+        assert!(matches!(chat.stop, Some(Stop::Array(ref stop)) if stop == &["\n"]));
+        assert!(matches!(&chat.messages[0].content, Some(Content::String(s)) if s == "Hello"));
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    path::Path,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+const THERMAL_DIR: &str = "/sys/class/thermal";
+/// sysfs 讀太頻繁沒意義，一秒更新一次
+const READ_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Power profile of the NPU workload, set once at startup with `--power-profile`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerProfile {
+    #[default]
+    Performance,
+    Balanced,
+    Quiet,
+}
+
+impl PowerProfile {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "performance" => Some(PowerProfile::Performance),
+            "balanced" => Some(PowerProfile::Balanced),
+            "quiet" => Some(PowerProfile::Quiet),
+            _ => None,
+        }
+    }
+
+    /// CPU cores rkllm may use as `(count, mask)`, `None` keeps the library default.
+    /// RK3588/RK3576 的大核是 cpu4-7
+    pub fn enabled_cpus(self) -> Option<(i8, u32)> {
+        match self {
+            PowerProfile::Performance => None,
+            PowerProfile::Balanced => Some((2, 0x30)),
+            PowerProfile::Quiet => Some((1, 0x10)),
+        }
+    }
+
+    /// SoC temperature (°C) above which tokens are paced.
+    pub fn throttle_temp(self) -> f32 {
+        match self {
+            PowerProfile::Performance => 85.0,
+            PowerProfile::Balanced => 75.0,
+            PowerProfile::Quiet => 65.0,
+        }
+    }
+
+    /// Pause between tokens while throttled, longer the hotter it gets.
+    pub fn pacing(self, temp: f32) -> Duration {
+        let over = temp - self.throttle_temp();
+        if over < 0.0 {
+            return Duration::ZERO;
+        }
+        let base = match self {
+            PowerProfile::Performance => 20,
+            PowerProfile::Balanced => 50,
+            PowerProfile::Quiet => 100,
+        };
+        // 每超過 5 度多停一倍，最多 4 倍
+        Duration::from_millis(base * (1 + (over / 5.0) as u64).min(4))
+    }
+}
+
+static PROFILE: OnceLock<PowerProfile> = OnceLock::new();
+static LAST_TEMP: Mutex<Option<(Instant, Option<f32>)>> = Mutex::new(None);
+
+pub fn set_profile(profile: PowerProfile) {
+    let _ = PROFILE.set(profile);
+}
+
+pub fn profile() -> PowerProfile {
+    PROFILE.get().copied().unwrap_or_default()
+}
+
+/// Hottest `thermal_zone*/temp` under `dir`, in °C.
+pub fn read_temp(dir: &Path) -> Option<f32> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if !path.file_name()?.to_str()?.starts_with("thermal_zone") {
+                return None;
+            }
+            let millis: f32 = std::fs::read_to_string(path.join("temp"))
+                .ok()?
+                .trim()
+                .parse()
+                .ok()?;
+            Some(millis / 1000.0)
+        })
+        .reduce(f32::max)
+}
+
+/// Current SoC temperature, cached for a second.
+pub fn soc_temp() -> Option<f32> {
+    let mut last = LAST_TEMP.lock().unwrap();
+    match *last {
+        Some((at, temp)) if at.elapsed() < READ_INTERVAL => temp,
+        _ => {
+            let temp = read_temp(Path::new(THERMAL_DIR));
+            *last = Some((Instant::now(), temp));
+            temp
+        }
+    }
+}
+
+/// Called from the rkllm callback for every token; blocking it holds back the NPU.
+pub fn pace() {
+    let Some(temp) = soc_temp() else {
+        return;
+    };
+    let delay = profile().pacing(temp);
+    if !delay.is_zero() {
+        log::debug!("SoC at {:.1}°C, pacing {:?}", temp, delay);
+        std::thread::sleep(delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pacing_and_sysfs() {
+        assert_eq!(PowerProfile::Balanced.pacing(60.0), Duration::ZERO);
+        assert_eq!(
+            PowerProfile::Balanced.pacing(76.0),
+            Duration::from_millis(50)
+        );
+        assert_eq!(PowerProfile::Quiet.pacing(99.0), Duration::from_millis(400));
+
+        let dir = std::env::temp_dir().join(format!("llmserver-thermal-{}", rand::random::<u32>()));
+        for (zone, temp) in [("thermal_zone0", "45000\n"), ("thermal_zone1", "71500\n")] {
+            std::fs::create_dir_all(dir.join(zone)).unwrap();
+            std::fs::write(dir.join(zone).join("temp"), temp).unwrap();
+        }
+        assert_eq!(read_temp(&dir), Some(71.5));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
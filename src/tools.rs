@@ -0,0 +1,279 @@
+use actix_web::{web, HttpResponse};
+use futures::StreamExt;
+use serde_json::{json, Value};
+
+use crate::{
+    chat::{append_system_prompt, sse_frame, ChatCompletionsRequest, ToolChoice, SSE_DONE},
+    mcp::{self, McpTool},
+    ws::{chunk_text, sse_data},
+    Content, FunctionCall, Message, MessageToolCall, Role,
+};
+
+const TOOL_RESPONSE_OPEN: &str = "<tool_response>";
+
+/// Tools of the request offered to the model, and whether it has to call one. `None` when there
+/// are none or `tool_choice` is `none`.
+fn offered(request: &ChatCompletionsRequest) -> Option<(Vec<McpTool>, bool)> {
+    let tools = request.tools.as_ref().filter(|tools| !tools.is_empty())?;
+    let (only, required) = match &request.tool_choice {
+        Some(ToolChoice::Mode(mode)) if mode == "none" => return None,
+        Some(ToolChoice::Mode(mode)) if mode == "required" => (None, true),
+        Some(ToolChoice::Function { function, .. }) => (Some(function.name.as_str()), true),
+        _ => (None, false),
+    };
+    let tools = tools
+        .iter()
+        .filter(|tool| only.is_none_or(|name| tool.function.name == name))
+        .map(|tool| McpTool {
+            name: tool.function.name.clone(),
+            description: tool.function.description.clone(),
+            input_schema: tool
+                .function
+                .parameters
+                .clone()
+                .unwrap_or_else(|| json!({})),
+        })
+        .collect();
+    Some((tools, required))
+}
+
+/// Whether the answer should be scanned for `<tool_call>` blocks.
+pub(crate) fn wants_tool_calls(request: &ChatCompletionsRequest) -> bool {
+    offered(request).is_some()
+}
+
+fn hermes_call(call: &MessageToolCall) -> String {
+    let arguments = serde_json::from_str(&call.function.arguments)
+        .unwrap_or_else(|_| Value::String(call.function.arguments.clone()));
+    let call = json!({ "name": call.function.name, "arguments": arguments });
+    format!("{}\n{}\n</tool_call>", mcp::TOOL_CALL_OPEN, call)
+}
+
+/// Messages for the chat template: earlier tool calls and results in Hermes format, the offered
+/// tools in the system prompt.
+pub(crate) fn prompt_messages(request: &ChatCompletionsRequest) -> Vec<Message> {
+    let mut messages: Vec<Message> = Vec::with_capacity(request.messages.len() + 1);
+    for message in &request.messages {
+        let text = message
+            .content
+            .as_ref()
+            .map(Content::text)
+            .unwrap_or_default();
+        match (&message.role, &message.tool_calls) {
+            (Some(Role::Tool), _) => {
+                let response = format!("{}\n{}\n</tool_response>", TOOL_RESPONSE_OPEN, text);
+                // 連續的工具結果併成同一則，跟 Qwen 的 template 一樣
+                if let Some(Message {
+                    role: Some(Role::User),
+                    content: Some(Content::String(previous)),
+                    ..
+                }) = messages.last_mut()
+                {
+                    if previous.starts_with(TOOL_RESPONSE_OPEN) {
+                        previous.push('\n');
+                        previous.push_str(&response);
+                        continue;
+                    }
+                }
+                messages.push(Message {
+                    role: Some(Role::User),
+                    content: Some(Content::String(response)),
+                    ..Default::default()
+                });
+            }
+            (Some(Role::Assistant), Some(calls)) if !calls.is_empty() => {
+                let calls: Vec<String> = calls.iter().map(hermes_call).collect();
+                let text = [text, calls.join("\n")].join("\n");
+                messages.push(Message {
+                    role: Some(Role::Assistant),
+                    content: Some(Content::String(text.trim_start().to_owned())),
+                    ..Default::default()
+                });
+            }
+            _ => messages.push(message.clone()),
+        }
+    }
+    if let Some((tools, required)) = offered(request) {
+        let mut prompt = mcp::tools_prompt(&tools);
+        if required {
+            prompt.push_str("\n\nYou must call at least one function.");
+        }
+        append_system_prompt(&mut messages, &prompt);
+    }
+    messages
+}
+
+fn sse(data: String) -> web::Bytes {
+    web::Bytes::from(sse_frame(&data))
+}
+
+/// `chunk` with its choices replaced.
+fn with_choice(chunk: &Value, choice: Value) -> web::Bytes {
+    let mut chunk = chunk.clone();
+    chunk["choices"] = json!([choice]);
+    sse(chunk.to_string())
+}
+
+/// Turn `<tool_call>` blocks in the streamed answer into `tool_calls` deltas, finished with
+/// `finish_reason: "tool_calls"`.
+pub(crate) fn relay(response: HttpResponse) -> HttpResponse {
+    if !response.status().is_success() {
+        return response;
+    }
+    let mut builder = HttpResponse::build(response.status());
+    for (name, value) in response.headers() {
+        builder.insert_header((name.clone(), value.clone()));
+    }
+    let mut chunks = Box::pin(sse_data(response));
+
+    let events = async_stream::stream! {
+        let mut text = String::new();
+        // 可能是 <tool_call> 開頭的 chunk 先扣著
+        let mut held: Vec<String> = Vec::new();
+        // finish 跟 usage 最後才送
+        let mut tail: Vec<String> = Vec::new();
+        let mut first: Option<Value> = None;
+        let mut calling = false;
+        while let Some(data) = chunks.next().await {
+            match chunk_text(&data) {
+                Ok(Some(delta)) => {
+                    if first.is_none() {
+                        first = serde_json::from_str(&data).ok();
+                    }
+                    text.push_str(&delta);
+                    if calling {
+                        continue;
+                    }
+                    if text.contains(mcp::TOOL_CALL_OPEN) {
+                        calling = true;
+                        held.clear();
+                        continue;
+                    }
+                    held.push(data);
+                    if mcp::partial_tag_len(&text) == 0 {
+                        for data in held.drain(..) {
+                            yield Ok::<_, actix_web::Error>(sse(data));
+                        }
+                    }
+                }
+                Ok(None) if mcp::is_finish_chunk(&data) || crate::usage::from_chunk(&data).is_some() => {
+                    tail.push(data)
+                }
+                Ok(None) => yield Ok(sse(data)),
+                Err(_) => {
+                    yield Ok(sse(data));
+                    return;
+                }
+            }
+        }
+
+        let calls = if calling { mcp::parse_tool_calls(&text) } else { vec![] };
+        match (&first, calls.is_empty()) {
+            (Some(chunk), false) => {
+                let tool_calls: Vec<MessageToolCall> = calls
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, call)| MessageToolCall {
+                        index: Some(index),
+                        id: format!("call_{:016x}", rand::random::<u64>()),
+                        r#type: "function".to_owned(),
+                        function: FunctionCall {
+                            name: call.name,
+                            arguments: call.arguments.to_string(),
+                        },
+                    })
+                    .collect();
+                yield Ok(with_choice(chunk, json!({
+                    "index": 0,
+                    "delta": { "tool_calls": tool_calls },
+                    "finish_reason": null,
+                })));
+                yield Ok(with_choice(chunk, json!({ "index": 0, "delta": {}, "finish_reason": "tool_calls" })));
+                for data in tail.into_iter().filter(|data| !mcp::is_finish_chunk(data)) {
+                    yield Ok(sse(data));
+                }
+            }
+            (first, _) => {
+                if let (Some(chunk), true) = (first, calling) {
+                    // 格式壞掉的 tool_call 就當成一般回答
+                    let rest = &text[text.find(mcp::TOOL_CALL_OPEN).unwrap_or(0)..];
+                    yield Ok(with_choice(chunk, json!({
+                        "index": 0,
+                        "delta": { "content": rest },
+                        "finish_reason": null,
+                    })));
+                }
+                for data in held.into_iter().chain(tail) {
+                    yield Ok(sse(data));
+                }
+            }
+        }
+        yield Ok(web::Bytes::from(SSE_DONE));
+    };
+
+    builder.content_type("text/event-stream").streaming(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn tool_calls_round_trip() {
+        let request: ChatCompletionsRequest = serde_json::from_value(json!({
+            "model": "qwen",
+            "messages": [
+                { "role": "user", "content": "Weather in Taipei?" },
+                { "role": "assistant", "content": null, "tool_calls": [{
+                    "id": "call_1", "type": "function",
+                    "function": { "name": "get_weather", "arguments": "{\"city\":\"Taipei\"}" }
+                }]},
+                { "role": "tool", "tool_call_id": "call_1", "content": "22°C" }
+            ],
+            "tools": [{ "type": "function", "function": { "name": "get_weather", "parameters": { "type": "object" } } }]
+        }))
+        .unwrap();
+        assert!(wants_tool_calls(&request));
+        let messages = prompt_messages(&request);
+        assert_eq!(messages.len(), 4);
+        let text = |i: usize| messages[i].content.as_ref().unwrap().text();
+        assert!(text(0).contains("<tools>"));
+        assert!(text(2).starts_with("<tool_call>\n{\"arguments\":{\"city\":\"Taipei\"}"));
+        assert_eq!(text(3), "<tool_response>\n22°C\n</tool_response>");
+
+        let chunk = |content: &str| {
+            crate::chat::create_sse_chunk_data(
+                "chatcmpl-1",
+                1,
+                "qwen",
+                None,
+                Some(Content::String(content.to_owned())),
+            )
+        };
+        let body = [
+            chunk("<tool"),
+            chunk("_call>\n{\"name\": \"get_weather\", "),
+            chunk("\"arguments\": {\"city\": \"Taipei\"}}\n</tool_call>"),
+        ]
+        .concat();
+        let response = HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .body(body);
+        let completion = crate::chat::collect(relay(response)).await;
+        let body = actix_web::body::to_bytes(completion.into_body())
+            .await
+            .unwrap();
+        let completion: Value = serde_json::from_slice(&body).unwrap();
+        let choice = &completion["choices"][0];
+        assert_eq!(choice["finish_reason"], "tool_calls");
+        assert_eq!(
+            choice["message"]["tool_calls"][0]["function"]["name"],
+            "get_weather"
+        );
+        assert_eq!(
+            choice["message"]["tool_calls"][0]["function"]["arguments"],
+            "{\"city\":\"Taipei\"}"
+        );
+        assert!(choice["message"]["content"].is_null());
+    }
+}
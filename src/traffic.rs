@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use crate::utils::ModelConfig;
+
+/// Response header naming the variant that served a traffic split request.
+pub const VARIANT_HEADER: &str = "X-Model-Variant";
+
+/// 依 `traffic_split` 抽一個變體，沒抽中就用原本的設定
+pub fn pick_variant<'a>(
+    config: &'a ModelConfig,
+    all_configs: &'a HashMap<String, ModelConfig>,
+) -> &'a ModelConfig {
+    if config.traffic_split.is_empty() {
+        return config;
+    }
+    pick_variant_with(config, all_configs, rand::random_range(0..100))
+}
+
+fn pick_variant_with<'a>(
+    config: &'a ModelConfig,
+    all_configs: &'a HashMap<String, ModelConfig>,
+    roll: u32,
+) -> &'a ModelConfig {
+    let mut upper = 0;
+    for split in &config.traffic_split {
+        upper += split.percent;
+        if roll < upper {
+            return match all_configs.get(&split.model) {
+                Some(variant) => variant,
+                None => {
+                    log::warn!(
+                        "Variant \"{}\" of model \"{}\" is not configured",
+                        split.model,
+                        config.model_name
+                    );
+                    config
+                }
+            };
+        }
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TrafficSplit;
+
+    #[test]
+    fn roll_selects_variant_by_percentage() {
+        let primary = ModelConfig {
+            model_name: "qwen-w8a8".to_owned(),
+            traffic_split: vec![TrafficSplit {
+                model: "qwen-w4a16".to_owned(),
+                percent: 30,
+            }],
+            ..Default::default()
+        };
+        let variant = ModelConfig {
+            model_name: "qwen-w4a16".to_owned(),
+            ..Default::default()
+        };
+        let all_configs = HashMap::from([
+            (primary.model_name.clone(), primary.clone()),
+            (variant.model_name.clone(), variant),
+        ]);
+
+        let pick = |roll| {
+            pick_variant_with(&primary, &all_configs, roll)
+                .model_name
+                .as_str()
+        };
+        assert_eq!(pick(0), "qwen-w4a16");
+        assert_eq!(pick(29), "qwen-w4a16");
+        assert_eq!(pick(30), "qwen-w8a8");
+        assert_eq!(pick(99), "qwen-w8a8");
+    }
+}
@@ -0,0 +1,151 @@
+use serde::Serialize;
+use serde_json::json;
+
+use crate::AudioSpan;
+
+/// One recognized part of a transcription.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Segment {
+    pub id: usize,
+    pub seek: usize,
+    /// Seconds from the start of the audio
+    pub start: f32,
+    pub end: f32,
+    pub text: String,
+}
+
+impl Segment {
+    pub fn new(id: usize, span: AudioSpan, text: String) -> Self {
+        Self {
+            id,
+            seek: 0,
+            start: span.start_ms as f32 / 1000.0,
+            end: span.end_ms as f32 / 1000.0,
+            text,
+        }
+    }
+}
+
+/// OpenAI language name of a SenseVoice language tag.
+pub fn language(language: sensevoice_rs::SenseVoiceLanguage) -> Option<&'static str> {
+    use sensevoice_rs::SenseVoiceLanguage::*;
+    match language {
+        En => Some("english"),
+        Zh => Some("chinese"),
+        Yue => Some("cantonese"),
+        Ja => Some("japanese"),
+        Ko => Some("korean"),
+        NoSpeech => None,
+    }
+}
+
+/// `verbose_json` body: the text with its language, duration and segments.
+pub fn verbose_json(
+    language: Option<&str>,
+    duration: f32,
+    segments: &[Segment],
+) -> serde_json::Value {
+    let text: String = segments.iter().map(|s| s.text.as_str()).collect();
+    json!({
+        "task": "transcribe",
+        "language": language.unwrap_or("unknown"),
+        "duration": duration,
+        "text": text,
+        "segments": segments,
+    })
+}
+
+/// `hh:mm:ss` plus milliseconds after `separator`, `,` for SRT and `.` for WebVTT.
+fn timestamp(seconds: f32, separator: char) -> String {
+    let ms = (seconds * 1000.0).round().max(0.0) as u64;
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        separator,
+        ms % 1000
+    )
+}
+
+/// 字幕裡的空行會被當成下一段
+fn cue_text(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn srt(segments: &[Segment]) -> String {
+    segments
+        .iter()
+        .enumerate()
+        .map(|(index, segment)| {
+            format!(
+                "{}\n{} --> {}\n{}\n\n",
+                index + 1,
+                timestamp(segment.start, ','),
+                timestamp(segment.end, ','),
+                cue_text(&segment.text)
+            )
+        })
+        .collect()
+}
+
+pub fn vtt(segments: &[Segment]) -> String {
+    let cues: String = segments
+        .iter()
+        .map(|segment| {
+            format!(
+                "{} --> {}\n{}\n\n",
+                timestamp(segment.start, '.'),
+                timestamp(segment.end, '.'),
+                cue_text(&segment.text)
+            )
+        })
+        .collect();
+    format!("WEBVTT\n\n{}", cues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbose_json_lists_segments() {
+        let segments = vec![
+            Segment::new(
+                0,
+                AudioSpan {
+                    start_ms: 200,
+                    end_ms: 1500,
+                },
+                "Hello.".to_owned(),
+            ),
+            Segment::new(
+                1,
+                AudioSpan {
+                    start_ms: 2000,
+                    end_ms: 3250,
+                },
+                " World.".to_owned(),
+            ),
+        ];
+        let body = verbose_json(Some("english"), 3.5, &segments);
+        assert_eq!(body["text"], "Hello. World.");
+        assert_eq!(body["language"], "english");
+        assert_eq!(body["segments"][1]["start"], 2.0);
+        assert_eq!(body["segments"][1]["end"], 3.25);
+
+        assert_eq!(
+            srt(&segments),
+            "1\n00:00:00,200 --> 00:00:01,500\nHello.\n\n2\n00:00:02,000 --> 00:00:03,250\nWorld.\n\n"
+        );
+        assert_eq!(
+            vtt(&segments[..1]),
+            "WEBVTT\n\n00:00:00.200 --> 00:00:01.500\nHello.\n\n"
+        );
+        assert_eq!(timestamp(3723.5, ','), "01:02:03,500");
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use actix::{Actor, ActorContext};
+use hf_hub::api::Progress;
+use serde::Deserialize;
+
+use crate::llm::simple::{fetch_file, render_local_path_template, resolve_model_filename};
+use crate::utils::ModelConfig;
+use crate::{AIModel, ModelProgress, ProcessSpeech, ShutdownMessages, SpeechAudio, TTS};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The parts of a piper `.onnx.json` voice config we need.
+#[derive(Debug, Default, Deserialize)]
+struct VoiceConfig {
+    audio: VoiceAudio,
+    #[serde(default)]
+    speaker_id_map: HashMap<String, i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct VoiceAudio {
+    sample_rate: u32,
+}
+
+#[derive(Debug)]
+struct Voice {
+    binary: String,
+    model: PathBuf,
+    config: PathBuf,
+    sample_rate: u32,
+    /// 設定檔的 voices 優先，再來是 voice 自帶的 speaker 名稱
+    speakers: HashMap<String, i64>,
+}
+
+impl Voice {
+    /// Speaker id of an OpenAI `voice`, `None` keeps the default speaker.
+    fn speaker(&self, voice: &str) -> Option<i64> {
+        self.speakers.get(voice).copied()
+    }
+
+    fn synthesize(&self, input: &str, voice: &str, speed: f32) -> Result<Vec<i16>, BoxError> {
+        let mut command = Command::new(&self.binary);
+        command
+            .arg("--model")
+            .arg(&self.model)
+            .arg("--config")
+            .arg(&self.config)
+            .arg("--output-raw")
+            .arg("--length_scale")
+            .arg((1.0 / speed).to_string());
+        if let Some(speaker) = self.speaker(voice) {
+            command.arg("--speaker").arg(speaker.to_string());
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("{}: {}", self.binary, e))?;
+        // piper 一行一句，換行都併成空白
+        let line = input.split_whitespace().collect::<Vec<_>>().join(" ");
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(line.as_bytes())?;
+            stdin.write_all(b"\n")?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(format!(
+                "{} exited with {}: {}",
+                self.binary,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        Ok(output
+            .stdout
+            .chunks_exact(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect())
+    }
+}
+
+/// Piper voices, run by the `piper` executable on the CPU.
+pub struct SimpleTTS {
+    voice: Arc<Mutex<Voice>>,
+}
+
+impl Actor for SimpleTTS {
+    type Context = actix::Context<Self>;
+}
+
+impl actix::Handler<ProcessSpeech> for SimpleTTS {
+    type Result = actix::ResponseFuture<Result<SpeechAudio, ()>>;
+
+    fn handle(&mut self, msg: ProcessSpeech, _ctx: &mut Self::Context) -> Self::Result {
+        let voice = self.voice.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                let voice = voice.lock().unwrap();
+                let started = Instant::now();
+                let samples = voice
+                    .synthesize(&msg.input, &msg.voice, msg.speed)
+                    .map_err(|e| log::error!("Speech synthesis failed: {}", e))?;
+                log::info!("Speech synthesized in {:?}", started.elapsed());
+                Ok(SpeechAudio {
+                    samples,
+                    sample_rate: voice.sample_rate,
+                })
+            })
+            .await
+            .map_err(|e| log::error!("Speech synthesis task failed: {}", e))?
+        })
+    }
+}
+
+impl actix::Handler<ShutdownMessages> for SimpleTTS {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, _: ShutdownMessages, ctx: &mut Self::Context) -> Self::Result {
+        let _guard = self.voice.lock().unwrap();
+        ctx.stop();
+        Ok(())
+    }
+}
+
+impl AIModel for SimpleTTS {
+    type Config = ModelConfig;
+
+    fn init_with_progress<P: Progress + ModelProgress + Clone>(
+        config: &Self::Config,
+        p: Option<P>,
+    ) -> Result<Self, BoxError> {
+        let speech = config.speech.clone().unwrap_or_default();
+        let model_file = resolve_model_filename(config);
+        let config_file = match &speech.config {
+            Some(file) => render_local_path_template(file, config),
+            None => format!("{}.json", model_file),
+        };
+        let (model, progress) = fetch_file(config, &model_file, p)?;
+        let (config_path, _) = fetch_file(config, &config_file, None::<P>)?;
+
+        let mut progress = progress;
+        if let Some(progress) = progress.as_mut() {
+            let size = std::fs::metadata(&model)?.len();
+            let filename = model.file_name().unwrap().to_string_lossy();
+            progress.model_load(size.try_into().unwrap(), &filename, Instant::now());
+        }
+        let voice: VoiceConfig = serde_json::from_str(&std::fs::read_to_string(&config_path)?)
+            .map_err(|e| format!("{}: {}", config_path.display(), e))?;
+        let mut speakers = voice.speaker_id_map;
+        speakers.extend(speech.voices);
+        if let Some(mut progress) = progress {
+            progress.model_finished();
+        }
+
+        Ok(Self {
+            voice: Arc::new(Mutex::new(Voice {
+                binary: speech.binary,
+                model,
+                config: config_path,
+                sample_rate: voice.audio.sample_rate,
+                speakers,
+            })),
+        })
+    }
+}
+
+impl TTS for SimpleTTS {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn voices_select_speakers() {
+        let config: VoiceConfig = serde_json::from_value(serde_json::json!({
+            "audio": { "sample_rate": 22050 },
+            "speaker_id_map": { "p225": 0, "p226": 1 }
+        }))
+        .unwrap();
+        let mut speakers = config.speaker_id_map;
+        speakers.extend([("alloy".to_owned(), 1)]);
+        let voice = Voice {
+            binary: "piper".to_owned(),
+            model: PathBuf::new(),
+            config: PathBuf::new(),
+            sample_rate: config.audio.sample_rate,
+            speakers,
+        };
+        assert_eq!(voice.speaker("alloy"), Some(1));
+        assert_eq!(voice.speaker("p225"), Some(0));
+        assert_eq!(voice.speaker("nova"), None);
+    }
+}
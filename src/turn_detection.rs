@@ -0,0 +1,160 @@
+use serde::Deserialize;
+
+/// 每 20ms 判斷一次有沒有在說話
+const FRAME_MS: u32 = 20;
+/// `threshold` 0.5 約等於 -34 dBFS
+const LEVEL_PER_THRESHOLD: f32 = 0.04;
+
+fn default_threshold() -> f32 {
+    0.5
+}
+
+fn default_prefix_padding_ms() -> u32 {
+    300
+}
+
+fn default_silence_duration_ms() -> u32 {
+    500
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// `turn_detection` of a realtime session, OpenAI `server_vad`.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ServerVadConfig {
+    pub r#type: String,
+    /// 0 to 1, higher needs louder speech
+    #[serde(default = "default_threshold")]
+    pub threshold: f32,
+    /// Audio kept before the speech started
+    #[serde(default = "default_prefix_padding_ms")]
+    pub prefix_padding_ms: u32,
+    /// Silence that ends a turn
+    #[serde(default = "default_silence_duration_ms")]
+    pub silence_duration_ms: u32,
+    /// Answer every turn without waiting for `response.create`
+    #[serde(default = "default_true")]
+    pub create_response: bool,
+    /// Cancel the running answer when the user starts speaking again
+    #[serde(default = "default_true")]
+    pub interrupt_response: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TurnEvent {
+    SpeechStarted { audio_start_ms: u32 },
+    SpeechStopped { audio_end_ms: u32 },
+}
+
+/// Finds turns in streamed audio by its loudness, like the `server_vad` of the OpenAI Realtime
+/// API. Positions are in milliseconds from the start of the input buffer.
+#[derive(Debug, Clone)]
+pub struct ServerVad {
+    pub config: ServerVadConfig,
+    sample_rate: u32,
+    frame: Vec<i16>,
+    position_ms: u32,
+    speech_start_ms: Option<u32>,
+    silence_ms: u32,
+}
+
+impl ServerVad {
+    pub fn new(config: ServerVadConfig, sample_rate: u32) -> Self {
+        Self {
+            config,
+            sample_rate,
+            frame: Vec::new(),
+            position_ms: 0,
+            speech_start_ms: None,
+            silence_ms: 0,
+        }
+    }
+
+    /// Forget the buffer, after it was committed or cleared.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config.clone(), self.sample_rate);
+    }
+
+    /// Where the speech of the current turn started, with the prefix padding.
+    pub fn turn_start_ms(&self) -> Option<u32> {
+        self.speech_start_ms
+            .map(|start| start.saturating_sub(self.config.prefix_padding_ms))
+    }
+
+    pub fn push(&mut self, samples: &[i16]) -> Vec<TurnEvent> {
+        let frame_len = (self.sample_rate * FRAME_MS / 1000) as usize;
+        let level = self.config.threshold * LEVEL_PER_THRESHOLD;
+        let mut events = vec![];
+        for sample in samples {
+            self.frame.push(*sample);
+            if self.frame.len() < frame_len {
+                continue;
+            }
+            let power = self
+                .frame
+                .iter()
+                .map(|s| (*s as f32 / i16::MAX as f32).powi(2))
+                .sum::<f32>()
+                / frame_len as f32;
+            self.frame.clear();
+            let speaking = power.sqrt() >= level;
+            match (self.speech_start_ms, speaking) {
+                (None, true) => {
+                    self.speech_start_ms = Some(self.position_ms);
+                    self.silence_ms = 0;
+                    events.push(TurnEvent::SpeechStarted {
+                        audio_start_ms: self.position_ms,
+                    });
+                }
+                (Some(_), true) => self.silence_ms = 0,
+                (Some(_), false) => {
+                    self.silence_ms += FRAME_MS;
+                    if self.silence_ms >= self.config.silence_duration_ms {
+                        events.push(TurnEvent::SpeechStopped {
+                            audio_end_ms: self.position_ms + FRAME_MS,
+                        });
+                    }
+                }
+                (None, false) => {}
+            }
+            self.position_ms += FRAME_MS;
+            // 停止後交給呼叫端 commit 跟 reset
+            if matches!(events.last(), Some(TurnEvent::SpeechStopped { .. })) {
+                break;
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_a_turn() {
+        let config: ServerVadConfig = serde_json::from_value(serde_json::json!({
+            "type": "server_vad", "silence_duration_ms": 200
+        }))
+        .unwrap();
+        assert_eq!(config.prefix_padding_ms, 300);
+        let mut vad = ServerVad::new(config, 1000);
+        assert!(vad.push(&[0; 400]).is_empty());
+        assert_eq!(
+            vad.push(&[8000; 100]),
+            vec![TurnEvent::SpeechStarted {
+                audio_start_ms: 400
+            }]
+        );
+        assert_eq!(vad.turn_start_ms(), Some(100));
+        assert!(vad.push(&[0; 100]).is_empty());
+        assert_eq!(
+            vad.push(&[0; 200]),
+            vec![TurnEvent::SpeechStopped { audio_end_ms: 700 }]
+        );
+        vad.reset();
+        assert_eq!(vad.turn_start_ms(), None);
+    }
+}
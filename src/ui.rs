@@ -0,0 +1,33 @@
+use actix_web::{get, HttpResponse, Responder};
+
+/// 直接編進 binary，不需要額外的靜態檔案
+const INDEX_HTML: &str = include_str!("../assets/ui/index.html");
+
+/// Minimal chat page for testing from a browser.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", body = str, content_type = "text/html")
+    )
+)]
+#[get("/ui")]
+pub async fn index() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(INDEX_HTML)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn ui_serves_index() {
+        let app = test::init_service(App::new().service(index)).await;
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri("/ui").to_request()).await;
+        assert!(response.status().is_success());
+        let body = test::read_body(response).await;
+        assert!(String::from_utf8_lossy(&body).contains("/v1/chat/completions"));
+    }
+}
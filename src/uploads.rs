@@ -0,0 +1,414 @@
+use std::{
+    collections::HashSet,
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse, Responder};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    files::{FileObject, FileStore},
+    OpenAiError,
+};
+
+pub const UPLOADS_DIR: &str = "assets/uploads";
+/// Bytes of the upload already stored, like tus.
+pub const OFFSET_HEADER: &str = "Upload-Offset";
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct Upload {
+    pub id: String,
+    pub object: String,
+    pub filename: String,
+    pub purpose: String,
+    /// Size of the whole file
+    pub bytes: u64,
+    /// Bytes received so far, resume from here
+    #[serde(default)]
+    pub offset: u64,
+    pub created_at: u64,
+    /// `pending` until every byte arrived, then `completed`
+    pub status: String,
+    /// The finished file, usable as `file_id`
+    pub file: Option<FileObject>,
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    NotFound,
+    /// 要從這裡接續
+    Offset(u64),
+    TooLarge(u64),
+    Completed,
+    /// 同一個上傳有別的連線正在寫
+    Busy,
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for UploadError {
+    fn from(e: std::io::Error) -> Self {
+        UploadError::Io(e)
+    }
+}
+
+/// 收到一半的檔案存成 `<id>.part`，描述存成 `<id>.json`，重啟後也能接著傳
+#[derive(Debug, Clone)]
+pub struct UploadStore {
+    dir: PathBuf,
+    writing: Arc<Mutex<HashSet<String>>>,
+}
+
+/// Releases the upload for the next connection when the request ends, even if it was dropped.
+pub struct UploadWriter {
+    pub file: std::fs::File,
+    /// Bytes still missing from the declared size
+    pub remaining: u64,
+    id: String,
+    writing: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Drop for UploadWriter {
+    fn drop(&mut self) {
+        self.writing.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl UploadStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            writing: Default::default(),
+        }
+    }
+
+    fn valid_id(id: &str) -> bool {
+        id.strip_prefix("upload-")
+            .is_some_and(|hex| !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()))
+    }
+
+    fn part(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.part", id))
+    }
+
+    fn save(&self, upload: &Upload) -> std::io::Result<()> {
+        std::fs::write(
+            self.dir.join(format!("{}.json", upload.id)),
+            serde_json::to_vec(upload)?,
+        )
+    }
+
+    pub fn create(&self, filename: String, purpose: String, bytes: u64) -> std::io::Result<Upload> {
+        std::fs::create_dir_all(&self.dir)?;
+        let upload = Upload {
+            id: format!("upload-{:016x}", rand::random::<u64>()),
+            object: "upload".to_owned(),
+            filename,
+            purpose,
+            bytes,
+            offset: 0,
+            created_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            status: "pending".to_owned(),
+            file: None,
+        };
+        std::fs::File::create(self.part(&upload.id))?;
+        self.save(&upload)?;
+        Ok(upload)
+    }
+
+    /// 進度以磁碟上實際收到的大小為準
+    pub fn get(&self, id: &str) -> Option<Upload> {
+        if !Self::valid_id(id) {
+            return None;
+        }
+        let json = std::fs::read(self.dir.join(format!("{}.json", id))).ok()?;
+        let mut upload: Upload = serde_json::from_slice(&json).ok()?;
+        if upload.file.is_none() {
+            upload.offset = std::fs::metadata(self.part(id)).map_or(0, |m| m.len());
+        }
+        Some(upload)
+    }
+
+    /// Open the upload for appending at `offset`, which must be what was received so far.
+    pub fn writer(&self, id: &str, offset: u64) -> Result<UploadWriter, UploadError> {
+        let upload = self.get(id).ok_or(UploadError::NotFound)?;
+        if upload.file.is_some() {
+            return Err(UploadError::Completed);
+        }
+        if upload.offset != offset {
+            return Err(UploadError::Offset(upload.offset));
+        }
+        if !self.writing.lock().unwrap().insert(id.to_owned()) {
+            return Err(UploadError::Busy);
+        }
+        let writer = UploadWriter {
+            file: std::fs::File::options().append(true).open(self.part(id))?,
+            remaining: upload.bytes.saturating_sub(offset),
+            id: id.to_owned(),
+            writing: self.writing.clone(),
+        };
+        Ok(writer)
+    }
+
+    /// Move the upload into `files` once every byte arrived.
+    pub fn finish(&self, id: &str, files: &FileStore) -> Result<Upload, UploadError> {
+        let mut upload = self.get(id).ok_or(UploadError::NotFound)?;
+        if upload.file.is_some() || upload.offset < upload.bytes {
+            return Ok(upload);
+        }
+        let file = files.create(
+            &self.part(id),
+            upload.filename.clone(),
+            upload.purpose.clone(),
+        )?;
+        upload.status = "completed".to_owned();
+        upload.file = Some(file);
+        self.save(&upload)?;
+        let _ = std::fs::remove_file(self.part(id));
+        Ok(upload)
+    }
+
+    pub fn delete(&self, id: &str) -> std::io::Result<bool> {
+        if self.get(id).is_none() {
+            return Ok(false);
+        }
+        std::fs::remove_file(self.dir.join(format!("{}.json", id)))?;
+        let _ = std::fs::remove_file(self.part(id));
+        Ok(true)
+    }
+}
+
+fn error_response(id: &str, error: UploadError) -> HttpResponse {
+    let (mut builder, message, code) = match error {
+        UploadError::NotFound => (
+            HttpResponse::NotFound(),
+            format!("No such upload: {}", id),
+            "upload_not_found",
+        ),
+        UploadError::Offset(offset) => {
+            let mut builder = HttpResponse::Conflict();
+            builder.insert_header((OFFSET_HEADER, offset.to_string()));
+            (
+                builder,
+                format!("Upload {} continues at offset {}.", id, offset),
+                "offset_mismatch",
+            )
+        }
+        UploadError::TooLarge(bytes) => (
+            HttpResponse::BadRequest(),
+            format!("Upload {} is larger than the declared {} bytes.", id, bytes),
+            "upload_too_large",
+        ),
+        UploadError::Completed => (
+            HttpResponse::Conflict(),
+            format!("Upload {} is already completed.", id),
+            "upload_completed",
+        ),
+        UploadError::Busy => (
+            HttpResponse::Conflict(),
+            format!("Upload {} is being written by another request.", id),
+            "upload_busy",
+        ),
+        UploadError::Io(e) => {
+            return HttpResponse::InternalServerError().json(OpenAiError {
+                message: e.to_string(),
+                code: "processing_error".to_owned(),
+                r#type: "internal_error".to_owned(),
+                param: None,
+            })
+        }
+    };
+    builder.json(OpenAiError {
+        message,
+        code: code.to_owned(),
+        r#type: "invalid_request_error".to_owned(),
+        param: None,
+    })
+}
+
+fn upload_response(upload: Upload) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((OFFSET_HEADER, upload.offset.to_string()))
+        .json(upload)
+}
+
+fn default_purpose() -> String {
+    "transcription".to_owned()
+}
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub struct CreateUploadRequest {
+    pub filename: String,
+    /// Size of the whole file in bytes
+    pub bytes: u64,
+    #[serde(default = "default_purpose")]
+    pub purpose: String,
+}
+
+/// Start a resumable upload.
+#[utoipa::path(
+    request_body = CreateUploadRequest,
+    responses(
+        (status = OK, description = "Success", body = Upload, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/uploads")]
+pub async fn create(
+    body: web::Json<CreateUploadRequest>,
+    uploads: web::Data<UploadStore>,
+) -> impl Responder {
+    let body = body.into_inner();
+    match uploads.create(body.filename, body.purpose, body.bytes) {
+        Ok(upload) => upload_response(upload),
+        Err(e) => error_response("", UploadError::Io(e)),
+    }
+}
+
+/// Get an upload and the offset to resume from.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", body = Upload, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/uploads/{id}")]
+pub async fn retrieve(path: web::Path<String>, uploads: web::Data<UploadStore>) -> impl Responder {
+    match uploads.get(&path) {
+        Some(upload) => upload_response(upload),
+        None => error_response(&path, UploadError::NotFound),
+    }
+}
+
+/// Append a chunk at `Upload-Offset`.
+#[utoipa::path(
+    request_body(content = Vec<u8>, content_type = "application/offset+octet-stream"),
+    params(
+        ("Upload-Offset" = u64, Header, description = "Bytes already received, from GET /uploads/{id}")
+    ),
+    responses(
+        (status = OK, description = "Success", body = Upload, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[patch("/uploads/{id}")]
+pub async fn append(
+    path: web::Path<String>,
+    mut payload: web::Payload,
+    uploads: web::Data<UploadStore>,
+    files: web::Data<FileStore>,
+    req: HttpRequest,
+) -> impl Responder {
+    let Some(offset) = req
+        .headers()
+        .get(OFFSET_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+    else {
+        return HttpResponse::BadRequest().json(OpenAiError {
+            message: format!("Missing or invalid {} header.", OFFSET_HEADER),
+            code: "invalid_offset".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: Some(OFFSET_HEADER.to_owned()),
+        });
+    };
+    let mut writer = match uploads.writer(&path, offset) {
+        Ok(writer) => writer,
+        Err(e) => return error_response(&path, e),
+    };
+    // 斷線前收到的部分照樣留著，下次從新的 offset 接
+    while let Some(chunk) = payload.next().await {
+        let Ok(chunk) = chunk else {
+            break;
+        };
+        if chunk.len() as u64 > writer.remaining {
+            let bytes = uploads.get(&path).map_or(0, |u| u.bytes);
+            return error_response(&path, UploadError::TooLarge(bytes));
+        }
+        writer.remaining -= chunk.len() as u64;
+        if let Err(e) = writer.file.write_all(&chunk) {
+            return error_response(&path, UploadError::Io(e));
+        }
+    }
+    drop(writer);
+    match uploads.finish(&path, &files) {
+        Ok(upload) => upload_response(upload),
+        Err(e) => error_response(&path, e),
+    }
+}
+
+/// Cancel an upload.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[delete("/uploads/{id}")]
+pub async fn remove(path: web::Path<String>, uploads: web::Data<UploadStore>) -> impl Responder {
+    match uploads.delete(&path) {
+        Ok(true) => HttpResponse::Ok().json(serde_json::json!({
+            "id": path.as_str(),
+            "object": "upload",
+            "deleted": true,
+        })),
+        Ok(false) => error_response(&path, UploadError::NotFound),
+        Err(e) => error_response(&path, UploadError::Io(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resumes_at_offset() {
+        let dir = std::env::temp_dir().join(format!("llmserver-uploads-{}", rand::random::<u32>()));
+        let uploads = UploadStore::new(dir.join("uploads"));
+        let files = FileStore::new(dir.join("files"));
+
+        let upload = uploads
+            .create("talk.wav".to_owned(), "transcription".to_owned(), 10)
+            .unwrap();
+        let mut writer = uploads.writer(&upload.id, 0).unwrap();
+        assert!(matches!(
+            uploads.writer(&upload.id, 0),
+            Err(UploadError::Busy)
+        ));
+        writer.file.write_all(b"hello").unwrap();
+        drop(writer);
+        assert_eq!(uploads.finish(&upload.id, &files).unwrap().offset, 5);
+
+        // 斷線後用舊的 offset 重傳會被擋下
+        assert!(matches!(
+            uploads.writer(&upload.id, 0),
+            Err(UploadError::Offset(5))
+        ));
+        let mut writer = uploads.writer(&upload.id, 5).unwrap();
+        writer.file.write_all(b"world").unwrap();
+        drop(writer);
+        let upload = uploads.finish(&upload.id, &files).unwrap();
+        assert_eq!(upload.status, "completed");
+        let file = upload.file.unwrap();
+        assert_eq!(files.content(&file.id).unwrap(), b"helloworld");
+        assert!(matches!(
+            uploads.writer(&upload.id, 10),
+            Err(UploadError::Completed)
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+use crate::chat::Usage;
+
+/// Milliseconds spent loading the model (0 when it was loaded already), reading the prompt up to
+/// the first token and generating the rest, measured around the run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct Timings {
+    pub load_ms: f64,
+    pub prompt_ms: f64,
+    pub generate_ms: f64,
+}
+
+/// Marks the token counts a model sends at the end of its text stream, like `prefill:`.
+const PREFIX: &str = "\u{0}usage:";
+
+pub fn usage(prompt_tokens: usize, completion_tokens: usize) -> Usage {
+    Usage {
+        prompt_tokens: prompt_tokens as i32,
+        completion_tokens: completion_tokens as i32,
+        total_tokens: (prompt_tokens + completion_tokens) as i32,
+    }
+}
+
+pub fn message(prompt_tokens: usize, completion_tokens: usize) -> String {
+    format!("{}{}/{}", PREFIX, prompt_tokens, completion_tokens)
+}
+
+/// Counts of a usage message, `None` for model output.
+pub fn parse(text: &str) -> Option<Usage> {
+    let (prompt, completion) = text.strip_prefix(PREFIX)?.split_once('/')?;
+    Some(usage(prompt.parse().ok()?, completion.parse().ok()?))
+}
+
+/// `usage` of a relayed chat completion chunk.
+pub fn from_chunk(data: &str) -> Option<Usage> {
+    let chunk: serde_json::Value = serde_json::from_str(data).ok()?;
+    serde_json::from_value(chunk.get("usage")?.clone()).ok()
+}
+
+/// `timings` of a relayed usage chunk, only local runs have them.
+pub fn timings_from_chunk(data: &str) -> Option<Timings> {
+    let chunk: serde_json::Value = serde_json::from_str(data).ok()?;
+    serde_json::from_value(chunk.get("timings")?.clone()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        assert_eq!(parse(&message(12, 30)), Some(usage(12, 30)));
+        assert_eq!(parse(&message(12, 30)).unwrap().total_tokens, 42);
+        assert_eq!(parse("usage:1/2"), None);
+        assert_eq!(
+            from_chunk(
+                r#"{"choices":[],"usage":{"prompt_tokens":1,"completion_tokens":2,"total_tokens":3}}"#
+            ),
+            Some(usage(1, 2))
+        );
+        assert_eq!(from_chunk(r#"{"choices":[]}"#), None);
+        assert_eq!(
+            timings_from_chunk(
+                r#"{"choices":[],"timings":{"load_ms":0.0,"prompt_ms":120.0,"generate_ms":800.5}}"#
+            )
+            .map(|timings| timings.generate_ms),
+            Some(800.5)
+        );
+    }
+}
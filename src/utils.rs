@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -7,16 +8,31 @@ use std::{
 };
 
 use hf_hub::api::Progress;
-use indicatif::HumanBytes;
 use serde::Deserialize;
 
-use crate::ModelProgress;
+use crate::{i18n::MessageKey, Content, Message, ModelProgress, Role};
 
 #[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
 pub enum ModelType {
     #[default]
     LLM,
+    VLM,
     ASR,
+    Embedding,
+    Rerank,
+    Echo,
+    Image,
+    TTS,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Pooling {
+    /// 取 [CLS] 的向量 (bge)
+    #[default]
+    Cls,
+    /// 依 attention mask 平均 (gte)
+    Mean,
 }
 
 #[derive(Debug, Clone, Default, Deserialize)]
@@ -28,24 +44,294 @@ pub struct ModelConfig {
     pub max_context_len: i32,
     pub model_path: Option<String>,
     pub tokenizer_repo: Option<String>,
+    /// 本機的 tokenizer 目錄或 tokenizer_config.json，可以用 `{model_name}`
+    pub tokenizer_path: Option<String>,
+    /// 蓋掉 tokenizer_config.json 的 chat template (Jinja)
+    pub chat_template: Option<String>,
+    /// 同上，從檔案讀，可以用 `{model_name}`
+    pub chat_template_path: Option<String>,
     pub local_repo: Option<String>,
     #[serde(skip_deserializing)]
     pub _asserts_path: String,
+    /// `{session}` 會換成對話 id，每個對話一個 prompt cache 檔
     pub cache_path: Option<String>,
+    pub cache_policy: Option<crate::prompt_cache::PromptCachePolicy>,
     pub think: Option<bool>,
+    pub vision_model_path: Option<String>,
+    /// 跟模型檔一起下載檢查的檔案：分割的模型檔、mmproj 等，可以用 `{model_name}`
+    #[serde(default)]
+    pub extra_files: Vec<String>,
+    pub img_start: Option<String>,
+    pub img_end: Option<String>,
+    pub img_content: Option<String>,
+    /// 請求裡圖片解碼後的上限 (bytes)，預設 20 MB
+    pub max_image_bytes: Option<usize>,
+    /// URL prefixes of request images that may be fetched from private addresses
+    #[serde(default)]
+    pub image_url_allow: Vec<String>,
+    pub vocab_path: Option<String>,
+    pub pooling: Option<Pooling>,
+    pub max_batch_pairs: Option<usize>,
+    pub echo: Option<EchoConfig>,
+    pub diffusion: Option<DiffusionConfig>,
+    pub speech: Option<SpeechConfig>,
+    /// 失敗時改用的模型：另一個 model_name，或遠端 OpenAI 相容的 chat/completions URL
+    pub fallback: Option<String>,
+    /// 把一部分請求分流到其他變體 (例如不同量化) 做 A/B 比較
+    #[serde(default)]
+    pub traffic_split: Vec<TrafficSplit>,
+    /// 客戶端沒給 system 訊息時用的 system prompt
+    pub system_prompt: Option<String>,
+    /// 伺服器端強制的 system prompt
+    pub system_prompt_policy: Option<SystemPromptPolicy>,
+    /// Queued plus running chat sessions allowed at once, the rest get 429
+    pub max_sessions: Option<usize>,
+    /// 不設定就依 model_type 推斷
+    pub capabilities: Option<Vec<crate::capabilities::Capability>>,
+    /// 有圖片的請求改問這個視覺模型 (另一個 model_name)
+    pub vision_companion: Option<String>,
+    /// 額外的 sampling preset，同名會蓋掉內建的
+    #[serde(default)]
+    pub sampling_presets: HashMap<String, crate::sampling::SamplingPreset>,
+    /// Sampling values of requests leaving them out
+    #[serde(default)]
+    pub sampling: crate::sampling::SamplingPreset,
+    /// Requests may ask for other sampling values, each change re-initializes the runtime
+    #[serde(default)]
+    pub per_request_sampling: bool,
+    /// Unload after this long without requests, for requests without `keep_alive`
+    #[serde(default)]
+    pub keep_alive: Option<crate::keep_alive::KeepAlive>,
+    /// 沒給 max_tokens 時最多生成的 token 數，預設 4096
+    pub max_new_tokens: Option<i32>,
+    /// Hugging Face mirror, cache and token of this model, over the `hub` of the server config
+    pub hub: Option<crate::hub::HubConfig>,
+    /// 模型檔的 sha256 跟大小，key 是檔名
+    #[serde(default)]
+    pub checksums: HashMap<String, crate::hub::Checksum>,
+    /// 載入後先生成幾個 token，第一個請求不用等冷啟動
+    pub warmup_prompt: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SystemPromptMode {
+    /// 放在客戶端的 system prompt 前面
+    #[default]
+    Prepend,
+    /// 丟掉客戶端的 system 訊息
+    Replace,
+    /// 客戶端帶 system 訊息就拒絕
+    RejectClientSystem,
+}
+
+/// Server controlled system prompt that clients cannot remove, e.g. for kiosks.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SystemPromptPolicy {
+    pub prompt: String,
+    #[serde(default)]
+    pub mode: SystemPromptMode,
+}
+
+/// The enforced `system_prompt_policy`, then the `system_prompt` of the model when the client sent
+/// no system message of its own.
+pub fn apply_system_prompts(
+    config: &ModelConfig,
+    messages: &mut Vec<Message>,
+) -> Result<(), String> {
+    let client_system = messages
+        .iter()
+        .any(|m| matches!(m.role, Some(Role::System | Role::Developer)));
+    if let Some(policy) = &config.system_prompt_policy {
+        policy.apply(messages)?;
+    }
+    if let Some(prompt) = config.system_prompt.as_deref().filter(|_| !client_system) {
+        crate::chat::append_system_prompt(messages, prompt);
+    }
+    Ok(())
+}
+
+impl SystemPromptPolicy {
+    pub fn apply(&self, messages: &mut Vec<Message>) -> Result<(), String> {
+        let is_system = |m: &Message| matches!(m.role, Some(Role::System | Role::Developer));
+        match self.mode {
+            SystemPromptMode::Prepend => {
+                if let Some(Message {
+                    role: Some(Role::System | Role::Developer),
+                    content: Some(Content::String(system)),
+                    ..
+                }) = messages.first_mut()
+                {
+                    *system = format!("{}\n\n{}", self.prompt, system);
+                    return Ok(());
+                }
+            }
+            SystemPromptMode::Replace => messages.retain(|m| !is_system(m)),
+            SystemPromptMode::RejectClientSystem => {
+                if messages.iter().any(is_system) {
+                    return Err("System messages are not allowed for this model.".to_owned());
+                }
+            }
+        }
+        messages.insert(
+            0,
+            Message {
+                role: Some(Role::System),
+                content: Some(Content::String(self.prompt.clone())),
+                ..Default::default()
+            },
+        );
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TrafficSplit {
+    /// `model_name` of the variant config
+    pub model: String,
+    /// Percentage of requests (0-100) sent to the variant
+    pub percent: u32,
+}
+
+/// Files and sampling defaults of the `Image` model type (Stable Diffusion 1.5 LCM on RKNN).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DiffusionConfig {
+    pub text_encoder: String,
+    pub unet: String,
+    pub vae_decoder: String,
+    pub vocab: String,
+    pub merges: String,
+    /// RKNN 的 shape 是固定的，只能輸出這個尺寸
+    pub size: String,
+    pub num_inference_steps: usize,
+    pub guidance_scale: f32,
+}
+
+impl Default for DiffusionConfig {
+    fn default() -> Self {
+        Self {
+            text_encoder: "text_encoder/model.rknn".to_owned(),
+            unet: "unet/model.rknn".to_owned(),
+            vae_decoder: "vae_decoder/model.rknn".to_owned(),
+            vocab: "tokenizer/vocab.json".to_owned(),
+            merges: "tokenizer/merges.txt".to_owned(),
+            size: "512x512".to_owned(),
+            num_inference_steps: 4,
+            guidance_scale: 8.0,
+        }
+    }
+}
+
+/// Piper voice of the `TTS` model type, `model_path` is the `.onnx` file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SpeechConfig {
+    /// piper 執行檔
+    pub binary: String,
+    /// Voice config, `{model_path}.json` by default
+    pub config: Option<String>,
+    /// OpenAI voice name -> piper speaker id
+    pub voices: HashMap<String, i64>,
+}
+
+impl Default for SpeechConfig {
+    fn default() -> Self {
+        Self {
+            binary: "piper".to_owned(),
+            config: None,
+            voices: HashMap::new(),
+        }
+    }
+}
+
+/// Canned responses of the `Echo` model type, for testing without model weights.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EchoConfig {
+    /// 每個 token 之間的延遲
+    #[serde(default)]
+    pub token_delay_ms: u64,
+    /// First matching rule wins, the last user message is echoed back if none matches.
+    #[serde(default)]
+    pub rules: Vec<EchoRule>,
+    /// Loading the model fails with this message, for testing load errors and fallbacks.
+    #[serde(default)]
+    pub load_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EchoRule {
+    /// Substring of the last user message, `None` matches everything.
+    pub when: Option<String>,
+    /// Raw model output, may contain `<think>` or `<tool_call>` blocks.
+    #[serde(default)]
+    pub response: String,
+    /// Emitted as a model error after `response`.
+    pub error: Option<String>,
+}
+
+/// Register each `alias -> model_name` of the mapping table as another name of that model.
+///
+/// 讓寫死 OpenAI 模型名稱的 app 也能打到本地模型，回應裡的 `model` 是本地模型名稱
+pub fn apply_model_map(
+    configs: &mut HashMap<String, ModelConfig>,
+    map: &HashMap<String, String>,
+) -> Result<(), String> {
+    for (alias, model_name) in map {
+        if configs.contains_key(alias) {
+            return Err(format!("Alias \"{}\" is already a model name", alias));
+        }
+        let Some(config) = configs.get(model_name) else {
+            return Err(format!(
+                "Alias \"{}\" maps to unknown model \"{}\"",
+                alias, model_name
+            ));
+        };
+        configs.insert(alias.clone(), config.clone());
+    }
+    Ok(())
 }
 
 fn default_max_context_len() -> i32 {
     16384
 }
 
+/// Year, month and day of the `days`-th day since 1970-01-01 (civil_from_days).
+pub fn civil_date(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// RFC 3339 UTC time of a unix time, like `2025-01-31T12:00:00Z`.
+pub fn rfc3339(secs: u64) -> String {
+    let (year, month, day) = civil_date((secs / 86400) as i64);
+    let time = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct ProgressMessage {
     pub current: usize,
     pub total: usize,
     pub download_done: bool,
     pub finished: bool,
-    pub message: String,
+    #[serde(rename = "message")]
+    pub key: MessageKey,
 }
 
 #[derive(Debug)]
@@ -54,6 +340,9 @@ pub struct OpenWebUIProgress {
     sender: tokio::sync::mpsc::Sender<ProgressMessage>,
     current: usize,
     total: usize,
+    // 多個檔案時，前面檔案的 bytes 跟全部的 bytes
+    offset: usize,
+    files_total: Option<usize>,
     // ModelProgress 相關狀態
     start_time: Option<std::time::Instant>, // 模型載入的開始時間
     stop_flag: Arc<AtomicBool>,
@@ -67,6 +356,8 @@ impl Clone for OpenWebUIProgress {
             sender: self.sender.clone(),
             current: self.current,
             total: self.total,
+            offset: self.offset,
+            files_total: self.files_total,
             start_time: self.start_time,
             stop_flag: Arc::new(AtomicBool::new(false)),
             update_handle: None,
@@ -82,6 +373,8 @@ impl OpenWebUIProgress {
             sender,
             total: 0,
             current: 0,
+            offset: 0,
+            files_total: None,
             start_time: None,
             stop_flag: Arc::new(AtomicBool::new(false)),
             update_handle: None,
@@ -91,14 +384,16 @@ impl OpenWebUIProgress {
 
 impl Progress for OpenWebUIProgress {
     fn init(&mut self, size: usize, filename: &str) {
-        self.total = size;
-        self.current = 0;
+        self.total = self.files_total.unwrap_or(size);
+        self.current = self.offset;
         let msg = ProgressMessage {
-            current: 0,
-            total: size,
+            current: self.current,
+            total: self.total,
             download_done: false,
             finished: false,
-            message: format!("開始下載模型：{}", filename),
+            key: MessageKey::DownloadStarted {
+                file: filename.to_owned(),
+            },
         };
         // 由於我們在同步 Trait 裡，不能 await，我們必須用 try_send 或 blocking_send (如果需要)
         // 這裡我們假設 MPSC 緩衝區夠大，使用 try_send
@@ -112,18 +407,25 @@ impl Progress for OpenWebUIProgress {
             total: self.total,
             download_done: false,
             finished: false,
-            message: format!("下載中... {}/{}\n", self.current, self.total),
+            key: MessageKey::Downloading {
+                current: self.current,
+                total: self.total,
+            },
         };
         let _ = self.sender.try_send(msg);
     }
 
     fn finish(&mut self) {
+        // 還有檔案要下載
+        if self.current < self.total && self.files_total.is_some() {
+            return;
+        }
         let msg = ProgressMessage {
             current: self.total,
             total: self.total,
             download_done: true,
             finished: false,
-            message: "下載完成，正在初始化模型...".to_owned(),
+            key: MessageKey::DownloadFinished,
         };
         let _ = self.sender.try_send(msg);
     }
@@ -131,6 +433,11 @@ impl Progress for OpenWebUIProgress {
 
 // 實作 ModelProgress
 impl ModelProgress for OpenWebUIProgress {
+    fn download_offset(&mut self, done: usize, total: usize) {
+        self.offset = done;
+        self.files_total = Some(total);
+    }
+
     fn model_load(&mut self, size: usize, filename: &str, start: std::time::Instant) {
         self.start_time = Some(start);
         // 發送載入開始訊息
@@ -139,11 +446,10 @@ impl ModelProgress for OpenWebUIProgress {
             total: self.total,
             download_done: true,
             finished: false,
-            message: format!(
-                "下載完成，開始載入 RKLLM 核心 {} ({})...",
-                filename,
-                HumanBytes(size as u64)
-            ),
+            key: MessageKey::ModelLoading {
+                file: filename.to_owned(),
+                size: size as u64,
+            },
         };
         let _ = self.sender.try_send(msg);
 
@@ -162,7 +468,9 @@ impl ModelProgress for OpenWebUIProgress {
                     total,
                     download_done: true,
                     finished: false,
-                    message: format!("讀取模型中，已過去{}秒", start.elapsed().as_secs()),
+                    key: MessageKey::ModelLoadingElapsed {
+                        secs: start.elapsed().as_secs(),
+                    },
                 };
                 let _ = sender_clone.try_send(msg);
                 std::thread::sleep(Duration::from_secs(1));
@@ -190,8 +498,110 @@ impl ModelProgress for OpenWebUIProgress {
             total: self.total,
             download_done: true,
             finished: true,
-            message: "模型完全初始化完成，正在啟動 Actor。".to_owned(),
+            key: MessageKey::ModelReady,
         };
         let _ = self.sender.try_send(msg);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn model_map_adds_aliases() {
+        let mut configs = HashMap::from([(
+            "Qwen2.5-3B".to_owned(),
+            ModelConfig {
+                model_name: "Qwen2.5-3B".to_owned(),
+                ..Default::default()
+            },
+        )]);
+        let map = HashMap::from([("gpt-4o-mini".to_owned(), "Qwen2.5-3B".to_owned())]);
+        apply_model_map(&mut configs, &map).unwrap();
+        assert_eq!(configs["gpt-4o-mini"].model_name, "Qwen2.5-3B");
+
+        let unknown = HashMap::from([("gpt-4o".to_owned(), "missing".to_owned())]);
+        assert!(apply_model_map(&mut configs, &unknown).is_err());
+    }
+
+    #[test]
+    fn progress_covers_all_files() {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+        let mut progress = OpenWebUIProgress::new(sender);
+        progress.download_offset(100, 300);
+        progress.init(200, "model-00002-of-00002.rkllm");
+        progress.update(50);
+        progress.finish();
+        let started = receiver.try_recv().unwrap();
+        assert_eq!((started.current, started.total), (100, 300));
+        let update = receiver.try_recv().unwrap();
+        assert_eq!((update.current, update.total), (150, 300));
+        // 後面還有檔案，不算下載完
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn formats_utc_times() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(1738324800), "2025-01-31T12:00:00Z");
+        assert_eq!(civil_date(19417), (2023, 3, 1));
+    }
+
+    #[test]
+    fn system_prompt_policy_modes() {
+        let message = |role: Role, text: &str| Message {
+            role: Some(role),
+            content: Some(Content::String(text.to_owned())),
+            ..Default::default()
+        };
+        let client = vec![message(Role::System, "Be rude."), message(Role::User, "Hi")];
+        let policy = |mode| SystemPromptPolicy {
+            prompt: "Be polite.".to_owned(),
+            mode,
+        };
+
+        let mut messages = client.clone();
+        policy(SystemPromptMode::Prepend)
+            .apply(&mut messages)
+            .unwrap();
+        assert!(
+            matches!(&messages[0].content, Some(Content::String(s)) if s == "Be polite.\n\nBe rude.")
+        );
+
+        let mut messages = client.clone();
+        policy(SystemPromptMode::Replace)
+            .apply(&mut messages)
+            .unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(matches!(&messages[0].content, Some(Content::String(s)) if s == "Be polite."));
+
+        let mut messages = client.clone();
+        assert!(policy(SystemPromptMode::RejectClientSystem)
+            .apply(&mut messages)
+            .is_err());
+        let mut messages = vec![message(Role::User, "Hi")];
+        policy(SystemPromptMode::RejectClientSystem)
+            .apply(&mut messages)
+            .unwrap();
+        assert_eq!(messages.len(), 2);
+
+        let mut config = ModelConfig {
+            system_prompt: Some("You are Lumi.".to_owned()),
+            ..Default::default()
+        };
+        let mut messages = vec![message(Role::User, "Hi")];
+        apply_system_prompts(&config, &mut messages).unwrap();
+        assert!(matches!(&messages[0].content, Some(Content::String(s)) if s == "You are Lumi."));
+        // 客戶端自己的 system prompt 優先
+        let mut messages = client.clone();
+        apply_system_prompts(&config, &mut messages).unwrap();
+        assert!(matches!(&messages[0].content, Some(Content::String(s)) if s == "Be rude."));
+        config.system_prompt_policy = Some(policy(SystemPromptMode::Prepend));
+        let mut messages = vec![message(Role::User, "Hi")];
+        apply_system_prompts(&config, &mut messages).unwrap();
+        assert!(
+            matches!(&messages[0].content, Some(Content::String(s)) if s == "Be polite.\n\nYou are Lumi.")
+        );
+    }
+}
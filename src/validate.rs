@@ -0,0 +1,394 @@
+//! Checks of the model configs, run at startup and by `llmserver validate-config`.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use serde::de::{DeserializeOwned, Visitor};
+use serde_json::Value;
+
+use crate::{
+    config_format,
+    llm::simple::render_local_path_template,
+    utils::{ModelConfig, ModelType},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// The config cannot be used, the server does not start
+    Error,
+    /// Probably a mistake, the server starts anyway
+    Warning,
+}
+
+/// Something wrong in a config file, with where it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub level: Level,
+    pub file: PathBuf,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn error(file: &Path, line: Option<usize>, message: String) -> Self {
+        Self {
+            level: Level::Error,
+            file: file.to_path_buf(),
+            line,
+            message,
+        }
+    }
+
+    pub fn warning(file: &Path, line: Option<usize>, message: String) -> Self {
+        Self {
+            level: Level::Warning,
+            ..Self::error(file, line, message)
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.level == Level::Error
+    }
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        let level = match self.level {
+            Level::Error => "error",
+            Level::Warning => "warning",
+        };
+        write!(f, ": {}: {}", level, self.message)
+    }
+}
+
+/// Field names `T` deserializes, as its derived `Deserialize` asks for them.
+fn field_names<T: DeserializeOwned>() -> &'static [&'static str] {
+    struct Fields(Option<&'static [&'static str]>);
+
+    impl<'de> serde::Deserializer<'de> for &mut Fields {
+        type Error = serde::de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+            Err(serde::de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            self.0 = Some(fields);
+            Err(serde::de::Error::custom("only the field names are needed"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+            option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+            ignored_any
+        }
+    }
+
+    let mut fields = Fields(None);
+    let _ = T::deserialize(&mut fields);
+    fields.0.unwrap_or_default()
+}
+
+/// Edit distance, to suggest the field a misspelled one meant.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(current)
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+/// Line of a top-level `key` in JSON (`"key":`), YAML (`key:`) or TOML (`key =`).
+fn line_of(contents: &str, key: &str) -> Option<usize> {
+    contents
+        .lines()
+        .position(|line| {
+            let text = line.trim_start().trim_start_matches(['"', '\'']);
+            text.strip_prefix(key).is_some_and(|rest| {
+                rest.trim_start_matches(['"', '\''])
+                    .trim_start()
+                    .starts_with([':', '='])
+            })
+        })
+        .map(|i| i + 1)
+}
+
+/// Fields nobody reads, which serde would skip without a word.
+fn unknown_fields(path: &Path, contents: &str, value: &Value) -> Vec<Problem> {
+    let known = field_names::<ModelConfig>();
+    let Some(object) = value.as_object() else {
+        return vec![Problem::error(
+            path,
+            None,
+            "a model config must be a map of fields".to_owned(),
+        )];
+    };
+    object
+        .keys()
+        .filter(|key| !known.contains(&key.as_str()))
+        .map(|key| {
+            let mut message = format!("unknown field `{}`", key);
+            if let Some(closest) = known
+                .iter()
+                .min_by_key(|field| distance(key, field))
+                .filter(|field| distance(key, field) <= 2)
+            {
+                message.push_str(&format!(", did you mean `{}`?", closest));
+            }
+            Problem::warning(path, line_of(contents, key), message)
+        })
+        .collect()
+}
+
+/// Files and directories the config points at that are not there.
+fn missing_paths(path: &Path, contents: &str, config: &ModelConfig) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut check = |field: &str, value: &Option<String>, level: Level, what: &str| {
+        let Some(value) = value else {
+            return;
+        };
+        let target = PathBuf::from(render_local_path_template(value, config));
+        let exists = match field {
+            "local_repo" => target.is_dir(),
+            // cache 檔還沒存過很正常，只看目錄
+            "cache_path" => target
+                .parent()
+                .is_none_or(|dir| dir.as_os_str().is_empty() || dir.is_dir()),
+            _ => target.exists(),
+        };
+        if !exists {
+            problems.push(Problem {
+                level,
+                file: path.to_path_buf(),
+                line: line_of(contents, field),
+                message: format!("{} {} does not exist, {}", field, target.display(), what),
+            });
+        }
+    };
+    check(
+        "tokenizer_path",
+        &config.tokenizer_path,
+        Level::Error,
+        "the model cannot load its tokenizer",
+    );
+    check(
+        "chat_template_path",
+        &config.chat_template_path,
+        Level::Error,
+        "the model cannot load its chat template",
+    );
+    check(
+        "local_repo",
+        &config.local_repo,
+        Level::Warning,
+        "the model files are downloaded from the hub instead",
+    );
+    check(
+        "cache_path",
+        &config.cache_path,
+        Level::Warning,
+        "the prompt cache cannot be saved",
+    );
+    problems
+}
+
+/// Check one config file, returning the config when it can be used.
+pub fn check_file(path: &Path, contents: &str) -> (Option<ModelConfig>, Vec<Problem>) {
+    let value: Value = match config_format::parse(path, contents) {
+        Ok(value) => value,
+        Err(e) => return (None, vec![Problem::error(path, None, e)]),
+    };
+    let mut problems = unknown_fields(path, contents, &value);
+    if problems.iter().any(Problem::is_error) {
+        return (None, problems);
+    }
+    let mut config: ModelConfig = match config_format::parse(path, contents) {
+        Ok(config) => config,
+        Err(e) => {
+            problems.push(Problem::error(path, None, e));
+            return (None, problems);
+        }
+    };
+    config._asserts_path = path.to_string_lossy().to_string();
+
+    let mut invalid = |field: &str, message: String| {
+        problems.push(Problem::error(path, line_of(contents, field), message));
+    };
+    if config.model_name.trim().is_empty() {
+        invalid("model_name", "model_name is empty".to_owned());
+    }
+    if config.model_repo.trim().is_empty() && config.local_repo.is_none() {
+        invalid(
+            "model_repo",
+            "model_repo is empty and there is no local_repo to load the files from".to_owned(),
+        );
+    }
+    if config.model_type == ModelType::VLM && config.vision_model_path.is_none() {
+        invalid(
+            "model_type",
+            "a VLM needs vision_model_path, the RKNN vision encoder".to_owned(),
+        );
+    }
+    problems.extend(missing_paths(path, contents, &config));
+    let usable = !problems.iter().any(Problem::is_error);
+    (usable.then_some(config), problems)
+}
+
+/// Check every config of the config directory, including model names used by several files.
+pub fn check_dir(dir: &Path) -> (HashMap<String, ModelConfig>, Vec<Problem>) {
+    let mut configs: HashMap<String, ModelConfig> = HashMap::new();
+    let mut problems = Vec::new();
+    let mut paths: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && config_format::is_config(path))
+            .collect(),
+        Err(e) => return (configs, vec![Problem::error(dir, None, e.to_string())]),
+    };
+    // 依檔名排序，每次回報的順序一樣
+    paths.sort();
+    for path in paths {
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                problems.push(Problem::error(&path, None, e.to_string()));
+                continue;
+            }
+        };
+        let (config, file_problems) = check_file(&path, &contents);
+        problems.extend(file_problems);
+        let Some(config) = config else {
+            continue;
+        };
+        if let Some(other) = configs.get(&config.model_name) {
+            problems.push(Problem::error(
+                &path,
+                line_of(&contents, "model_name"),
+                format!(
+                    "model_name \"{}\" is already used by {}",
+                    config.model_name, other._asserts_path
+                ),
+            ));
+            continue;
+        }
+        configs.insert(config.model_name.clone(), config);
+    }
+    (configs, problems)
+}
+
+/// Check `model_map.json` against the configs and add its names to them.
+pub fn check_model_map(path: &Path, configs: &mut HashMap<String, ModelConfig>) -> Vec<Problem> {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let map: HashMap<String, String> = match serde_json::from_str(&contents) {
+        Ok(map) => map,
+        Err(e) => return vec![Problem::error(path, Some(e.line()), e.to_string())],
+    };
+    match crate::utils::apply_model_map(configs, &map) {
+        Ok(()) => Vec::new(),
+        Err(e) => vec![Problem::error(path, None, e)],
+    }
+}
+
+/// Check `assets/server.json`, and that the models it preloads have a config.
+pub fn check_server_config(path: &Path, configs: &HashMap<String, ModelConfig>) -> Vec<Problem> {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let server: crate::preload::ServerConfig = match serde_json::from_str(&contents) {
+        Ok(server) => server,
+        Err(e) => return vec![Problem::error(path, Some(e.line()), e.to_string())],
+    };
+    server
+        .preload
+        .iter()
+        .filter(|model| !configs.contains_key(*model))
+        .map(|model| {
+            let quoted = format!("\"{}\"", model);
+            let line = contents.lines().position(|line| line.contains(&quoted));
+            Problem::error(
+                path,
+                line.map(|i| i + 1),
+                format!("preloaded model \"{}\" has no config", model),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_config_problems() {
+        let path = Path::new("assets/config/qwen.json");
+        let contents = r#"{
+    "model_repo": "kautism/qwen",
+    "model_name": "qwen",
+    "model_type": "VLM",
+    "vison_model_path": "vision.rknn",
+    "tokenizer_path": "/nonexistent/tokenizer"
+}"#;
+        let (config, problems) = check_file(path, contents);
+        assert!(config.is_none());
+        let messages: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "assets/config/qwen.json:5: warning: unknown field `vison_model_path`, did you mean `vision_model_path`?",
+                "assets/config/qwen.json:4: error: a VLM needs vision_model_path, the RKNN vision encoder",
+                "assets/config/qwen.json:6: error: tokenizer_path /nonexistent/tokenizer does not exist, the model cannot load its tokenizer",
+            ]
+        );
+
+        let (_, problems) = check_file(path, r#"{"model_repo": "kautism/qwen"}"#);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].message.contains("missing field `model_name`"));
+    }
+
+    #[test]
+    fn reports_duplicate_model_names() {
+        let dir = std::env::temp_dir().join(format!("llmserver-config-{}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("a.json"),
+            r#"{"model_repo": "a/qwen", "model_name": "qwen", "model_type": "LLM"}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("b.yaml"),
+            "model_repo: b/qwen\nmodel_name: qwen\nmodel_type: LLM\n",
+        )
+        .unwrap();
+        let (configs, problems) = check_dir(&dir);
+        assert_eq!(configs["qwen"].model_repo, "a/qwen");
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].line, Some(2));
+        assert!(problems[0].message.contains("is already used by"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
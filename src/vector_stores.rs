@@ -0,0 +1,617 @@
+use std::collections::HashMap;
+
+use actix_web::{delete, get, http::StatusCode, post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    embeddings::EmbeddingPool,
+    files::FileStore,
+    rag::{
+        add_documents, error, query, KnowledgeBase, RagStore, DEFAULT_CHUNK_OVERLAP,
+        DEFAULT_CHUNK_SIZE,
+    },
+    utils::ModelConfig,
+};
+
+const DEFAULT_MAX_RESULTS: usize = 10;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct FileCounts {
+    pub in_progress: usize,
+    pub completed: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct VectorStore {
+    pub id: String,
+    pub object: String,
+    pub created_at: u64,
+    pub name: Option<String>,
+    pub usage_bytes: usize,
+    pub file_counts: FileCounts,
+    pub status: String,
+    pub metadata: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct VectorStoreFile {
+    pub id: String,
+    pub object: String,
+    pub created_at: u64,
+    pub usage_bytes: usize,
+    pub vector_store_id: String,
+    pub status: String,
+    pub last_error: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct ListVectorStores {
+    pub object: String,
+    pub data: Vec<VectorStore>,
+    pub first_id: Option<String>,
+    pub last_id: Option<String>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct ListVectorStoreFiles {
+    pub object: String,
+    pub data: Vec<VectorStoreFile>,
+    pub first_id: Option<String>,
+    pub last_id: Option<String>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[schema(example = json!({ "name": "manuals", "file_ids": ["file-0123456789abcdef"] }))]
+pub struct CreateVectorStoreRequest {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub file_ids: Vec<String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    /// Local embedding model, defaults to the first one configured
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct ModifyVectorStoreRequest {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct CreateVectorStoreFileRequest {
+    pub file_id: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(untagged)]
+pub enum SearchQuery {
+    String(String),
+    Array(Vec<String>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct SearchRequest {
+    pub query: SearchQuery,
+    #[serde(default)]
+    pub max_num_results: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct SearchContent {
+    pub r#type: String,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct SearchResult {
+    pub file_id: String,
+    pub filename: String,
+    pub score: f32,
+    pub attributes: HashMap<String, String>,
+    pub content: Vec<SearchContent>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct SearchResponse {
+    pub object: String,
+    pub search_query: Vec<String>,
+    pub data: Vec<SearchResult>,
+    pub has_more: bool,
+    pub next_page: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    pub limit: Option<usize>,
+    pub after: Option<String>,
+    /// `desc` (default) or `asc` by `created_at`
+    pub order: Option<String>,
+}
+
+impl ListQuery {
+    /// 回傳這一頁與是否還有下一頁
+    fn page<T>(
+        &self,
+        mut items: Vec<T>,
+        id: impl Fn(&T) -> &str,
+        created_at: impl Fn(&T) -> u64,
+    ) -> (Vec<T>, bool) {
+        items.sort_by_key(|item| created_at(item));
+        if self.order.as_deref() != Some("asc") {
+            items.reverse();
+        }
+        if let Some(after) = &self.after {
+            if let Some(pos) = items.iter().position(|item| id(item) == after) {
+                items.drain(..=pos);
+            }
+        }
+        let limit = self.limit.unwrap_or(20).clamp(1, 100);
+        let has_more = items.len() > limit;
+        items.truncate(limit);
+        (items, has_more)
+    }
+}
+
+fn not_found(id: &str) -> HttpResponse {
+    error(
+        StatusCode::NOT_FOUND,
+        format!("No vector store found with id '{}'.", id),
+        "not_found",
+    )
+}
+
+fn vector_store(id: &str, kb: &KnowledgeBase) -> VectorStore {
+    let files = kb.documents();
+    VectorStore {
+        id: id.to_owned(),
+        object: "vector_store".to_owned(),
+        created_at: kb.created,
+        name: kb.name.clone(),
+        usage_bytes: kb.chunks.iter().map(|chunk| chunk.text.len()).sum(),
+        file_counts: FileCounts {
+            completed: files,
+            total: files,
+            ..Default::default()
+        },
+        status: "completed".to_owned(),
+        metadata: kb.metadata.clone(),
+    }
+}
+
+fn vector_store_files(id: &str, kb: &KnowledgeBase) -> Vec<VectorStoreFile> {
+    let mut usage: Vec<(String, usize)> = Vec::new();
+    for chunk in &kb.chunks {
+        match usage
+            .iter_mut()
+            .find(|(file_id, _)| *file_id == chunk.document_id)
+        {
+            Some((_, bytes)) => *bytes += chunk.text.len(),
+            None => usage.push((chunk.document_id.clone(), chunk.text.len())),
+        }
+    }
+    usage
+        .into_iter()
+        .map(|(file_id, usage_bytes)| VectorStoreFile {
+            id: file_id,
+            object: "vector_store.file".to_owned(),
+            created_at: kb.created,
+            usage_bytes,
+            vector_store_id: id.to_owned(),
+            status: "completed".to_owned(),
+            last_error: None,
+        })
+        .collect()
+}
+
+/// 只支援文字檔
+fn read_files(
+    files: &FileStore,
+    file_ids: &[String],
+) -> Result<Vec<(String, String)>, HttpResponse> {
+    file_ids
+        .iter()
+        .map(|file_id| {
+            let bytes = files.content(file_id).ok_or_else(|| {
+                error(
+                    StatusCode::BAD_REQUEST,
+                    format!("No such file: {}", file_id),
+                    "file_not_found",
+                )
+            })?;
+            let text = String::from_utf8(bytes).map_err(|_| {
+                error(
+                    StatusCode::BAD_REQUEST,
+                    format!("File {} is not UTF-8 text.", file_id),
+                    "unsupported_file",
+                )
+            })?;
+            Ok((file_id.clone(), text))
+        })
+        .collect()
+}
+
+fn internal_error(e: std::io::Error) -> HttpResponse {
+    error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        e.to_string(),
+        "processing_error",
+    )
+}
+
+/// Create a vector store, optionally indexing uploaded files.
+#[utoipa::path(
+    request_body = CreateVectorStoreRequest,
+    responses(
+        (status = OK, description = "Success", body = VectorStore, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/vector_stores")]
+pub async fn create(
+    body: web::Json<CreateVectorStoreRequest>,
+    rag: web::Data<RagStore>,
+    files: web::Data<FileStore>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    embedding_pool: web::Data<EmbeddingPool>,
+) -> impl Responder {
+    let body = body.into_inner();
+    let documents = match read_files(&files, &body.file_ids) {
+        Ok(documents) => documents,
+        Err(response) => return response,
+    };
+    let id = format!("vs_{:016x}", rand::random::<u64>());
+    if let Err(response) = add_documents(
+        &rag,
+        &id,
+        body.model.as_deref(),
+        documents,
+        (DEFAULT_CHUNK_SIZE, DEFAULT_CHUNK_OVERLAP),
+        &all_configs,
+        &embedding_pool,
+    )
+    .await
+    {
+        return response;
+    }
+    match rag.update(&id, |kb| {
+        kb.name = body.name;
+        kb.metadata = body.metadata;
+        vector_store(&id, kb)
+    }) {
+        Ok(store) => HttpResponse::Ok().json(store),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// List vector stores, every knowledge base is one.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", body = ListVectorStores, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/vector_stores")]
+pub async fn list(query: web::Query<ListQuery>, rag: web::Data<RagStore>) -> impl Responder {
+    let (data, has_more) = query.page(
+        rag.list(vector_store),
+        |store| &store.id,
+        |store| store.created_at,
+    );
+    HttpResponse::Ok().json(ListVectorStores {
+        object: "list".to_owned(),
+        first_id: data.first().map(|store| store.id.clone()),
+        last_id: data.last().map(|store| store.id.clone()),
+        data,
+        has_more,
+    })
+}
+
+/// Retrieve a vector store.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", body = VectorStore, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/vector_stores/{id}")]
+pub async fn retrieve(path: web::Path<String>, rag: web::Data<RagStore>) -> impl Responder {
+    match rag.read(&path, |kb| vector_store(&path, kb)) {
+        Some(store) => HttpResponse::Ok().json(store),
+        None => not_found(&path),
+    }
+}
+
+/// Change the name or metadata of a vector store.
+#[utoipa::path(
+    request_body = ModifyVectorStoreRequest,
+    responses(
+        (status = OK, description = "Success", body = VectorStore, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/vector_stores/{id}")]
+pub async fn modify(
+    path: web::Path<String>,
+    body: web::Json<ModifyVectorStoreRequest>,
+    rag: web::Data<RagStore>,
+) -> impl Responder {
+    if rag.read(&path, |_| ()).is_none() {
+        return not_found(&path);
+    }
+    let body = body.into_inner();
+    match rag.update(&path, |kb| {
+        if body.name.is_some() {
+            kb.name = body.name;
+        }
+        if let Some(metadata) = body.metadata {
+            kb.metadata = metadata;
+        }
+        vector_store(&path, kb)
+    }) {
+        Ok(store) => HttpResponse::Ok().json(store),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Delete a vector store. Uploaded files are kept.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[delete("/vector_stores/{id}")]
+pub async fn remove(path: web::Path<String>, rag: web::Data<RagStore>) -> impl Responder {
+    match rag.remove(&path) {
+        Ok(true) => HttpResponse::Ok().json(serde_json::json!({
+            "id": path.as_str(),
+            "object": "vector_store.deleted",
+            "deleted": true,
+        })),
+        Ok(false) => not_found(&path),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Index an uploaded file into a vector store.
+#[utoipa::path(
+    request_body = CreateVectorStoreFileRequest,
+    responses(
+        (status = OK, description = "Success", body = VectorStoreFile, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/vector_stores/{id}/files")]
+pub async fn create_file(
+    path: web::Path<String>,
+    body: web::Json<CreateVectorStoreFileRequest>,
+    rag: web::Data<RagStore>,
+    files: web::Data<FileStore>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    embedding_pool: web::Data<EmbeddingPool>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if rag.read(&id, |_| ()).is_none() {
+        return not_found(&id);
+    }
+    let documents = match read_files(&files, std::slice::from_ref(&body.file_id)) {
+        Ok(documents) => documents,
+        Err(response) => return response,
+    };
+    if let Err(response) = add_documents(
+        &rag,
+        &id,
+        None,
+        documents,
+        (DEFAULT_CHUNK_SIZE, DEFAULT_CHUNK_OVERLAP),
+        &all_configs,
+        &embedding_pool,
+    )
+    .await
+    {
+        return response;
+    }
+    let file = rag
+        .read(&id, |kb| vector_store_files(&id, kb))
+        .unwrap_or_default()
+        .into_iter()
+        .find(|file| file.id == body.file_id);
+    match file {
+        Some(file) => HttpResponse::Ok().json(file),
+        // 空檔案沒有任何 chunk
+        None => HttpResponse::Ok().json(VectorStoreFile {
+            id: body.file_id.clone(),
+            object: "vector_store.file".to_owned(),
+            created_at: 0,
+            usage_bytes: 0,
+            vector_store_id: id,
+            status: "completed".to_owned(),
+            last_error: None,
+        }),
+    }
+}
+
+/// List the files of a vector store.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success", body = ListVectorStoreFiles, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/vector_stores/{id}/files")]
+pub async fn list_files(
+    path: web::Path<String>,
+    query: web::Query<ListQuery>,
+    rag: web::Data<RagStore>,
+) -> impl Responder {
+    let Some(items) = rag.read(&path, |kb| vector_store_files(&path, kb)) else {
+        return not_found(&path);
+    };
+    let (data, has_more) = query.page(items, |file| &file.id, |file| file.created_at);
+    HttpResponse::Ok().json(ListVectorStoreFiles {
+        object: "list".to_owned(),
+        first_id: data.first().map(|file| file.id.clone()),
+        last_id: data.last().map(|file| file.id.clone()),
+        data,
+        has_more,
+    })
+}
+
+/// Remove a file from a vector store. The uploaded file is kept.
+#[utoipa::path(
+    responses(
+        (status = OK, description = "Success")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[delete("/vector_stores/{id}/files/{file_id}")]
+pub async fn remove_file(
+    path: web::Path<(String, String)>,
+    rag: web::Data<RagStore>,
+) -> impl Responder {
+    let (id, file_id) = path.into_inner();
+    if rag.read(&id, |_| ()).is_none() {
+        return not_found(&id);
+    }
+    match rag.update(&id, |kb| kb.remove_document(&file_id)) {
+        Ok(0) => error(
+            StatusCode::NOT_FOUND,
+            format!(
+                "No file found with id '{}' in vector store '{}'.",
+                file_id, id
+            ),
+            "not_found",
+        ),
+        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
+            "id": file_id,
+            "object": "vector_store.file.deleted",
+            "deleted": true,
+        })),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Search a vector store for the chunks closest to a query.
+#[utoipa::path(
+    request_body = SearchRequest,
+    responses(
+        (status = OK, description = "Success", body = SearchResponse, content_type = "application/json")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[post("/vector_stores/{id}/search")]
+pub async fn search(
+    path: web::Path<String>,
+    body: web::Json<SearchRequest>,
+    rag: web::Data<RagStore>,
+    files: web::Data<FileStore>,
+    all_configs: web::Data<HashMap<String, ModelConfig>>,
+    embedding_pool: web::Data<EmbeddingPool>,
+) -> impl Responder {
+    let search_query = match &body.query {
+        SearchQuery::String(query) => vec![query.clone()],
+        SearchQuery::Array(queries) => queries.clone(),
+    };
+    let top_k = body
+        .max_num_results
+        .unwrap_or(DEFAULT_MAX_RESULTS)
+        .clamp(1, 50);
+    let results = match query(
+        &rag,
+        &path,
+        &search_query.join("\n"),
+        top_k,
+        &all_configs,
+        &embedding_pool,
+    )
+    .await
+    {
+        Ok(Some(results)) => results,
+        Ok(None) => return not_found(&path),
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, e, "processing_error"),
+    };
+    HttpResponse::Ok().json(SearchResponse {
+        object: "vector_store.search_results.page".to_owned(),
+        search_query,
+        data: results
+            .into_iter()
+            .map(|result| SearchResult {
+                filename: files
+                    .get(&result.document_id)
+                    .map(|file| file.filename)
+                    .unwrap_or_else(|| result.document_id.clone()),
+                file_id: result.document_id,
+                score: result.score,
+                attributes: HashMap::new(),
+                content: vec![SearchContent {
+                    r#type: "text".to_owned(),
+                    text: result.text,
+                }],
+            })
+            .collect(),
+        has_more: false,
+        next_page: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rag::Chunk;
+
+    #[test]
+    fn files_and_paging() {
+        let chunk = |id: &str, text: &str| Chunk {
+            document_id: id.to_owned(),
+            text: text.to_owned(),
+            vector: vec![],
+        };
+        let kb = KnowledgeBase {
+            created: 1,
+            chunks: vec![chunk("a", "xx"), chunk("b", "y"), chunk("a", "zzz")],
+            ..Default::default()
+        };
+        let store = vector_store("vs_1", &kb);
+        assert_eq!(store.usage_bytes, 6);
+        assert_eq!(store.file_counts.total, 2);
+        let files = vector_store_files("vs_1", &kb);
+        assert_eq!((files[0].id.as_str(), files[0].usage_bytes), ("a", 5));
+
+        let query = ListQuery {
+            limit: Some(1),
+            after: Some("a".to_owned()),
+            order: Some("asc".to_owned()),
+        };
+        let (page, has_more) = query.page(files, |f| &f.id, |f| f.created_at);
+        assert_eq!(page[0].id, "b");
+        assert!(!has_more);
+    }
+}
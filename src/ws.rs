@@ -0,0 +1,263 @@
+use std::{
+    collections::HashMap,
+    future::{ready, Ready},
+};
+
+use actix_web::{
+    body::MessageBody, dev::Payload, get, rt::task::JoinHandle, web, FromRequest, HttpRequest,
+    HttpResponse,
+};
+use actix_ws::{Message, Session};
+use futures::{Stream, StreamExt};
+
+use crate::{
+    chat::{chat_response, ChatCompletionsRequest, LlmPool, ShutdownPool},
+    cluster::Cluster,
+    fallback::FailedModels,
+    utils::ModelConfig,
+    OpenAiError,
+};
+
+/// 每個 WebSocket 連線共用的資源
+#[derive(Clone)]
+pub(crate) struct ChatContext {
+    pub(crate) llm_pool: web::Data<LlmPool>,
+    pub(crate) shutdown_pool: web::Data<ShutdownPool>,
+    pub(crate) all_configs: web::Data<HashMap<String, ModelConfig>>,
+    pub(crate) failed_models: web::Data<FailedModels>,
+    pub(crate) cluster: web::Data<Cluster>,
+    pub(crate) req: HttpRequest,
+}
+
+impl ChatContext {
+    /// Same pipeline as `/v1/chat/completions`.
+    pub(crate) async fn chat(&self, body: ChatCompletionsRequest) -> HttpResponse {
+        chat_response(
+            body,
+            self.llm_pool.clone(),
+            self.shutdown_pool.clone(),
+            self.all_configs.clone(),
+            self.failed_models.clone(),
+            self.cluster.clone(),
+            &self.req,
+        )
+        .await
+    }
+}
+
+fn app_data<T: 'static>(req: &HttpRequest) -> Result<web::Data<T>, actix_web::Error> {
+    req.app_data::<web::Data<T>>()
+        .cloned()
+        .ok_or_else(|| actix_web::error::ErrorInternalServerError("App data is not configured"))
+}
+
+impl FromRequest for ChatContext {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready((|| {
+            Ok(ChatContext {
+                llm_pool: app_data(req)?,
+                shutdown_pool: app_data(req)?,
+                all_configs: app_data(req)?,
+                failed_models: app_data(req)?,
+                cluster: app_data(req)?,
+                req: req.clone(),
+            })
+        })())
+    }
+}
+
+fn error_frame(message: String) -> String {
+    serde_json::json!({
+        "error": OpenAiError {
+            message,
+            code: "invalid_request_error".to_owned(),
+            r#type: "invalid_request_error".to_owned(),
+            param: None,
+        }
+    })
+    .to_string()
+}
+
+/// 從緩衝區取出完整的 SSE 事件，回傳每個 `data:` 的內容
+pub(crate) fn take_sse_events(buf: &mut Vec<u8>) -> Vec<String> {
+    let mut events = Vec::new();
+    while let Some(pos) = buf.windows(2).position(|w| w == b"\n\n") {
+        let event: Vec<u8> = buf.drain(..pos + 2).collect();
+        for line in String::from_utf8_lossy(&event).lines() {
+            if let Some(data) = line.strip_prefix("data:") {
+                events.push(data.trim_start().to_owned());
+            }
+        }
+    }
+    events
+}
+
+/// `data:` payloads of an SSE response body, without the `[DONE]` at the end.
+pub(crate) fn sse_data(response: HttpResponse) -> impl Stream<Item = String> {
+    let mut body = Box::pin(response.into_body());
+    async_stream::stream! {
+        let mut buf = Vec::new();
+        while let Some(chunk) = futures::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await {
+            let Ok(chunk) = chunk else {
+                break;
+            };
+            buf.extend_from_slice(&chunk);
+            for data in take_sse_events(&mut buf) {
+                if data != "[DONE]" {
+                    yield data;
+                }
+            }
+        }
+    }
+}
+
+/// Text deltas of a chat completions SSE payload, loading progress is skipped.
+pub(crate) fn chunk_text(data: &str) -> Result<Option<String>, String> {
+    let Ok(chunk) = serde_json::from_str::<serde_json::Value>(data) else {
+        return Ok(None);
+    };
+    if let Some(error) = chunk.get("error") {
+        return Err(error
+            .as_str()
+            .or_else(|| error["message"].as_str())
+            .map(str::to_owned)
+            .unwrap_or_else(|| error.to_string()));
+    }
+    let delta = &chunk["choices"][0]["delta"];
+    if delta["role"] == "system" {
+        return Ok(None);
+    }
+    Ok(delta["content"].as_str().map(str::to_owned))
+}
+
+/// Run one chat request and relay its SSE chunks as text frames.
+async fn stream_chat(mut session: Session, body: ChatCompletionsRequest, ctx: ChatContext) {
+    let response = ctx.chat(body).await;
+
+    if !response.status().is_success() {
+        // 錯誤是一般的 JSON，包成 {"error": ...}
+        let bytes = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap_or_default();
+        let _ = session
+            .text(format!(
+                "{{\"error\": {}}}",
+                String::from_utf8_lossy(&bytes)
+            ))
+            .await;
+        return;
+    }
+
+    let mut events = std::pin::pin!(sse_data(response));
+    while let Some(data) = events.next().await {
+        if session.text(data).await.is_err() {
+            return;
+        }
+    }
+    let _ = session.text(r#"{"type":"done"}"#).await;
+}
+
+/// Chat completions over a WebSocket.
+///
+/// Send a chat completions request as a text frame, every chunk comes back as a text frame and
+/// `{"type":"done"}` ends the response. `{"type":"cancel"}` stops the running generation.
+#[utoipa::path(
+    responses(
+        (status = 101, description = "Switching protocols")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+)]
+#[get("/ws/chat")]
+pub async fn ws_chat(
+    req: HttpRequest,
+    payload: web::Payload,
+    ctx: ChatContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (response, mut session, mut msg_stream) = actix_ws::handle(&req, payload)?;
+
+    actix_web::rt::spawn(async move {
+        let mut running: Option<JoinHandle<()>> = None;
+        while let Some(Ok(msg)) = msg_stream.recv().await {
+            match msg {
+                Message::Text(text) => {
+                    let value: serde_json::Value = match serde_json::from_str(&text) {
+                        Ok(value) => value,
+                        Err(e) => {
+                            let _ = session
+                                .text(error_frame(format!("Invalid JSON: {}", e)))
+                                .await;
+                            continue;
+                        }
+                    };
+                    if value.get("type").and_then(|t| t.as_str()) == Some("cancel") {
+                        // 丟掉 stream 就會讓模型停止生成
+                        if let Some(task) = running.take() {
+                            task.abort();
+                            let _ = session.text(r#"{"type":"cancelled"}"#).await;
+                        }
+                        continue;
+                    }
+                    if running.as_ref().is_some_and(|task| !task.is_finished()) {
+                        let _ = session
+                            .text(error_frame(
+                                "A response is still running, cancel it first.".to_owned(),
+                            ))
+                            .await;
+                        continue;
+                    }
+                    let mut body: ChatCompletionsRequest = match serde_json::from_value(value) {
+                        Ok(body) => body,
+                        Err(e) => {
+                            let _ = session
+                                .text(error_frame(format!("Invalid request: {}", e)))
+                                .await;
+                            continue;
+                        }
+                    };
+                    // WebSocket 一定是串流
+                    body.stream = true;
+                    running = Some(actix_web::rt::spawn(stream_chat(
+                        session.clone(),
+                        body,
+                        ctx.clone(),
+                    )));
+                }
+                Message::Ping(bytes) => {
+                    let _ = session.pong(&bytes).await;
+                }
+                Message::Close(reason) => {
+                    if let Some(task) = running.take() {
+                        task.abort();
+                    }
+                    let _ = session.close(reason).await;
+                    return;
+                }
+                _ => {}
+            }
+        }
+        if let Some(task) = running.take() {
+            task.abort();
+        }
+    });
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sse_events_survive_split_chunks() {
+        let mut buf = b"data: {\"a\":1}\n\ndata: {\"b\"".to_vec();
+        assert_eq!(take_sse_events(&mut buf), vec!["{\"a\":1}"]);
+        buf.extend_from_slice(b":2}\n\n");
+        assert_eq!(take_sse_events(&mut buf), vec!["{\"b\":2}"]);
+        assert!(buf.is_empty());
+    }
+}